@@ -0,0 +1,31 @@
+/*
+The C interface to the calculator, built as the cdylib of the crate
+(libcalculator.so, calculator.dll or libcalculator.dylib). See src/ffi.rs
+for who owns what.
+*/
+#ifndef CALCULATOR_H
+#define CALCULATOR_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct Session calculator_session;
+
+calculator_session *calculator_new(void);
+void calculator_free(calculator_session *session);
+
+/* null on success, with the value in *result; otherwise an error message
+   to be released with calculator_free_string */
+char *calculator_evaluate(calculator_session *session, const char *input, double *result);
+void calculator_free_string(char *s);
+
+/* 0 on success, -1 otherwise, as for the name of a constant or function */
+int calculator_set_variable(calculator_session *session, const char *name, double value);
+int calculator_get_variable(const calculator_session *session, const char *name, double *value);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,151 @@
+use std::io;
+use std::io::BufRead;
+
+use calculator::AngleUnit;
+use calculator::Context;
+use calculator::Dialect;
+use calculator::eval;
+use calculator::parser;
+use calculator::stats::Moments;
+
+use crate::messages;
+use crate::messages::Msg;
+use crate::report;
+
+#[derive(Clone, Copy)]
+pub enum Aggregate {
+    Count,
+    Sum,
+    Mean,
+    Min,
+    Max,
+    Var,
+    Stddev
+}
+
+impl Aggregate {
+    fn from_name(name : &str) -> Option<Aggregate> {
+        match name {
+            "count" => Some(Aggregate::Count),
+            "sum" => Some(Aggregate::Sum),
+            "mean" => Some(Aggregate::Mean),
+            "min" => Some(Aggregate::Min),
+            "max" => Some(Aggregate::Max),
+            "var" => Some(Aggregate::Var),
+            "stddev" => Some(Aggregate::Stddev),
+            _ => None
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Aggregate::Count => "count",
+            Aggregate::Sum => "sum",
+            Aggregate::Mean => "mean",
+            Aggregate::Min => "min",
+            Aggregate::Max => "max",
+            Aggregate::Var => "var",
+            Aggregate::Stddev => "stddev",
+        }
+    }
+}
+
+// Parses a comma separated list such as "sum, mean, max".
+pub fn parse_list(spec : &str) -> Result<Vec<Aggregate>, String> {
+    spec.split(',')
+        .map(|name| name.trim())
+        .map(|name| Aggregate::from_name(name).ok_or_else(|| Msg::UnknownAggregate(name).to_string()))
+        .collect()
+}
+
+/*
+Running statistics in constant memory, with the library's compensated
+sums and Welford's update for the variance, so they stay accurate for
+long streams and agree with sum, mean and var of a list.
+*/
+struct Stats {
+    moments : Moments<f64>,
+    min : f64,
+    max : f64
+}
+
+impl Stats {
+    fn new() -> Stats {
+        Stats { moments : Moments::new(), min : f64::NAN, max : f64::NAN }
+    }
+
+    fn push(&mut self, x : f64) {
+        self.moments.push(&x, 0);
+
+        // f64::min/max ignore a NaN operand, so the initial NaN drops out
+        self.min = self.min.min(x);
+        self.max = self.max.max(x);
+    }
+
+    fn value(&self, aggregate : Aggregate) -> f64 {
+        match aggregate {
+            Aggregate::Count => self.moments.count() as f64,
+            Aggregate::Sum => self.moments.sum(),
+            Aggregate::Mean => self.moments.mean(0).unwrap_or(f64::NAN),
+            Aggregate::Min => self.min,
+            Aggregate::Max => self.max,
+            Aggregate::Var => self.moments.variance(0).unwrap_or(f64::NAN),
+            Aggregate::Stddev => self.value(Aggregate::Var).sqrt(),
+        }
+    }
+}
+
+// Every non-empty line of stdin is evaluated as an expression and fed into
+// the running statistics. Returns the process exit code.
+pub fn run(aggregates : &[Aggregate], dialect : Dialect, angle : AngleUnit, profile : bool) -> i32 {
+    let mut stats = Stats::new();
+    let mut report = calculator::profile::EvalReport::default();
+    let ctx = Context { angle, ..Context::default() };
+
+    let mut stdin = io::stdin().lock();
+    let mut line = String::new();
+    let mut n = 0;
+
+    loop {
+        line.clear();
+        if stdin.read_line(&mut line).expect("Something wrong") == 0 {
+            break;
+        }
+
+        n += 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let result = if profile {
+            let (result, line_report) = calculator::profile::evaluate(&line, dialect, parser::parse, |expr| eval::evaluate(&expr, &ctx));
+            report.add(&line_report);
+            result
+        }
+        else {
+            calculator::evaluate_with(&line, dialect, &ctx)
+        };
+
+        match result {
+            Ok(value) => {
+                for quantity in value.flatten() {
+                    stats.push(quantity.value);
+                }
+            }
+            Err(e) => {
+                eprintln!("{}", Msg::AtLine(n, e.column(&line), &messages::error(&e).to_string()));
+                return 1;
+            }
+        }
+    }
+
+    for aggregate in aggregates {
+        println!("{} = {}", aggregate.name(), stats.value(*aggregate));
+    }
+
+    if profile {
+        eprint!("{}", report::Summary(&report));
+    }
+
+    0
+}
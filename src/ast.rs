@@ -0,0 +1,755 @@
+use std::fmt;
+
+use crate::date;
+use crate::lexer::Token;
+use crate::rational::Rational;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Unary {
+    Plus,
+    Minus,
+    BitNot,
+    // postfix, 50% is 0.5
+    Percent,
+    Not
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Binary {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    FloorDiv,
+    Mod,
+    Pow,
+    BitAnd,
+    BitOr,
+    Xor,
+    ShiftLeft,
+    ShiftRight,
+    ApproxEq,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    // both short-circuit
+    And,
+    Or
+}
+
+/*
+An expression as parsed. The offsets are where in the input the node came
+from, for errors found while evaluating: the operator of a binary
+operation or unary `~`, the name of a variable or call.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    Number(Rational),
+    // in seconds since 1970, as a date is evaluated
+    Date(i64),
+    Variable { name : String, offset : usize },
+    UnaryOp { op : Unary, operand : Box<Expr>, offset : usize },
+    BinaryOp { op : Binary, lhs : Box<Expr>, rhs : Box<Expr>, offset : usize },
+    Call { name : String, args : Vec<Expr>, offset : usize },
+    List(Vec<Expr>),
+    // `list[index]`, with the offset of the `[`
+    Index { list : Box<Expr>, index : Box<Expr>, offset : usize },
+    // `cond ? then : otherwise`, or `if(cond, then, otherwise)`
+    Conditional { cond : Box<Expr>, then : Box<Expr>, otherwise : Box<Expr> }
+}
+
+// What is done to an expression kept as one.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Symbolic {
+    Simplify,
+    Expand,
+    // by the variable named
+    Derivative(String)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Statement {
+    Expr(Expr),
+    // `name = value`, with the offset of the name
+    Assign(String, usize, Expr),
+    // `name(params) = body`
+    Define(String, Vec<String>, Expr),
+    // `simplify(expr)`, `expand(expr)` or `d/dx(expr)`
+    Symbolic(Symbolic, Expr)
+}
+
+impl Unary {
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Unary::Plus => "+",
+            Unary::Minus => "-",
+            Unary::BitNot => "~",
+            Unary::Percent => "%",
+            Unary::Not => "not"
+        }
+    }
+}
+
+impl Binary {
+    pub fn from_token(token : &Token) -> Option<Binary> {
+        match token {
+            Token::Add => Some(Binary::Add),
+            Token::Sub => Some(Binary::Sub),
+            Token::Mul => Some(Binary::Mul),
+            Token::Div => Some(Binary::Div),
+            Token::FloorDiv => Some(Binary::FloorDiv),
+            Token::Mod => Some(Binary::Mod),
+            Token::Pow => Some(Binary::Pow),
+            Token::BitAnd => Some(Binary::BitAnd),
+            Token::BitOr => Some(Binary::BitOr),
+            Token::Xor => Some(Binary::Xor),
+            Token::ShiftLeft => Some(Binary::ShiftLeft),
+            Token::ShiftRight => Some(Binary::ShiftRight),
+            Token::ApproxEq => Some(Binary::ApproxEq),
+            Token::Equal => Some(Binary::Equal),
+            Token::NotEqual => Some(Binary::NotEqual),
+            Token::Less => Some(Binary::Less),
+            Token::LessEqual => Some(Binary::LessEqual),
+            Token::Greater => Some(Binary::Greater),
+            Token::GreaterEqual => Some(Binary::GreaterEqual),
+            Token::And => Some(Binary::And),
+            Token::Or => Some(Binary::Or),
+            _ => None
+        }
+    }
+
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Binary::Add => "+",
+            Binary::Sub => "-",
+            Binary::Mul => "*",
+            Binary::Div => "/",
+            Binary::FloorDiv => "//",
+            Binary::Mod => "%",
+            Binary::Pow => "^",
+            Binary::BitAnd => "&",
+            Binary::BitOr => "|",
+            Binary::Xor => "xor",
+            Binary::ShiftLeft => "<<",
+            Binary::ShiftRight => ">>",
+            Binary::ApproxEq => "~=",
+            Binary::Equal => "==",
+            Binary::NotEqual => "!=",
+            Binary::Less => "<",
+            Binary::LessEqual => "<=",
+            Binary::Greater => ">",
+            Binary::GreaterEqual => ">=",
+            Binary::And => "and",
+            Binary::Or => "or"
+        }
+    }
+}
+
+// Binding strength of each grammar level, loosest first.
+const CONDITIONAL : u8 = 0;
+const OR : u8 = 1;
+const AND : u8 = 2;
+const NOT : u8 = 3;
+const COMPARISON : u8 = 4;
+const BIT_OR : u8 = 5;
+const XOR : u8 = 6;
+const BIT_AND : u8 = 7;
+const SHIFT : u8 = 8;
+const EXPRESSION : u8 = 9;
+const TERM : u8 = 10;
+const FACTOR : u8 = 11;
+const POWER : u8 = 12;
+const PRIMARY : u8 = 13;
+
+impl Expr {
+    fn precedence(&self) -> u8 {
+        match self {
+            Expr::Number(_) | Expr::Date(_) | Expr::Variable { .. } | Expr::Call { .. } | Expr::List(_) | Expr::Index { .. } => PRIMARY,
+            Expr::Conditional { .. } => CONDITIONAL,
+            Expr::UnaryOp { op : Unary::Not, .. } => NOT,
+            Expr::UnaryOp { .. } => FACTOR,
+            Expr::BinaryOp { op, .. } => match op {
+                Binary::Or => OR,
+                Binary::And => AND,
+                Binary::ApproxEq | Binary::Equal | Binary::NotEqual | Binary::Less | Binary::LessEqual | Binary::Greater | Binary::GreaterEqual => COMPARISON,
+                Binary::BitOr => BIT_OR,
+                Binary::Xor => XOR,
+                Binary::BitAnd => BIT_AND,
+                Binary::ShiftLeft | Binary::ShiftRight => SHIFT,
+                Binary::Add | Binary::Sub => EXPRESSION,
+                Binary::Mul | Binary::Div | Binary::FloorDiv | Binary::Mod => TERM,
+                Binary::Pow => POWER
+            }
+        }
+    }
+}
+
+// `expr`, in brackets if it binds looser than `min` allows.
+fn operand(f : &mut fmt::Formatter<'_>, expr : &Expr, min : u8) -> fmt::Result {
+    if expr.precedence() < min {
+        write!(f, "({})", expr)
+    }
+    else {
+        write!(f, "{}", expr)
+    }
+}
+
+fn items(f : &mut fmt::Formatter<'_>, exprs : &[Expr]) -> fmt::Result {
+    for (i, expr) in exprs.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", expr)?;
+    }
+    Ok(())
+}
+
+/*
+Prints the expression in the standard dialect, with only the brackets the
+grammar needs: the operators of a level are left associative except `^`,
+whose base is a primary and whose exponent is a factor, comparisons don't
+chain, and a conditional nests to the right.
+*/
+impl fmt::Display for Expr {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            // a literal such as `2 1/3` has no decimal form
+            Expr::Number(value) => match value.to_decimal() {
+                Some(decimal) => write!(f, "{}", decimal),
+                None => write!(f, "({})", value)
+            },
+            Expr::Date(seconds) => write!(f, "{}", date::format(*seconds)),
+            Expr::Variable { name, .. } => write!(f, "{}", name),
+            Expr::UnaryOp { op : Unary::Percent, operand : inner, .. } => {
+                operand(f, inner, PRIMARY)?;
+                write!(f, "%")
+            }
+            Expr::UnaryOp { op : Unary::Not, operand : inner, .. } => {
+                write!(f, "not ")?;
+                operand(f, inner, NOT)
+            }
+            Expr::UnaryOp { op, operand : inner, .. } => {
+                write!(f, "{}", op.symbol())?;
+                // -(-2) rather than --2
+                let nested = matches!(**inner, Expr::UnaryOp { op : Unary::Plus | Unary::Minus, .. });
+                operand(f, inner, if nested { PRIMARY } else { FACTOR })
+            }
+            Expr::BinaryOp { op : Binary::Pow, lhs, rhs, .. } => {
+                operand(f, lhs, PRIMARY)?;
+                write!(f, "^")?;
+                operand(f, rhs, FACTOR)
+            }
+            Expr::BinaryOp { op, lhs, rhs, .. } => {
+                let level = self.precedence();
+                operand(f, lhs, if level == COMPARISON { level + 1 } else { level })?;
+                write!(f, " {} ", op.symbol())?;
+                operand(f, rhs, level + 1)
+            }
+            Expr::Call { name, args, .. } => {
+                write!(f, "{}(", name)?;
+                items(f, args)?;
+                write!(f, ")")
+            }
+            Expr::List(elements) => {
+                write!(f, "[")?;
+                items(f, elements)?;
+                write!(f, "]")
+            }
+            Expr::Index { list, index, .. } => {
+                operand(f, list, PRIMARY)?;
+                write!(f, "[{}]", index)
+            }
+            Expr::Conditional { cond, then, otherwise } => {
+                operand(f, cond, OR)?;
+                write!(f, " ? ")?;
+                operand(f, then, CONDITIONAL)?;
+                write!(f, " : ")?;
+                operand(f, otherwise, CONDITIONAL)
+            }
+        }
+    }
+}
+
+// Names with a symbol of their own in LaTeX.
+const LATEX_NAMES : &[(&str, &str)] = &[("pi", "\\pi"), ("π", "\\pi"), ("tau", "\\tau"), ("phi", "\\phi"), ("inf", "\\infty")];
+
+// Functions LaTeX has a command for, such as `\sin`.
+const LATEX_FUNCTIONS : &[&str] = &["sin", "cos", "tan", "sinh", "cosh", "tanh", "exp", "ln", "log", "det", "min", "max"];
+
+/*
+An expression as LaTeX for typesetting, e.g. (1+2)/3^2 as
+\frac{1+2}{3^{2}}: quotients as fractions, exponents and indexes raised
+or lowered, and bracketed where Display would bracket the expression. Names
+longer than a letter are upright, and a conditional is written as cases.
+*/
+pub struct Latex<'a>(pub &'a Expr);
+
+fn latex_operand(f : &mut fmt::Formatter<'_>, expr : &Expr, min : u8) -> fmt::Result {
+    if expr.precedence() < min {
+        write!(f, "\\left({}\\right)", Latex(expr))
+    }
+    else {
+        write!(f, "{}", Latex(expr))
+    }
+}
+
+fn latex_items(f : &mut fmt::Formatter<'_>, exprs : &[Expr]) -> fmt::Result {
+    for (i, expr) in exprs.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", Latex(expr))?;
+    }
+    Ok(())
+}
+
+fn latex_name(f : &mut fmt::Formatter<'_>, name : &str) -> fmt::Result {
+    match LATEX_NAMES.iter().find(|(known, _)| *known == name) {
+        Some((_, symbol)) => write!(f, "{}", symbol),
+        None if name.chars().count() == 1 => write!(f, "{}", name),
+        None => write!(f, "\\mathrm{{{}}}", name.replace('_', "\\_"))
+    }
+}
+
+impl fmt::Display for Latex<'_> {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        let expr = self.0;
+        match expr {
+            Expr::Number(value) => match value.to_decimal() {
+                Some(decimal) => write!(f, "{}", decimal),
+                None => {
+                    let fraction = value.to_string();
+                    let (numerator, denominator) = fraction.split_once('/').unwrap_or((&fraction, "1"));
+                    write!(f, "\\frac{{{}}}{{{}}}", numerator, denominator)
+                }
+            },
+            Expr::Date(seconds) => write!(f, "\\text{{{}}}", date::format(*seconds)),
+            Expr::Variable { name, .. } => latex_name(f, name),
+            Expr::UnaryOp { op : Unary::Percent, operand, .. } => {
+                latex_operand(f, operand, PRIMARY)?;
+                write!(f, "\\%")
+            }
+            Expr::UnaryOp { op : Unary::Not, operand, .. } => {
+                write!(f, "\\lnot ")?;
+                latex_operand(f, operand, NOT)
+            }
+            Expr::UnaryOp { op, operand, .. } => {
+                let symbol = match op {
+                    Unary::Plus => "+",
+                    Unary::Minus => "-",
+                    Unary::BitNot => "\\sim ",
+                    Unary::Percent | Unary::Not => unreachable!("written above")
+                };
+                write!(f, "{}", symbol)?;
+                let nested = matches!(**operand, Expr::UnaryOp { op : Unary::Plus | Unary::Minus, .. });
+                latex_operand(f, operand, if nested { PRIMARY } else { FACTOR })
+            }
+            // the fraction bar groups both sides
+            Expr::BinaryOp { op : Binary::Div, lhs, rhs, .. } => write!(f, "\\frac{{{}}}{{{}}}", Latex(lhs), Latex(rhs)),
+            Expr::BinaryOp { op : Binary::FloorDiv, lhs, rhs, .. } => {
+                write!(f, "\\left\\lfloor\\frac{{{}}}{{{}}}\\right\\rfloor", Latex(lhs), Latex(rhs))
+            }
+            Expr::BinaryOp { op : Binary::Pow, lhs, rhs, .. } => {
+                latex_operand(f, lhs, PRIMARY)?;
+                write!(f, "^{{{}}}", Latex(rhs))
+            }
+            Expr::BinaryOp { op, lhs, rhs, .. } => {
+                let symbol = match op {
+                    Binary::Mul => " \\cdot ",
+                    Binary::Mod => " \\bmod ",
+                    Binary::BitAnd => " \\mathbin{\\&} ",
+                    Binary::BitOr => " \\mid ",
+                    Binary::Xor => " \\oplus ",
+                    Binary::ShiftLeft => " \\ll ",
+                    Binary::ShiftRight => " \\gg ",
+                    Binary::ApproxEq => " \\approx ",
+                    Binary::Equal => " = ",
+                    Binary::NotEqual => " \\neq ",
+                    Binary::LessEqual => " \\leq ",
+                    Binary::GreaterEqual => " \\geq ",
+                    Binary::And => " \\land ",
+                    Binary::Or => " \\lor ",
+                    _ => op.symbol()
+                };
+                let level = expr.precedence();
+                latex_operand(f, lhs, if level == COMPARISON { level + 1 } else { level })?;
+                write!(f, "{}", symbol)?;
+                latex_operand(f, rhs, level + 1)
+            }
+            Expr::Call { name, args, .. } => match (name.as_str(), args.as_slice()) {
+                ("sqrt", [arg]) => write!(f, "\\sqrt{{{}}}", Latex(arg)),
+                ("cbrt", [arg]) => write!(f, "\\sqrt[3]{{{}}}", Latex(arg)),
+                ("abs", [arg]) => write!(f, "\\left|{}\\right|", Latex(arg)),
+                ("floor", [arg]) => write!(f, "\\left\\lfloor{}\\right\\rfloor", Latex(arg)),
+                ("ceil", [arg]) => write!(f, "\\left\\lceil{}\\right\\rceil", Latex(arg)),
+                _ => {
+                    if LATEX_FUNCTIONS.contains(&name.as_str()) {
+                        write!(f, "\\{}", name)?;
+                    }
+                    else {
+                        write!(f, "\\operatorname{{{}}}", name.replace('_', "\\_"))?;
+                    }
+                    write!(f, "\\left(")?;
+                    latex_items(f, args)?;
+                    write!(f, "\\right)")
+                }
+            },
+            Expr::List(elements) => {
+                write!(f, "\\left[")?;
+                latex_items(f, elements)?;
+                write!(f, "\\right]")
+            }
+            Expr::Index { list, index, .. } => {
+                latex_operand(f, list, PRIMARY)?;
+                write!(f, "_{{{}}}", Latex(index))
+            }
+            Expr::Conditional { cond, then, otherwise } => {
+                write!(f, "\\begin{{cases}} {} & \\text{{if }} {} \\\\ {} & \\text{{otherwise}} \\end{{cases}}", Latex(then), Latex(cond), Latex(otherwise))
+            }
+        }
+    }
+}
+
+// Names with a symbol of their own in MathML.
+const MATHML_NAMES : &[(&str, &str)] = &[("pi", "π"), ("tau", "τ"), ("phi", "φ"), ("inf", "∞")];
+
+const MATHML_NAMESPACE : &str = "http://www.w3.org/1998/Math/MathML";
+
+/*
+An expression as presentation MathML, for pages and books that can't take
+LaTeX; laid out as Latex lays it out, with quotients as fractions, powers
+and indexes raised or lowered, and the brackets Display would write. This
+is what goes inside a <math> element; Expr::to_mathml gives the element.
+*/
+pub struct MathMl<'a>(pub &'a Expr);
+
+// `text` with the characters XML gives a meaning escaped.
+fn xml_escape(text : &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+fn mathml_operand(f : &mut fmt::Formatter<'_>, expr : &Expr, min : u8) -> fmt::Result {
+    if expr.precedence() < min {
+        write!(f, "<mrow><mo>(</mo>{}<mo>)</mo></mrow>", MathMl(expr))
+    }
+    else {
+        write!(f, "{}", MathMl(expr))
+    }
+}
+
+// `exprs` separated by commas, between `open` and `close`.
+fn mathml_fenced(f : &mut fmt::Formatter<'_>, open : &str, exprs : &[Expr], close : &str) -> fmt::Result {
+    write!(f, "<mrow><mo>{}</mo>", open)?;
+    for (i, expr) in exprs.iter().enumerate() {
+        if i > 0 {
+            write!(f, "<mo>,</mo>")?;
+        }
+        write!(f, "{}", MathMl(expr))?;
+    }
+    write!(f, "<mo>{}</mo></mrow>", close)
+}
+
+fn mathml_name(f : &mut fmt::Formatter<'_>, name : &str) -> fmt::Result {
+    match MATHML_NAMES.iter().find(|(known, _)| *known == name) {
+        Some((_, symbol)) => write!(f, "<mi>{}</mi>", symbol),
+        None => write!(f, "<mi>{}</mi>", xml_escape(name))
+    }
+}
+
+impl fmt::Display for MathMl<'_> {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        let expr = self.0;
+        match expr {
+            Expr::Number(value) => match value.to_decimal() {
+                Some(decimal) => write!(f, "<mn>{}</mn>", decimal),
+                None => {
+                    let fraction = value.to_string();
+                    let (numerator, denominator) = fraction.split_once('/').unwrap_or((&fraction, "1"));
+                    write!(f, "<mfrac><mn>{}</mn><mn>{}</mn></mfrac>", numerator, denominator)
+                }
+            },
+            Expr::Date(seconds) => write!(f, "<mtext>{}</mtext>", date::format(*seconds)),
+            Expr::Variable { name, .. } => mathml_name(f, name),
+            Expr::UnaryOp { op : Unary::Percent, operand, .. } => {
+                write!(f, "<mrow>")?;
+                mathml_operand(f, operand, PRIMARY)?;
+                write!(f, "<mo>%</mo></mrow>")
+            }
+            Expr::UnaryOp { op : Unary::Not, operand, .. } => {
+                write!(f, "<mrow><mo>¬</mo>")?;
+                mathml_operand(f, operand, NOT)?;
+                write!(f, "</mrow>")
+            }
+            Expr::UnaryOp { op, operand, .. } => {
+                let symbol = match op {
+                    Unary::Plus => "+",
+                    Unary::Minus => "−",
+                    Unary::BitNot => "~",
+                    Unary::Percent | Unary::Not => unreachable!("written above")
+                };
+                write!(f, "<mrow><mo>{}</mo>", symbol)?;
+                let nested = matches!(**operand, Expr::UnaryOp { op : Unary::Plus | Unary::Minus, .. });
+                mathml_operand(f, operand, if nested { PRIMARY } else { FACTOR })?;
+                write!(f, "</mrow>")
+            }
+            // the fraction bar groups both sides
+            Expr::BinaryOp { op : Binary::Div, lhs, rhs, .. } => write!(f, "<mfrac>{}{}</mfrac>", MathMl(lhs), MathMl(rhs)),
+            Expr::BinaryOp { op : Binary::FloorDiv, lhs, rhs, .. } => {
+                write!(f, "<mrow><mo>⌊</mo><mfrac>{}{}</mfrac><mo>⌋</mo></mrow>", MathMl(lhs), MathMl(rhs))
+            }
+            Expr::BinaryOp { op : Binary::Pow, lhs, rhs, .. } => {
+                write!(f, "<msup>")?;
+                mathml_operand(f, lhs, PRIMARY)?;
+                write!(f, "{}</msup>", MathMl(rhs))
+            }
+            Expr::BinaryOp { op, lhs, rhs, .. } => {
+                let symbol = match op {
+                    Binary::Sub => "−",
+                    Binary::Mul => "⋅",
+                    Binary::Mod => "mod",
+                    Binary::Xor => "⊕",
+                    Binary::ShiftLeft => "≪",
+                    Binary::ShiftRight => "≫",
+                    Binary::ApproxEq => "≈",
+                    Binary::Equal => "=",
+                    Binary::NotEqual => "≠",
+                    Binary::LessEqual => "≤",
+                    Binary::GreaterEqual => "≥",
+                    Binary::And => "∧",
+                    Binary::Or => "∨",
+                    _ => op.symbol()
+                };
+                let level = expr.precedence();
+                write!(f, "<mrow>")?;
+                mathml_operand(f, lhs, if level == COMPARISON { level + 1 } else { level })?;
+                write!(f, "<mo>{}</mo>", xml_escape(symbol))?;
+                mathml_operand(f, rhs, level + 1)?;
+                write!(f, "</mrow>")
+            }
+            Expr::Call { name, args, .. } => match (name.as_str(), args.as_slice()) {
+                ("sqrt", [arg]) => write!(f, "<msqrt>{}</msqrt>", MathMl(arg)),
+                ("cbrt", [arg]) => write!(f, "<mroot>{}<mn>3</mn></mroot>", MathMl(arg)),
+                ("abs", [_]) => mathml_fenced(f, "|", args, "|"),
+                ("floor", [_]) => mathml_fenced(f, "⌊", args, "⌋"),
+                ("ceil", [_]) => mathml_fenced(f, "⌈", args, "⌉"),
+                _ => {
+                    // U+2061 is function application, so readers say "of"
+                    write!(f, "<mrow><mi>{}</mi><mo>\u{2061}</mo>", xml_escape(name))?;
+                    mathml_fenced(f, "(", args, ")")?;
+                    write!(f, "</mrow>")
+                }
+            },
+            Expr::List(elements) => mathml_fenced(f, "[", elements, "]"),
+            Expr::Index { list, index, .. } => {
+                write!(f, "<msub>")?;
+                mathml_operand(f, list, PRIMARY)?;
+                write!(f, "{}</msub>", MathMl(index))
+            }
+            Expr::Conditional { cond, then, otherwise } => {
+                write!(f, "<mrow><mo>{{</mo><mtable>")?;
+                write!(f, "<mtr><mtd>{}</mtd><mtd><mtext>if </mtext>{}</mtd></mtr>", MathMl(then), MathMl(cond))?;
+                write!(f, "<mtr><mtd>{}</mtd><mtd><mtext>otherwise</mtext></mtd></mtr>", MathMl(otherwise))?;
+                write!(f, "</mtable></mrow>")
+            }
+        }
+    }
+}
+
+impl Expr {
+    // The expression as a <math> element of its own.
+    pub fn to_mathml(&self) -> String {
+        format!("<math xmlns=\"{}\">{}</math>", MATHML_NAMESPACE, MathMl(self))
+    }
+}
+
+impl Statement {
+    // The statement as a <math> element: an expression as Expr::to_mathml
+    // has it, and the rest as equations, d/dx as a fraction.
+    pub fn to_mathml(&self) -> String {
+        let math = match self {
+            Statement::Expr(expr) => { return expr.to_mathml(); }
+            Statement::Assign(name, _, expr) => format!("<mi>{}</mi><mo>=</mo>{}", xml_escape(name), MathMl(expr)),
+            Statement::Define(name, params, body) => {
+                let params = params.iter().map(|param| format!("<mi>{}</mi>", xml_escape(param))).collect::<Vec<_>>().join("<mo>,</mo>");
+                format!("<mi>{}</mi><mo>\u{2061}</mo><mrow><mo>(</mo>{}<mo>)</mo></mrow><mo>=</mo>{}", xml_escape(name), params, MathMl(body))
+            }
+            Statement::Symbolic(Symbolic::Derivative(variable), expr) => {
+                format!("<mfrac><mi>d</mi><mrow><mi>d</mi><mi>{}</mi></mrow></mfrac><mrow><mo>(</mo>{}<mo>)</mo></mrow>", xml_escape(variable), MathMl(expr))
+            }
+            Statement::Symbolic(symbolic, expr) => {
+                let name = if *symbolic == Symbolic::Simplify { "simplify" } else { "expand" };
+                format!("<mi>{}</mi><mo>\u{2061}</mo><mrow><mo>(</mo>{}<mo>)</mo></mrow>", name, MathMl(expr))
+            }
+        };
+        format!("<math xmlns=\"{}\"><mrow>{}</mrow></math>", MATHML_NAMESPACE, math)
+    }
+}
+
+/*
+A statement as an S-expression, for scripts and tests to compare how input
+was parsed: 2 + 3 * 4 is (+ 2 (* 3 4)). An operation or call is its
+operator or name followed by its operands, a list is (list ...), an index
+(index list i) and a conditional (if cond then otherwise). Statements other
+than expressions are (= x value), (define (f x) body), (simplify expr),
+(expand expr) and (d/dx expr).
+*/
+pub struct Sexp<'a>(pub &'a Statement);
+
+fn sexp_form(f : &mut fmt::Formatter<'_>, head : &str, exprs : &[&Expr]) -> fmt::Result {
+    write!(f, "({}", head)?;
+    for expr in exprs {
+        write!(f, " ")?;
+        sexp(f, expr)?;
+    }
+    write!(f, ")")
+}
+
+fn sexp(f : &mut fmt::Formatter<'_>, expr : &Expr) -> fmt::Result {
+    match expr {
+        Expr::Number(value) => write!(f, "{}", value.to_decimal().unwrap_or_else(|| value.to_string())),
+        Expr::Date(seconds) => write!(f, "{}", date::format(*seconds)),
+        Expr::Variable { name, .. } => write!(f, "{}", name),
+        Expr::UnaryOp { op, operand, .. } => sexp_form(f, op.symbol(), &[operand]),
+        Expr::BinaryOp { op, lhs, rhs, .. } => sexp_form(f, op.symbol(), &[lhs, rhs]),
+        Expr::Call { name, args, .. } => sexp_form(f, name, &args.iter().collect::<Vec<_>>()),
+        Expr::List(elements) => sexp_form(f, "list", &elements.iter().collect::<Vec<_>>()),
+        Expr::Index { list, index, .. } => sexp_form(f, "index", &[list, index]),
+        Expr::Conditional { cond, then, otherwise } => sexp_form(f, "if", &[cond, then, otherwise])
+    }
+}
+
+impl fmt::Display for Sexp<'_> {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Statement::Expr(expr) => sexp(f, expr),
+            Statement::Assign(name, _, expr) => sexp_form(f, &format!("= {}", name), &[expr]),
+            Statement::Define(name, params, body) => {
+                let signature = std::iter::once(name.as_str()).chain(params.iter().map(String::as_str)).collect::<Vec<_>>();
+                sexp_form(f, &format!("define ({})", signature.join(" ")), &[body])
+            }
+            Statement::Symbolic(Symbolic::Simplify, expr) => sexp_form(f, "simplify", &[expr]),
+            Statement::Symbolic(Symbolic::Expand, expr) => sexp_form(f, "expand", &[expr]),
+            Statement::Symbolic(Symbolic::Derivative(variable), expr) => sexp_form(f, &format!("d/d{}", variable), &[expr])
+        }
+    }
+}
+
+/*
+A statement as a Graphviz graph, one node for each of the tree and an edge
+from each to its operands in order, for `dot -Tsvg` to draw. A node is
+labelled with its operator, name or value; a list, an index and a
+conditional are labelled as such, and the statement other than an
+expression is the root, as `x =`, `f(x) =` or `d/dx`.
+*/
+pub struct Dot<'a>(pub &'a Statement);
+
+// Writes the node for `label` with an edge to each of `children`, and
+// those below, numbering them from `next`. The number of the node.
+fn dot_node(f : &mut fmt::Formatter<'_>, label : &str, children : &[&Expr], next : &mut usize) -> Result<usize, fmt::Error> {
+    let id = *next;
+    *next += 1;
+    writeln!(f, "    n{} [label=\"{}\"];", id, label.replace('\\', "\\\\").replace('"', "\\\""))?;
+    for child in children {
+        let child = dot_expr(f, child, next)?;
+        writeln!(f, "    n{} -> n{};", id, child)?;
+    }
+    Ok(id)
+}
+
+fn dot_expr(f : &mut fmt::Formatter<'_>, expr : &Expr, next : &mut usize) -> Result<usize, fmt::Error> {
+    match expr {
+        Expr::Number(value) => dot_node(f, &value.to_decimal().unwrap_or_else(|| value.to_string()), &[], next),
+        Expr::Date(seconds) => dot_node(f, &date::format(*seconds), &[], next),
+        Expr::Variable { name, .. } => dot_node(f, name, &[], next),
+        Expr::UnaryOp { op, operand, .. } => dot_node(f, op.symbol(), &[operand], next),
+        Expr::BinaryOp { op, lhs, rhs, .. } => dot_node(f, op.symbol(), &[lhs, rhs], next),
+        Expr::Call { name, args, .. } => dot_node(f, &format!("{}()", name), &args.iter().collect::<Vec<_>>(), next),
+        Expr::List(elements) => dot_node(f, "list", &elements.iter().collect::<Vec<_>>(), next),
+        Expr::Index { list, index, .. } => dot_node(f, "index", &[list, index], next),
+        Expr::Conditional { cond, then, otherwise } => dot_node(f, "?:", &[cond, then, otherwise], next)
+    }
+}
+
+impl fmt::Display for Dot<'_> {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "digraph ast {{")?;
+        writeln!(f, "    ordering=out;")?;
+        let mut next = 0;
+        match self.0 {
+            Statement::Expr(expr) => dot_expr(f, expr, &mut next)?,
+            Statement::Assign(name, _, expr) => dot_node(f, &format!("{} =", name), &[expr], &mut next)?,
+            Statement::Define(name, params, body) => dot_node(f, &format!("{}({}) =", name, params.join(", ")), &[body], &mut next)?,
+            Statement::Symbolic(Symbolic::Simplify, expr) => dot_node(f, "simplify", &[expr], &mut next)?,
+            Statement::Symbolic(Symbolic::Expand, expr) => dot_node(f, "expand", &[expr], &mut next)?,
+            Statement::Symbolic(Symbolic::Derivative(variable), expr) => dot_node(f, &format!("d/d{}", variable), &[expr], &mut next)?
+        };
+        write!(f, "}}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Expr;
+    use super::Latex;
+    use super::MathMl;
+    use crate::Dialect;
+    use crate::parser;
+
+    fn parsed(input : &str) -> Expr {
+        parser::parse(crate::tokenize(input, Dialect::Standard)).expect("an expression")
+    }
+
+    fn mathml(input : &str) -> String {
+        MathMl(&parsed(input)).to_string()
+    }
+
+    fn latex(input : &str) -> String {
+        Latex(&parsed(input)).to_string()
+    }
+
+    fn pretty(input : &str) -> String {
+        parsed(input).to_string()
+    }
+
+    #[test]
+    fn latex_layout() {
+        assert_eq!(latex("(1+2)/3^2"), "\\frac{1+2}{3^{2}}");
+        assert_eq!(latex("2 * (x - 1)"), "2 \\cdot \\left(x-1\\right)");
+        assert_eq!(latex("sqrt(pi) + abs(x_1)"), "\\sqrt{\\pi}+\\left|\\mathrm{x\\_1}\\right|");
+        assert_eq!(latex("sin(rate) * v[0]"), "\\sin\\left(\\mathrm{rate}\\right) \\cdot v_{0}");
+        assert_eq!(latex("7 // 2 + 1/3"), "\\left\\lfloor\\frac{7}{2}\\right\\rfloor+\\frac{1}{3}");
+        assert_eq!(latex("x > 0 ? x : -x"), "\\begin{cases} x & \\text{if } x>0 \\\\ -x & \\text{otherwise} \\end{cases}");
+    }
+
+    #[test]
+    fn pretty_brackets() {
+        assert_eq!(pretty("((1 + 2)) * (3)"), "(1 + 2) * 3");
+        assert_eq!(pretty("1 - (2 - 3)"), "1 - (2 - 3)");
+        assert_eq!(pretty("(1 - 2) - 3"), "1 - 2 - 3");
+        assert_eq!(pretty("2 ^ (3 ^ 2)"), "2^3^2");
+        assert_eq!(pretty("(2 ^ 3) ^ 2"), "(2^3)^2");
+        assert_eq!(pretty("-(2^2)"), "-2^2");
+        assert_eq!(pretty("(a and b) or not (c < d)"), "a and b or not c < d");
+        assert_eq!(pretty("a and (b or c)"), "a and (b or c)");
+        assert_eq!(pretty("(a ? b : c) ? d : (e ? f : g)"), "(a ? b : c) ? d : e ? f : g");
+        assert_eq!(pretty("(x + 1)% + [1, (2)][(0)]"), "(x + 1)% + [1, 2][0]");
+    }
+
+    #[test]
+    fn mathml_layout() {
+        assert_eq!(mathml("(1 + 2) / 3^2"), "<mfrac><mrow><mn>1</mn><mo>+</mo><mn>2</mn></mrow><msup><mn>3</mn><mn>2</mn></msup></mfrac>");
+        assert_eq!(mathml("2 * (x - 1)"), "<mrow><mn>2</mn><mo>⋅</mo><mrow><mo>(</mo><mrow><mi>x</mi><mo>−</mo><mn>1</mn></mrow><mo>)</mo></mrow></mrow>");
+        assert_eq!(mathml("sqrt(pi)"), "<msqrt><mi>π</mi></msqrt>");
+        assert_eq!(mathml("a < b"), "<mrow><mi>a</mi><mo>&lt;</mo><mi>b</mi></mrow>");
+        assert_eq!(mathml("max(1, v[0])"), "<mrow><mi>max</mi><mo>\u{2061}</mo><mrow><mo>(</mo><mn>1</mn><mo>,</mo><msub><mi>v</mi><mn>0</mn></msub><mo>)</mo></mrow></mrow>");
+    }
+
+    #[test]
+    fn mathml_statements() {
+        let statement = parser::parse_statement(crate::tokenize("x = 1", Dialect::Standard)).expect("a statement");
+        assert_eq!(statement.to_mathml(), "<math xmlns=\"http://www.w3.org/1998/Math/MathML\"><mrow><mi>x</mi><mo>=</mo><mn>1</mn></mrow></math>");
+    }
+}
@@ -0,0 +1,232 @@
+use calculator::ast::Binary;
+use calculator::ast::Expr;
+use calculator::ast::Statement;
+use calculator::ast::Symbolic;
+use calculator::ast::Unary;
+use calculator::date;
+use calculator::functions;
+use calculator::lexer;
+use calculator::Dialect;
+use calculator::Number;
+use calculator::Rational;
+use calculator::Token;
+
+use crate::json::Value;
+
+/*
+The parse tree as JSON, for other tools to read or build. Each node is an
+object whose "type" says what it is:
+
+    {"type": "number", "value": "2.5"}            or "7/3", exactly
+    {"type": "date", "value": "2024-03-01"}       or "2024-03-01T12:30"
+    {"type": "variable", "name": "x"}
+    {"type": "unary", "op": "-", "operand": ...}  + - ~ % not
+    {"type": "binary", "op": "+", "lhs": ..., "rhs": ...}
+    {"type": "call", "name": "sqrt", "args": [...]}
+    {"type": "list", "items": [...]}
+    {"type": "index", "list": ..., "index": ...}
+    {"type": "conditional", "cond": ..., "then": ..., "otherwise": ...}
+
+The operators of binary nodes are written as in the standard dialect. A
+statement other than an expression is a node of its own: "assign" with a
+"name" and "value", "define" with a "name", "params" and "body", or
+"simplify", "expand" and "derivative" with an "expr", the last with the
+"variable" too. Variables, operators, calls, indexes and assignments
+carry the "offset" into the input they were parsed from, which may be left
+out when reading one back. A number may also be a JSON number.
+*/
+
+fn node(kind : &str, mut members : Vec<(&str, Value)>) -> Value {
+    members.insert(0, ("type", Value::string(kind)));
+    Value::object(members)
+}
+
+pub fn expr_to_json(expr : &Expr) -> Value {
+    let offset = |offset : &usize| ("offset", Value::Number(*offset as f64));
+    let many = |exprs : &[Expr]| Value::Array(exprs.iter().map(expr_to_json).collect());
+    match expr {
+        Expr::Number(value) => node("number", vec![("value", Value::String(value.to_decimal().unwrap_or_else(|| value.to_string())))]),
+        Expr::Date(seconds) => node("date", vec![("value", Value::String(date::format(*seconds)))]),
+        Expr::Variable { name, offset : at } => node("variable", vec![("name", Value::string(name)), offset(at)]),
+        Expr::UnaryOp { op, operand, offset : at } => {
+            node("unary", vec![("op", Value::string(op.symbol())), ("operand", expr_to_json(operand)), offset(at)])
+        }
+        Expr::BinaryOp { op, lhs, rhs, offset : at } => {
+            node("binary", vec![("op", Value::string(op.symbol())), ("lhs", expr_to_json(lhs)), ("rhs", expr_to_json(rhs)), offset(at)])
+        }
+        Expr::Call { name, args, offset : at } => node("call", vec![("name", Value::string(name)), ("args", many(args)), offset(at)]),
+        Expr::List(items) => node("list", vec![("items", many(items))]),
+        Expr::Index { list, index, offset : at } => node("index", vec![("list", expr_to_json(list)), ("index", expr_to_json(index)), offset(at)]),
+        Expr::Conditional { cond, then, otherwise } => {
+            node("conditional", vec![("cond", expr_to_json(cond)), ("then", expr_to_json(then)), ("otherwise", expr_to_json(otherwise))])
+        }
+    }
+}
+
+pub fn to_json(statement : &Statement) -> Value {
+    match statement {
+        Statement::Expr(expr) => expr_to_json(expr),
+        Statement::Assign(name, at, expr) => node("assign", vec![("name", Value::string(name)), ("value", expr_to_json(expr)), ("offset", Value::Number(*at as f64))]),
+        Statement::Define(name, params, body) => {
+            let params = Value::Array(params.iter().map(|param| Value::string(param)).collect());
+            node("define", vec![("name", Value::string(name)), ("params", params), ("body", expr_to_json(body))])
+        }
+        Statement::Symbolic(Symbolic::Simplify, expr) => node("simplify", vec![("expr", expr_to_json(expr))]),
+        Statement::Symbolic(Symbolic::Expand, expr) => node("expand", vec![("expr", expr_to_json(expr))]),
+        Statement::Symbolic(Symbolic::Derivative(variable), expr) => {
+            node("derivative", vec![("variable", Value::string(variable)), ("expr", expr_to_json(expr))])
+        }
+    }
+}
+
+fn string(value : &Value, key : &str) -> Option<String> {
+    value.get(key)?.as_str().map(str::to_string)
+}
+
+fn child(value : &Value, key : &str) -> Option<Box<Expr>> {
+    expr_from_json(value.get(key)?).map(Box::new)
+}
+
+fn children(value : &Value, key : &str) -> Option<Vec<Expr>> {
+    match value.get(key)? {
+        Value::Array(items) => items.iter().map(expr_from_json).collect(),
+        _ => None
+    }
+}
+
+fn offset(value : &Value) -> Option<usize> {
+    match value.get("offset") {
+        None => Some(0),
+        Some(Value::Number(n)) if *n >= 0.0 && n.fract() == 0.0 => Some(*n as usize),
+        Some(_) => None
+    }
+}
+
+// A number written as in the output, "2.5" or "7/3", without a sign.
+fn number(value : &Value) -> Option<Rational> {
+    let decimal = |s : &str| Some(Rational::from_decimal(s)).filter(|_| !s.is_empty() && s.chars().all(|c| c.is_ascii_digit() || c == '.') && s.matches('.').count() <= 1);
+    match value.get("value")? {
+        Value::Number(n) if *n >= 0.0 => Some(Rational::from_f64(*n)),
+        Value::String(s) => match s.split_once('/') {
+            Some((numerator, denominator)) => decimal(numerator)?.div(&decimal(denominator)?, 0),
+            None => decimal(s)
+        },
+        _ => None
+    }
+}
+
+// A date written as in the input.
+fn date(value : &Value) -> Option<i64> {
+    let mut tokens = lexer::tokens(value.get("value")?.as_str()?, Dialect::Standard);
+    match (tokens.next(), tokens.next()) {
+        (Some(Token::Date(seconds)), None) => Some(seconds),
+        _ => None
+    }
+}
+
+fn binary_op(symbol : &str) -> Option<Binary> {
+    let mut tokens = lexer::tokens(symbol, Dialect::Standard);
+    let op = Binary::from_token(&tokens.next()?)?;
+    tokens.next().is_none().then_some(op)
+}
+
+fn unary_op(symbol : &str) -> Option<Unary> {
+    [Unary::Plus, Unary::Minus, Unary::BitNot, Unary::Percent, Unary::Not].into_iter().find(|op| op.symbol() == symbol)
+}
+
+// None if `value` isn't a tree as expr_to_json writes them.
+pub fn expr_from_json(value : &Value) -> Option<Expr> {
+    let expr = match string(value, "type")?.as_str() {
+        "number" => Expr::Number(number(value)?),
+        "date" => Expr::Date(date(value)?),
+        "variable" => Expr::Variable { name : string(value, "name")?, offset : offset(value)? },
+        "unary" => Expr::UnaryOp { op : unary_op(&string(value, "op")?)?, operand : child(value, "operand")?, offset : offset(value)? },
+        "binary" => Expr::BinaryOp { op : binary_op(&string(value, "op")?)?, lhs : child(value, "lhs")?, rhs : child(value, "rhs")?, offset : offset(value)? },
+        "call" => Expr::Call { name : string(value, "name")?, args : children(value, "args")?, offset : offset(value)? },
+        "list" => Expr::List(children(value, "items")?),
+        "index" => Expr::Index { list : child(value, "list")?, index : child(value, "index")?, offset : offset(value)? },
+        "conditional" => Expr::Conditional { cond : child(value, "cond")?, then : child(value, "then")?, otherwise : child(value, "otherwise")? },
+        _ => { return None; }
+    };
+    Some(expr)
+}
+
+// As the parser, this doesn't let a constant be assigned or be a parameter.
+pub fn from_json(value : &Value) -> Option<Statement> {
+    let expr = |key : &str| value.get(key).and_then(expr_from_json);
+    let name = |key : &str| string(value, key).filter(|name| functions::constant(name).is_none());
+    let statement = match string(value, "type")?.as_str() {
+        "assign" => Statement::Assign(name("name")?, offset(value)?, expr("value")?),
+        "define" => {
+            let params = match value.get("params")? {
+                Value::Array(params) => params.iter().map(|param| param.as_str().filter(|param| functions::constant(param).is_none()).map(str::to_string)).collect::<Option<Vec<_>>>()?,
+                _ => { return None; }
+            };
+            Statement::Define(string(value, "name")?, params, expr("body")?)
+        }
+        "simplify" => Statement::Symbolic(Symbolic::Simplify, expr("expr")?),
+        "expand" => Statement::Symbolic(Symbolic::Expand, expr("expr")?),
+        "derivative" => Statement::Symbolic(Symbolic::Derivative(string(value, "variable")?), expr("expr")?),
+        _ => Statement::Expr(expr_from_json(value)?)
+    };
+    Some(statement)
+}
+
+#[cfg(test)]
+mod tests {
+    use calculator::ast::Sexp;
+    use calculator::parser;
+    use calculator::Dialect;
+
+    use crate::json;
+
+    use super::from_json;
+    use super::to_json;
+
+    fn written(input : &str) -> String {
+        to_json(&parser::parse_statement(calculator::tokenize(input, Dialect::Standard)).expect("a statement")).to_string()
+    }
+
+    fn read(text : &str) -> Option<String> {
+        from_json(&json::parse(text)?).map(|statement| Sexp(&statement).to_string())
+    }
+
+    #[test]
+    fn written_out() {
+        assert_eq!(written("-x"), r#"{"type":"unary","op":"-","operand":{"type":"variable","name":"x","offset":1},"offset":0}"#);
+        assert_eq!(written("y = 2"), r#"{"type":"assign","name":"y","value":{"type":"number","value":"2"},"offset":0}"#);
+        assert_eq!(written("[2.5, 1/3][0]"), concat!(
+            r#"{"type":"index","list":{"type":"list","items":[{"type":"number","value":"2.5"},"#,
+            r#"{"type":"binary","op":"/","lhs":{"type":"number","value":"1"},"rhs":{"type":"number","value":"3"},"offset":7}]},"#,
+            r#""index":{"type":"number","value":"0"},"offset":10}"#
+        ));
+        assert_eq!(written("f(a, b) = a ? b : 2024-03-01"), concat!(
+            r#"{"type":"define","name":"f","params":["a","b"],"body":{"type":"conditional","#,
+            r#""cond":{"type":"variable","name":"a","offset":10},"then":{"type":"variable","name":"b","offset":14},"#,
+            r#""otherwise":{"type":"date","value":"2024-03-01"}}}"#
+        ));
+    }
+
+    #[test]
+    fn read_back() {
+        for input in ["1 + 2 * x", "x = [1, 2][0]", "f(a) = sqrt(a) >= 2 ? a : -a", "2024-03-01T12:30 + 1 day", "simplify x + x", "d/dx x^2", "0.1 ~= 1/3"] {
+            let statement = parser::parse_statement(calculator::tokenize(input, Dialect::Standard)).expect("a statement");
+            assert_eq!(from_json(&to_json(&statement)), Some(statement), "{}", input);
+        }
+        // offsets may be left out, and a number be one of JSON
+        let power = r#"{"type":"binary","op":"^","lhs":{"type":"number","value":2},"rhs":{"type":"number","value":"7/3"}}"#;
+        assert_eq!(read(power).as_deref(), Some("(^ 2 7/3)"));
+    }
+
+    #[test]
+    fn malformed() {
+        assert_eq!(read(r#"{"type":"number","value":"-1"}"#), None);
+        assert_eq!(read(r#"{"type":"number","value":"1/0"}"#), None);
+        assert_eq!(read(r#"{"type":"binary","op":"+ +","lhs":{"type":"number","value":1},"rhs":{"type":"number","value":1}}"#), None);
+        assert_eq!(read(r#"{"type":"variable","name":"x","offset":-1}"#), None);
+        assert_eq!(read(r#"{"type":"assign","name":"pi","value":{"type":"number","value":3}}"#), None);
+        assert_eq!(read(r#"{"type":"define","name":"f","params":["e"],"body":{"type":"number","value":1}}"#), None);
+        assert_eq!(read(r#"{"type":"call","name":"f","args":{}}"#), None);
+        assert_eq!(read(r#"{"type":"matrix"}"#), None);
+    }
+}
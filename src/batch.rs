@@ -0,0 +1,435 @@
+use std::io;
+use std::io::BufRead;
+use std::process;
+
+use calculator::ast::Dot;
+use calculator::ast::Sexp;
+use calculator::ast::Statement;
+use calculator::eval;
+use calculator::format;
+use calculator::parser;
+use calculator::units;
+use calculator::lexer::Lexer;
+use calculator::Context;
+use calculator::Decimal;
+use calculator::Dialect;
+use calculator::Number;
+use calculator::Outcome;
+use calculator::Rational;
+use calculator::Token;
+use calculator::Value;
+
+use crate::ast_json;
+use crate::bc;
+use crate::cli::Emit;
+use crate::cli::Numbers;
+use crate::cli::Options;
+use crate::crosscheck;
+use crate::json;
+use crate::lint;
+use crate::mapped;
+use crate::messages;
+use crate::messages::Msg;
+use crate::report;
+use crate::rpn;
+
+/*
+Non-interactive use, for scripts: the expressions given as arguments, or
+else every line of stdin, are evaluated in order in one session, so later
+ones can use variables and results of earlier ones. Results go to stdout,
+errors to stderr, and a failure anywhere makes the exit code 1.
+
+A script file given with --file holds one statement per line, or several
+separated by `;` outside brackets, as the European dialect writes
+max(1; 2). Only expressions print their value, not assignments, and the
+script stops at the first error.
+
+Comments are found on the tokens of the line, so they can be anywhere on
+it. A `#` starting a line always starts a comment; after the start, one
+that names a result, as #3 does, is that result. `//` starts a comment at
+the start of a statement, or with space on both sides, as in
+`x + 1  // one more`. Elsewhere it is floor division, so a script writes
+that spaced on one side at most, as 7//2 or 7 //2.
+*/
+
+struct Batch<'a, N> {
+    options : &'a Options,
+    ctx : Context<N>,
+    rpn : Option<rpn::Rpn<N>>,
+    bc : Option<bc::Bc>,
+    // whether assignments print the value assigned
+    echo_assignments : bool,
+    failed : bool
+}
+
+impl<'a, N: Number> Batch<'a, N> {
+    fn new(options : &'a Options, echo_assignments : bool) -> Batch<'a, N> {
+        let mut ctx = Context { angle : options.angle, ..Context::default() };
+        let bc = (options.dialect == Dialect::Bc).then(|| bc::Bc::new(&mut ctx));
+        Batch {
+            options,
+            ctx,
+            rpn : if options.rpn { Some(rpn::Rpn::new()) } else { None },
+            // bc prints nothing for an assignment
+            echo_assignments : echo_assignments && bc.is_none(),
+            bc,
+            failed : false
+        }
+    }
+
+    // Each statement of `line` in turn, where bc ends one at `;` as well as
+    // at the end of the line; other dialects take the line as one.
+    fn run_line(&mut self, line : &str, position : impl Fn(usize, &str) -> String) -> bool {
+        if self.bc.is_none() {
+            return self.run(line, position);
+        }
+        for (start, statement) in statements(line, Dialect::Bc) {
+            let before = line[..start].chars().count();
+            if !self.run(statement, |column, e| position(before + column, e)) {
+                return false;
+            }
+        }
+        true
+    }
+
+    // `position` tells where the input came from in error messages, given
+    // the column of the error. False if the input failed.
+    fn run(&mut self, input : &str, position : impl Fn(usize, &str) -> String) -> bool {
+        if input.trim().is_empty() {
+            return true;
+        }
+
+        let decimal;
+        let input = match &self.bc {
+            Some(_) if input.trim() == "quit" => { process::exit(if self.failed { 1 } else { 0 }); }
+            Some(bc) => {
+                decimal = bc.literals(input);
+                decimal.as_str()
+            }
+            None => input
+        };
+
+        if let Some(rpn) = &mut self.rpn {
+            match rpn.execute(input, &mut self.ctx) {
+                Ok(()) => println!("{}", rpn),
+                Err(e) => {
+                    eprintln!("{}", position(1, &e.to_string()));
+                    self.failed = true;
+                    return false;
+                }
+            }
+            return true;
+        }
+
+        let dialect = self.options.dialect;
+        if let Some(emit) = self.options.emit {
+            match parser::parse_statement(calculator::tokenize(input, dialect)) {
+                Ok(statement) => match emit {
+                    Emit::Ast => println!("{}", Sexp(&statement)),
+                    Emit::AstDot => println!("{}", Dot(&statement)),
+                    Emit::AstJson => println!("{}", ast_json::to_json(&statement)),
+                    Emit::MathMl => println!("{}", statement.to_mathml())
+                },
+                Err(e) => {
+                    eprintln!("{}", position(e.column(input), &messages::error(&e).to_string()));
+                    self.failed = true;
+                    return false;
+                }
+            }
+            return true;
+        }
+
+        // the statement is run, so later ones are linted against what it
+        // defines, but only what is pointed out is printed
+        if self.options.lint {
+            let warnings = lint::lint(input, dialect, &self.ctx);
+            for (column, warning) in &warnings {
+                println!("{}", position(*column, warning));
+            }
+            self.failed |= !warnings.is_empty();
+            return match calculator::execute(input, dialect, &mut self.ctx) {
+                Ok(Outcome::Value(value) | Outcome::Assigned(_, value)) => {
+                    self.ctx.results.push(value);
+                    true
+                }
+                Ok(_) => true,
+                Err(e) => {
+                    eprintln!("{}", position(e.column(input), &messages::error(&e).to_string()));
+                    self.failed = true;
+                    false
+                }
+            };
+        }
+
+        // a tree has no `->` or unit conversion of its own, nor columns
+        let tree = self.options.from_ast_json;
+        let (input, notation) = match format::split_conversion(input) {
+            _ if tree => (input, Some(self.options.numbers.notation())),
+            Ok((input, conversion)) => (input, conversion),
+            Err(name) => {
+                let column = input.rfind("->").map_or(0, |i| input[..i].chars().count()) + 1;
+                eprintln!("{}", position(column, &Msg::UnknownDisplay(name).to_string()));
+                self.failed = true;
+                return false;
+            }
+        };
+        let line = input;
+        let (input, unit) = if tree { (input, None) } else { units::split_conversion(input, dialect) };
+        if self.options.crosscheck && !tree {
+            self.crosscheck(input, &position);
+        }
+        let ctx = &mut self.ctx;
+        let result = if tree {
+            match json::parse(input).as_ref().and_then(ast_json::from_json) {
+                Some(statement) => eval::execute(statement, ctx),
+                None => {
+                    eprintln!("{}", position(1, &Msg::InvalidAst.to_string()));
+                    self.failed = true;
+                    return false;
+                }
+            }
+        }
+        else if self.options.profile {
+            let (result, report) = calculator::profile::evaluate(input, dialect, parser::parse_statement, |statement| eval::execute(statement, ctx));
+            eprint!("{}", report::Summary(&report));
+            result
+        }
+        else {
+            calculator::execute(input, dialect, ctx)
+        };
+
+        let shown = match &result {
+            Ok(Outcome::Value(value) | Outcome::Assigned(_, value)) => match unit {
+                Some((offset, unit)) => match calculator::convert(value, unit, offset, dialect, &self.ctx) {
+                    Ok(number) => format!("{} {}", format::format_value(&number, notation.unwrap_or(self.options.numbers.notation()), self.options.style), unit),
+                    Err(e) => {
+                        eprintln!("{}", position(e.column(line), &messages::error(&e).to_string()));
+                        self.failed = true;
+                        return false;
+                    }
+                },
+                None => {
+                    let notation = notation.unwrap_or_else(|| format::keeping_clock(input, dialect, value, self.options.numbers.notation()));
+                    match (&self.bc, value) {
+                        (Some(bc), Value::Scalar(quantity)) if quantity.dimension.is_none() => {
+                            bc.show(&quantity.value).unwrap_or_else(|| format::format_value(value, notation, self.options.style))
+                        }
+                        _ => format::format_value(value, notation, self.options.style)
+                    }
+                }
+            },
+            _ => String::new()
+        };
+
+        match result {
+            Ok(Outcome::Value(value)) => {
+                println!("{}", shown);
+                self.ctx.results.push(value);
+            }
+            Ok(Outcome::Assigned(name, value)) => {
+                if let Some(bc) = &mut self.bc {
+                    if let Err(e) = bc.assigned(&name, &value, &mut self.ctx) {
+                        eprintln!("{}", position(1, &e));
+                        self.failed = true;
+                        return false;
+                    }
+                }
+                if self.echo_assignments {
+                    println!("{}", shown);
+                }
+                self.ctx.results.push(value);
+            }
+            Ok(Outcome::Defined(_)) => {}
+            Ok(Outcome::Expression(expr)) => println!("{}", expr),
+            Err(e) => {
+                let column = if tree { 1 } else { e.column(input) };
+                eprintln!("{}", position(column, &messages::error(&e).to_string()));
+                self.failed = true;
+                return false;
+            }
+        }
+
+        true
+    }
+
+    // Reports where the ways of evaluating the expression of `input`
+    // disagree, which fails the run but doesn't stop it.
+    fn crosscheck(&mut self, input : &str, position : impl Fn(usize, &str) -> String) {
+        let expr = match parser::parse_statement(calculator::tokenize(input, self.options.dialect)) {
+            Ok(Statement::Expr(expr) | Statement::Assign(_, _, expr)) => expr,
+            _ => { return; }
+        };
+        if let Some(values) = crosscheck::check(&expr, &self.ctx) {
+            eprintln!("{}", position(1, &Msg::Disagree(&values).to_string()));
+            self.failed = true;
+        }
+    }
+}
+
+// The statements of a script line before its comment, with where each
+// starts.
+pub fn statements(line : &str, dialect : Dialect) -> Vec<(usize, &str)> {
+    let spaced = |text : &str| text.is_empty() || text.ends_with(char::is_whitespace);
+    let mut statements = Vec::new();
+    let mut start = 0;
+    let mut depth : usize = 0;
+    let mut end = line.len();
+
+    for (i, token) in Lexer::new(line, dialect).enumerate() {
+        let comment = match &token.token {
+            Token::Invalid('#') => true,
+            Token::Ident(name) => i == 0 && name.starts_with('#'),
+            Token::FloorDiv => {
+                let after = &line[token.end..];
+                line[start..token.start].trim().is_empty() || (spaced(&line[..token.start]) && (after.is_empty() || after.starts_with(char::is_whitespace)))
+            }
+            _ => false
+        };
+        if comment {
+            end = token.start;
+            break;
+        }
+
+        match token.token {
+            Token::LeftBracket | Token::LeftSquare => depth += 1,
+            Token::RightBracket | Token::RightSquare => depth = depth.saturating_sub(1),
+            _ if depth == 0 && &line[token.start..token.end] == ";" => {
+                statements.push((start, &line[start..token.start]));
+                start = token.end;
+            }
+            _ => {}
+        }
+    }
+
+    statements.push((start, &line[start..end]));
+    statements
+}
+
+// Returns the process exit code.
+pub fn run(options : &Options) -> i32 {
+    match options.numbers {
+        Numbers::Float => run_in(Batch::<f64>::new(options, true)),
+        Numbers::Decimal => run_in(Batch::<Decimal>::new(options, true)),
+        Numbers::Rational => run_in(Batch::<Rational>::new(options, true))
+    }
+}
+
+fn run_in<N: Number>(mut batch : Batch<'_, N>) -> i32 {
+    let options = batch.options;
+
+    if !options.expressions.is_empty() {
+        for (n, expression) in options.expressions.iter().enumerate() {
+            batch.run_line(expression, |column, e| Msg::AtArgument(n + 1, column, e).to_string());
+        }
+    }
+    else {
+        let mut stdin = io::stdin().lock();
+        let mut line = String::new();
+        let mut n = 0;
+
+        loop {
+            line.clear();
+            if stdin.read_line(&mut line).expect("Something wrong") == 0 {
+                break;
+            }
+            n += 1;
+            batch.run_line(&line, |column, e| Msg::AtLine(n, column, e).to_string());
+        }
+    }
+
+    if batch.failed { 1 } else { 0 }
+}
+
+// The file is mapped rather than read (see mapped.rs), and taken a line at
+// a time, so its size doesn't matter.
+pub fn run_file(options : &Options, path : &str) -> i32 {
+    let script = match mapped::Script::open(path) {
+        Ok(script) => script,
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            return 1;
+        }
+    };
+
+    match options.numbers {
+        Numbers::Float => run_script(&mut Batch::<f64>::new(options, false), path, script.bytes()),
+        Numbers::Decimal => run_script(&mut Batch::<Decimal>::new(options, false), path, script.bytes()),
+        Numbers::Rational => run_script(&mut Batch::<Rational>::new(options, false), path, script.bytes())
+    }
+}
+
+fn run_script<N: Number>(batch : &mut Batch<'_, N>, path : &str, script : &[u8]) -> i32 {
+    for (n, line) in mapped::lines(script).enumerate() {
+        let line = match line {
+            Ok(line) => line,
+            Err(column) => {
+                eprintln!("{}", Msg::AtFile(path, n + 1, column, &Msg::NotUtf8.to_string()));
+                return 1;
+            }
+        };
+        for (start, statement) in statements(line, batch.options.dialect) {
+            // columns count from the start of the line, not the statement
+            let before = line[..start].chars().count();
+            let ok = batch.run(statement, |column, e| Msg::AtFile(path, n + 1, before + column, e).to_string());
+            if !ok {
+                return 1;
+            }
+        }
+    }
+
+    if batch.failed { 1 } else { 0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use calculator::Dialect;
+    use calculator::Value;
+
+    use super::run_script;
+    use super::statements;
+    use super::Batch;
+    use crate::cli::Options;
+
+    fn texts(line : &str, dialect : Dialect) -> Vec<&str> {
+        statements(line, dialect).into_iter().map(|(_, statement)| statement.trim()).collect()
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(texts("z = x + y  // comment", Dialect::Standard), ["z = x + y"]);
+        assert_eq!(texts("// all of it", Dialect::Standard), [""]);
+        assert_eq!(texts("x = 1; // the rest", Dialect::Standard), ["x = 1", ""]);
+        assert_eq!(texts("#1 + 1 is a comment", Dialect::Standard), [""]);
+        assert_eq!(texts("x = 2 # a note", Dialect::Standard), ["x = 2"]);
+        // only at the start of a line is #N a comment
+        assert_eq!(texts("#1 * 2", Dialect::Standard), [""]);
+        assert_eq!(texts("2 * #1", Dialect::Standard), ["2 * #1"]);
+        // floor division, not spaced on both sides
+        assert_eq!(texts("7//2; 7 //2", Dialect::Standard), ["7//2", "7 //2"]);
+    }
+
+    #[test]
+    fn statements_split_outside_brackets() {
+        assert_eq!(texts("x = 1; y = 2", Dialect::Standard), ["x = 1", "y = 2"]);
+        assert_eq!(texts("max(1; 2); 3", Dialect::European), ["max(1; 2)", "3"]);
+        assert_eq!(texts("[1; 2]", Dialect::European), ["[1; 2]"]);
+        assert_eq!(statements("a; b", Dialect::Standard), [(0, "a"), (2, " b")]);
+    }
+
+    #[test]
+    fn scripts() {
+        let options = Options::default();
+        let mut batch = Batch::<f64>::new(&options, false);
+        let script = "# totals\nx = 3  // three\ny = 4; z = x + y\n#1 is not used\nz * 2\n7//2";
+        assert_eq!(run_script(&mut batch, "test.calc", script.as_bytes()), 0);
+        assert_eq!(batch.ctx.results, [Value::number(3.0), Value::number(4.0), Value::number(7.0), Value::number(14.0), Value::number(3.0)]);
+
+        let options = Options { dialect : Dialect::European, ..Options::default() };
+        let mut batch = Batch::<f64>::new(&options, false);
+        assert_eq!(run_script(&mut batch, "test.calc", b"max(1; 2); min(3; 4)"), 0);
+        assert_eq!(batch.ctx.results, [Value::number(2.0), Value::number(3.0)]);
+
+        assert_eq!(run_script(&mut batch, "test.calc", b"1 +\n2"), 1);
+        assert_eq!(run_script(&mut batch, "test.calc", b"1\n2 \xff"), 1);
+    }
+}
@@ -0,0 +1,211 @@
+use std::cmp::Ordering;
+
+use calculator::Context;
+use calculator::Number;
+use calculator::Rational;
+use calculator::Value;
+
+use crate::messages::Msg;
+
+/*
+What --dialect bc needs besides its syntax, which the lexer reads, and its
+arithmetic, which the evaluator does when the context truncates (see
+eval::apply_in). The scale is the context's precision. Statements end at a
+newline or `;`, only expressions print, and `quit` ends the input.
+
+`scale`, `ibase` and `obase` are variables as in bc, whose assignment
+changes how the session computes, reads numbers and prints them. Numbers
+are read in ibase, with A to F as the digits from 10 to 15 as bc has them,
+whatever the base; a number is rewritten in decimal before the statement is
+evaluated, so the column of an error after one counts in what it became.
+Results are printed in obase, with no 0 before the point, as in .5.
+The functions of bc's math library, s, c, a, l and e, are there without
+-l, but like every function here they are computed in f64, so only their
+first 16 digits or so are to be trusted however large the scale.
+
+Unlike bc the numbers printed don't keep trailing zeros to the scale, so
+scale=4; 1/4 is .25 rather than .2500, and long ones aren't broken over
+lines.
+*/
+
+// ibase and obase from 2 up to this, as their digits are 0-9 and A-F
+const MAX_BASE : u32 = 16;
+const MAX_SCALE : u32 = 10_000;
+
+pub struct Bc {
+    ibase : u32,
+    obase : u32
+}
+
+impl Bc {
+    // A session working as bc does, at scale 0.
+    pub fn new<N: Number>(ctx : &mut Context<N>) -> Bc {
+        ctx.truncate = true;
+        ctx.precision = 0;
+        let bc = Bc { ibase : 10, obase : 10 };
+        for name in ["scale", "ibase", "obase"] {
+            let value = bc.setting(name, ctx);
+            ctx.variables.insert(name.to_string(), Value::number(N::from_integer(value as i128)));
+        }
+        bc
+    }
+
+    fn setting<N>(&self, name : &str, ctx : &Context<N>) -> u32 {
+        match name {
+            "ibase" => self.ibase,
+            "obase" => self.obase,
+            _ => ctx.precision as u32
+        }
+    }
+
+    // Takes up an assignment of `value` to `name`, if it is a setting. A
+    // value that the setting can't take is put back to what it was.
+    pub fn assigned<N: Number>(&mut self, name : &str, value : &Value<N>, ctx : &mut Context<N>) -> Result<(), String> {
+        let (min, max) = match name {
+            "scale" => (0, MAX_SCALE),
+            "ibase" | "obase" => (2, MAX_BASE),
+            _ => { return Ok(()); }
+        };
+        let number = match value {
+            Value::Scalar(quantity) if quantity.dimension.is_none() => quantity.value.to_integer(),
+            _ => None
+        };
+        let setting = match number {
+            Some(n) if n >= min as i128 && n <= max as i128 => n as u32,
+            _ => {
+                let old = self.setting(name, ctx);
+                ctx.variables.insert(name.to_string(), Value::number(N::from_integer(old as i128)));
+                return Err(Msg::BadSetting(name, min, max).to_string());
+            }
+        };
+        match name {
+            "ibase" => self.ibase = setting,
+            "obase" => self.obase = setting,
+            _ => ctx.precision = setting as usize
+        }
+        Ok(())
+    }
+
+    // `statement` with its numbers written in decimal.
+    pub fn literals(&self, statement : &str) -> String {
+        let mut out = String::new();
+        let mut chars = statement.char_indices().peekable();
+        let mut after_name = false;
+        while let Some((start, c)) = chars.next() {
+            if after_name || !is_digit(c) {
+                after_name = c.is_alphanumeric() || c == '_';
+                out.push(c);
+                continue;
+            }
+            let mut end = start + c.len_utf8();
+            while let Some((i, c)) = chars.next_if(|(_, c)| is_digit(*c)) {
+                end = i + c.len_utf8();
+            }
+            let literal = &statement[start..end];
+            if self.ibase == 10 && !literal.contains(|c : char| c.is_ascii_uppercase()) {
+                out.push_str(literal);
+            }
+            else {
+                out.push_str(&read(literal, self.ibase));
+            }
+        }
+        out
+    }
+
+    // `value` as bc prints it, in obase.
+    pub fn show<N: Number>(&self, value : &N) -> Option<String> {
+        let value = value.to_rational();
+        let places = value.decimal_places()?;
+        let negative = value.compare(&Rational::integer(0)) == Some(Ordering::Less);
+        let value = if negative { value.neg() } else { value };
+        let base = Rational::integer(self.obase as i128);
+
+        let whole = value.truncated(0);
+        let mut digits = Vec::new();
+        let mut rest = whole.clone();
+        while rest != Rational::integer(0) {
+            digits.push(digit(&rest.modulo(&base)?));
+            rest = rest.floor_div(&base)?;
+        }
+        digits.reverse();
+
+        // as many digits in obase as are needed for `places` in decimal
+        let mut fraction = value.sub(&whole);
+        if places > 0 {
+            digits.push('.');
+            let wanted = (places as f64 * 10f64.ln() / (self.obase as f64).ln()).ceil() as usize;
+            for _ in 0..wanted {
+                fraction = fraction.mul(&base);
+                let next = fraction.truncated(0);
+                digits.push(digit(&next));
+                fraction = fraction.sub(&next);
+            }
+        }
+
+        if digits.is_empty() {
+            digits.push('0');
+        }
+        Some(format!("{}{}", if negative { "-" } else { "" }, digits.into_iter().collect::<String>()))
+    }
+}
+
+fn is_digit(c : char) -> bool {
+    c.is_ascii_digit() || ('A'..='F').contains(&c) || c == '.'
+}
+
+fn digit(value : &Rational) -> char {
+    let n = value.to_integer().unwrap_or(0) as u32;
+    char::from_digit(n, MAX_BASE).unwrap_or('0').to_ascii_uppercase()
+}
+
+// A number written in `base`, in decimal with as many places as it has in
+// `base`, cut off as bc does.
+fn read(literal : &str, base : u32) -> String {
+    let (whole, fraction) = literal.split_once('.').unwrap_or((literal, ""));
+    let base_number = Rational::integer(base as i128);
+    let value = |digits : &str| digits.chars().fold(Rational::integer(0), |n, c| {
+        n.mul(&base_number).add(&Rational::integer(c.to_digit(MAX_BASE).unwrap_or(0) as i128))
+    });
+    let scale = base_number.pow(&Rational::integer(fraction.len() as i128), 0).unwrap_or(Rational::integer(1));
+    let places = fraction.len();
+    let fraction = value(fraction).div(&scale, 0).unwrap_or(Rational::integer(0));
+    let number = value(whole).add(&fraction.truncated(places));
+    number.to_decimal().unwrap_or_else(|| literal.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use calculator::Context;
+    use calculator::Decimal;
+    use calculator::Number;
+    use calculator::Value;
+
+    use super::Bc;
+
+    #[test]
+    fn bases() {
+        let mut ctx = Context::<Decimal>::default();
+        let mut bc = Bc::new(&mut ctx);
+        assert_eq!(bc.literals("x1 + 10"), "x1 + 10");
+        bc.ibase = 16;
+        assert_eq!(bc.literals("FF + 1.8 - x1"), "255 + 1.5 - x1");
+        bc.ibase = 2;
+        assert_eq!(bc.literals("101.1"), "5.5");
+        bc.obase = 16;
+        assert_eq!(bc.show(&Decimal::from_integer(255)).as_deref(), Some("FF"));
+        bc.obase = 10;
+        assert_eq!(bc.show(&Decimal::from_f64(-0.25)).as_deref(), Some("-.25"));
+        assert_eq!(bc.show(&Decimal::from_integer(0)).as_deref(), Some("0"));
+    }
+
+    #[test]
+    fn settings() {
+        let mut ctx = Context::<Decimal>::default();
+        let mut bc = Bc::new(&mut ctx);
+        assert!(bc.assigned("scale", &Value::number(Decimal::from_integer(2)), &mut ctx).is_ok());
+        assert_eq!(ctx.precision, 2);
+        assert!(bc.assigned("obase", &Value::number(Decimal::from_integer(1)), &mut ctx).is_err());
+        assert_eq!(ctx.variables.get("obase"), Some(&Value::number(Decimal::from_integer(10))));
+        assert!(bc.assigned("x", &Value::number(Decimal::from_integer(-1)), &mut ctx).is_ok());
+    }
+}
@@ -0,0 +1,183 @@
+/*
+Derivatives, integrals and roots of functions of one variable, worked out
+numerically in f64. The function is anything that gives a number for a
+number, in practice an expression evaluated again for each point, and
+whatever error it fails with is passed on. None means the method didn't
+arrive at a finite answer.
+*/
+
+// How often an integral may evaluate the function.
+pub const MAX_EVALUATIONS : usize = 200_000;
+
+const TOLERANCE : f64 = 1e-12;
+
+// A power of two about `fraction` of x, or of 1 at 0, so that x plus or
+// minus it is exact.
+fn step(x : f64, fraction : f64) -> f64 {
+    let scale = if x == 0.0 { 1.0 } else { x.abs() };
+    (scale * fraction).log2().floor().exp2()
+}
+
+// Central differences at three step sizes, combined by Richardson
+// extrapolation, which leaves an error of the order of the step to the
+// sixth. Where f itself has no value there is none, as the differences
+// around a pole of 1/x would cancel to some large number.
+pub fn derivative<E>(f : &mut impl FnMut(f64) -> Result<f64, E>, x : f64) -> Result<Option<f64>, E> {
+    if !f(x)?.is_finite() {
+        return Ok(None);
+    }
+    let h = step(x, 1e-2);
+    let mut central = |h : f64| -> Result<f64, E> { Ok((f(x + h)? - f(x - h)?) / (2.0 * h)) };
+    let (d1, d2, d3) = (central(h)?, central(h / 2.0)?, central(h / 4.0)?);
+    let (r1, r2) = ((4.0 * d2 - d1) / 3.0, (4.0 * d3 - d2) / 3.0);
+    let value = (16.0 * r2 - r1) / 15.0;
+    Ok(Some(value).filter(|value| value.is_finite()))
+}
+
+struct Simpson<'a, F> {
+    f : &'a mut F,
+    evaluations : usize
+}
+
+impl<F, E> Simpson<'_, F> where F : FnMut(f64) -> Result<f64, E> {
+    fn at(&mut self, x : f64) -> Result<Option<f64>, E> {
+        self.evaluations += 1;
+        if self.evaluations > MAX_EVALUATIONS {
+            return Ok(None);
+        }
+        (self.f)(x).map(Some)
+    }
+
+    // The interval [a, b] with f at both ends and its middle m, and the
+    // Simpson estimate over it: halved until the halves agree with it.
+    fn integrate(&mut self, (a, fa) : (f64, f64), (m, fm) : (f64, f64), (b, fb) : (f64, f64), whole : f64, tolerance : f64) -> Result<Option<f64>, E> {
+        let (lm, rm) = ((a + m) / 2.0, (m + b) / 2.0);
+        let (flm, frm) = match (self.at(lm)?, self.at(rm)?) {
+            (Some(flm), Some(frm)) => (flm, frm),
+            _ => { return Ok(None); }
+        };
+        let left = (m - a) / 6.0 * (fa + 4.0 * flm + fm);
+        let right = (b - m) / 6.0 * (fm + 4.0 * frm + fb);
+        let delta = left + right - whole;
+        // once the halves are as narrow as f64 allows, this is as good as it gets
+        if delta.abs() <= 15.0 * tolerance || lm <= a || rm >= b {
+            return Ok(Some(left + right + delta / 15.0));
+        }
+
+        let left = self.integrate((a, fa), (lm, flm), (m, fm), left, tolerance / 2.0)?;
+        let right = self.integrate((m, fm), (rm, frm), (b, fb), right, tolerance / 2.0)?;
+        Ok(left.zip(right).map(|(left, right)| left + right))
+    }
+}
+
+// Adaptive Simpson quadrature over the finite interval from `a` to `b`.
+pub fn integral<E>(f : &mut impl FnMut(f64) -> Result<f64, E>, a : f64, b : f64) -> Result<Option<f64>, E> {
+    if !a.is_finite() || !b.is_finite() {
+        return Ok(None);
+    }
+    let mut simpson = Simpson { f, evaluations : 0 };
+    let m = (a + b) / 2.0;
+    let (fa, fm, fb) = match (simpson.at(a)?, simpson.at(m)?, simpson.at(b)?) {
+        (Some(fa), Some(fm), Some(fb)) => (fa, fm, fb),
+        _ => { return Ok(None); }
+    };
+    let whole = (b - a) / 6.0 * (fa + 4.0 * fm + fb);
+    let value = simpson.integrate((a, fa), (m, fm), (b, fb), whole, TOLERANCE * (b - a).abs().max(1.0))?;
+    Ok(value.filter(|value| value.is_finite()))
+}
+
+// Bisection of [a, b], where f changes sign.
+fn bisect<E>(f : &mut impl FnMut(f64) -> Result<f64, E>, mut a : f64, mut b : f64) -> Result<f64, E> {
+    let negative = f(a)? < 0.0;
+    for _ in 0..200 {
+        let m = (a + b) / 2.0;
+        if m <= a.min(b) || m >= a.max(b) {
+            break;
+        }
+        if (f(m)? < 0.0) == negative {
+            a = m;
+        }
+        else {
+            b = m;
+        }
+    }
+    Ok((a + b) / 2.0)
+}
+
+/*
+A root near `guess`, by Newton's method. Should that not settle, the
+interval around the guess is widened until f changes sign across it, and
+then halved down to the root.
+*/
+pub fn root<E>(f : &mut impl FnMut(f64) -> Result<f64, E>, guess : f64) -> Result<Option<f64>, E> {
+    let mut x = guess;
+    for _ in 0..100 {
+        let y = f(x)?;
+        if y == 0.0 {
+            return Ok(Some(x));
+        }
+        let h = step(x, 1e-6);
+        let slope = (f(x + h)? - f(x - h)?) / (2.0 * h);
+        let change = y / slope;
+        if !change.is_finite() {
+            break;
+        }
+        x -= change;
+        if change.abs() <= 1e-14 * x.abs().max(1.0) {
+            return Ok(Some(x).filter(|x| x.is_finite()));
+        }
+    }
+
+    let negative = f(guess)? < 0.0;
+    let mut width = 0.1 * guess.abs().max(1.0);
+    for _ in 0..60 {
+        for end in [guess - width, guess + width] {
+            let y = f(end)?;
+            if y == 0.0 {
+                return Ok(Some(end));
+            }
+            if (y < 0.0) != negative && !y.is_nan() {
+                return bisect(f, guess, end).map(Some);
+            }
+        }
+        width *= 2.0;
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::derivative;
+    use super::integral;
+    use super::root;
+
+    fn close(value : Option<f64>, expected : f64) -> bool {
+        value.is_some_and(|value| (value - expected).abs() <= 1e-9 * expected.abs().max(1.0))
+    }
+
+    #[test]
+    fn derivatives() {
+        let mut cube = |x : f64| Ok::<_, ()>(x * x * x);
+        assert!(close(derivative(&mut cube, 2.0).unwrap(), 12.0));
+        assert!(close(derivative(&mut |x : f64| Ok::<_, ()>(x.sin()), 0.0).unwrap(), 1.0));
+        assert_eq!(derivative(&mut |x : f64| Ok::<_, ()>(1.0 / x), 0.0), Ok(None));
+    }
+
+    #[test]
+    fn integrals() {
+        assert!(close(integral(&mut |x : f64| Ok::<_, ()>(x.sin()), 0.0, std::f64::consts::PI).unwrap(), 2.0));
+        assert!(close(integral(&mut |x : f64| Ok::<_, ()>(x * x), 3.0, 0.0).unwrap(), -9.0));
+        assert_eq!(integral(&mut |x : f64| Ok::<_, ()>(x), 0.0, f64::INFINITY), Ok(None));
+        // an error of the integrand ends it
+        assert_eq!(integral(&mut |x : f64| if x > 0.5 { Err("at") } else { Ok(x) }, 0.0, 1.0), Err("at"));
+    }
+
+    #[test]
+    fn roots() {
+        assert!(close(root(&mut |x : f64| Ok::<_, ()>(x * x - 2.0), 1.0).unwrap(), 2f64.sqrt()));
+        assert!(close(root(&mut |x : f64| Ok::<_, ()>(x.cos() - x), 0.0).unwrap(), 0.7390851332151607));
+        // no slope at the guess: the interval around it is widened instead
+        assert!(close(root(&mut |x : f64| Ok::<_, ()>(x * x * x - 8.0), 0.0).unwrap(), 2.0));
+        assert_eq!(root(&mut |x : f64| Ok::<_, ()>(x * x + 1.0), 1.0), Ok(None));
+    }
+}
@@ -0,0 +1,501 @@
+use std::env;
+use std::fmt::Write;
+
+use calculator::format;
+use calculator::AngleUnit;
+use calculator::Dialect;
+
+use crate::aggregate;
+use crate::messages;
+use crate::messages::Msg;
+
+const BIN : &str = env!("CARGO_PKG_NAME");
+
+pub enum Arg {
+    None,
+    Choice(&'static [&'static str]),
+    Value(&'static str),
+    // a path, which the completions complete as a file name
+    File(&'static str)
+}
+
+pub struct Opt {
+    pub name : &'static str,
+    pub arg : Arg,
+    pub help : Msg<'static>
+}
+
+/*
+Every flag and subcommand the binary understands. parse_args, the usage
+text and the shell completion scripts are all generated from these tables,
+so a new flag only has to be added here and handled in parse_args.
+*/
+pub const OPTIONS : &[Opt] = &[
+    Opt { name : "--help", arg : Arg::None, help : Msg::HelpHelp },
+    Opt { name : "--rpn", arg : Arg::None, help : Msg::HelpRpn },
+    Opt { name : "--dialect", arg : Arg::Choice(&["standard", "python", "european", "excel", "bc"]), help : Msg::HelpDialect },
+    Opt { name : "--numbers", arg : Arg::Choice(&["float", "decimal", "rational"]), help : Msg::HelpNumbers },
+    Opt { name : "--angle", arg : Arg::Choice(&["rad", "deg"]), help : Msg::HelpAngle },
+    Opt { name : "--format", arg : Arg::Value("STYLE"), help : Msg::HelpFormat },
+    Opt { name : "--trim", arg : Arg::None, help : Msg::HelpTrim },
+    Opt { name : "--separators", arg : Arg::None, help : Msg::HelpSeparators },
+    Opt { name : "--aggregate", arg : Arg::Value("LIST"), help : Msg::HelpAggregate },
+    Opt { name : "--fields", arg : Arg::Value("EXPRESSION"), help : Msg::HelpFields },
+    Opt { name : "--delimiter", arg : Arg::Value("TEXT"), help : Msg::HelpDelimiter },
+    Opt { name : "--csv", arg : Arg::File("FILE"), help : Msg::HelpCsv },
+    Opt { name : "--expr", arg : Arg::Value("EXPRESSION"), help : Msg::HelpExpr },
+    Opt { name : "--out", arg : Arg::Value("NAME"), help : Msg::HelpOut },
+    Opt { name : "--file", arg : Arg::File("FILE"), help : Msg::HelpFile },
+    Opt { name : "--config", arg : Arg::File("FILE"), help : Msg::HelpConfig },
+    Opt { name : "--profile", arg : Arg::None, help : Msg::HelpProfile },
+    Opt { name : "--crosscheck", arg : Arg::None, help : Msg::HelpCrosscheck },
+    Opt { name : "--lint", arg : Arg::None, help : Msg::HelpLint },
+    Opt { name : "--ast-json", arg : Arg::None, help : Msg::HelpAstJson },
+    Opt { name : "--emit", arg : Arg::Choice(&["ast", "ast-dot", "ast-json", "mathml"]), help : Msg::HelpEmit },
+    Opt { name : "--from-ast-json", arg : Arg::None, help : Msg::HelpFromAstJson },
+    Opt { name : "--lang", arg : Arg::Choice(messages::LANGUAGES), help : Msg::HelpLang },
+    Opt { name : "--mcp", arg : Arg::None, help : Msg::HelpMcp },
+    Opt { name : "--rpc", arg : Arg::None, help : Msg::HelpRpc },
+    Opt { name : "--lsp", arg : Arg::None, help : Msg::HelpLsp },
+    Opt { name : "--serve", arg : Arg::Value("ADDRESS"), help : Msg::HelpServe },
+    Opt { name : "--ws", arg : Arg::Value("ADDRESS"), help : Msg::HelpWs },
+    Opt { name : "--jupyter-kernel", arg : Arg::File("FILE"), help : Msg::HelpJupyter },
+    Opt { name : "--grid", arg : Arg::None, help : Msg::HelpGrid },
+];
+
+pub const SUBCOMMANDS : &[Opt] = &[
+    Opt { name : "completions", arg : Arg::Choice(&["bash", "zsh", "fish"]), help : Msg::HelpCompletions },
+];
+
+// What the expressions are computed in.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Numbers {
+    Float,
+    Decimal,
+    Rational
+}
+
+impl Numbers {
+    pub fn from_name(name : &str) -> Option<Numbers> {
+        match name {
+            "float" => Some(Numbers::Float),
+            "decimal" => Some(Numbers::Decimal),
+            "rational" => Some(Numbers::Rational),
+            _ => None
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Numbers::Float => "float",
+            Numbers::Decimal => "decimal",
+            Numbers::Rational => "rational"
+        }
+    }
+
+    // How results are shown unless asked otherwise.
+    pub fn notation(self) -> format::Notation {
+        match self {
+            Numbers::Rational => format::Notation::Fraction,
+            _ => format::Notation::Decimal
+        }
+    }
+}
+
+// What to print of each statement instead of running it.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Emit {
+    // the parse tree as an S-expression
+    Ast,
+    // the parse tree as a Graphviz graph
+    AstDot,
+    AstJson,
+    // the statement as presentation MathML, for typesetting
+    MathMl
+}
+
+pub struct Options {
+    pub mcp : bool,
+    // a session kept over JSON-RPC on stdio
+    pub rpc : bool,
+    // a language server for scripts on stdio
+    pub lsp : bool,
+    // where to listen for evaluation requests over HTTP
+    pub serve : Option<String>,
+    // where to listen for WebSocket sessions
+    pub ws : Option<String>,
+    // the connection file Jupyter starts a kernel with
+    pub jupyter : Option<String>,
+    pub rpn : bool,
+    // a spreadsheet instead of a session
+    pub grid : bool,
+    pub profile : bool,
+    // evaluate each expression in more ways than one, to compare them
+    pub crosscheck : bool,
+    // print what :lint would point out in each statement instead of results
+    pub lint : bool,
+    pub emit : Option<Emit>,
+    // read each statement as such a tree
+    pub from_ast_json : bool,
+    pub aggregate : Option<Vec<aggregate::Aggregate>>,
+    // the expression evaluated for each line of stdin, and what splits the
+    // line into fields if not whitespace
+    pub fields : Option<String>,
+    pub delimiter : Option<String>,
+    // the CSV file a column is added to, the expression of the column and
+    // its header
+    pub csv : Option<String>,
+    pub expr : Option<String>,
+    pub out : Option<String>,
+    pub dialect : Dialect,
+    pub numbers : Numbers,
+    pub angle : AngleUnit,
+    pub style : format::Style,
+    pub file : Option<String>,
+    // the init script of the REPL, if not the usual one
+    pub config : Option<String>,
+    // expressions given on the command line, evaluated instead of a session
+    pub expressions : Vec<String>
+}
+
+pub enum Command {
+    Run(Box<Options>),
+    Help,
+    Completions(String)
+}
+
+fn find(table : &'static [Opt], name : &str) -> Option<&'static Opt> {
+    table.iter().find(|opt| opt.name == name)
+}
+
+fn take_value(opt : &Opt, args : &mut impl Iterator<Item = String>) -> Result<String, String> {
+    let value = match args.next() {
+        Some(v) => v,
+        None => { return Err(Msg::MissingValue(opt.name).to_string()); }
+    };
+
+    if let Arg::Choice(choices) = opt.arg {
+        if !choices.contains(&value.as_str()) {
+            return Err(Msg::InvalidValue(&value, opt.name, &choices.join(", ")).to_string());
+        }
+    }
+
+    Ok(value)
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options { mcp : false, rpc : false, lsp : false, serve : None, ws : None, jupyter : None, rpn : false, grid : false, profile : false, crosscheck : false, lint : false, emit : None, from_ast_json : false, aggregate : None, fields : None, delimiter : None, csv : None, expr : None, out : None, dialect : Dialect::Standard, numbers : Numbers::Float, angle : AngleUnit::Radians, style : format::Style::default(), file : None, config : None, expressions : Vec::new() }
+    }
+}
+
+pub fn parse_args() -> Result<Command, String> {
+    parse(env::args().skip(1))
+}
+
+fn parse(args : impl Iterator<Item = String>) -> Result<Command, String> {
+    let mut options = Options::default();
+    let mut args = args.peekable();
+
+    if let Some(sub) = args.peek().and_then(|arg| find(SUBCOMMANDS, arg)) {
+        args.next();
+        let value = take_value(sub, &mut args)?;
+        if let Some(extra) = args.next() {
+            return Err(Msg::UnexpectedArgument(&extra).to_string());
+        }
+        return Ok(Command::Completions(value));
+    }
+
+    while let Some(arg) = args.next() {
+        // anything that isn't a flag is an expression, and so is everything
+        // after `--`, which allows for ones like `--1`
+        if arg == "--" {
+            options.expressions.extend(args.by_ref());
+            break;
+        }
+        if !arg.starts_with("--") {
+            options.expressions.push(arg);
+            continue;
+        }
+
+        let opt = match find(OPTIONS, &arg) {
+            Some(opt) => opt,
+            None => { return Err(Msg::UnknownOption(&arg).to_string()); }
+        };
+        let value = match opt.arg {
+            Arg::None => String::new(),
+            _ => take_value(opt, &mut args)?
+        };
+
+        match opt.name {
+            "--help" => { return Ok(Command::Help); }
+            "--rpn" => { options.rpn = true; }
+            "--grid" => { options.grid = true; }
+            "--profile" => { options.profile = true; }
+            "--crosscheck" => { options.crosscheck = true; }
+            "--lint" => { options.lint = true; }
+            "--ast-json" => { options.emit = Some(Emit::AstJson); }
+            "--emit" => {
+                options.emit = match value.as_str() {
+                    "ast" => Some(Emit::Ast),
+                    "ast-dot" => Some(Emit::AstDot),
+                    "mathml" => Some(Emit::MathMl),
+                    _ => Some(Emit::AstJson)
+                };
+            }
+            "--from-ast-json" => { options.from_ast_json = true; }
+            "--lang" => {
+                if let Some(lang) = messages::Lang::from_code(&value) {
+                    messages::set_lang(lang);
+                }
+            }
+            "--mcp" => { options.mcp = true; }
+            "--rpc" => { options.rpc = true; }
+            "--lsp" => { options.lsp = true; }
+            "--serve" => { options.serve = Some(value); }
+            "--ws" => { options.ws = Some(value); }
+            "--jupyter-kernel" => { options.jupyter = Some(value); }
+            "--file" => { options.file = Some(value); }
+            "--config" => { options.config = Some(value); }
+            "--fields" => { options.fields = Some(value); }
+            "--delimiter" => { options.delimiter = Some(value); }
+            "--csv" => { options.csv = Some(value); }
+            "--expr" => { options.expr = Some(value); }
+            "--out" => { options.out = Some(value); }
+            "--aggregate" => { options.aggregate = Some(aggregate::parse_list(&value)?); }
+            // not a rounding, but what --emit mathml does
+            "--format" if value == "mathml" => { options.emit = Some(Emit::MathMl); }
+            "--format" => {
+                let (name, digits) = match value.split_once(':') {
+                    Some((name, digits)) => (name, Some(digits)),
+                    None => (value.as_str(), None)
+                };
+                options.style.rounding = match format::Rounding::from_words(name, digits) {
+                    Some(rounding) => rounding,
+                    None => { return Err(Msg::InvalidValue(&value, opt.name, "plain, fixed:N, sig:N, sci:N, eng:N, mathml").to_string()); }
+                };
+            }
+            "--trim" => { options.style.trim = true; }
+            "--separators" => { options.style.separators = true; }
+            "--numbers" => { options.numbers = Numbers::from_name(&value).unwrap_or(Numbers::Float); }
+            "--angle" => { options.angle = if value == "deg" { AngleUnit::Degrees } else { AngleUnit::Radians }; }
+            "--dialect" => {
+                options.dialect = match value.as_str() {
+                    "python" => Dialect::Python,
+                    "european" => Dialect::European,
+                    "excel" => Dialect::Excel,
+                    "bc" => Dialect::Bc,
+                    _ => Dialect::Standard
+                };
+            }
+            _ => unreachable!("option {} is not handled", opt.name)
+        }
+    }
+
+    match (&options.csv, &options.expr, &options.out) {
+        (Some(_), None, _) => { return Err(Msg::Requires("--csv", "--expr").to_string()); }
+        (None, Some(_), _) => { return Err(Msg::Requires("--expr", "--csv").to_string()); }
+        (None, _, Some(_)) => { return Err(Msg::Requires("--out", "--csv").to_string()); }
+        _ => {}
+    }
+    if options.delimiter.is_some() && options.csv.is_none() && options.fields.is_none() {
+        return Err(Msg::RequiresEither("--delimiter", "--csv", "--fields").to_string());
+    }
+
+    // each of these runs instead of the others, so only one may be given,
+    // and none of them takes expressions
+    let modes = [
+        ("--mcp", options.mcp),
+        ("--rpc", options.rpc),
+        ("--lsp", options.lsp),
+        ("--serve", options.serve.is_some()),
+        ("--ws", options.ws.is_some()),
+        ("--jupyter-kernel", options.jupyter.is_some()),
+        ("--grid", options.grid),
+        ("--aggregate", options.aggregate.is_some()),
+        ("--csv", options.csv.is_some()),
+        ("--fields", options.fields.is_some()),
+        ("--file", options.file.is_some())
+    ];
+    let mut given = modes.iter().filter(|(_, given)| *given).map(|(name, _)| *name);
+    if let Some(mode) = given.next() {
+        if let Some(other) = given.next() {
+            return Err(Msg::Conflicts(mode, other).to_string());
+        }
+        if let Some(expression) = options.expressions.first() {
+            return Err(Msg::UnexpectedArgument(expression).to_string());
+        }
+    }
+
+    // results are written the way the input is
+    options.style.decimal_comma = options.dialect == Dialect::European;
+    // bc's arithmetic is decimal
+    if options.dialect == Dialect::Bc {
+        options.numbers = Numbers::Decimal;
+    }
+    Ok(Command::Run(Box::new(options)))
+}
+
+fn arg_name(opt : &Opt) -> String {
+    match opt.arg {
+        Arg::None => String::new(),
+        Arg::Choice(choices) => format!(" <{}>", choices.join("|")),
+        Arg::Value(name) | Arg::File(name) => format!(" <{}>", name)
+    }
+}
+
+pub fn usage() -> String {
+    let mut s = String::new();
+    let usage = Msg::Usage.to_string();
+    let indent = " ".repeat(usage.chars().count());
+    let _ = writeln!(s, "{} {} [OPTIONS] [EXPRESSION]...", usage, BIN);
+    for sub in SUBCOMMANDS {
+        let _ = writeln!(s, "{} {} {}{}", indent, BIN, sub.name, arg_name(sub));
+    }
+
+    // the descriptions line up two spaces after the longest option
+    let shown = |opt : &Opt| format!("{}{}", opt.name, arg_name(opt));
+    let width = OPTIONS.iter().chain(SUBCOMMANDS).map(|opt| shown(opt).chars().count()).max().unwrap_or(0) + 2;
+
+    let _ = writeln!(s, "\n{}", Msg::Options);
+    for opt in OPTIONS {
+        let _ = writeln!(s, "  {:<width$}{}", shown(opt), opt.help);
+    }
+
+    let _ = writeln!(s, "\n{}", Msg::Commands);
+    for sub in SUBCOMMANDS {
+        let _ = writeln!(s, "  {:<width$}{}", shown(sub), sub.help);
+    }
+
+    s
+}
+
+fn names(table : &[Opt]) -> String {
+    table.iter().map(|opt| opt.name).collect::<Vec<_>>().join(" ")
+}
+
+fn bash() -> String {
+    let mut s = String::new();
+    let _ = writeln!(s, "_{}() {{", BIN);
+    let _ = writeln!(s, "    local cur=\"${{COMP_WORDS[COMP_CWORD]}}\"");
+    let _ = writeln!(s, "    local prev=\"${{COMP_WORDS[COMP_CWORD-1]}}\"");
+    let _ = writeln!(s, "    case \"$prev\" in");
+    for opt in OPTIONS.iter().chain(SUBCOMMANDS) {
+        match opt.arg {
+            Arg::None => {}
+            Arg::Choice(choices) => {
+                let _ = writeln!(s, "        {}) COMPREPLY=($(compgen -W \"{}\" -- \"$cur\")); return ;;", opt.name, choices.join(" "));
+            }
+            Arg::Value(_) => {
+                let _ = writeln!(s, "        {}) COMPREPLY=(); return ;;", opt.name);
+            }
+            Arg::File(_) => {
+                let _ = writeln!(s, "        {}) COMPREPLY=($(compgen -f -- \"$cur\")); return ;;", opt.name);
+            }
+        }
+    }
+    let _ = writeln!(s, "    esac");
+    let _ = writeln!(s, "    if [[ $COMP_CWORD -eq 1 ]]; then");
+    let _ = writeln!(s, "        COMPREPLY=($(compgen -W \"{} {}\" -- \"$cur\"))", names(OPTIONS), names(SUBCOMMANDS));
+    let _ = writeln!(s, "    elif [[ \" {} \" != *\" ${{COMP_WORDS[1]}} \"* ]]; then", names(SUBCOMMANDS));
+    let _ = writeln!(s, "        COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))", names(OPTIONS));
+    let _ = writeln!(s, "    fi");
+    let _ = writeln!(s, "}}");
+    let _ = writeln!(s, "complete -F _{} {}", BIN, BIN);
+    s
+}
+
+fn zsh_escape(help : &str) -> String {
+    help.replace('\'', "'\\''").replace('[', "\\[").replace(']', "\\]").replace(':', "\\:")
+}
+
+fn zsh() -> String {
+    let mut s = String::new();
+    let _ = writeln!(s, "#compdef {}", BIN);
+    let _ = writeln!(s);
+    for sub in SUBCOMMANDS {
+        if let Arg::Choice(choices) = sub.arg {
+            let _ = writeln!(s, "if (( CURRENT == 3 )) && [[ ${{words[2]}} == {} ]]; then", sub.name);
+            let _ = writeln!(s, "    _values '{}' {}", sub.name, choices.join(" "));
+            let _ = writeln!(s, "    return");
+            let _ = writeln!(s, "fi");
+        }
+    }
+    let _ = writeln!(s);
+    let _ = writeln!(s, "_arguments \\");
+    for opt in OPTIONS {
+        let spec = match opt.arg {
+            Arg::None => String::new(),
+            Arg::Choice(choices) => format!(":{}:({})", &opt.name[2..], choices.join(" ")),
+            Arg::Value(name) => format!(":{}: ", name),
+            Arg::File(name) => format!(":{}:_files", name)
+        };
+        let _ = writeln!(s, "    '{}[{}]{}' \\", opt.name, zsh_escape(&opt.help.to_string()), spec);
+    }
+    let commands = SUBCOMMANDS.iter()
+        .map(|sub| format!("{}\\:\"{}\"", sub.name, zsh_escape(&sub.help.to_string())))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let _ = writeln!(s, "    '1::command:(({}))'", commands);
+    s
+}
+
+fn fish_escape(help : &str) -> String {
+    help.replace('\\', "\\\\").replace('\'', "\\'")
+}
+
+fn fish() -> String {
+    let mut s = String::new();
+    let _ = writeln!(s, "complete -c {} -f", BIN);
+    for opt in OPTIONS {
+        let arg = match opt.arg {
+            Arg::None => String::new(),
+            Arg::Choice(choices) => format!(" -x -a '{}'", choices.join(" ")),
+            Arg::Value(_) => " -x".to_string(),
+            Arg::File(_) => " -r -F".to_string()
+        };
+        let _ = writeln!(s, "complete -c {} -l {}{} -d '{}'", BIN, &opt.name[2..], arg, fish_escape(&opt.help.to_string()));
+    }
+    for sub in SUBCOMMANDS {
+        let _ = writeln!(s, "complete -c {} -n '__fish_use_subcommand' -a {} -d '{}'", BIN, sub.name, fish_escape(&sub.help.to_string()));
+        if let Arg::Choice(choices) = sub.arg {
+            let _ = writeln!(s, "complete -c {} -n '__fish_seen_subcommand_from {}' -x -a '{}'", BIN, sub.name, choices.join(" "));
+        }
+    }
+    s
+}
+
+pub fn completions(shell : &str) -> String {
+    match shell {
+        "bash" => bash(),
+        "zsh" => zsh(),
+        "fish" => fish(),
+        _ => unreachable!("shell {} is rejected by parse_args", shell)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+    use super::Command;
+
+    fn error(args : &[&str]) -> Option<String> {
+        parse(args.iter().map(|arg| arg.to_string())).err()
+    }
+
+    #[test]
+    fn modes_exclude_each_other() {
+        assert_eq!(error(&["--mcp", "--serve", "127.0.0.1:8080"]).as_deref(), Some("--mcp can't be used with --serve"));
+        assert_eq!(error(&["--file", "a.calc", "--fields", "$1"]).as_deref(), Some("--fields can't be used with --file"));
+        assert_eq!(error(&["--fields", "$1", "1 + 1"]).as_deref(), Some("Unexpected argument '1 + 1'"));
+        assert!(matches!(parse(["--fields", "$1"].iter().map(|arg| arg.to_string())), Ok(Command::Run(_))));
+        assert!(matches!(parse(["--rpn", "1 2 +"].iter().map(|arg| arg.to_string())), Ok(Command::Run(_))));
+    }
+
+    #[test]
+    fn delimiter_needs_fields_or_csv() {
+        assert_eq!(error(&["--delimiter", ",", "1"]).as_deref(), Some("--delimiter needs --csv or --fields"));
+        assert_eq!(error(&["--fields", "$1", "--delimiter", ","]), None);
+        assert_eq!(error(&["--csv", "a.csv", "--expr", "$1", "--delimiter", ";"]), None);
+    }
+}
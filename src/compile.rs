@@ -0,0 +1,269 @@
+use crate::ast::Binary;
+use crate::ast::Expr;
+use crate::ast::Unary;
+use crate::eval;
+use crate::eval::Scope;
+use crate::functions;
+use crate::functions::Builtin;
+use crate::number::Number;
+use crate::profile;
+use crate::CalcError;
+use crate::Context;
+use crate::Value;
+
+/*
+An expression turned into a program for a stack machine, for when it's
+evaluated over and over with different values of a few parameters: the
+terms of a sum, or the points a derivative, integral or root is worked
+out from. Each instruction takes its operands off the stack and pushes its
+result, so the program for x^2 + 1 is x, 2, ^, 1, +.
+
+Whatever doesn't depend on the parameters is worked out once while
+compiling and pushed as a value, so in sum(i, 1, 100, i * sin(pi/4)) only
+the product is left to do for each term. What draws a random number, if
+only in a user function it calls, is drawn again each time instead. A
+constant part that fails gives its error only when it is reached, as it
+may not be. Anything that's unusual, such as a call of a user function or
+a sum inside the sum, is kept as a tree and evaluated as one when
+reached, which gives the same value and the same errors as evaluating the
+whole tree would.
+
+A program is made for one evaluation and not kept, in memory or on disk
+for a server or batch run to start with. What it pushed as constants comes
+from the session it was compiled in, its variables, functions and angle
+unit, so a kept one would be wrong in any other session unless those were
+part of the key, and then a hot formula in a session that changes would
+miss anyway. Nor would there be much to save: compiling is one walk over
+the tree, about as long as parsing it, which is less than reading back and
+checking a stored program would take.
+*/
+
+enum Instruction<N> {
+    Push(Value<N>),
+    // the value of the parameter at this index
+    Load(usize),
+    Unary(Unary, usize),
+    Binary(Binary, usize),
+    // `lhs + rhs%` or `lhs - rhs%`, where the percentage is of the lhs
+    Percentage(Binary, usize),
+    ApproxEq(usize),
+    // replaces the value on top with its truth, as `and` and `or` need
+    Truth(usize),
+    // to the end of an `and` or `or` if the truth on top decides it,
+    // keeping it as the result, and otherwise takes it off
+    ShortCircuit(Binary, usize),
+    // to the index if the condition taken off the top is false
+    Branch(usize),
+    Jump(usize),
+    // a built-in function of that many arguments
+    Call(Builtin, usize, usize),
+    List(usize),
+    Index(usize),
+    Evaluate(Expr),
+    // what a constant part gave when compiling, raised if it is reached
+    Fail(CalcError)
+}
+
+pub struct Program<'a, N> {
+    params : Vec<&'a str>,
+    code : Vec<Instruction<N>>
+}
+
+// Whether any of `params` appears in `expr`, possibly only as a name some
+// part of it binds itself, which is harmless to count.
+fn depends(expr : &Expr, params : &[&str]) -> bool {
+    match expr {
+        Expr::Number(_) | Expr::Date(_) => false,
+        Expr::Variable { name, .. } => params.contains(&name.as_str()),
+        Expr::UnaryOp { operand, .. } => depends(operand, params),
+        Expr::BinaryOp { lhs, rhs, .. } => depends(lhs, params) || depends(rhs, params),
+        Expr::Call { args, .. } | Expr::List(args) => args.iter().any(|arg| depends(arg, params)),
+        Expr::Index { list, index, .. } => depends(list, params) || depends(index, params),
+        Expr::Conditional { cond, then, otherwise } => [cond, then, otherwise].iter().any(|expr| depends(expr, params))
+    }
+}
+
+// Whether evaluating `expr` may call rand or another random function;
+// `seen` are the user functions already looked into.
+fn draws<N>(expr : &Expr, ctx : &Context<N>, seen : &mut Vec<String>) -> bool {
+    match expr {
+        Expr::Number(_) | Expr::Date(_) | Expr::Variable { .. } => false,
+        Expr::UnaryOp { operand, .. } => draws(operand, ctx, seen),
+        Expr::BinaryOp { lhs, rhs, .. } => draws(lhs, ctx, seen) || draws(rhs, ctx, seen),
+        Expr::Call { name, args, .. } => {
+            let call = match ctx.functions.get(name) {
+                Some(function) if !seen.contains(name) => {
+                    seen.push(name.clone());
+                    draws(&function.body, ctx, seen)
+                }
+                Some(_) => false,
+                None => functions::find_random(name).is_some()
+            };
+            call || args.iter().any(|arg| draws(arg, ctx, seen))
+        }
+        Expr::List(items) => items.iter().any(|item| draws(item, ctx, seen)),
+        Expr::Index { list, index, .. } => draws(list, ctx, seen) || draws(index, ctx, seen),
+        Expr::Conditional { cond, then, otherwise } => [cond, then, otherwise].iter().any(|expr| draws(expr, ctx, seen))
+    }
+}
+
+fn pop<N>(stack : &mut Vec<Value<N>>) -> Value<N> {
+    stack.pop().unwrap_or_else(|| unreachable!("an operand on the stack"))
+}
+
+impl<'a, N: Number> Program<'a, N> {
+    // `expr` as a program of `params`; names other than those are taken
+    // from `scope` as they are now.
+    pub fn compile(expr : &Expr, params : &[&'a str], scope : &Scope<'_, N>) -> Program<'a, N> {
+        profile::compiling(|| {
+            let mut program = Program { params : params.to_vec(), code : Vec::new() };
+            program.emit(expr, scope);
+            program
+        })
+    }
+
+    fn emit(&mut self, expr : &Expr, scope : &Scope<'_, N>) {
+        if !depends(expr, &self.params) && !draws(expr, scope.context(), &mut Vec::new()) {
+            let instruction = match scope.evaluate(expr) {
+                Ok(value) => Instruction::Push(value),
+                Err(e) => Instruction::Fail(e)
+            };
+            self.code.push(instruction);
+            return;
+        }
+
+        match expr {
+            Expr::Variable { name, .. } => {
+                let index = self.params.iter().position(|param| param == name).unwrap_or_else(|| unreachable!("depends on it"));
+                self.code.push(Instruction::Load(index));
+            }
+            Expr::UnaryOp { op, operand, offset } => {
+                self.emit(operand, scope);
+                self.code.push(Instruction::Unary(*op, *offset));
+            }
+            Expr::BinaryOp { op : op @ (Binary::And | Binary::Or), lhs, rhs, offset } => {
+                self.emit(lhs, scope);
+                self.code.push(Instruction::Truth(*offset));
+                let jump = self.code.len();
+                self.code.push(Instruction::ShortCircuit(*op, 0));
+                self.emit(rhs, scope);
+                self.code.push(Instruction::Truth(*offset));
+                self.code[jump] = Instruction::ShortCircuit(*op, self.code.len());
+            }
+            Expr::BinaryOp { op, lhs, rhs, offset } => {
+                self.emit(lhs, scope);
+                self.emit(rhs, scope);
+                let instruction = match op {
+                    Binary::Add | Binary::Sub if matches!(**rhs, Expr::UnaryOp { op : Unary::Percent, .. }) => Instruction::Percentage(*op, *offset),
+                    Binary::ApproxEq => Instruction::ApproxEq(*offset),
+                    _ => Instruction::Binary(*op, *offset)
+                };
+                self.code.push(instruction);
+            }
+            // unless a user function of the name hides it
+            Expr::Call { name, args, offset } => match functions::builtin(name) {
+                Some(builtin) if !scope.context().functions.contains_key(name) && !eval::binds_names(name, args) => {
+                    for arg in args {
+                        self.emit(arg, scope);
+                    }
+                    self.code.push(Instruction::Call(builtin, args.len(), *offset));
+                }
+                _ => self.code.push(Instruction::Evaluate(expr.clone()))
+            },
+            Expr::List(items) => {
+                for item in items {
+                    self.emit(item, scope);
+                }
+                self.code.push(Instruction::List(items.len()));
+            }
+            Expr::Index { list, index, offset } => {
+                self.emit(list, scope);
+                self.emit(index, scope);
+                self.code.push(Instruction::Index(*offset));
+            }
+            Expr::Conditional { cond, then, otherwise } => {
+                self.emit(cond, scope);
+                let branch = self.code.len();
+                self.code.push(Instruction::Branch(0));
+                self.emit(then, scope);
+                let jump = self.code.len();
+                self.code.push(Instruction::Jump(0));
+                self.code[branch] = Instruction::Branch(self.code.len());
+                self.emit(otherwise, scope);
+                self.code[jump] = Instruction::Jump(self.code.len());
+            }
+            _ => self.code.push(Instruction::Evaluate(expr.clone()))
+        }
+    }
+
+    // The value of the expression with the parameters bound to `args`, in
+    // their order, and everything else as in `scope`.
+    pub fn run(&self, args : &[Value<N>], scope : &Scope<'_, N>) -> Result<Value<N>, CalcError> {
+        let ctx = scope.context();
+        let precision = ctx.precision;
+        let mut stack = Vec::new();
+        let mut pc = 0;
+        while let Some(instruction) = self.code.get(pc) {
+            pc += 1;
+            match instruction {
+                Instruction::Push(value) => stack.push(value.clone()),
+                Instruction::Load(index) => stack.push(args[*index].clone()),
+                Instruction::Unary(op, offset) => {
+                    let operand = pop(&mut stack);
+                    stack.push(operand.try_map(&mut |quantity| eval::apply_unary(*op, quantity, precision, *offset))?);
+                }
+                Instruction::Binary(op, offset) => {
+                    let (rhs, lhs) = (pop(&mut stack), pop(&mut stack));
+                    stack.push(eval::apply_in(*op, lhs, rhs, ctx, *offset)?);
+                }
+                Instruction::Percentage(op, offset) => {
+                    let (rate, lhs) = (pop(&mut stack), pop(&mut stack));
+                    let part = eval::apply_values(Binary::Mul, lhs.clone(), rate, precision, *offset)?;
+                    stack.push(eval::apply_values(*op, lhs, part, precision, *offset)?);
+                }
+                Instruction::ApproxEq(offset) => {
+                    let (rhs, lhs) = (pop(&mut stack), pop(&mut stack));
+                    stack.push(eval::approx_values(lhs, rhs, ctx.epsilon, *offset)?);
+                }
+                Instruction::Truth(offset) => {
+                    let value = eval::scalar(pop(&mut stack), *offset)?;
+                    stack.push(Value::Scalar(eval::truth(eval::is_true(&value.value))));
+                }
+                Instruction::ShortCircuit(op, end) => {
+                    let decided = matches!(stack.last(), Some(Value::Scalar(value)) if eval::is_true(&value.value) == (*op == Binary::Or));
+                    if decided {
+                        pc = *end;
+                    }
+                    else {
+                        pop(&mut stack);
+                    }
+                }
+                // a conditional keeps no offset of its own
+                Instruction::Branch(otherwise) => {
+                    if !eval::is_true(&eval::scalar(pop(&mut stack), 0)?.value) {
+                        pc = *otherwise;
+                    }
+                }
+                Instruction::Jump(end) => pc = *end,
+                Instruction::Call(builtin, count, offset) => {
+                    let args = stack.split_off(stack.len() - count);
+                    stack.push(eval::call_builtin(*builtin, args, *offset, ctx)?);
+                }
+                Instruction::List(count) => {
+                    let items = stack.split_off(stack.len() - count);
+                    stack.push(Value::List(items));
+                }
+                Instruction::Index(offset) => {
+                    let (index, list) = (pop(&mut stack), pop(&mut stack));
+                    stack.push(eval::evaluate_index(list, index, *offset)?);
+                }
+                Instruction::Fail(e) => { return Err(e.clone()); }
+                Instruction::Evaluate(expr) => {
+                    let bound = self.params.iter().copied().zip(args.iter().cloned()).collect::<Vec<_>>();
+                    stack.push(scope.evaluate_with(expr, &bound)?);
+                }
+            }
+        }
+        Ok(pop(&mut stack))
+    }
+}
@@ -0,0 +1,112 @@
+use calculator::ast::Expr;
+use calculator::eval;
+use calculator::format;
+use calculator::CalcError;
+use calculator::Context;
+use calculator::Decimal;
+use calculator::Number;
+use calculator::Quantity;
+use calculator::Value;
+
+use crate::messages;
+
+/*
+What --crosscheck compares: every expression, and the right-hand side of
+every assignment, is evaluated four ways before the session goes on, by
+walking the tree and by running it compiled (see eval::evaluate_compiled)
+in the numbers of the session, and by walking the tree in f64 and in
+decimal. If any of the values isn't that of the first within the
+tolerance of `~=`, or some fail and some don't, all of them are reported.
+
+The compiled run only differs from the tree where the expression uses
+variables, as what doesn't is worked out while compiling by walking the
+tree. Each way draws its random numbers from a copy of the session's
+generator, so rand() gives all of them the same.
+*/
+
+// The values and errors of `expr` by each way, with what the way is called,
+// if they don't all agree.
+pub fn check<N: Number>(expr : &Expr, ctx : &Context<N>) -> Option<String> {
+    // copied before any of them draws from the generator
+    let (session, float, decimal) = (ctx.clone(), ctx.clone().convert::<f64>(), ctx.clone().convert::<Decimal>());
+    let tree = eval::evaluate(expr, ctx);
+    let compiled = eval::evaluate_compiled(expr, &session);
+    let float = eval::evaluate(expr, &float);
+    let decimal = eval::evaluate(expr, &decimal);
+
+    let results = [("tree", as_f64(&tree)), ("compiled", as_f64(&compiled)), ("f64", float.clone()), ("decimal", as_f64(&decimal))];
+    let (_, first) = &results[0];
+    if results.iter().all(|(_, result)| agree(first, result, ctx.epsilon)) {
+        return None;
+    }
+
+    let shown = [("tree", shown(&tree)), ("compiled", shown(&compiled)), ("f64", shown(&float)), ("decimal", shown(&decimal))];
+    Some(shown.iter().map(|(name, value)| format!("{} {}", name, value)).collect::<Vec<_>>().join(", "))
+}
+
+pub fn as_f64<N: Number>(result : &Result<Value<N>, CalcError>) -> Result<Value<f64>, CalcError> {
+    result.clone().map(|value| value.map(&mut |quantity| Quantity { value : quantity.value.to_f64(), dimension : quantity.dimension }))
+}
+
+fn shown<N: Number>(result : &Result<Value<N>, CalcError>) -> String {
+    match result {
+        Ok(value) => format::format_value(value, format::Notation::Decimal, format::Style::default()),
+        Err(e) => format!("({})", messages::error(e))
+    }
+}
+
+// Both errors, or values of the same shape and units, within `epsilon` of
+// each other either in absolute terms or relative to the larger.
+pub fn agree(a : &Result<Value<f64>, CalcError>, b : &Result<Value<f64>, CalcError>, epsilon : f64) -> bool {
+    match (a, b) {
+        (Err(_), Err(_)) => true,
+        (Ok(a), Ok(b)) => same(a, b, epsilon),
+        _ => false
+    }
+}
+
+fn same(a : &Value<f64>, b : &Value<f64>, epsilon : f64) -> bool {
+    match (a, b) {
+        (Value::Scalar(a), Value::Scalar(b)) => {
+            let (x, y) = (a.value, b.value);
+            let close = x == y || (x.is_nan() && y.is_nan()) || (x - y).abs() <= epsilon.max(epsilon * x.abs().max(y.abs()));
+            a.dimension == b.dimension && close
+        }
+        (Value::List(a), Value::List(b)) => a.len() == b.len() && a.iter().zip(b).all(|(a, b)| same(a, b, epsilon)),
+        _ => false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use calculator::parser;
+    use calculator::Context;
+    use calculator::Dialect;
+    use calculator::Value;
+
+    use super::check;
+
+    fn checked(input : &str, ctx : &Context<f64>) -> Option<String> {
+        let expr = parser::parse(calculator::tokenize(input, Dialect::Standard)).expect("an expression");
+        check(&expr, ctx)
+    }
+
+    #[test]
+    fn agreement() {
+        let mut ctx = Context::<f64>::default();
+        ctx.variables.insert("x".to_string(), Value::number(3.0));
+        assert_eq!(checked("0.1 + 0.2", &ctx), None);
+        assert_eq!(checked("sum(i, 1, 10, i * x) + sin(x)", &ctx), None);
+        assert_eq!(checked("1 / 0", &ctx), None);
+        assert_eq!(checked("[x, 2 km]", &ctx), None);
+    }
+
+    #[test]
+    fn disagreement() {
+        // f64 has no more than 17 digits, decimal keeps all 31
+        let ctx = Context::<f64>::default();
+        let shown = checked("2^100 + 1 - 2^100", &ctx).unwrap_or_default();
+        assert!(shown.starts_with("tree 0, compiled 0, f64 0, decimal 1"), "{}", shown);
+        assert_eq!(checked("2^10 + 1 - 2^10", &ctx), None);
+    }
+}
@@ -0,0 +1,170 @@
+use std::fs;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+
+use calculator::lexer;
+use calculator::Context;
+use calculator::Decimal;
+use calculator::Number;
+use calculator::Rational;
+use calculator::Token;
+use calculator::Value;
+
+use crate::cli::Numbers;
+use crate::cli::Options;
+use crate::fields;
+use crate::fields::Formula;
+use crate::messages::Msg;
+
+/*
+Column arithmetic on a CSV file: the expression of --expr is evaluated for
+each row, with the columns bound as variables named by the header, and the
+file is written to stdout with its value in a new column, named by --out or
+else after the expression. A header that isn't a name, such as `unit price`,
+binds nothing.
+
+Fields are separated by commas, or by the one character given with
+--delimiter, and may be quoted as in RFC 4180, with quotes doubled inside
+and line breaks allowed. A cell that isn't a number is left unbound, so
+only an expression using it fails on that row; the row is still written,
+with the new cell empty, but the exit code is then 1. `-` reads stdin.
+*/
+
+// The records of `text` with the line each starts on. A blank line is no
+// record.
+fn records(text : &str, delimiter : char) -> Vec<(usize, Vec<String>)> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut line = 1;
+    let mut start = 1;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' if quoted => { quoted = false; }
+            '"' if field.is_empty() => { quoted = true; }
+            '\n' if !quoted => {
+                if field.ends_with('\r') {
+                    field.pop();
+                }
+                record.push(std::mem::take(&mut field));
+                if record.len() > 1 || !record[0].is_empty() {
+                    records.push((start, std::mem::take(&mut record)));
+                }
+                record.clear();
+                line += 1;
+                start = line;
+            }
+            c if c == delimiter && !quoted => record.push(std::mem::take(&mut field)),
+            c => {
+                if c == '\n' {
+                    line += 1;
+                }
+                field.push(c);
+            }
+        }
+    }
+
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push((start, record));
+    }
+    records
+}
+
+// A field quoted if it has to be.
+fn written(field : &str, delimiter : char) -> String {
+    if field.contains([delimiter, '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    }
+    else {
+        field.to_string()
+    }
+}
+
+// A header that can be used as a variable.
+fn is_name(header : &str) -> bool {
+    let mut tokens = lexer::tokens(header, calculator::Dialect::Standard);
+    matches!((tokens.next(), tokens.next()), (Some(Token::Ident(name)), None) if name == header)
+}
+
+// Returns the process exit code.
+pub fn run(options : &Options, path : &str, expr : &str) -> i32 {
+    match options.numbers {
+        Numbers::Float => run_in::<f64>(options, path, expr),
+        Numbers::Decimal => run_in::<Decimal>(options, path, expr),
+        Numbers::Rational => run_in::<Rational>(options, path, expr)
+    }
+}
+
+fn run_in<N: Number>(options : &Options, path : &str, expr : &str) -> i32 {
+    let delimiter = match options.delimiter.as_deref().map(|d| d.chars().collect::<Vec<_>>()).as_deref() {
+        None => ',',
+        Some([c]) => *c,
+        Some(_) => {
+            eprintln!("{}", Msg::InvalidDelimiter(options.delimiter.as_deref().unwrap_or("")));
+            return 2;
+        }
+    };
+    let formula = match Formula::parse(expr, "--expr", options) {
+        Ok(formula) => formula,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 2;
+        }
+    };
+
+    let mut text = String::new();
+    let read = if path == "-" { io::stdin().read_to_string(&mut text).map(|_| ()) } else { fs::read_to_string(path).map(|read| text = read) };
+    if let Err(e) = read {
+        eprintln!("{}: {}", path, e);
+        return 1;
+    }
+
+    let mut records = records(&text, delimiter).into_iter();
+    let header = match records.next() {
+        Some((_, header)) => header,
+        None => { return 0; }
+    };
+    let names = header.iter().map(|name| name.trim().to_string()).collect::<Vec<_>>();
+    let out = options.out.as_deref().unwrap_or(expr);
+
+    let mut stdout = io::stdout().lock();
+    let row = |fields : &[String], last : &str| {
+        fields.iter().map(|field| field.as_str()).chain([last]).map(|field| written(field, delimiter)).collect::<Vec<_>>().join(&delimiter.to_string())
+    };
+    let _ = writeln!(stdout, "{}", row(&header, out));
+
+    let mut ctx = Context::<N> { angle : options.angle, ..Context::default() };
+    let mut failed = false;
+    for (line, record) in records {
+        ctx.variables.clear();
+        for (name, field) in names.iter().zip(&record) {
+            if is_name(name) {
+                if let Some(value) = fields::number::<N>(field, options.dialect, ctx.precision) {
+                    ctx.variables.insert(name.clone(), Value::number(value));
+                }
+            }
+        }
+
+        let shown = match formula.evaluate(&ctx, options) {
+            Ok(shown) => shown,
+            Err(e) => {
+                let values = record.iter().map(String::as_str).collect::<Vec<_>>();
+                eprintln!("{}", fields::failure(line, &e, &names, &values));
+                failed = true;
+                String::new()
+            }
+        };
+        let _ = writeln!(stdout, "{}", row(&record, &shown));
+    }
+
+    if failed { 1 } else { 0 }
+}
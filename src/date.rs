@@ -0,0 +1,112 @@
+use std::time::SystemTime;
+
+/*
+Dates on the Gregorian calendar, extended back before it was introduced,
+counted in seconds since 1970-01-01T00:00:00. They are in UTC and leave
+out leap seconds, so every day is 86400 seconds long. A date is written
+2024-03-01, or with the time of day as 2024-03-01T12:30 or
+2024-03-01T12:30:15.
+
+The days are converted to and from the calendar by Howard Hinnant's
+algorithms, which shift the year to start in March so that the leap day
+comes last.
+*/
+
+pub const DAY : i64 = 86_400;
+
+fn is_leap(year : i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year : i64, month : u32) -> u32 {
+    match month {
+        2 if is_leap(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31
+    }
+}
+
+// Days since 1970-01-01.
+fn days_from_civil(year : i64, month : u32, day : u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let day_of_year = (153 * ((month as i64 + 9) % 12) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+fn civil_from_days(days : i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = era * 400 + year_of_era + if month <= 2 { 1 } else { 0 };
+    (year, month as u32, day as u32)
+}
+
+// The seconds of a date and time, if the calendar has that day and the
+// clock that time.
+pub fn seconds(year : i64, month : u32, day : u32, hour : u32, minute : u32, second : u32) -> Option<i64> {
+    if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) || hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+    Some(days_from_civil(year, month, day) * DAY + (hour * 3600 + minute * 60 + second) as i64)
+}
+
+// As 2024-03-01, with the time of day unless it is midnight.
+pub fn format(seconds : i64) -> String {
+    let (year, month, day) = civil_from_days(seconds.div_euclid(DAY));
+    let date = format!("{:04}-{:02}-{:02}", year, month, day);
+    let time = seconds.rem_euclid(DAY);
+    let (hour, minute, second) = (time / 3600, time / 60 % 60, time % 60);
+    match (time, second) {
+        (0, _) => date,
+        (_, 0) => format!("{}T{:02}:{:02}", date, hour, minute),
+        _ => format!("{}T{:02}:{:02}:{:02}", date, hour, minute, second)
+    }
+}
+
+// The current time to the second.
+pub fn now() -> i64 {
+    match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(since) => since.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs_f64().ceil() as i64)
+    }
+}
+
+// Midnight at the start of the current day.
+pub fn today() -> i64 {
+    now().div_euclid(DAY) * DAY
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format;
+    use super::seconds;
+    use super::DAY;
+
+    #[test]
+    fn calendar() {
+        assert_eq!(seconds(1970, 1, 1, 0, 0, 0), Some(0));
+        assert_eq!(seconds(2024, 3, 1, 0, 0, 0).zip(seconds(2024, 2, 1, 0, 0, 0)).map(|(a, b)| (a - b) / DAY), Some(29));
+        assert!(seconds(2024, 2, 29, 0, 0, 0).is_some());
+        assert!(seconds(2000, 2, 29, 0, 0, 0).is_some());
+        assert_eq!(seconds(2023, 2, 29, 0, 0, 0), None);
+        assert_eq!(seconds(1900, 2, 29, 0, 0, 0), None);
+        assert_eq!(seconds(2024, 4, 31, 0, 0, 0), None);
+        assert_eq!(seconds(2024, 1, 1, 24, 0, 0), None);
+    }
+
+    #[test]
+    fn formatting() {
+        assert_eq!(seconds(2024, 3, 1, 0, 0, 0).map(format), Some("2024-03-01".to_string()));
+        assert_eq!(seconds(2024, 3, 1, 12, 30, 0).map(format), Some("2024-03-01T12:30".to_string()));
+        assert_eq!(format(-1), "1969-12-31T23:59:59");
+    }
+}
@@ -0,0 +1,317 @@
+use std::fmt;
+
+use crate::natural;
+use crate::natural::Natural;
+use crate::number::Number;
+use crate::rational::Rational;
+
+/*
+Exact decimal arithmetic: a decimal is a big integer and how many of its
+digits come after the point. Addition, subtraction, multiplication and
+integer powers are exact; a quotient is exact if it terminates within the
+precision asked for and rounded half away from zero to it otherwise.
+
+Infinities and NaN only come out of the f64 fallbacks, e.g. sqrt(-1), and
+anything computed with them is done in f64 as well.
+*/
+
+// Integer powers are worked out exactly up to about this many digits, and
+// in f64 beyond that.
+const MAX_POW_DIGITS : usize = 10_000;
+
+#[derive(Clone, Debug)]
+pub struct Decimal(Repr);
+
+#[derive(Clone, Debug)]
+enum Repr {
+    // digits / 10^scale, without trailing zeros after the point; zero is
+    // never negative
+    Finite { negative : bool, digits : Natural, scale : usize },
+    // an infinity or NaN
+    Special(f64)
+}
+
+// The sign, digits and scale of a finite decimal.
+type Parts<'a> = (bool, &'a Natural, usize);
+
+impl Decimal {
+    fn finite(negative : bool, mut digits : Natural, mut scale : usize) -> Decimal {
+        while scale >= natural::BASE_DIGITS && digits.0.first() == Some(&0) {
+            digits.0.remove(0);
+            scale -= natural::BASE_DIGITS;
+        }
+        while scale > 0 {
+            let (quotient, rem) = digits.div_rem_small(10);
+            if rem != 0 {
+                break;
+            }
+            digits = quotient;
+            scale -= 1;
+        }
+        if digits.is_zero() {
+            scale = 0;
+        }
+
+        Decimal(Repr::Finite { negative : negative && !digits.is_zero(), digits, scale })
+    }
+
+    fn one() -> Decimal {
+        Decimal::finite(false, Natural::small(1), 0)
+    }
+
+    fn parts(&self) -> Option<Parts<'_>> {
+        match &self.0 {
+            Repr::Finite { negative, digits, scale } => Some((*negative, digits, *scale)),
+            Repr::Special(_) => None
+        }
+    }
+
+    fn is_zero(&self) -> bool {
+        self.parts().is_some_and(|(_, digits, _)| digits.is_zero())
+    }
+
+    // Both operands brought to the same scale, if both are finite.
+    fn aligned(&self, rhs : &Decimal) -> Option<(bool, Natural, bool, Natural, usize)> {
+        let (an, a, sa) = self.parts()?;
+        let (bn, b, sb) = rhs.parts()?;
+        let scale = sa.max(sb);
+        Some((an, a.shifted(scale - sa), bn, b.shifted(scale - sb), scale))
+    }
+
+    fn via_f64(&self, rhs : &Decimal, f : impl Fn(f64, f64) -> f64) -> Decimal {
+        Decimal::from_f64(f(self.to_f64(), rhs.to_f64()))
+    }
+
+    // Square and multiply, for a non-negative exponent.
+    fn powi(&self, mut exponent : u64) -> Decimal {
+        let mut base = self.clone();
+        let mut result = Decimal::one();
+        while exponent > 0 {
+            if exponent % 2 == 1 {
+                result = result.mul(&base);
+            }
+            exponent /= 2;
+            if exponent > 0 {
+                base = base.mul(&base);
+            }
+        }
+        result
+    }
+}
+
+impl PartialEq for Decimal {
+    fn eq(&self, other : &Decimal) -> bool {
+        match (&self.0, &other.0) {
+            (Repr::Special(a), Repr::Special(b)) => a == b,
+            (Repr::Finite { negative : an, digits : a, scale : sa }, Repr::Finite { negative : bn, digits : b, scale : sb }) => {
+                an == bn && a == b && sa == sb
+            }
+            _ => false
+        }
+    }
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (negative, digits, scale) = match &self.0 {
+            Repr::Finite { negative, digits, scale } => (*negative, digits, *scale),
+            Repr::Special(value) => { return write!(f, "{}", value); }
+        };
+
+        let mut s = digits.digits();
+        if scale > 0 {
+            if s.len() <= scale {
+                s = "0".repeat(scale + 1 - s.len()) + &s;
+            }
+            s.insert(s.len() - scale, '.');
+        }
+        write!(f, "{}{}", if negative { "-" } else { "" }, s)
+    }
+}
+
+impl Number for Decimal {
+    // The shortest decimal that reads back as the same f64, so a literal
+    // such as 0.1 is exactly one tenth. A whole float is exact, as its
+    // shortest decimal may end in zeros it doesn't have.
+    fn from_f64(value : f64) -> Decimal {
+        if !value.is_finite() {
+            return Decimal(Repr::Special(value));
+        }
+        if value.fract() == 0.0 {
+            return Decimal::from_rational(&Rational::exactly(value), 0);
+        }
+
+        let s = value.to_string();
+        let (negative, s) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s.as_str())
+        };
+        let (integer, fraction) = s.split_once('.').unwrap_or((s, ""));
+        Decimal::finite(negative, Natural::from_digits(&format!("{}{}", integer, fraction)), fraction.len())
+    }
+
+    fn to_f64(&self) -> f64 {
+        match &self.0 {
+            Repr::Special(value) => *value,
+            Repr::Finite { .. } => self.to_string().parse().unwrap_or(f64::NAN)
+        }
+    }
+
+    fn from_rational(value : &Rational, precision : usize) -> Decimal {
+        let (negative, numerator, denominator) = match value.parts() {
+            Some(parts) => parts,
+            None => { return Decimal::from_f64(value.to_f64()); }
+        };
+
+        match value.decimal_places() {
+            Some(places) => Decimal::finite(negative, numerator.shifted(places).div_rem(denominator).0, places),
+            None => {
+                let quotient = Decimal::finite(negative, numerator.clone(), 0).div(&Decimal::finite(false, denominator.clone(), 0), precision);
+                quotient.unwrap_or_else(|| Decimal::from_f64(f64::NAN))
+            }
+        }
+    }
+
+    fn to_rational(&self) -> Rational {
+        match &self.0 {
+            Repr::Finite { negative, digits, scale } => Rational::from_parts(*negative, digits.clone(), Natural::small(1).shifted(*scale)),
+            Repr::Special(value) => Rational::from_f64(*value)
+        }
+    }
+
+    fn add(&self, rhs : &Decimal) -> Decimal {
+        let (an, a, bn, b, scale) = match self.aligned(rhs) {
+            Some(aligned) => aligned,
+            None => { return self.via_f64(rhs, |a, b| a + b); }
+        };
+
+        if an == bn {
+            Decimal::finite(an, a.sum(&b), scale)
+        }
+        else if a >= b {
+            Decimal::finite(an, a.difference(&b), scale)
+        }
+        else {
+            Decimal::finite(bn, b.difference(&a), scale)
+        }
+    }
+
+    fn sub(&self, rhs : &Decimal) -> Decimal {
+        self.add(&rhs.neg())
+    }
+
+    fn mul(&self, rhs : &Decimal) -> Decimal {
+        match (self.parts(), rhs.parts()) {
+            (Some((an, a, sa)), Some((bn, b, sb))) => Decimal::finite(an != bn, a.product(b), sa + sb),
+            _ => self.via_f64(rhs, |a, b| a * b)
+        }
+    }
+
+    fn neg(&self) -> Decimal {
+        match &self.0 {
+            Repr::Finite { negative, digits, scale } => Decimal::finite(!negative, digits.clone(), *scale),
+            Repr::Special(value) => Decimal(Repr::Special(-value))
+        }
+    }
+
+    // a / b is A * 10^(sb + precision) / (B * 10^sa), then scaled back by
+    // 10^precision.
+    fn div(&self, rhs : &Decimal, precision : usize) -> Option<Decimal> {
+        if rhs.is_zero() {
+            return None;
+        }
+        let ((an, a, sa), (bn, b, sb)) = match (self.parts(), rhs.parts()) {
+            (Some(a), Some(b)) => (a, b),
+            _ => { return Some(self.via_f64(rhs, |a, b| a / b)); }
+        };
+
+        let divisor = b.shifted(sa);
+        let (mut quotient, rem) = a.shifted(sb + precision).div_rem(&divisor);
+        if rem.times_small(2) >= divisor {
+            quotient = quotient.sum(&Natural::small(1));
+        }
+        Some(Decimal::finite(an != bn, quotient, precision))
+    }
+
+    fn floor_div(&self, rhs : &Decimal) -> Option<Decimal> {
+        if rhs.is_zero() {
+            return None;
+        }
+        let ((an, a, sa), (bn, b, sb)) = match (self.parts(), rhs.parts()) {
+            (Some(a), Some(b)) => (a, b),
+            _ => { return Some(self.via_f64(rhs, |a, b| (a / b).floor())); }
+        };
+
+        // a negative quotient that isn't whole rounds away from zero
+        let negative = an != bn;
+        let (mut quotient, rem) = a.shifted(sb).div_rem(&b.shifted(sa));
+        if negative && !rem.is_zero() {
+            quotient = quotient.sum(&Natural::small(1));
+        }
+        Some(Decimal::finite(negative, quotient, 0))
+    }
+
+    fn modulo(&self, rhs : &Decimal) -> Option<Decimal> {
+        if rhs.is_zero() {
+            return None;
+        }
+        if self.parts().is_none() || rhs.parts().is_none() {
+            return Some(self.via_f64(rhs, |a, b| a.modulo(&b).unwrap_or(f64::NAN)));
+        }
+        let quotient = self.floor_div(rhs)?;
+        Some(self.sub(&rhs.mul(&quotient)))
+    }
+
+    fn pow(&self, rhs : &Decimal, precision : usize) -> Option<Decimal> {
+        if self.is_zero() && rhs.to_f64() < 0.0 {
+            return None;
+        }
+        let exact = match (self.parts(), rhs.parts()) {
+            (Some((_, base, _)), Some((negative, exponent, 0))) => {
+                let exponent = exponent.digits().parse::<u64>().ok();
+                let len = base.digits().len() as u64;
+                exponent.filter(|e| len.saturating_mul(*e) <= MAX_POW_DIGITS as u64).map(|e| (negative, e))
+            }
+            _ => None
+        };
+
+        Some(match exact {
+            Some((false, exponent)) => self.powi(exponent),
+            Some((true, exponent)) => Decimal::one().div(&self.powi(exponent), precision).unwrap_or_else(Decimal::one),
+            _ => self.via_f64(rhs, f64::powf)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Decimal;
+    use crate::number::Number;
+    use crate::rational::Rational;
+
+    fn decimal(literal : &str) -> Decimal {
+        match literal.strip_prefix('-') {
+            Some(literal) => decimal(literal).neg(),
+            None => Decimal::from_rational(&Rational::from_decimal(literal), 0)
+        }
+    }
+
+    #[test]
+    fn exact_arithmetic() {
+        assert_eq!(decimal("0.1").add(&decimal("0.2")), decimal("0.3"));
+        assert_eq!(decimal("1.5").mul(&decimal("1.5")).to_string(), "2.25");
+        assert_eq!(decimal("1").sub(&decimal("1.10")).to_string(), "-0.1");
+        assert_eq!(decimal("2").pow(&decimal("-2"), 10), Some(decimal("0.25")));
+        assert_eq!(decimal("-7").modulo(&decimal("3")), Some(decimal("2")));
+        assert_eq!(decimal("-3.5").floor_div(&decimal("1")), Some(decimal("-4")));
+    }
+
+    #[test]
+    fn quotients_round_half_away_from_zero() {
+        assert_eq!(decimal("1").div(&decimal("8"), 3), Some(decimal("0.125")));
+        assert_eq!(decimal("1").div(&decimal("8"), 2), Some(decimal("0.13")));
+        assert_eq!(decimal("2").div(&decimal("3"), 5).map(|q| q.to_string()), Some("0.66667".to_string()));
+        assert_eq!(decimal("-2").div(&decimal("3"), 5).map(|q| q.to_string()), Some("-0.66667".to_string()));
+        assert_eq!(decimal("1").div(&decimal("0"), 5), None);
+    }
+}
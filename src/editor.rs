@@ -0,0 +1,327 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::io::BufRead;
+use std::io::IsTerminal;
+use std::io::Read;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process;
+
+use calculator::Dialect;
+
+use crate::highlight;
+
+/*
+Line editor for interactive sessions: cursor movement, Home/End, kill to
+the end or start of the line, and history recall with the arrow keys.
+History is kept in ~/.calculator_history between sessions.
+
+The terminal is put into non-canonical mode with `stty` only while a line
+is being read, so whatever is printed in between, and Ctrl-C while
+evaluating, behave as usual. When stdin is not a terminal, lines are read
+as they are.
+*/
+
+const HISTORY_FILE : &str = ".calculator_history";
+const MAX_HISTORY : usize = 1000;
+
+// What came of reading a line.
+#[derive(PartialEq)]
+pub enum Input {
+    Line,
+    // Ctrl-C, which drops the line
+    Interrupted,
+    End
+}
+
+pub enum Key {
+    Char(char),
+    Enter,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    KillToEnd,
+    KillToStart,
+    Interrupt,
+    Eof,
+    // stdin was closed
+    Closed,
+    Ignored
+}
+
+// Restores the terminal settings saved by `stty -g` when dropped.
+pub struct RawMode {
+    saved : String
+}
+
+impl RawMode {
+    pub fn enable() -> Option<RawMode> {
+        let output = process::Command::new("stty").arg("-g").stdin(process::Stdio::inherit()).output().ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        let saved = String::from_utf8(output.stdout).ok()?.trim().to_string();
+
+        let status = process::Command::new("stty").args(["-icanon", "-echo", "-isig", "min", "1"]).status().ok()?;
+        if !status.success() {
+            return None;
+        }
+
+        Some(RawMode { saved })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = process::Command::new("stty").arg(&self.saved).status();
+    }
+}
+
+fn read_byte(input : &mut impl Read) -> Option<u8> {
+    let mut byte = [0u8];
+    match input.read(&mut byte) {
+        Ok(1) => Some(byte[0]),
+        _ => None
+    }
+}
+
+// The rest of an escape sequence, after ESC.
+fn escape(input : &mut impl Read) -> Key {
+    let kind = read_byte(input);
+    if kind != Some(b'[') && kind != Some(b'O') {
+        return Key::Ignored;
+    }
+
+    match read_byte(input) {
+        Some(b'A') => Key::Up,
+        Some(b'B') => Key::Down,
+        Some(b'C') => Key::Right,
+        Some(b'D') => Key::Left,
+        Some(b'H') => Key::Home,
+        Some(b'F') => Key::End,
+        // ESC [ n ~
+        Some(digit @ b'0'..=b'9') => {
+            let mut n = (digit - b'0') as u32;
+            loop {
+                match read_byte(input) {
+                    Some(d @ b'0'..=b'9') => { n = n * 10 + (d - b'0') as u32; }
+                    Some(b'~') => break,
+                    _ => { return Key::Ignored; }
+                }
+            }
+            match n {
+                1 | 7 => Key::Home,
+                3 => Key::Delete,
+                4 | 8 => Key::End,
+                _ => Key::Ignored
+            }
+        }
+        _ => Key::Ignored
+    }
+}
+
+pub fn read_key(input : &mut impl Read) -> Key {
+    let first = match read_byte(input) {
+        Some(byte) => byte,
+        None => { return Key::Closed; }
+    };
+
+    match first {
+        b'\r' | b'\n' => Key::Enter,
+        0x7f | 0x08 => Key::Backspace,
+        0x01 => Key::Home,
+        0x05 => Key::End,
+        0x02 => Key::Left,
+        0x06 => Key::Right,
+        0x10 => Key::Up,
+        0x0e => Key::Down,
+        0x0b => Key::KillToEnd,
+        0x15 => Key::KillToStart,
+        0x03 => Key::Interrupt,
+        0x04 => Key::Eof,
+        0x1b => escape(input),
+        byte if byte < 0x20 => Key::Ignored,
+        byte => {
+            // the remaining bytes of a UTF-8 sequence
+            let len = match byte {
+                0xc0..=0xdf => 2,
+                0xe0..=0xef => 3,
+                0xf0..=0xf7 => 4,
+                _ => 1
+            };
+            let mut bytes = vec![byte];
+            for _ in 1..len {
+                match read_byte(input) {
+                    Some(b) => bytes.push(b),
+                    None => { return Key::Closed; }
+                }
+            }
+            match std::str::from_utf8(&bytes).ok().and_then(|s| s.chars().next()) {
+                Some(c) => Key::Char(c),
+                None => Key::Ignored
+            }
+        }
+    }
+}
+
+fn history_path() -> Option<PathBuf> {
+    let home = env::var_os("HOME")?;
+    Some(PathBuf::from(home).join(HISTORY_FILE))
+}
+
+pub struct Editor {
+    history : Vec<String>,
+    path : Option<PathBuf>,
+    interactive : bool,
+    // the dialect to colour the line being typed in, if at all
+    highlight : Option<Dialect>
+}
+
+impl Editor {
+    pub fn new(highlight : Option<Dialect>) -> Editor {
+        let interactive = io::stdin().is_terminal() && io::stdout().is_terminal();
+        let path = if interactive { history_path() } else { None };
+
+        let mut history = Vec::new();
+        if let Some(contents) = path.as_ref().and_then(|p| fs::read_to_string(p).ok()) {
+            history = contents.lines().map(str::to_string).collect::<Vec<_>>();
+            if history.len() > MAX_HISTORY {
+                history.drain(..history.len() - MAX_HISTORY);
+                if let Some(path) = &path {
+                    let _ = fs::write(path, history.join("\n") + "\n");
+                }
+            }
+        }
+
+        Editor { history, path, interactive, highlight }
+    }
+
+    fn remember(&mut self, line : &str) {
+        if line.trim().is_empty() || self.history.last().is_some_and(|last| last == line) {
+            return;
+        }
+        self.history.push(line.to_string());
+
+        if let Some(path) = &self.path {
+            if let Ok(mut file) = fs::OpenOptions::new().create(true).append(true).open(path) {
+                let _ = writeln!(file, "{}", line);
+            }
+        }
+    }
+
+    // Reads a line into `buf`, which is cleared first, without the line
+    // ending.
+    pub fn read_line(&mut self, prompt : &str, buf : &mut String) -> Input {
+        buf.clear();
+
+        if self.interactive {
+            if let Some(raw) = RawMode::enable() {
+                let read = self.edit(prompt, buf);
+                drop(raw);
+                if read == Input::Line {
+                    self.remember(buf);
+                }
+                return read;
+            }
+        }
+
+        print!("{}", prompt);
+        if let Err(e) = io::stdout().flush() {
+            panic!("Flush error: {}", e);
+        }
+        let read = io::stdin().lock().read_line(buf).expect("Something wrong");
+        let len = buf.trim_end_matches(['\n', '\r']).len();
+        buf.truncate(len);
+        if read > 0 { Input::Line } else { Input::End }
+    }
+
+    fn edit(&self, prompt : &str, buf : &mut String) -> Input {
+        let mut stdin = io::stdin().lock();
+        let mut stdout = io::stdout();
+
+        let mut line : Vec<char> = Vec::new();
+        let mut cursor = 0;
+        // position in the history while recalling, and the line that was
+        // being typed before
+        let mut recalled = self.history.len();
+        let mut draft : Vec<char> = Vec::new();
+
+        loop {
+            let all : String = line.iter().collect();
+            let column = prompt.chars().count() + cursor;
+            // commands aren't expressions
+            let shown = match self.highlight {
+                Some(dialect) if !all.trim_start().starts_with(':') => highlight::expression(&all, dialect),
+                _ => all
+            };
+            let _ = write!(stdout, "\r{}{}\x1b[K\r", prompt, shown);
+            if column > 0 {
+                let _ = write!(stdout, "\x1b[{}C", column);
+            }
+            if let Err(e) = stdout.flush() {
+                panic!("Flush error: {}", e);
+            }
+
+            match read_key(&mut stdin) {
+                Key::Char(c) => {
+                    line.insert(cursor, c);
+                    cursor += 1;
+                }
+                Key::Enter => break,
+                Key::Backspace if cursor > 0 => {
+                    cursor -= 1;
+                    line.remove(cursor);
+                }
+                Key::Delete if cursor < line.len() => {
+                    line.remove(cursor);
+                }
+                Key::Left if cursor > 0 => { cursor -= 1; }
+                Key::Right if cursor < line.len() => { cursor += 1; }
+                Key::Home => { cursor = 0; }
+                Key::End => { cursor = line.len(); }
+                Key::KillToEnd => { line.truncate(cursor); }
+                Key::KillToStart => {
+                    line.drain(..cursor);
+                    cursor = 0;
+                }
+                Key::Up if recalled > 0 => {
+                    if recalled == self.history.len() {
+                        draft = line.clone();
+                    }
+                    recalled -= 1;
+                    line = self.history[recalled].chars().collect();
+                    cursor = line.len();
+                }
+                Key::Down if recalled < self.history.len() => {
+                    recalled += 1;
+                    line = match self.history.get(recalled) {
+                        Some(entry) => entry.chars().collect(),
+                        None => draft.clone()
+                    };
+                    cursor = line.len();
+                }
+                Key::Interrupt => {
+                    // abandon the line, and whatever it was going on from
+                    let _ = writeln!(stdout, "^C");
+                    return Input::Interrupted;
+                }
+                Key::Eof if line.is_empty() => { return Input::End; }
+                Key::Closed => { return Input::End; }
+                Key::Eof if cursor < line.len() => {
+                    line.remove(cursor);
+                }
+                _ => {}
+            }
+        }
+
+        let _ = writeln!(stdout);
+        buf.extend(line);
+        Input::Line
+    }
+}
@@ -0,0 +1,163 @@
+use calculator::ast::Binary;
+use calculator::ast::Expr;
+use calculator::ast::Symbolic;
+use calculator::eval;
+use calculator::random::Random;
+use calculator::symbolic;
+use calculator::CalcError;
+use calculator::Context;
+use calculator::Number;
+use calculator::Rational;
+use calculator::Value;
+
+use crate::crosscheck;
+use crate::messages;
+
+/*
+What :equiv decides of `lhs == rhs`. If expanding lhs - rhs leaves 0 the
+two are the same for every value of their variables. Otherwise they are
+evaluated at SAMPLES points, the names the session has no value for being
+given whole numbers from -3 to 3 at first and then numbers from -10 to 10
+with three places, so that a counterexample reads well; the generator is
+seeded the same way each time, so the same points are tried. Two values
+are the same within the tolerance of `~=`. A point where both sides fail
+proves nothing and is passed over, but one where only one side does is a
+counterexample, as for sqrt(x)^2 == x at x = -1.
+*/
+
+const SAMPLES : usize = 200;
+const SEED : u64 = 0x5eed;
+
+pub enum Verdict {
+    Simplified,
+    // equal at that many points
+    Sampled(usize),
+    // the values of the variables, and what each side gave there
+    Counterexample(Vec<(String, f64)>, String, String)
+}
+
+// The names in `expr` that the session has no value for, other than those
+// a call such as sum(i, 1, 10, i) binds itself.
+fn free<N: Number>(expr : &Expr, ctx : &Context<N>, bound : &[&str], names : &mut Vec<String>) {
+    match expr {
+        Expr::Number(_) | Expr::Date(_) => {}
+        Expr::Variable { name, .. } => {
+            let undefined = matches!(eval::evaluate(expr, ctx), Err(CalcError::UndefinedVariable(..)));
+            if undefined && !bound.contains(&name.as_str()) && !names.contains(name) {
+                names.push(name.clone());
+            }
+        }
+        Expr::UnaryOp { operand, .. } => free(operand, ctx, bound, names),
+        Expr::BinaryOp { lhs, rhs, .. } => {
+            free(lhs, ctx, bound, names);
+            free(rhs, ctx, bound, names);
+        }
+        Expr::Call { name, args, .. } => {
+            let mut inner = bound.to_vec();
+            if eval::binds_names(name, args) {
+                for arg in args.iter().take(2) {
+                    if let Expr::Variable { name, .. } = arg {
+                        inner.push(name);
+                    }
+                }
+            }
+            for arg in args {
+                free(arg, ctx, &inner, names);
+            }
+        }
+        Expr::List(items) => {
+            for item in items {
+                free(item, ctx, bound, names);
+            }
+        }
+        Expr::Index { list, index, .. } => {
+            free(list, ctx, bound, names);
+            free(index, ctx, bound, names);
+        }
+        Expr::Conditional { cond, then, otherwise } => {
+            for expr in [cond, then, otherwise] {
+                free(expr, ctx, bound, names);
+            }
+        }
+    }
+}
+
+fn shown<N: Number>(result : &Result<Value<N>, CalcError>) -> String {
+    match result {
+        Ok(value) => value.to_string(),
+        Err(e) => format!("({})", messages::error(e))
+    }
+}
+
+pub fn check<N: Number>(lhs : &Expr, rhs : &Expr, ctx : &Context<N>) -> Verdict {
+    let difference = Expr::BinaryOp { op : Binary::Sub, lhs : Box::new(lhs.clone()), rhs : Box::new(rhs.clone()), offset : 0 };
+    if let Ok(Expr::Number(n)) = symbolic::apply(&Symbolic::Expand, &difference) {
+        if n == Rational::integer(0) {
+            return Verdict::Simplified;
+        }
+    }
+
+    let mut names = Vec::new();
+    free(lhs, ctx, &[], &mut names);
+    free(rhs, ctx, &[], &mut names);
+    let random = Random::new(SEED);
+    let mut equal = 0;
+    for i in 0..SAMPLES {
+        let point : Vec<(String, f64)> = names.iter().map(|name| {
+            let x = if i < 7 { random.up_to(6) as f64 - 3.0 } else { (random.uniform() * 20_000.0).round() / 1000.0 - 10.0 };
+            (name.clone(), x)
+        }).collect();
+        let bound : Vec<(&str, Value<N>)> = point.iter().map(|(name, x)| (name.as_str(), Value::number(N::from_f64(*x)))).collect();
+        let left = eval::evaluate_bound(lhs, &bound, ctx);
+        let right = eval::evaluate_bound(rhs, &bound, ctx);
+        match (&left, &right) {
+            (Err(_), Err(_)) => {}
+            _ if crosscheck::agree(&crosscheck::as_f64(&left), &crosscheck::as_f64(&right), ctx.epsilon) => equal += 1,
+            _ => { return Verdict::Counterexample(point, shown(&left), shown(&right)); }
+        }
+        // without variables one point is all there is
+        if names.is_empty() {
+            break;
+        }
+    }
+    Verdict::Sampled(equal)
+}
+
+#[cfg(test)]
+mod tests {
+    use calculator::ast::Binary;
+    use calculator::ast::Expr;
+    use calculator::parser;
+    use calculator::Context;
+    use calculator::Dialect;
+
+    use super::check;
+    use super::Verdict;
+
+    fn verdict(input : &str) -> Verdict {
+        match parser::parse(calculator::tokenize(input, Dialect::Standard)) {
+            Ok(Expr::BinaryOp { op : Binary::Equal, lhs, rhs, .. }) => check(&lhs, &rhs, &Context::<f64>::default()),
+            _ => panic!("not an equation: {}", input)
+        }
+    }
+
+    #[test]
+    fn equivalent() {
+        assert!(matches!(verdict("(a+b)^2 == a^2 + 2*a*b + b^2"), Verdict::Simplified));
+        assert!(matches!(verdict("sin(x)^2 + cos(x)^2 == 1"), Verdict::Sampled(200)));
+        assert!(matches!(verdict("sum(i, 1, 3, i * x) == 6 * x"), Verdict::Sampled(200)));
+    }
+
+    #[test]
+    fn counterexamples() {
+        match verdict("(x+1)^2 == x^2 + 1") {
+            Verdict::Counterexample(point, left, right) => {
+                assert_eq!(point.len(), 1);
+                assert_ne!(left, right);
+            }
+            _ => panic!("expected a counterexample")
+        }
+        assert!(matches!(verdict("sqrt(x)^2 == x"), Verdict::Counterexample(..)));
+        assert!(matches!(verdict("1 + 1 == 3"), Verdict::Counterexample(point, ..) if point.is_empty()));
+    }
+}
@@ -0,0 +1,938 @@
+use std::cell::Cell;
+use std::cmp::Ordering;
+use std::time::Instant;
+
+use crate::ast::Binary;
+use crate::ast::Expr;
+use crate::ast::Statement;
+use crate::ast::Unary;
+use crate::calculus;
+use crate::compile::Program;
+use crate::functions;
+use crate::functions::Angles;
+use crate::functions::Builtin;
+use crate::functions::Function;
+use crate::functions::Distribution;
+use crate::functions::IntegerFunction;
+use crate::functions::RandomFunction;
+use crate::functions::Statistic;
+use crate::matrix;
+use crate::natural::Natural;
+use crate::number::Number;
+use crate::stack;
+use crate::stack::Stack;
+use crate::stats::Moments;
+use crate::symbolic;
+use crate::units;
+use crate::units::Dimension;
+use crate::units::Quantity;
+use crate::AngleUnit;
+use crate::CalcError;
+use crate::Context;
+use crate::NativeFunction;
+use crate::Outcome;
+use crate::Rational;
+use crate::UserFunction;
+use crate::Value;
+
+/*
+Walks the tree the parser built, computing in whichever `Number` type the
+context holds. Names are only resolved here, so a function's body may use
+variables and functions defined after it.
+
+Every value carries its dimension along, which is checked wherever two
+values have to be of the same kind, as in 5 km + 3 s.
+*/
+
+// How many terms a sum or product may have.
+pub const MAX_TERMS : i128 = 1_000_000;
+
+// What one evaluation has done so far of what the context allows.
+struct Work {
+    steps : Cell<u64>,
+    deadline : Option<Instant>
+}
+
+// What an expression is evaluated in: the session, plus the parameters of
+// the user function being called and how deeply calls are nested.
+pub struct Scope<'a, N> {
+    ctx : &'a Context<N>,
+    locals : &'a [(&'a str, Value<N>)],
+    depth : usize,
+    stack : Stack,
+    work : &'a Work
+}
+
+impl<N: Number> Scope<'_, N> {
+    pub fn context(&self) -> &Context<N> {
+        self.ctx
+    }
+
+    pub fn evaluate(&self, expr : &Expr) -> Result<Value<N>, CalcError> {
+        evaluate_in(expr, self)
+    }
+
+    // With `bound` as locals too, shadowing the ones there are.
+    pub fn evaluate_with(&self, expr : &Expr, bound : &[(&str, Value<N>)]) -> Result<Value<N>, CalcError> {
+        let locals = bound.iter().chain(self.locals).cloned().collect::<Vec<_>>();
+        evaluate_in(expr, &Scope { locals : &locals, ..*self })
+    }
+
+    // One more step, which is an error once the context's limits are
+    // passed. The clock is only read every so many steps.
+    fn step(&self, offset : usize) -> Result<(), CalcError> {
+        let steps = self.work.steps.get() + 1;
+        self.work.steps.set(steps);
+        let late = || steps.is_multiple_of(64) && self.work.deadline.is_some_and(|deadline| Instant::now() >= deadline);
+        if self.ctx.max_steps.is_some_and(|max| steps > max) || late() {
+            return Err(CalcError::Budget(offset));
+        }
+        Ok(())
+    }
+
+    // Parameters shadow variables, and variables shadow constants, units
+    // and the names of results.
+    fn lookup(&self, name : &str) -> Option<Value<N>> {
+        if let Some((_, value)) = self.locals.iter().find(|(local, _)| *local == name) {
+            return Some(value.clone());
+        }
+        if let Some(value) = self.ctx.variables.get(name) {
+            return Some(value.clone());
+        }
+        if let Some(value) = functions::constant(name).or_else(|| self.ctx.constants.get(name).copied()) {
+            return Some(Value::number(N::from_f64(value)));
+        }
+        if let Some((value, dimension)) = units::find(name) {
+            return Some(Value::Scalar(Quantity { value : N::from_rational(&value, self.ctx.precision), dimension }));
+        }
+
+        let results = &self.ctx.results;
+        if name == "ans" {
+            return results.last().cloned();
+        }
+        let index = name.strip_prefix("ans").or_else(|| name.strip_prefix('#'))?;
+        match index.parse::<usize>() {
+            Ok(n) if n >= 1 => results.get(n - 1).cloned(),
+            _ => None
+        }
+    }
+}
+
+// Equal within `epsilon`, either in absolute terms or relative to the larger
+// magnitude, so both 1e-12 ~= 0 and 1e20 ~= 1e20 + 1 hold.
+fn approx_eq(a : f64, b : f64, epsilon : f64) -> bool {
+    a == b || (a - b).abs() <= epsilon.max(epsilon * a.abs().max(b.abs()))
+}
+
+pub fn truth<N: Number>(value : bool) -> Quantity<N> {
+    Quantity::number(N::from_f64(if value { 1.0 } else { 0.0 }))
+}
+
+// Anything but zero is true; NaN is too, as in C.
+pub fn is_true<N: Number>(value : &N) -> bool {
+    *value != N::from_f64(0.0)
+}
+
+// Whether `lhs op rhs` holds, false if either is NaN.
+fn ordered(op : Binary, ordering : Option<Ordering>) -> bool {
+    match (op, ordering) {
+        (_, None) => false,
+        (Binary::Less, Some(o)) => o == Ordering::Less,
+        (Binary::LessEqual, Some(o)) => o != Ordering::Greater,
+        (Binary::Greater, Some(o)) => o == Ordering::Greater,
+        (Binary::GreaterEqual, Some(o)) => o != Ordering::Less,
+        _ => unreachable!("not an ordering")
+    }
+}
+
+// Two's complement in i128, as Python does with its integers; None if a
+// left shift overflows. A negative count shifts the other way.
+fn bitwise(op : Binary, lhs : i128, rhs : i128) -> Option<i128> {
+    match op {
+        Binary::BitAnd => Some(lhs & rhs),
+        Binary::BitOr => Some(lhs | rhs),
+        Binary::Xor => Some(lhs ^ rhs),
+        Binary::ShiftLeft | Binary::ShiftRight => {
+            let count = rhs.unsigned_abs();
+            if (op == Binary::ShiftLeft) != (rhs >= 0) {
+                return Some(lhs >> count.min(127));
+            }
+            if count > 127 {
+                return if lhs == 0 { Some(0) } else { None };
+            }
+            let shifted = lhs << count;
+            if shifted >> count == lhs { Some(shifted) } else { None }
+        }
+        _ => None
+    }
+}
+
+// A failure is reported at `offset`. `~=` compares exactly here, having no
+// epsilon to go by, and `and` and `or` don't short-circuit.
+pub fn apply_binary<N: Number>(op : Binary, lhs : &N, rhs : &N, precision : usize, offset : usize) -> Result<N, CalcError> {
+    let value = match op {
+        Binary::Add => Some(lhs.add(rhs)),
+        Binary::Sub => Some(lhs.sub(rhs)),
+        Binary::Mul => Some(lhs.mul(rhs)),
+        Binary::Div => lhs.div(rhs, precision),
+        Binary::FloorDiv => lhs.floor_div(rhs),
+        Binary::Mod => lhs.modulo(rhs),
+        // zero to a negative power divides by zero
+        Binary::Pow => match lhs.pow(rhs, precision) {
+            None if *lhs != N::from_integer(0) => { return Err(CalcError::TooLarge(offset)); }
+            power => power
+        },
+        Binary::ApproxEq | Binary::Equal => Some(truth(lhs == rhs).value),
+        Binary::NotEqual => Some(truth(lhs != rhs).value),
+        Binary::Less | Binary::LessEqual | Binary::Greater | Binary::GreaterEqual => Some(truth(ordered(op, lhs.compare(rhs))).value),
+        Binary::And => Some(truth(is_true(lhs) && is_true(rhs)).value),
+        Binary::Or => Some(truth(is_true(lhs) || is_true(rhs)).value),
+        Binary::BitAnd | Binary::BitOr | Binary::Xor | Binary::ShiftLeft | Binary::ShiftRight => {
+            let value = lhs.to_integer().zip(rhs.to_integer()).and_then(|(a, b)| bitwise(op, a, b));
+            return value.map(N::from_integer).ok_or(CalcError::NotAnInteger(offset));
+        }
+    };
+    value.ok_or(CalcError::DivisionByZero(offset))
+}
+
+// The dimension of `lhs op rhs`, if the operands' dimensions fit together.
+fn binary_dimension<N: Number>(op : Binary, lhs : &Quantity<N>, rhs : &Quantity<N>) -> Option<Dimension> {
+    let (a, b) = (lhs.dimension, rhs.dimension);
+    match op {
+        Binary::Add => a.sum(b),
+        Binary::Sub => a.difference(b),
+        Binary::Mod => (a == b && !a.is_date()).then_some(a),
+        Binary::Mul => a.product(b),
+        Binary::Div | Binary::FloorDiv => a.quotient(b),
+        Binary::Pow if b.is_none() => a.pow(&rhs.value.to_rational()),
+        Binary::Pow => None,
+        Binary::ApproxEq | Binary::Equal | Binary::NotEqual | Binary::Less | Binary::LessEqual | Binary::Greater | Binary::GreaterEqual => {
+            (a == b).then_some(Dimension::NONE)
+        }
+        Binary::BitAnd | Binary::BitOr | Binary::Xor | Binary::ShiftLeft | Binary::ShiftRight => {
+            (a.is_none() && b.is_none()).then_some(Dimension::NONE)
+        }
+        Binary::And | Binary::Or => Some(Dimension::NONE)
+    }
+}
+
+pub fn apply<N: Number>(op : Binary, lhs : &Quantity<N>, rhs : &Quantity<N>, precision : usize, offset : usize) -> Result<Quantity<N>, CalcError> {
+    let dimension = match binary_dimension(op, lhs, rhs) {
+        Some(dimension) => dimension,
+        None if lhs.dimension.is_date() || rhs.dimension.is_date() => { return Err(CalcError::Date(offset)); }
+        None => { return Err(CalcError::Dimension(offset)); }
+    };
+    let value = apply_binary(op, &lhs.value, &rhs.value, precision, offset)?;
+    Ok(Quantity { value, dimension })
+}
+
+// `f` of each pair of elements of two lists of the same length, or of each
+// element of one list and a single value.
+fn zip_with<N: Number>(lhs : Value<N>, rhs : Value<N>, offset : usize, f : &mut impl FnMut(Quantity<N>, Quantity<N>) -> Result<Quantity<N>, CalcError>) -> Result<Value<N>, CalcError> {
+    let items : Result<Vec<_>, _> = match (lhs, rhs) {
+        (Value::Scalar(a), Value::Scalar(b)) => { return f(a, b).map(Value::Scalar); }
+        (Value::List(a), Value::List(b)) if a.len() != b.len() => { return Err(CalcError::Shape(offset)); }
+        (Value::List(a), Value::List(b)) => a.into_iter().zip(b).map(|(a, b)| zip_with(a, b, offset, f)).collect(),
+        (Value::List(a), b) => a.into_iter().map(|a| zip_with(a, b.clone(), offset, f)).collect(),
+        (a, Value::List(b)) => b.into_iter().map(|b| zip_with(a.clone(), b, offset, f)).collect()
+    };
+    items.map(Value::List)
+}
+
+// `lhs ~= rhs`, element by element.
+pub fn approx_values<N: Number>(lhs : Value<N>, rhs : Value<N>, epsilon : f64, offset : usize) -> Result<Value<N>, CalcError> {
+    zip_with(lhs, rhs, offset, &mut |lhs, rhs| {
+        if lhs.dimension != rhs.dimension {
+            return Err(CalcError::Dimension(offset));
+        }
+        Ok(truth(lhs == rhs || approx_eq(lhs.value.to_f64(), rhs.value.to_f64(), epsilon)))
+    })
+}
+
+pub fn apply_values<N: Number>(op : Binary, lhs : Value<N>, rhs : Value<N>, precision : usize, offset : usize) -> Result<Value<N>, CalcError> {
+    zip_with(lhs, rhs, offset, &mut |a, b| apply(op, &a, &b, precision, offset))
+}
+
+// The most decimal places of any number in `value`.
+fn decimal_places<N: Number>(value : &Value<N>) -> usize {
+    match value {
+        Value::Scalar(quantity) => quantity.value.to_rational().decimal_places().unwrap_or(0),
+        Value::List(items) => items.iter().map(decimal_places).max().unwrap_or(0)
+    }
+}
+
+/*
+`lhs op rhs` in the session, which is `apply_values` unless it works as bc
+does. Then a product, quotient, remainder or power is worked out exactly
+and cut off, toward zero, after as many decimal places as bc keeps:
+
+    a / b    the scale
+    a % b    a - (a / b) * b, with the quotient cut off as above
+    a * b    the scale, or more if a or b has more places
+    a ^ n    the scale, or more if a has more places, for n >= 0
+
+bc counts the places of a number as written, so 1.50 has two, where here
+a value has as many as it needs: 1.50 * 1.50 keeps one place at scale 0,
+so it is 2.2 here and 2.25 in bc.
+*/
+pub fn apply_in<N: Number>(op : Binary, lhs : Value<N>, rhs : Value<N>, ctx : &Context<N>, offset : usize) -> Result<Value<N>, CalcError> {
+    let precision = ctx.precision;
+    if !ctx.truncate || !matches!(op, Binary::Mul | Binary::Div | Binary::Mod | Binary::Pow) {
+        return apply_values(op, lhs, rhs, precision, offset);
+    }
+    zip_with(lhs, rhs, offset, &mut |a, b| {
+        let exact = |quantity : Quantity<N>| Quantity { value : quantity.value.to_rational(), dimension : quantity.dimension };
+        let (a, b) = (exact(a), exact(b));
+        let result = apply(op, &a, &b, precision, offset)?;
+        let places = |x : &Rational| x.decimal_places().unwrap_or(0).max(precision);
+        let value = match op {
+            Binary::Mul => result.value.truncated(places(&a.value).max(places(&b.value))),
+            Binary::Pow if b.value.compare(&Rational::integer(0)) != Some(Ordering::Less) => result.value.truncated(places(&a.value)),
+            Binary::Mod => match a.value.div(&b.value, precision) {
+                Some(quotient) => a.value.sub(&quotient.truncated(precision).mul(&b.value)),
+                None => result.value
+            },
+            _ => result.value.truncated(precision)
+        };
+        Ok(Quantity { value : N::from_rational(&value, precision), dimension : result.dimension })
+    })
+}
+
+pub fn scalar<N>(value : Value<N>, offset : usize) -> Result<Quantity<N>, CalcError> {
+    match value {
+        Value::Scalar(quantity) => Ok(quantity),
+        Value::List(_) => Err(CalcError::List(offset))
+    }
+}
+
+pub fn apply_unary<N: Number>(op : Unary, operand : Quantity<N>, precision : usize, offset : usize) -> Result<Quantity<N>, CalcError> {
+    let Quantity { value, dimension } = operand;
+    if dimension.is_date() && !matches!(op, Unary::Plus | Unary::Not) {
+        return Err(CalcError::Date(offset));
+    }
+    let value = match op {
+        Unary::Plus => value,
+        Unary::Minus => value.neg(),
+        Unary::BitNot if !dimension.is_none() => { return Err(CalcError::Dimension(offset)); }
+        Unary::BitNot => value.to_integer().map(|n| N::from_integer(!n)).ok_or(CalcError::NotAnInteger(offset))?,
+        Unary::Percent => value.mul(&N::from_rational(&Rational::ratio(1, 100), precision)),
+        Unary::Not => { return Ok(truth(!is_true(&value))); }
+    };
+    Ok(Quantity { value, dimension })
+}
+
+// `f` of the arguments, or element by element if some of them are lists,
+// which then have to be of the same length. The other arguments stand in
+// for every element.
+fn broadcast<N: Number>(args : &[Value<N>], offset : usize, f : &mut impl FnMut(&[Quantity<N>]) -> Result<Quantity<N>, CalcError>) -> Result<Value<N>, CalcError> {
+    let len = args.iter().find_map(|arg| match arg {
+        Value::List(items) => Some(items.len()),
+        Value::Scalar(_) => None
+    });
+    let len = match len {
+        Some(len) => len,
+        None => {
+            let args = args.iter().cloned().map(|arg| scalar(arg, offset)).collect::<Result<Vec<_>, _>>()?;
+            return f(&args).map(Value::Scalar);
+        }
+    };
+
+    let mut items = Vec::with_capacity(len);
+    for i in 0..len {
+        let row = args.iter().map(|arg| match arg {
+            Value::List(items) if items.len() == len => Ok(items[i].clone()),
+            Value::List(_) => Err(CalcError::Shape(offset)),
+            Value::Scalar(_) => Ok(arg.clone())
+        });
+        items.push(broadcast(&row.collect::<Result<Vec<_>, _>>()?, offset, f)?);
+    }
+    Ok(Value::List(items))
+}
+
+fn evaluate_arguments<N: Number>(args : &[Expr], scope : &Scope<'_, N>) -> Result<Vec<Value<N>>, CalcError> {
+    args.iter().map(|arg| evaluate_in(arg, scope)).collect()
+}
+
+// Checks how many arguments there are, and with `variadic` set flattens
+// lists among them into arguments of their own.
+fn count_arguments<N>(mut args : Vec<Value<N>>, min_args : usize, max_args : usize, name : &str, offset : usize) -> Result<Vec<Value<N>>, CalcError> {
+    if args.len() < min_args || args.len() > max_args {
+        return Err(CalcError::ArgumentCount(name.to_string(), args.len(), offset));
+    }
+    if max_args == functions::VARIADIC {
+        args = args.into_iter().flat_map(Value::flatten).map(Value::Scalar).collect();
+    }
+    Ok(args)
+}
+
+// The body of a user function, with its parameters bound to `args`. Errors
+// inside the body are reported at the call, since that is what was typed.
+fn evaluate_user<N: Number>(function : &UserFunction, args : Vec<Value<N>>, scope : &Scope<'_, N>, name : &str, offset : usize) -> Result<Value<N>, CalcError> {
+    if args.len() != function.params.len() {
+        return Err(CalcError::ArgumentCount(name.to_string(), args.len(), offset));
+    }
+    if scope.depth >= scope.ctx.max_depth {
+        return Err(CalcError::RecursionLimit(name.to_string(), offset));
+    }
+
+    let locals = function.params.iter().map(String::as_str).zip(args).collect::<Vec<_>>();
+    let inner = Scope { locals : &locals, depth : scope.depth + 1, ..*scope };
+    evaluate_in(&function.body, &inner).map_err(|e| e.with_offset(offset))
+}
+
+// Exactly, in naturals. A result too large to work out is infinite.
+fn evaluate_integer<N: Number>(function : &IntegerFunction, args : &[Quantity<N>], offset : usize, precision : usize) -> Result<Quantity<N>, CalcError> {
+    let mut naturals = Vec::with_capacity(args.len());
+    for arg in args {
+        if !arg.dimension.is_none() {
+            return Err(CalcError::Dimension(offset));
+        }
+        match arg.value.to_rational().parts() {
+            Some((false, numerator, denominator)) if *denominator == Natural::small(1) => naturals.push(numerator.clone()),
+            _ => { return Err(CalcError::Domain(function.name.to_string(), offset)); }
+        }
+    }
+
+    let value = match (function.apply)(&naturals) {
+        Some(value) => N::from_rational(&Rational::from_parts(false, value, Natural::small(1)), precision),
+        None => N::from_f64(f64::INFINITY)
+    };
+    Ok(Quantity::number(value))
+}
+
+// One number drawn from the context's generator. The bounds of rand, and the
+// mean and deviation of normal, may have a unit, which the result has too.
+fn evaluate_random<N: Number>(function : &RandomFunction, args : &[Quantity<N>], offset : usize, ctx : &Context<N>) -> Result<Quantity<N>, CalcError> {
+    let (a, b) = match args {
+        [] => { return Ok(Quantity::number(N::from_f64(ctx.random.uniform()))); }
+        [a, b] if a.dimension == b.dimension => (a, b),
+        _ => { return Err(CalcError::Dimension(offset)); }
+    };
+    let dimension = a.dimension;
+    let (x, y) = (a.value.to_f64(), b.value.to_f64());
+    let value = match function.distribution {
+        Distribution::Uniform => N::from_f64(x + (y - x) * ctx.random.uniform()),
+        Distribution::Normal => N::from_f64(x + y * ctx.random.normal()),
+        Distribution::Integer => {
+            if !dimension.is_none() {
+                return Err(CalcError::Dimension(offset));
+            }
+            let bounds = (a.value.to_integer(), b.value.to_integer());
+            let (from, span) = match bounds {
+                (Some(from), Some(to)) => (from, to.checked_sub(from).and_then(|span| u64::try_from(span).ok())),
+                _ => (0, None)
+            };
+            let span = span.ok_or(CalcError::Range(function.name.to_string(), offset))?;
+            N::from_integer(from + ctx.random.up_to(span) as i128)
+        }
+    };
+    Ok(Quantity { value, dimension })
+}
+
+// In f64, converting angles if the session works in degrees. An argument
+// with the dimension of an angle is in radians already.
+fn evaluate_builtin<N: Number>(function : &Function, args : &[Quantity<N>], offset : usize, angle : AngleUnit) -> Result<Quantity<N>, CalcError> {
+    let radians = function.angles == Angles::Argument && !args.is_empty() && args.iter().all(|arg| arg.dimension == Dimension::ANGLE);
+    let dimensions = args.iter().map(|arg| if radians { Dimension::NONE } else { arg.dimension }).collect::<Vec<_>>();
+    let dimension = function.units.result(&dimensions).ok_or(CalcError::Dimension(offset))?;
+    let mut args = args.iter().map(|arg| arg.value.to_f64()).collect::<Vec<_>>();
+
+    let angle = if radians { AngleUnit::Radians } else { angle };
+    let value = match (angle, function.angles) {
+        (AngleUnit::Radians, _) | (_, Angles::None) => (function.apply)(&args),
+        (AngleUnit::Degrees, Angles::Argument) => {
+            for arg in args.iter_mut() {
+                *arg = arg.to_radians();
+            }
+            (function.apply)(&args)
+        }
+        (AngleUnit::Degrees, Angles::Result) => (function.apply)(&args).to_degrees()
+    };
+    Ok(Quantity { value : N::from_f64(value), dimension })
+}
+
+// Of all the numbers in `args`, which have to be of one dimension. The
+// mean and median of no numbers are NaN, and so are the variance and
+// deviation of fewer than two. Sums are compensated, as in --aggregate.
+fn evaluate_statistic<N: Number>(statistic : Statistic, args : Vec<Value<N>>, offset : usize, precision : usize) -> Result<Value<N>, CalcError> {
+    let all = args.into_iter().flat_map(Value::flatten).collect::<Vec<_>>();
+    if statistic == Statistic::Len {
+        return Ok(Value::number(N::from_integer(all.len() as i128)));
+    }
+    let dimension = all.first().map_or(Dimension::NONE, |q| q.dimension);
+    if all.iter().any(|q| q.dimension != dimension) {
+        return Err(CalcError::Dimension(offset));
+    }
+    // the mean and median of dates are dates, but not their sum
+    if dimension.is_date() && matches!(statistic, Statistic::Sum | Statistic::Var | Statistic::Stddev) {
+        return Err(CalcError::Date(offset));
+    }
+
+    let mut numbers = all.into_iter().map(|q| q.value).collect::<Vec<_>>();
+    let nan = N::from_f64(f64::NAN);
+    let mut moments = Moments::new();
+    for n in &numbers {
+        moments.push(n, precision);
+    }
+    let variance = || {
+        let dimension = dimension.product(dimension).ok_or(CalcError::Dimension(offset))?;
+        Ok((moments.variance(precision).unwrap_or_else(|| nan.clone()), dimension))
+    };
+
+    let value = match statistic {
+        Statistic::Len => unreachable!("counted above"),
+        Statistic::Sum => moments.sum(),
+        Statistic::Mean => moments.mean(precision).unwrap_or_else(|| nan.clone()),
+        Statistic::Var => {
+            let (value, dimension) = variance()?;
+            return Ok(Value::Scalar(Quantity { value, dimension }));
+        }
+        Statistic::Stddev => N::from_f64(variance()?.0.to_f64().sqrt()),
+        Statistic::Min | Statistic::Max => {
+            let wanted = if statistic == Statistic::Min { Ordering::Less } else { Ordering::Greater };
+            let start = N::from_f64(if statistic == Statistic::Min { f64::INFINITY } else { f64::NEG_INFINITY });
+            numbers.into_iter().fold(start, |best, n| if n.compare(&best) == Some(wanted) { n } else { best })
+        }
+        Statistic::Median | Statistic::Sort => {
+            numbers.sort_by(|a, b| a.compare(b).unwrap_or(Ordering::Equal));
+            if statistic == Statistic::Sort {
+                return Ok(Value::List(numbers.into_iter().map(|value| Value::Scalar(Quantity { value, dimension })).collect()));
+            }
+            let half = numbers.len() / 2;
+            match numbers.len() {
+                0 => nan,
+                n if n % 2 == 1 => numbers[half].clone(),
+                _ => numbers[half - 1].add(&numbers[half]).div(&N::from_integer(2), precision).unwrap_or(nan)
+            }
+        }
+    };
+    Ok(Value::Scalar(Quantity { value, dimension }))
+}
+
+// `sum(i, from, to, body)` or `prod(...)`: `body` evaluated with `i` bound
+// to each integer from `from` to `to`, and the results added up or
+// multiplied. An empty range gives 0 or 1.
+fn evaluate_series<N: Number>(op : Binary, name : &str, var : &str, args : &[Expr], offset : usize, scope : &Scope<'_, N>) -> Result<Value<N>, CalcError> {
+    let bound = |arg : &Expr| -> Result<i128, CalcError> {
+        let bound = scalar(evaluate_in(arg, scope)?, offset)?;
+        match bound.value.to_integer() {
+            Some(n) if bound.dimension.is_none() => Ok(n),
+            _ => Err(CalcError::Bounds(name.to_string(), offset))
+        }
+    };
+    let (from, to) = (bound(&args[1])?, bound(&args[2])?);
+    if to.saturating_sub(from) >= MAX_TERMS {
+        return Err(CalcError::Bounds(name.to_string(), offset));
+    }
+
+    let precision = scope.ctx.precision;
+    let body = Program::compile(&args[3], &[var], scope);
+    let mut result = None;
+    for i in from..=to {
+        scope.step(offset)?;
+        let term = body.run(&[Value::number(N::from_integer(i))], scope)?;
+        result = Some(match result {
+            Some(acc) => apply_values(op, acc, term, precision, offset)?,
+            None => term
+        });
+    }
+
+    let empty = if op == Binary::Add { 0 } else { 1 };
+    Ok(result.unwrap_or_else(|| Value::number(N::from_integer(empty))))
+}
+
+/*
+`diff(body, x, at)`, `integrate(body, x, a, b)` or `solve(body, x, guess)`:
+`body` as a function of `x`, evaluated at whichever points the numerical
+method needs. `x` takes the dimension of the points given, so the
+derivative of a distance by a time comes out as a speed.
+*/
+fn evaluate_calculus<N: Number>(name : &str, var : &str, args : &[Expr], offset : usize, scope : &Scope<'_, N>) -> Result<Value<N>, CalcError> {
+    let points = args[2..].iter().map(|arg| scalar(evaluate_in(arg, scope)?, offset)).collect::<Result<Vec<_>, _>>()?;
+    let dimension = points[0].dimension;
+    if points.iter().any(|point| point.dimension != dimension) {
+        return Err(CalcError::Dimension(offset));
+    }
+
+    let body = Program::compile(&args[0], &[var], scope);
+    let mut result = None;
+    let mut f = |x : f64| -> Result<f64, CalcError> {
+        scope.step(offset)?;
+        let y = scalar(body.run(&[Value::Scalar(Quantity { value : N::from_f64(x), dimension })], scope)?, offset)?;
+        if *result.get_or_insert(y.dimension) != y.dimension {
+            return Err(CalcError::Dimension(offset));
+        }
+        Ok(y.value.to_f64())
+    };
+
+    let x = points[0].value.to_f64();
+    let value = match name {
+        "diff" => calculus::derivative(&mut f, x)?,
+        "integrate" => calculus::integral(&mut f, x, points[1].value.to_f64())?,
+        _ => calculus::root(&mut f, x)?
+    };
+    let value = value.ok_or(CalcError::NoConvergence(name.to_string(), offset))?;
+    let result = result.unwrap_or(Dimension::NONE);
+    let dimension = match name {
+        "diff" => result.quotient(dimension),
+        "integrate" => result.product(dimension),
+        _ => Some(dimension)
+    };
+    Ok(Value::Scalar(Quantity { value : N::from_f64(value), dimension : dimension.ok_or(CalcError::Dimension(offset))? }))
+}
+
+// The first name in `expr` that has no value, which solveprop solves for.
+fn unknown<'e, N: Number>(expr : &'e Expr, scope : &Scope<'_, N>) -> Option<&'e str> {
+    match expr {
+        Expr::Number(_) | Expr::Date(_) => None,
+        Expr::Variable { name, .. } => scope.lookup(name).is_none().then_some(name.as_str()),
+        Expr::UnaryOp { operand, .. } => unknown(operand, scope),
+        Expr::BinaryOp { lhs, rhs, .. } => unknown(lhs, scope).or_else(|| unknown(rhs, scope)),
+        Expr::Call { args, .. } | Expr::List(args) => args.iter().find_map(|arg| unknown(arg, scope)),
+        Expr::Index { list, index, .. } => unknown(list, scope).or_else(|| unknown(index, scope)),
+        Expr::Conditional { cond, then, otherwise } => [cond, then, otherwise].iter().find_map(|expr| unknown(expr, scope))
+    }
+}
+
+/*
+solveprop(lhs = rhs): the value of the one unknown that makes the two sides
+equal. A side that is a quotient, as a ratio is read, is multiplied out
+with the other's denominator, so a/b = c/d becomes a*d - c*b = 0, which is
+linear in the unknown whichever of the four it is in, and so is found from
+that difference at 0 and 1, exactly if the numbers are. Then the value is
+checked in the equation as it was given, within the tolerance of `~=`,
+which an unknown that isn't linear after all fails.
+*/
+fn evaluate_proportion<N: Number>(lhs : &Expr, rhs : &Expr, offset : usize, scope : &Scope<'_, N>) -> Result<Value<N>, CalcError> {
+    let name = "solveprop";
+    let var = unknown(lhs, scope).or_else(|| unknown(rhs, scope)).ok_or(CalcError::NoConvergence(name.to_string(), offset))?;
+    let split = |side : &Expr| match side {
+        Expr::BinaryOp { op : Binary::Div, lhs, rhs, .. } => ((**lhs).clone(), (**rhs).clone()),
+        _ => (side.clone(), Expr::Number(Rational::integer(1)))
+    };
+    let ((a, b), (c, d)) = (split(lhs), split(rhs));
+    let product = |x : Expr, y : Expr| Expr::BinaryOp { op : Binary::Mul, lhs : Box::new(x), rhs : Box::new(y), offset };
+    let difference = Expr::BinaryOp { op : Binary::Sub, lhs : Box::new(product(a, d)), rhs : Box::new(product(c, b)), offset };
+
+    let at = |x : i128, expr : &Expr| scope.evaluate_with(expr, &[(var, Value::number(N::from_integer(x)))]);
+    let (g0, g1) = (at(0, &difference)?, at(1, &difference)?);
+    let slope = apply_in(Binary::Sub, g0.clone(), g1, scope.ctx, offset)?;
+    let x = apply_in(Binary::Div, g0, slope, scope.ctx, offset).map_err(|_| CalcError::NoConvergence(name.to_string(), offset))?;
+
+    let bound = [(var, x.clone())];
+    let (left, right) = (scope.evaluate_with(lhs, &bound)?, scope.evaluate_with(rhs, &bound)?);
+    let (left, right) = (scalar(left, offset)?, scalar(right, offset)?);
+    if left.dimension != right.dimension || !approx_eq(left.value.to_f64(), right.value.to_f64(), scope.ctx.epsilon) {
+        return Err(CalcError::NoConvergence(name.to_string(), offset));
+    }
+    Ok(x)
+}
+
+// Whether this may be a call of sum, diff or the like that binds a name of
+// its own, which it might be if one of the first two arguments is a name.
+pub fn binds_names(name : &str, args : &[Expr]) -> bool {
+    matches!(name, "sum" | "Σ" | "prod" | "Π" | "diff" | "integrate" | "solve")
+        && args.iter().take(2).any(|arg| matches!(arg, Expr::Variable { .. }))
+}
+
+// A registered function, of plain numbers.
+fn evaluate_native<N: Number>(function : &NativeFunction, args : Vec<Value<N>>, name : &str, offset : usize) -> Result<Value<N>, CalcError> {
+    if args.len() != function.arity {
+        return Err(CalcError::ArgumentCount(name.to_string(), args.len(), offset));
+    }
+    broadcast(&args, offset, &mut |args| {
+        if args.iter().any(|arg| !arg.dimension.is_none()) {
+            return Err(CalcError::Dimension(offset));
+        }
+        let args = args.iter().map(|arg| arg.value.to_f64()).collect::<Vec<_>>();
+        Ok(Quantity::number(N::from_f64((function.apply)(&args))))
+    })
+}
+
+// Functions defined in the session take precedence over registered and
+// built-in ones. Those are computed in f64, except for the integer, list
+// and matrix functions and randint.
+fn evaluate_call<N: Number>(name : &str, args : &[Expr], offset : usize, scope : &Scope<'_, N>) -> Result<Value<N>, CalcError> {
+    scope.step(offset)?;
+    if scope.stack.exhausted() {
+        if scope.ctx.functions.contains_key(name) {
+            return Err(CalcError::RecursionLimit(name.to_string(), offset));
+        }
+        return Err(CalcError::Nesting(offset));
+    }
+    if let Some(function) = scope.ctx.functions.get(name) {
+        let args = evaluate_arguments(args, scope)?;
+        return evaluate_user(function, args, scope, name, offset);
+    }
+    if let Some(function) = scope.ctx.native.get(name) {
+        return evaluate_native(function, evaluate_arguments(args, scope)?, name, offset);
+    }
+    if let [Expr::Variable { name : var, .. }, _, _, _] = args {
+        match name {
+            "sum" | "Σ" => { return evaluate_series(Binary::Add, name, var, args, offset, scope); }
+            "prod" | "Π" => { return evaluate_series(Binary::Mul, name, var, args, offset, scope); }
+            _ => {}
+        }
+    }
+    if let ("solveprop", [Expr::BinaryOp { op : Binary::Equal, lhs, rhs, .. }]) = (name, args) {
+        return evaluate_proportion(lhs, rhs, offset, scope);
+    }
+    if let [_, Expr::Variable { name : var, .. }, points @ ..] = args {
+        if let ("diff" | "solve", 1) | ("integrate", 2) = (name, points.len()) {
+            return evaluate_calculus(name, var, args, offset, scope);
+        }
+    }
+    let builtin = functions::builtin(name).ok_or(CalcError::UnknownFunction(name.to_string(), offset))?;
+    call_builtin(builtin, evaluate_arguments(args, scope)?, offset, scope.ctx)
+}
+
+// A call of one of the built-in functions that take values, which is all
+// of them but the likes of sum and diff.
+pub fn call_builtin<N: Number>(builtin : Builtin, args : Vec<Value<N>>, offset : usize, ctx : &Context<N>) -> Result<Value<N>, CalcError> {
+    let name = builtin.name();
+    match builtin {
+        Builtin::List(function) => {
+            let args = count_arguments(args, 1, functions::VARIADIC, name, offset)?;
+            evaluate_statistic(function.statistic, args, offset, ctx.precision)
+        }
+        Builtin::Matrix(function) => {
+            if args.len() != function.args {
+                return Err(CalcError::ArgumentCount(name.to_string(), args.len(), offset));
+            }
+            matrix::apply(function.operation, args, ctx.precision, offset)
+        }
+        Builtin::Integer(function) => {
+            let args = count_arguments(args, function.min_args, function.max_args, name, offset)?;
+            broadcast(&args, offset, &mut |args| evaluate_integer(function, args, offset, ctx.precision))
+        }
+        Builtin::Random(function) => {
+            if !function.args.contains(&args.len()) {
+                return Err(CalcError::ArgumentCount(name.to_string(), args.len(), offset));
+            }
+            broadcast(&args, offset, &mut |args| evaluate_random(function, args, offset, ctx))
+        }
+        Builtin::Function(function) => {
+            let args = count_arguments(args, function.min_args, function.max_args, name, offset)?;
+            let value = broadcast(&args, offset, &mut |args| evaluate_builtin(function, args, offset, ctx.angle))?;
+            if !ctx.truncate {
+                return Ok(value);
+            }
+            // cut off as bc does sqrt, after the scale or the places of the
+            // arguments, whichever is more
+            let places = args.iter().map(decimal_places).max().unwrap_or(0).max(ctx.precision);
+            Ok(value.map(&mut |quantity| Quantity { value : N::from_rational(&quantity.value.to_rational().truncated(places), places), dimension : quantity.dimension }))
+        }
+    }
+}
+
+// `list[index]`, counting from 0, or from the end for a negative index.
+pub fn evaluate_index<N: Number>(list : Value<N>, index : Value<N>, offset : usize) -> Result<Value<N>, CalcError> {
+    let index = scalar(index, offset)?;
+    let (mut items, index) = match (list, index.value.to_integer()) {
+        (Value::List(items), Some(n)) if index.dimension.is_none() => (items, n),
+        _ => { return Err(CalcError::Index(offset)); }
+    };
+    let len = items.len() as i128;
+    let index = if index < 0 { index + len } else { index };
+    if index < 0 || index >= len {
+        return Err(CalcError::Index(offset));
+    }
+    Ok(items.swap_remove(index as usize))
+}
+
+fn evaluate_in<N: Number>(expr : &Expr, scope : &Scope<'_, N>) -> Result<Value<N>, CalcError> {
+    let precision = scope.ctx.precision;
+    match expr {
+        Expr::Number(value) => Ok(Value::number(N::from_rational(value, precision))),
+        Expr::Date(seconds) => Ok(Value::Scalar(Quantity { value : N::from_integer(*seconds as i128), dimension : Dimension::DATE })),
+        Expr::Variable { name, offset } => scope.lookup(name).ok_or(CalcError::UndefinedVariable(name.clone(), *offset)),
+        Expr::UnaryOp { op, operand, offset } => {
+            evaluate_in(operand, scope)?.try_map(&mut |quantity| apply_unary(*op, quantity, precision, *offset))
+        }
+        // a percentage added to or taken off a value is a percentage of it,
+        // so 200 + 10% is 220
+        Expr::BinaryOp { op : op @ (Binary::Add | Binary::Sub), lhs, rhs, offset } if matches!(**rhs, Expr::UnaryOp { op : Unary::Percent, .. }) => {
+            let (lhs, rate) = (evaluate_in(lhs, scope)?, evaluate_in(rhs, scope)?);
+            let part = apply_values(Binary::Mul, lhs.clone(), rate, precision, *offset)?;
+            apply_values(*op, lhs, part, precision, *offset)
+        }
+        Expr::BinaryOp { op : Binary::ApproxEq, lhs, rhs, offset } => {
+            let (lhs, rhs) = (evaluate_in(lhs, scope)?, evaluate_in(rhs, scope)?);
+            approx_values(lhs, rhs, scope.ctx.epsilon, *offset)
+        }
+        // the right operand is only evaluated if it decides the result
+        Expr::BinaryOp { op : op @ (Binary::And | Binary::Or), lhs, rhs, offset } => {
+            let lhs = is_true(&scalar(evaluate_in(lhs, scope)?, *offset)?.value);
+            if lhs == (*op == Binary::Or) {
+                return Ok(Value::Scalar(truth(lhs)));
+            }
+            Ok(Value::Scalar(truth(is_true(&scalar(evaluate_in(rhs, scope)?, *offset)?.value))))
+        }
+        Expr::BinaryOp { op, lhs, rhs, offset } => {
+            let (lhs, rhs) = (evaluate_in(lhs, scope)?, evaluate_in(rhs, scope)?);
+            apply_in(*op, lhs, rhs, scope.ctx, *offset)
+        }
+        Expr::Call { name, args, offset } => evaluate_call(name, args, *offset, scope),
+        Expr::List(items) => evaluate_arguments(items, scope).map(Value::List),
+        Expr::Index { list, index, offset } => evaluate_index(evaluate_in(list, scope)?, evaluate_in(index, scope)?, *offset),
+        Expr::Conditional { cond, then, otherwise } => {
+            // a conditional keeps no offset of its own
+            let cond = scalar(evaluate_in(cond, scope)?, 0)?;
+            if is_true(&cond.value) {
+                evaluate_in(then, scope)
+            }
+            else {
+                evaluate_in(otherwise, scope)
+            }
+        }
+    }
+}
+
+// On a stack of its own, so that calls of user functions may go as deep as
+// that allows.
+pub fn evaluate<N: Number>(expr : &Expr, ctx : &Context<N>) -> Result<Value<N>, CalcError> {
+    evaluate_bound(expr, &[], ctx)
+}
+
+// Like `evaluate`, with `bound` as names of the expression alone, as the
+// parameters of a user function are.
+pub fn evaluate_bound<N: Number>(expr : &Expr, bound : &[(&str, Value<N>)], ctx : &Context<N>) -> Result<Value<N>, CalcError> {
+    let deadline = ctx.time_limit.map(|limit| Instant::now() + limit);
+    stack::run(|stack| {
+        let work = Work { steps : Cell::new(0), deadline };
+        evaluate_in(expr, &Scope { ctx, locals : bound, depth : 0, stack, work : &work })
+    })
+}
+
+// Like `evaluate`, but run as a program of the session's variables (see
+// compile.rs), so only what doesn't use any of them is evaluated as a tree:
+// a second way to the same value, to check the first against.
+pub fn evaluate_compiled<N: Number>(expr : &Expr, ctx : &Context<N>) -> Result<Value<N>, CalcError> {
+    let (names, args) : (Vec<&str>, Vec<Value<N>>) = ctx.variables.iter().map(|(name, value)| (name.as_str(), value.clone())).unzip();
+    let deadline = ctx.time_limit.map(|limit| Instant::now() + limit);
+    stack::run(|stack| {
+        let work = Work { steps : Cell::new(0), deadline };
+        let scope = Scope { ctx, locals : &[], depth : 0, stack, work : &work };
+        Program::compile(expr, &names, &scope).run(&args, &scope)
+    })
+}
+
+// Evaluates an expression, or stores an assignment or definition in `ctx`.
+pub fn execute<N: Number>(statement : Statement, ctx : &mut Context<N>) -> Result<Outcome<N>, CalcError> {
+    match statement {
+        Statement::Expr(expr) => evaluate(&expr, ctx).map(Outcome::Value),
+        // the parser only knows of the built-in constants
+        Statement::Assign(name, offset, _) if ctx.constants.contains_key(&name) => Err(CalcError::Constant(name, offset)),
+        Statement::Assign(name, _, expr) => {
+            let val = evaluate(&expr, ctx)?;
+            ctx.variables.insert(name.clone(), val.clone());
+            Ok(Outcome::Assigned(name, val))
+        }
+        Statement::Define(name, params, body) => {
+            if let Some(param) = params.iter().find(|param| ctx.constants.contains_key(*param)) {
+                return Err(CalcError::Constant(param.clone(), 0));
+            }
+            ctx.functions.insert(name.clone(), UserFunction { params, body });
+            Ok(Outcome::Defined(name))
+        }
+        Statement::Symbolic(operation, expr) => symbolic::apply(&operation, &expr).map(Outcome::Expression)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::execute;
+    use crate::CalcError;
+    use crate::Context;
+    use crate::Dialect;
+    use crate::Outcome;
+    use crate::Value;
+
+    // The value of the last of `lines`, executed in turn in `ctx`.
+    fn run(lines : &[&str], ctx : &mut Context) -> Result<f64, CalcError> {
+        let mut last = None;
+        for line in lines {
+            last = Some(execute(line, Dialect::Standard, ctx)?);
+        }
+        match last {
+            Some(Outcome::Value(Value::Scalar(quantity))) => Ok(quantity.value),
+            other => panic!("not a number: {:?}", other)
+        }
+    }
+
+    const SUMS : &str = "h(n) = n <= 0 ? 0 : 1 + sum(i, 1, 1, h(n - 1))";
+
+    #[test]
+    fn recursion_through_sums() {
+        assert_eq!(run(&[SUMS, "h(250)"], &mut Context::default()), Ok(250.0));
+    }
+
+    #[test]
+    fn recursion_through_nested_sums() {
+        let body = format!("{}n{}", "sum(i, 1, 1, ".repeat(60), ")".repeat(60));
+        let g = format!("g(n) = n <= 0 ? 0 : {} + g(n - 1)", body);
+        assert_eq!(run(&[&g, "g(255)"], &mut Context::default()), Ok(32640.0));
+    }
+
+    #[test]
+    fn depth_limit() {
+        let f = "f(n) = n <= 0 ? 0 : 1 + f(n - 1)";
+        let mut ctx = Context { max_depth : 400, ..Context::default() };
+        assert_eq!(run(&[f, "f(399)"], &mut ctx), Ok(399.0));
+        assert_eq!(run(&["f(400)"], &mut ctx), Err(CalcError::RecursionLimit("f".to_string(), 0)));
+
+        ctx.max_depth = crate::DEPTH_LIMIT;
+        assert_eq!(run(&["f(999)"], &mut ctx), Ok(999.0));
+    }
+
+    #[test]
+    fn step_and_time_limits() {
+        let mut ctx = Context { max_steps : Some(1000), ..Context::default() };
+        assert_eq!(run(&["sum(i, 1, 999, i)"], &mut ctx), Ok(499500.0));
+        assert_eq!(run(&["sum(i, 1, 10^6, i)"], &mut ctx), Err(CalcError::Budget(0)));
+
+        let mut ctx = Context { time_limit : Some(std::time::Duration::from_millis(10)), ..Context::default() };
+        assert_eq!(run(&["sum(i, 1, 999999, sum(j, 1, 999999, i * j))"], &mut ctx), Err(CalcError::Budget(18)));
+    }
+
+    // deeper than the stack allows, which stops with an error all the same
+    #[test]
+    fn stack_limit() {
+        let mut ctx = Context { max_depth : usize::MAX, ..Context::default() };
+        let f = "f(n) = n <= 0 ? 0 : 1 + f(n - 1)";
+        assert_eq!(run(&[f, "f(10^7)"], &mut ctx), Err(CalcError::RecursionLimit("f".to_string(), 0)));
+        assert!(matches!(run(&[SUMS, "h(10^7)"], &mut ctx), Err(CalcError::Nesting(0) | CalcError::RecursionLimit(..))));
+    }
+
+    #[test]
+    fn comparisons_and_conditionals() {
+        let value = |line| run(&[line], &mut Context::default());
+        assert_eq!(value("(1 < 2) + (2 <= 1) + (1 == 1.0) + (1 != 2)"), Ok(3.0));
+        assert_eq!(value("0.1 + 0.2 == 0.3"), Ok(0.0));
+        assert_eq!(value("0.1 + 0.2 ~= 0.3"), Ok(1.0));
+        assert_eq!(value("1 km > 2 m"), Ok(1.0));
+        assert_eq!(value("1 km < 2 s"), Err(CalcError::Dimension(5)));
+        assert_eq!(value("1 < 2 and not (2 > 3 or 0)"), Ok(1.0));
+        // only the branch taken, and only as much of `and` and `or` as
+        // decides them, is evaluated
+        assert_eq!(value("0 ? 1/0 : 5"), Ok(5.0));
+        assert_eq!(value("if(1 < 2, 10, 1/0)"), Ok(10.0));
+        assert_eq!(value("(0 and 1/0) + (1 or 1/0)"), Ok(1.0));
+        assert_eq!(value("1 ? 0 ? 2 : 3 : 4"), Ok(3.0));
+        assert_eq!(value("1 < 2 < 3"), Err(CalcError::TrailingInput(6)));
+    }
+
+    #[test]
+    fn sums_and_products() {
+        let value = |line| run(&[line], &mut Context::default());
+        assert_eq!(value("sum(i, 1, 100, i^2)"), Ok(338350.0));
+        assert_eq!(value("prod(k, 1, 10, k)"), Ok(3628800.0));
+        assert_eq!(value("Σ(i, 1, 4, i) + Π(k, 1, 5, k)"), Ok(130.0));
+        assert_eq!(value("sum(i, 1, 0, i) + prod(i, 1, 0, i)"), Ok(1.0));
+        assert_eq!(value("sum(i, 1, 3, sum(j, 1, i, j))"), Ok(10.0));
+        assert_eq!(value("sum(i, 1.5, 3, i)"), Err(CalcError::Bounds("sum".to_string(), 0)));
+        // the name is bound inside only
+        let mut ctx = Context::default();
+        assert_eq!(run(&["i = 7", "sum(i, 1, 3, i) + i"], &mut ctx), Ok(13.0));
+    }
+}
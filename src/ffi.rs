@@ -0,0 +1,232 @@
+use std::ffi::c_char;
+use std::ffi::c_int;
+use std::ffi::CStr;
+use std::ffi::CString;
+use std::panic;
+use std::ptr;
+
+use crate::registrable;
+use crate::Context;
+use crate::Dialect;
+use crate::Outcome;
+use crate::Value;
+
+/*
+A C interface to a session of the calculator, for embedding it in programs
+written in other languages; include/calculator.h declares it. A session is
+created with calculator_new and released with calculator_free, and holds
+the variables and functions defined in it, as the REPL does. Numbers are
+f64 and the input is in the standard dialect.
+
+Strings going in are UTF-8 and terminated by a nul, and are only read
+during the call. The error messages coming out belong to the caller, who
+releases them with calculator_free_string. Any pointer may be null, which
+gives an error rather than undefined behaviour; other than that, pointers
+have to be valid, and a session used by one thread at a time. A panic
+while evaluating mustn't unwind into the caller's frames, which aborts
+the host, so it is caught and becomes an error message.
+*/
+
+pub struct Session {
+    ctx : Context<f64>
+}
+
+fn message(text : &str) -> *mut c_char {
+    // a nul inside the message can't be passed on, so it ends there
+    let text = text.split('\0').next().unwrap_or_default();
+    CString::new(text).map_or(ptr::null_mut(), CString::into_raw)
+}
+
+unsafe fn text<'a>(s : *const c_char) -> Option<&'a str> {
+    if s.is_null() {
+        return None;
+    }
+    CStr::from_ptr(s).to_str().ok()
+}
+
+// A plain number, which is all that can be passed on as a double.
+fn number(value : &Value<f64>) -> Result<f64, String> {
+    match value {
+        Value::Scalar(quantity) if quantity.dimension.is_none() => Ok(quantity.value),
+        Value::Scalar(_) => Err("The result has a unit".to_string()),
+        Value::List(_) => Err("The result is a list".to_string())
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn calculator_new() -> *mut Session {
+    Box::into_raw(Box::new(Session { ctx : Context::default() }))
+}
+
+/// # Safety
+/// `session` is null or came from calculator_new, and isn't used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn calculator_free(session : *mut Session) {
+    if !session.is_null() {
+        drop(Box::from_raw(session));
+    }
+}
+
+/// # Safety
+/// `s` is null or a message returned by this interface, and isn't used
+/// afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn calculator_free_string(s : *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+// Evaluates `input`, which may also assign a variable or define a function
+// as in the REPL. Gives null and stores the value in `result` if there is
+// one, and otherwise an error message such as "column 3: Unexpected '+'";
+// a definition leaves `result` as it is.
+/// # Safety
+/// `session` came from calculator_new, `input` is a nul-terminated string,
+/// and `result` points to a double or is null.
+#[no_mangle]
+pub unsafe extern "C" fn calculator_evaluate(session : *mut Session, input : *const c_char, result : *mut f64) -> *mut c_char {
+    let (session, input) = match (session.as_mut(), text(input)) {
+        (Some(session), Some(input)) => (session, input),
+        _ => { return message("No session, or input that isn't UTF-8"); }
+    };
+
+    panic::catch_unwind(panic::AssertUnwindSafe(|| evaluate(session, input, result))).unwrap_or_else(|_| message("The calculator failed on this input"))
+}
+
+unsafe fn evaluate(session : &mut Session, input : &str, result : *mut f64) -> *mut c_char {
+    let value = match crate::execute(input, Dialect::Standard, &mut session.ctx) {
+        Ok(Outcome::Value(value) | Outcome::Assigned(_, value)) => value,
+        Ok(Outcome::Defined(_) | Outcome::Expression(_)) => { return ptr::null_mut(); }
+        Err(e) => { return message(&format!("column {}: {}", e.column(input), e)); }
+    };
+    let number = number(&value);
+    session.ctx.results.push(value);
+    match number {
+        Ok(number) => {
+            if let Some(result) = result.as_mut() {
+                *result = number;
+            }
+            ptr::null_mut()
+        }
+        Err(e) => message(&e)
+    }
+}
+
+// Sets the variable `name` to `value`; 0 if it could be, and -1 if there's
+// no session or the name can't be assigned: one that isn't a single
+// identifier, or is that of a built-in or registered constant or function.
+/// # Safety
+/// `session` came from calculator_new and `name` is a nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn calculator_set_variable(session : *mut Session, name : *const c_char, value : f64) -> c_int {
+    match (session.as_mut(), text(name)) {
+        (Some(session), Some(name)) if registrable(name).is_ok() && !session.ctx.constants.contains_key(name) => {
+            session.ctx.variables.insert(name.to_string(), Value::number(value));
+            0
+        }
+        _ => -1
+    }
+}
+
+// Stores the value of the variable `name` in `value`; 0 if it is defined
+// and a plain number, and -1 otherwise.
+/// # Safety
+/// `session` came from calculator_new, `name` is a nul-terminated string
+/// and `value` points to a double or is null.
+#[no_mangle]
+pub unsafe extern "C" fn calculator_get_variable(session : *const Session, name : *const c_char, value : *mut f64) -> c_int {
+    let found = match (session.as_ref(), text(name)) {
+        (Some(session), Some(name)) => session.ctx.variables.get(name).and_then(|found| number(found).ok()),
+        _ => None
+    };
+    match (found, value.as_mut()) {
+        (Some(found), Some(value)) => {
+            *value = found;
+            0
+        }
+        (Some(_), None) => 0,
+        (None, _) => -1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::c_int;
+    use std::ffi::CStr;
+    use std::ffi::CString;
+    use std::ptr;
+
+    use super::calculator_evaluate;
+    use super::calculator_free;
+    use super::calculator_free_string;
+    use super::calculator_get_variable;
+    use super::calculator_new;
+    use super::calculator_set_variable;
+    use super::Session;
+
+    fn evaluate_text(session : *mut Session, input : &str, result : &mut f64) -> Option<String> {
+        let input = CString::new(input).unwrap();
+        unsafe {
+            let error = calculator_evaluate(session, input.as_ptr(), result);
+            if error.is_null() {
+                return None;
+            }
+            let text = CStr::from_ptr(error).to_string_lossy().into_owned();
+            calculator_free_string(error);
+            Some(text)
+        }
+    }
+
+    fn set(session : *mut Session, name : &str, value : f64) -> c_int {
+        let name = CString::new(name).unwrap();
+        unsafe { calculator_set_variable(session, name.as_ptr(), value) }
+    }
+
+    #[test]
+    fn evaluation() {
+        let session = calculator_new();
+        let mut result = 0.0;
+        assert_eq!(evaluate_text(session, "x = 2 * 3", &mut result), None);
+        assert_eq!(result, 6.0);
+        assert_eq!(evaluate_text(session, "x + 1", &mut result), None);
+        assert_eq!(result, 7.0);
+        assert_eq!(evaluate_text(session, "1 +", &mut result).as_deref().map(|e| e.starts_with("column ")), Some(true));
+        assert_eq!(evaluate_text(session, "[1, 2]", &mut result).as_deref(), Some("The result is a list"));
+        assert_eq!(result, 7.0);
+        unsafe {
+            assert!(!calculator_evaluate(ptr::null_mut(), c"1".as_ptr(), &mut result).is_null());
+            calculator_free(session);
+        }
+    }
+
+    #[test]
+    fn panics_become_errors() {
+        let session = calculator_new();
+        unsafe { (*session).ctx.register_fn("boom", 0, |_| panic!("boom")).unwrap(); }
+        let mut result = 0.0;
+        assert_eq!(evaluate_text(session, "boom()", &mut result).as_deref(), Some("The calculator failed on this input"));
+        assert_eq!(evaluate_text(session, "1 + 1", &mut result), None);
+        assert_eq!(result, 2.0);
+        unsafe { calculator_free(session); }
+    }
+
+    #[test]
+    fn variables() {
+        let session = calculator_new();
+        unsafe { (*session).ctx.register_constant("c0", 1.5).unwrap(); }
+        assert_eq!(set(session, "rate", 0.25), 0);
+        assert_eq!(set(session, "pi", 3.0), -1);
+        assert_eq!(set(session, "c0", 3.0), -1);
+        assert_eq!(set(session, "sin", 3.0), -1);
+        assert_eq!(set(session, "a b", 3.0), -1);
+        assert_eq!(set(session, "", 3.0), -1);
+        let mut value = 0.0;
+        unsafe {
+            assert_eq!(calculator_get_variable(session, c"rate".as_ptr(), &mut value), 0);
+            assert_eq!(value, 0.25);
+            assert_eq!(calculator_get_variable(session, c"c0".as_ptr(), &mut value), -1);
+            calculator_free(session);
+        }
+    }
+}
@@ -0,0 +1,169 @@
+use std::io;
+use std::io::BufRead;
+
+use calculator::ast::Expr;
+use calculator::eval;
+use calculator::format;
+use calculator::lexer;
+use calculator::parser;
+use calculator::units;
+use calculator::CalcError;
+use calculator::Context;
+use calculator::Decimal;
+use calculator::Dialect;
+use calculator::Number;
+use calculator::Rational;
+use calculator::Token;
+use calculator::Value;
+
+use crate::cli::Numbers;
+use crate::cli::Options;
+use crate::messages;
+use crate::messages::Msg;
+
+/*
+The expression of --fields evaluated for each line of stdin, as a small awk
+program would be: the line is split on whitespace, or on the --delimiter
+given, and the fields are bound as f1, f2, ... for the expression, whose
+value is printed on a line of its own.
+
+Blank lines are skipped. A field that isn't a number is left unbound, and is
+only an error on a line whose expression uses it. An error on one line is
+reported and the rest are still evaluated, but make the exit code 1.
+*/
+
+// A field as a number: a literal of the dialect, with a sign if any.
+pub fn number<N: Number>(text : &str, dialect : Dialect, precision : usize) -> Option<N> {
+    let mut tokens = lexer::tokens(text.trim(), dialect);
+    let (negative, token) = match tokens.next()? {
+        Token::Sub | Token::Negate => (true, tokens.next()?),
+        Token::Add => (false, tokens.next()?),
+        token => (false, token)
+    };
+    match (token, tokens.next()) {
+        (Token::Number(value), None) => Some(N::from_rational(&if negative { value.neg() } else { value }, precision)),
+        _ => None
+    }
+}
+
+// Returns the process exit code.
+pub fn run(options : &Options, expr : &str) -> i32 {
+    match options.numbers {
+        Numbers::Float => run_in::<f64>(options, expr),
+        Numbers::Decimal => run_in::<Decimal>(options, expr),
+        Numbers::Rational => run_in::<Rational>(options, expr)
+    }
+}
+
+/*
+An expression given with an option, parsed once and evaluated for many
+lines with their values bound, as by --fields and --csv. It may end in a
+`->` notation, and before that an `in` unit.
+*/
+pub struct Formula<'a> {
+    expr : Expr,
+    unit : Option<(usize, &'a str)>,
+    notation : format::Notation
+}
+
+impl<'a> Formula<'a> {
+    // The message for an error, at its column of `line`, if it doesn't parse.
+    pub fn parse(line : &'a str, option : &str, options : &Options) -> Result<Formula<'a>, String> {
+        let dialect = options.dialect;
+        let at = |column : usize, e : &str| Msg::AtOption(option, column, e).to_string();
+        let (input, notation) = match format::split_conversion(line) {
+            Ok((input, conversion)) => (input, conversion.unwrap_or(options.numbers.notation())),
+            Err(name) => {
+                let column = line.rfind("->").map_or(0, |i| line[..i].chars().count()) + 1;
+                return Err(at(column, &Msg::UnknownDisplay(name).to_string()));
+            }
+        };
+        let (input, unit) = units::split_conversion(input, dialect);
+        match parser::parse(calculator::tokenize(input, dialect)) {
+            Ok(expr) => Ok(Formula { expr, unit, notation }),
+            Err(e) => Err(at(e.column(input), &messages::error(&e).to_string()))
+        }
+    }
+
+    // The value as shown, with the unit converted to if any.
+    pub fn evaluate<N: Number>(&self, ctx : &Context<N>, options : &Options) -> Result<String, CalcError> {
+        let value = eval::evaluate(&self.expr, ctx)?;
+        match self.unit {
+            Some((offset, unit)) => {
+                let value = calculator::convert(&value, unit, offset, options.dialect, ctx)?;
+                Ok(format!("{} {}", format::format_value(&value, self.notation, options.style), unit))
+            }
+            None => Ok(format::format_value(&value, self.notation, options.style))
+        }
+    }
+}
+
+// The message for an error evaluating line `n`. An undefined variable that
+// is one of `names` is a value there that isn't a number, `values`.
+pub fn failure(n : usize, e : &CalcError, names : &[String], values : &[&str]) -> String {
+    let field = match e {
+        CalcError::UndefinedVariable(name, _) => names.iter().position(|known| known == name).filter(|i| *i < values.len()),
+        _ => None
+    };
+    match field {
+        Some(i) => Msg::AtRecord(n, &Msg::NotANumber(&names[i], values[i]).to_string()).to_string(),
+        None => Msg::AtRecord(n, &messages::error(e).to_string()).to_string()
+    }
+}
+
+fn run_in<N: Number>(options : &Options, line : &str) -> i32 {
+    let dialect = options.dialect;
+    let formula = match Formula::parse(line, "--fields", options) {
+        Ok(formula) => formula,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 2;
+        }
+    };
+
+    let mut ctx = Context::<N> { angle : options.angle, ..Context::default() };
+    let mut failed = false;
+    let mut stdin = io::stdin().lock();
+    let mut text = String::new();
+    let mut n = 0;
+
+    loop {
+        text.clear();
+        if stdin.read_line(&mut text).expect("Something wrong") == 0 {
+            break;
+        }
+
+        n += 1;
+        if text.trim().is_empty() {
+            continue;
+        }
+
+        let record = text.trim_end_matches(['\n', '\r']);
+        let fields = match &options.delimiter {
+            Some(delimiter) => record.split(delimiter.as_str()).collect::<Vec<_>>(),
+            None => record.split_whitespace().collect()
+        };
+        let names = (1..=fields.len()).map(|i| format!("f{}", i)).collect::<Vec<_>>();
+        ctx.variables.retain(|name, _| !is_field(name));
+        for (name, field) in names.iter().zip(&fields) {
+            if let Some(value) = number::<N>(field, dialect, ctx.precision) {
+                ctx.variables.insert(name.clone(), Value::number(value));
+            }
+        }
+
+        match formula.evaluate(&ctx, options) {
+            Ok(shown) => println!("{}", shown),
+            Err(e) => {
+                eprintln!("{}", failure(n, &e, &names, &fields));
+                failed = true;
+            }
+        }
+    }
+
+    if failed { 1 } else { 0 }
+}
+
+// f1, f2 and so on.
+fn is_field(name : &str) -> bool {
+    name.len() > 1 && name.starts_with('f') && name[1..].chars().all(|c| c.is_ascii_digit())
+}
@@ -0,0 +1,612 @@
+use crate::date;
+use crate::lexer;
+use crate::lexer::Token;
+use crate::natural::Natural;
+use crate::number::Number;
+use crate::units;
+use crate::units::Dimension;
+use crate::units::Quantity;
+use crate::value::Value;
+use crate::Dialect;
+use crate::DEFAULT_PRECISION;
+use crate::PRECISION_LIMIT;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Notation {
+    Decimal,
+    // 7/3, or 2 1/3 as a mixed number
+    Fraction,
+    Mixed,
+    // 0xff, 0o17 and 0b1010, and 0x1.8 for a non-integer
+    Hex,
+    Octal,
+    Binary,
+    FeetInches,
+    Degrees,
+    // hours as 03:45, and durations as hours
+    Clock,
+    // the same as 3:45, for a duration rather than a time of day
+    HoursMinutes,
+    // as decimal, but written as LaTeX, with a fraction that has no decimal
+    // form as \frac{1}{3}
+    Latex
+}
+
+impl Notation {
+    pub fn from_name(name : &str) -> Option<Notation> {
+        match name {
+            "decimal" => Some(Notation::Decimal),
+            "fraction" => Some(Notation::Fraction),
+            "mixed" => Some(Notation::Mixed),
+            "hex" => Some(Notation::Hex),
+            "oct" => Some(Notation::Octal),
+            "bin" => Some(Notation::Binary),
+            "ftin" => Some(Notation::FeetInches),
+            "dms" => Some(Notation::Degrees),
+            "hms" => Some(Notation::Clock),
+            "hm" => Some(Notation::HoursMinutes),
+            "latex" => Some(Notation::Latex),
+            _ => None
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Notation::Decimal => "decimal",
+            Notation::Fraction => "fraction",
+            Notation::Mixed => "mixed",
+            Notation::Hex => "hex",
+            Notation::Octal => "oct",
+            Notation::Binary => "bin",
+            Notation::FeetInches => "ftin",
+            Notation::Degrees => "dms",
+            Notation::Clock => "hms",
+            Notation::HoursMinutes => "hm",
+            Notation::Latex => "latex"
+        }
+    }
+}
+
+// How far a result in decimal notation is rounded, and how it is written.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Rounding {
+    // as computed
+    None,
+    // to digits after the point: 3.1416
+    Fixed(usize),
+    // to significant digits: 3.142, 1234000
+    Significant(usize),
+    // to significant digits with an exponent: 1.234e6
+    Scientific(usize),
+    // the same with the exponent a multiple of three: 1.234e6, 12.34e-3
+    Engineering(usize)
+}
+
+#[derive(Clone, Copy)]
+pub struct Style {
+    pub rounding : Rounding,
+    // drop zeros at the end of a rounded result, so 2.5 stays 2.5 at fixed 4
+    pub trim : bool,
+    // group the digits before the point in thousands
+    pub separators : bool,
+    // write 1.234,5 rather than 1,234.5
+    pub decimal_comma : bool
+}
+
+impl Default for Style {
+    fn default() -> Style {
+        Style { rounding : Rounding::None, trim : false, separators : false, decimal_comma : false }
+    }
+}
+
+impl Rounding {
+    // `plain`, or one of `fixed`, `sig`, `sci` and `eng` with the number of
+    // digits.
+    pub fn from_words(name : &str, digits : Option<&str>) -> Option<Rounding> {
+        let digits = match digits {
+            Some(digits) => Some(digits.parse::<usize>().ok().filter(|n| *n <= PRECISION_LIMIT)?),
+            None => None
+        };
+
+        match (name, digits) {
+            ("plain", None) => Some(Rounding::None),
+            ("fixed", Some(n)) => Some(Rounding::Fixed(n)),
+            ("sig", Some(n)) if n > 0 => Some(Rounding::Significant(n)),
+            ("sci", Some(n)) if n > 0 => Some(Rounding::Scientific(n)),
+            ("eng", Some(n)) if n > 0 => Some(Rounding::Engineering(n)),
+            _ => None
+        }
+    }
+
+    // The words from_words takes for it.
+    pub fn words(self) -> String {
+        match self {
+            Rounding::None => "plain".to_string(),
+            Rounding::Fixed(n) => format!("fixed {}", n),
+            Rounding::Significant(n) => format!("sig {}", n),
+            Rounding::Scientific(n) => format!("sci {}", n),
+            Rounding::Engineering(n) => format!("eng {}", n)
+        }
+    }
+}
+
+// A trailing conversion such as `255 -> hex` shows one result in another
+// notation. The input without it, and the notation if there was one; the
+// name after `->` if it is neither a notation nor a unit. One to a unit,
+// as in 5'10" -> cm, is left for units::split_conversion.
+pub fn split_conversion(input : &str) -> Result<(&str, Option<Notation>), &str> {
+    match input.rsplit_once("->") {
+        Some((expr, name)) => match Notation::from_name(name.trim()) {
+            Some(notation) => Ok((expr, Some(notation))),
+            None if units::is_unit(name) => Ok((input, None)),
+            None => Err(name.trim())
+        },
+        None => Ok((input, None))
+    }
+}
+
+// How a result of `input` that no `->` asks for a notation of is shown: a
+// duration worked out from times written as 1:45 stays in hours and
+// minutes, and anything else is in `notation`.
+pub fn keeping_clock<N: Number>(input : &str, dialect : Dialect, value : &Value<N>, notation : Notation) -> Notation {
+    let duration = matches!(value, Value::Scalar(quantity) if quantity.dimension == Dimension::TIME);
+    if duration && lexer::tokens(input, dialect).any(|token| matches!(token, Token::Clock(..))) {
+        return Notation::HoursMinutes;
+    }
+    notation
+}
+
+fn round_to(value : f64, places : i32) -> f64 {
+    let scale = 10f64.powi(places);
+    (value * scale).round() / scale
+}
+
+// A length in inches as 6'1.5"
+fn feet_inches(inches : f64) -> String {
+    let sign = if inches < 0.0 { "-" } else { "" };
+    let mut feet = (inches.abs() / 12.0).floor();
+    let mut rest = round_to(inches.abs() - feet * 12.0, 4);
+    if rest >= 12.0 {
+        feet += 1.0;
+        rest -= 12.0;
+    }
+
+    if feet == 0.0 {
+        format!("{}{}\"", sign, rest)
+    }
+    else {
+        format!("{}{}'{}\"", sign, feet, rest)
+    }
+}
+
+// An angle in decimal degrees as 45°30'15"
+fn degrees(angle : f64) -> String {
+    let sign = if angle < 0.0 { "-" } else { "" };
+    let total_seconds = round_to(angle.abs() * 3600.0, 2);
+    let degrees = (total_seconds / 3600.0).floor();
+    let minutes = ((total_seconds - degrees * 3600.0) / 60.0).floor();
+    let seconds = round_to(total_seconds - degrees * 3600.0 - minutes * 60.0, 2);
+
+    format!("{}{}°{}'{}\"", sign, degrees, minutes, seconds)
+}
+
+// Hours as 03:45, or 03:45:30 when there are seconds left over
+// Hours on a clock as 03:45, or as 3:45 if not `padded`.
+fn clock(hours : f64, padded : bool) -> String {
+    let sign = if hours < 0.0 { "-" } else { "" };
+    let total_seconds = round_to(hours.abs() * 3600.0, 2);
+    let hours = (total_seconds / 3600.0).floor();
+    let minutes = ((total_seconds - hours * 3600.0) / 60.0).floor();
+    let seconds = round_to(total_seconds - hours * 3600.0 - minutes * 60.0, 2);
+    let width = if padded { 2 } else { 1 };
+
+    if seconds == 0.0 {
+        format!("{}{:0width$}:{:02}", sign, hours, minutes)
+    }
+    else {
+        format!("{}{:0width$}:{:02}:{:02}", sign, hours, minutes, seconds)
+    }
+}
+
+// numerator / denominator * 10^shift, rounded half away from zero
+fn scaled(numerator : &Natural, denominator : &Natural, shift : i64) -> Natural {
+    let (numerator, denominator) = if shift >= 0 {
+        (numerator.shifted(shift as usize), denominator.clone())
+    }
+    else {
+        (numerator.clone(), denominator.shifted(shift.unsigned_abs() as usize))
+    };
+
+    let (quotient, rem) = numerator.div_rem(&denominator);
+    if rem.times_small(2) >= denominator { quotient.sum(&Natural::small(1)) } else { quotient }
+}
+
+// The power of ten of the leading digit, e.g. 2 for 123.4 and -2 for 0.05.
+// For a non-zero value.
+fn magnitude(numerator : &Natural, denominator : &Natural) -> i64 {
+    let mut exponent = numerator.digits().len() as i64 - denominator.digits().len() as i64;
+    let below = if exponent >= 0 {
+        *numerator < denominator.shifted(exponent as usize)
+    }
+    else {
+        numerator.shifted(exponent.unsigned_abs() as usize) < *denominator
+    };
+    if below {
+        exponent -= 1;
+    }
+    exponent
+}
+
+// `digits` divided by 10^places, written out: 12345 and 2 give 123.45,
+// 5 and 3 give 0.005, 12 and -2 give 1200.
+fn with_point(digits : String, places : i64, trim : bool) -> String {
+    if places <= 0 {
+        return digits + &"0".repeat(places.unsigned_abs() as usize);
+    }
+
+    let places = places as usize;
+    let mut s = if digits.len() <= places { "0".repeat(places + 1 - digits.len()) + &digits } else { digits };
+    s.insert(s.len() - places, '.');
+    if trim {
+        s.truncate(s.trim_end_matches('0').trim_end_matches('.').len());
+    }
+    s
+}
+
+// A finite value rounded as `style` asks, worked out exactly.
+fn rounded<N: Number>(value : &N, style : Style) -> String {
+    let rational = value.to_literal();
+    let (negative, numerator, denominator) = match (style.rounding, rational.parts()) {
+        (Rounding::None, _) | (_, None) => { return value.to_decimal_string(); }
+        (_, Some(parts)) => parts
+    };
+
+    // the digits kept, how many of them come after the point, and the
+    // exponent written after them
+    let (digits, places, exponent) = match style.rounding {
+        Rounding::None => unreachable!("returned above"),
+        Rounding::Fixed(places) => (scaled(numerator, denominator, places as i64), places as i64, None),
+        Rounding::Significant(n) | Rounding::Scientific(n) | Rounding::Engineering(n) => {
+            let n = n as i64;
+            let mut lead = if numerator.is_zero() { 0 } else { magnitude(numerator, denominator) };
+            let mut digits = scaled(numerator, denominator, n - 1 - lead);
+            // rounding up carried into another digit, as in 9.99 to 10.0
+            if digits.digits().len() as i64 > n {
+                lead += 1;
+                digits = scaled(numerator, denominator, n - 1 - lead);
+            }
+
+            match style.rounding {
+                Rounding::Significant(_) => (digits, n - 1 - lead, None),
+                Rounding::Scientific(_) => (digits, n - 1, Some(lead)),
+                _ => {
+                    let exponent = lead - lead.rem_euclid(3);
+                    (digits, n - 1 - (lead - exponent), Some(exponent))
+                }
+            }
+        }
+    };
+
+    let sign = if negative && !digits.is_zero() { "-" } else { "" };
+    let mantissa = with_point(digits.digits(), places, style.trim);
+    match exponent {
+        Some(exponent) => format!("{}{}e{}", sign, mantissa, exponent),
+        None => format!("{}{}", sign, mantissa)
+    }
+}
+
+// The decimal point and thousands separators of `style` in a decimal such
+// as -1234.5 or 1.2345e3.
+fn localized(s : &str, style : Style) -> String {
+    let (point, separator) = if style.decimal_comma { (',', '.') } else { ('.', ',') };
+    let (sign, rest) = match s.strip_prefix('-') {
+        Some(rest) => ("-", rest),
+        None => ("", s)
+    };
+    let (integer, tail) = rest.split_at(rest.find(|c : char| !c.is_ascii_digit()).unwrap_or(rest.len()));
+
+    let mut out = sign.to_string();
+    for (i, c) in integer.chars().enumerate() {
+        if style.separators && i > 0 && (integer.len() - i) % 3 == 0 {
+            out.push(separator);
+        }
+        out.push(c);
+    }
+    out.push_str(&tail.replacen('.', &point.to_string(), 1));
+    out
+}
+
+// A number with the prefix of its base: 0xff, or 0x1.8 for 1.5. Integers
+// are exact however large. The three bases are powers of two, so a fraction
+// whose denominator is one is written out exactly too; any other, such as
+// 1/3, is rounded to about as many digits as a float has bits.
+fn radix<N: Number>(value : &N, radix : u32, prefix : &str) -> String {
+    let rational = value.to_rational();
+    let (negative, numerator, denominator) = match rational.parts() {
+        Some(parts) => parts,
+        None => { return value.to_decimal_string(); }
+    };
+
+    // bits per digit, and the power of two in the denominator
+    let bits = radix.trailing_zeros() as usize;
+    let mut twos = 0usize;
+    let mut odd = denominator.clone();
+    loop {
+        let (half, rem) = odd.div_rem_small(2);
+        if rem != 0 {
+            break;
+        }
+        odd = half;
+        twos += 1;
+    }
+
+    let places = if odd == Natural::small(1) {
+        twos.div_ceil(bits)
+    }
+    else {
+        let significant = 53usize.div_ceil(bits);
+        let whole = numerator.div_rem(denominator).0;
+        if whole.is_zero() {
+            let mut zeros = 0;
+            let mut scaled = numerator.times_small(radix);
+            while scaled < *denominator {
+                scaled = scaled.times_small(radix);
+                zeros += 1;
+            }
+            zeros + significant
+        }
+        else {
+            significant.saturating_sub(whole.to_radix(radix).len())
+        }
+    };
+
+    let (digits, rem) = numerator.product(&Natural::small(radix).power(places as u64)).div_rem(denominator);
+    let digits = if rem.times_small(2) >= *denominator { digits.sum(&Natural::small(1)) } else { digits };
+    format!("{}{}{}", if negative { "-" } else { "" }, prefix, with_point(digits.to_radix(radix), places as i64, true))
+}
+
+// A number as LaTeX: 1.5, 1.2 \times 10^{6} for 1.2e6, or \frac{1}{3} if
+// it has no decimal form however it is rounded.
+fn latex<N: Number>(value : &N, style : Style) -> String {
+    let float = value.to_f64();
+    if float.is_nan() {
+        return "\\mathrm{NaN}".to_string();
+    }
+    if float.is_infinite() {
+        return if float < 0.0 { "-\\infty" } else { "\\infty" }.to_string();
+    }
+
+    let rational = value.to_literal();
+    if let Some((negative, numerator, denominator)) = rational.parts() {
+        if rational.to_decimal().is_none() {
+            return format!("{}\\frac{{{}}}{{{}}}", if negative { "-" } else { "" }, numerator.digits(), denominator.digits());
+        }
+    }
+    // a comma, as separator or point, is punctuation to LaTeX unless braced
+    let decimal = localized(&rounded(value, style), style).replace(',', "{,}");
+    match decimal.split_once('e') {
+        Some((mantissa, exponent)) => format!("{} \\times 10^{{{}}}", mantissa, exponent),
+        None => decimal
+    }
+}
+
+// A unit as LaTeX, such as \mathrm{m^{-1} \cdot s^{-2}} for m^-1*s^-2.
+fn latex_unit(unit : &str) -> String {
+    let mut out = String::new();
+    let mut chars = unit.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '^' => {
+                let mut exponent = String::new();
+                while let Some(&c) = chars.peek().filter(|c| c.is_ascii_digit() || (**c == '-' && exponent.is_empty())) {
+                    exponent.push(c);
+                    chars.next();
+                }
+                out.push_str(&format!("^{{{}}}", exponent));
+            }
+            '*' => out.push_str(" \\cdot "),
+            c => out.push(c)
+        }
+    }
+    format!("\\mathrm{{{}}}", out)
+}
+
+// Feet and inches or degrees are worked out in f64, whatever the number type.
+// Fractions and rounded decimals come from the value a float stands for,
+// e.g. 0.1 is 1/10, and other bases from its exact binary value.
+// `style` only applies to decimal notation.
+pub fn format<N: Number>(value : &N, notation : Notation, style : Style) -> String {
+    let float = value.to_f64();
+    if notation == Notation::Latex {
+        return latex(value, style);
+    }
+    if !float.is_finite() {
+        return value.to_string();
+    }
+
+    match notation {
+        Notation::Decimal => localized(&rounded(value, style), style),
+        Notation::Fraction => value.to_literal().to_string(),
+        Notation::Mixed => value.to_literal().mixed(),
+        Notation::Hex => radix(value, 16, "0x"),
+        Notation::Octal => radix(value, 8, "0o"),
+        Notation::Binary => radix(value, 2, "0b"),
+        Notation::FeetInches => feet_inches(float),
+        Notation::Degrees => degrees(float),
+        Notation::Clock => clock(float, true),
+        Notation::HoursMinutes => clock(float, false),
+        Notation::Latex => unreachable!("written above")
+    }
+}
+
+// The value followed by its dimension in base units, e.g. 5300 m, or a
+// duration in a unit that suits it, e.g. 3.75 h. A date is shown as one
+// whatever the notation, and a duration on a clock, a length in feet and
+// inches and an angle in degrees take no unit; any other quantity is in
+// decimal in those notations. In LaTeX the unit is upright, and a date is
+// text.
+pub fn format_quantity<N: Number>(quantity : &Quantity<N>, notation : Notation, style : Style) -> String {
+    if let Some(seconds) = quantity.date() {
+        if notation == Notation::Latex {
+            return format!("\\text{{{}}}", date::format(seconds));
+        }
+        return date::format(seconds);
+    }
+    if matches!(notation, Notation::Clock | Notation::HoursMinutes) && quantity.dimension == Dimension::TIME {
+        return format(&N::from_f64(quantity.value.to_f64() / 3600.0), notation, style);
+    }
+    if notation == Notation::FeetInches && quantity.dimension == Dimension::LENGTH {
+        return format(&N::from_f64(quantity.value.to_f64() / 0.0254), notation, style);
+    }
+    if notation == Notation::Degrees && quantity.dimension == Dimension::ANGLE {
+        return format(&N::from_f64(quantity.value.to_f64().to_degrees()), notation, style);
+    }
+
+    // a quantity of another kind, as 2 m -> dms, in decimals and its unit
+    let measured = matches!(notation, Notation::FeetInches | Notation::Degrees | Notation::Clock | Notation::HoursMinutes);
+    let notation = if measured && !quantity.dimension.is_none() { Notation::Decimal } else { notation };
+
+    let (value, unit) = match duration(quantity) {
+        Some((value, unit)) => (value, unit.to_string()),
+        None => (quantity.value.clone(), quantity.dimension.to_string())
+    };
+    let number = format(&value, notation, style);
+    if quantity.dimension.is_none() {
+        number
+    }
+    else if notation == Notation::Latex {
+        format!("{}\\,{}", number, latex_unit(&unit))
+    }
+    else {
+        format!("{} {}", number, unit)
+    }
+}
+
+// A duration in the largest of these units it makes at least one of, so
+// 13500 s is 3.75 h; less than a minute stays in seconds.
+const DURATIONS : &[(&str, i128)] = &[("day", 86_400), ("h", 3600), ("min", 60)];
+
+fn duration<N: Number>(quantity : &Quantity<N>) -> Option<(N, &'static str)> {
+    if quantity.dimension != Dimension::TIME {
+        return None;
+    }
+    let seconds = quantity.value.to_f64().abs();
+    let &(unit, scale) = DURATIONS.iter().find(|(_, scale)| seconds >= *scale as f64)?;
+    Some((quantity.value.div(&N::from_integer(scale), DEFAULT_PRECISION)?, unit))
+}
+
+// A list as [a, b], or [a; b] when the decimal separator is a comma, and
+// in LaTeX as \left[a, b\right].
+pub fn format_value<N: Number>(value : &Value<N>, notation : Notation, style : Style) -> String {
+    match value {
+        Value::Scalar(quantity) => format_quantity(quantity, notation, style),
+        Value::List(items) => {
+            let separator = if style.decimal_comma { "; " } else { ", " };
+            let items = items.iter().map(|item| format_value(item, notation, style)).collect::<Vec<_>>();
+            if notation == Notation::Latex {
+                format!("\\left[{}\\right]", items.join(separator))
+            }
+            else {
+                format!("[{}]", items.join(separator))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::number::Number;
+    use crate::rational::Rational;
+    use crate::units::Dimension;
+    use crate::units::Quantity;
+    use crate::Dialect;
+    use crate::Value;
+
+    use super::format;
+    use super::format_quantity;
+    use super::keeping_clock;
+    use super::split_conversion;
+    use super::Notation;
+    use super::Rounding;
+    use super::Style;
+
+    fn rounded(value : f64, rounding : Rounding) -> String {
+        format(&value, Notation::Decimal, Style { rounding, ..Style::default() })
+    }
+
+    #[test]
+    fn rounding() {
+        assert_eq!(rounded(1234567.891, Rounding::Fixed(2)), "1234567.89");
+        assert_eq!(rounded(2.5, Rounding::Fixed(0)), "3");
+        assert_eq!(rounded(-2.5, Rounding::Fixed(0)), "-3");
+        assert_eq!(rounded(1234567.891, Rounding::Significant(3)), "1230000");
+        assert_eq!(rounded(0.000123456, Rounding::Significant(3)), "0.000123");
+        assert_eq!(rounded(1234567.891, Rounding::Scientific(4)), "1.235e6");
+        assert_eq!(rounded(0.000123456, Rounding::Engineering(3)), "123e-6");
+        assert_eq!(rounded(0.1 + 0.2, Rounding::None), "0.30000000000000004");
+        // trailing zeros are kept unless trimmed
+        assert_eq!(rounded(2.5, Rounding::Fixed(3)), "2.500");
+        assert_eq!(format(&2.5, Notation::Decimal, Style { rounding : Rounding::Fixed(3), trim : true, ..Style::default() }), "2.5");
+        let grouped = Style { separators : true, decimal_comma : true, ..Style::default() };
+        assert_eq!(format(&-1234567.5, Notation::Decimal, grouped), "-1.234.567,5");
+        assert!(Rounding::from_words("sig", Some("0")).is_none());
+        assert!(Rounding::from_words("eng", Some("3")) == Some(Rounding::Engineering(3)));
+    }
+
+    #[test]
+    fn radixes() {
+        let style = Style::default();
+        assert_eq!(format(&255.0, Notation::Hex, style), "0xff");
+        assert_eq!(format(&-10.0, Notation::Binary, style), "-0b1010");
+        assert_eq!(format(&12.375, Notation::Octal, style), "0o14.3");
+        // a float in hex has as many digits as it has bits, so it reads back
+        // as the same float
+        assert_eq!(format(&0.1, Notation::Hex, style), "0x0.1999999999999a");
+        assert_eq!(format(&Rational::ratio(1, 3), Notation::Hex, style), "0x0.55555555555555");
+        assert_eq!(format(&f64::INFINITY, Notation::Hex, style), "inf");
+    }
+
+    #[test]
+    fn fractions() {
+        let style = Style::default();
+        assert_eq!(format(&Rational::ratio(7, 3), Notation::Fraction, style), "7/3");
+        assert_eq!(format(&Rational::ratio(7, 3).neg(), Notation::Mixed, style), "-2 1/3");
+        assert_eq!(format(&0.75, Notation::Mixed, style), "3/4");
+        assert_eq!(format(&Rational::ratio(1, 3), Notation::Latex, style), "\\frac{1}{3}");
+    }
+
+    #[test]
+    fn measurements() {
+        let style = Style::default();
+        let length = |metres : f64| format_quantity(&Quantity { value : metres, dimension : Dimension::LENGTH }, Notation::FeetInches, style);
+        assert_eq!(length(71.0 * 0.0254), "5'11\"");
+        assert_eq!(length(11.99999 * 0.0254), "1'0\"");
+        assert_eq!(length(-3.0 * 0.0254), "-3\"");
+        let angle = |degrees : f64| format_quantity(&Quantity { value : degrees.to_radians(), dimension : Dimension::ANGLE }, Notation::Degrees, style);
+        assert_eq!(angle(-45.5), "-45°30'0\"");
+        assert_eq!(angle(10.2625), "10°15'45\"");
+        // another quantity keeps its unit
+        assert_eq!(format_quantity(&Quantity { value : 2.0, dimension : Dimension::LENGTH }, Notation::Degrees, style), "2 m");
+    }
+
+    #[test]
+    fn clocks() {
+        let style = Style::default();
+        let time = |seconds : f64, notation| format_quantity(&Quantity { value : seconds, dimension : Dimension::TIME }, notation, style);
+        assert_eq!(time(13500.0, Notation::Clock), "03:45");
+        assert_eq!(time(13500.0, Notation::HoursMinutes), "3:45");
+        assert_eq!(time(-30.5, Notation::HoursMinutes), "-0:00:30.5");
+        assert_eq!(time(13500.0, Notation::Decimal), "3.75 h");
+        let duration = Value::Scalar(Quantity { value : 8100.0, dimension : Dimension::TIME });
+        assert!(keeping_clock("1:45 + 0:30", Dialect::Standard, &duration, Notation::Decimal) == Notation::HoursMinutes);
+        assert!(keeping_clock("135 min", Dialect::Standard, &duration, Notation::Decimal) == Notation::Decimal);
+        assert!(keeping_clock("1:45 / 0:15", Dialect::Standard, &Value::number(7.0), Notation::Decimal) == Notation::Decimal);
+    }
+
+    #[test]
+    fn conversions() {
+        assert!(matches!(split_conversion("255 -> hex"), Ok(("255 ", Some(Notation::Hex)))));
+        assert!(matches!(split_conversion("5'10\" -> cm"), Ok(("5'10\" -> cm", None))));
+        assert!(matches!(split_conversion("1 -> nothing"), Err("nothing")));
+    }
+}
@@ -0,0 +1,459 @@
+use crate::date;
+use crate::natural::Natural;
+use crate::units::Dimension;
+
+/*
+The built-in functions, looked up by name when a call is evaluated. The
+arguments are checked against `min_args..=max_args` before `apply` sees
+them, so `apply` can index into the slice freely.
+
+The constants are names that can't be assigned to, looked up after the
+session's variables.
+
+The integer functions are computed exactly whatever the number type, and
+only take non-negative integers. `3!` is a call of factorial.
+
+Given a list, a function is applied to each element of it. The list
+functions and the variadic gcd and lcm instead take the numbers in lists as
+arguments of their own, so min(3, [1, 2]) is 1.
+
+The random functions draw from the context's generator, so each call has a
+value of its own.
+
+The functions of f64 are computed by the platform's libm, so the last bit of
+sin, exp, powf and the like may differ between platforms. The arithmetic
+operators, sqrt and the integer functions give the same result on every
+platform, and so does decimal or rational arithmetic.
+*/
+
+// Whether a function takes or returns an angle, which is converted to and
+// from radians when the session works in degrees.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Angles {
+    None,
+    Argument,
+    Result
+}
+
+// What a function makes of quantities with units.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Units {
+    // only takes plain numbers
+    None,
+    // takes arguments all of one dimension, and returns that dimension
+    Same,
+    // takes arguments all of one dimension, and returns a plain number
+    Ratio,
+    // the n-th root of the argument's dimension, as for sqrt
+    Root(i8),
+    // takes nothing and returns a date
+    Date
+}
+
+impl Units {
+    // The dimension of the result for arguments of `args`, if they fit.
+    // None of these functions takes a date.
+    pub fn result(self, args : &[Dimension]) -> Option<Dimension> {
+        let first = args.first().copied().unwrap_or(Dimension::NONE);
+        let same = args.iter().all(|d| *d == first);
+        if first.is_date() {
+            return None;
+        }
+        match self {
+            Units::None => (same && first.is_none()).then_some(Dimension::NONE),
+            Units::Same => same.then_some(first),
+            Units::Ratio => same.then_some(Dimension::NONE),
+            Units::Root(n) => first.root(n),
+            Units::Date => args.is_empty().then_some(Dimension::DATE)
+        }
+    }
+}
+
+pub struct Function {
+    pub name : &'static str,
+    pub min_args : usize,
+    pub max_args : usize,
+    pub angles : Angles,
+    pub units : Units,
+    pub apply : fn(&[f64]) -> f64
+}
+
+pub struct Constant {
+    pub name : &'static str,
+    pub value : f64
+}
+
+pub const CONSTANTS : &[Constant] = &[
+    Constant { name : "pi", value : std::f64::consts::PI },
+    Constant { name : "e", value : std::f64::consts::E },
+    Constant { name : "tau", value : std::f64::consts::TAU },
+    // the golden ratio
+    Constant { name : "phi", value : 1.618033988749895 },
+    Constant { name : "inf", value : f64::INFINITY },
+];
+
+// The largest float has no next one, so its gap is taken to the one below;
+// infinity's is infinite.
+fn ulp(x : f64) -> f64 {
+    let x = x.abs();
+    if x.is_infinite() {
+        x
+    }
+    else if x == f64::MAX {
+        x - x.next_down()
+    }
+    else {
+        x.next_up() - x
+    }
+}
+
+fn next_after(x : f64, toward : f64) -> f64 {
+    if x < toward {
+        x.next_up()
+    }
+    else if x > toward {
+        x.next_down()
+    }
+    else {
+        x + toward
+    }
+}
+
+// `x` to 15 significant digits, as many as a spreadsheet shows, so what
+// is written 2.675 is taken to be that rather than the float just below it.
+fn significant(x : f64) -> f64 {
+    format!("{:.14e}", x).parse().unwrap_or(x)
+}
+
+// `x` rounded to `digits` decimal places, or to tens, hundreds and so on
+// when negative, halfway cases away from zero as written in decimal:
+// round(2.675, 2) is 2.68.
+fn round_to(x : f64, digits : f64) -> f64 {
+    let digits = digits.trunc().clamp(-400.0, 400.0) as i32;
+    let scale = 10f64.powi(digits.abs());
+    let rounded = if digits >= 0 { significant(significant(x) * scale).round() / scale } else { significant(significant(x) / scale).round() * scale };
+    if rounded.is_finite() { rounded } else { x }
+}
+
+fn pmt(rate : f64, periods : f64, present : f64, future : f64, at_start : bool) -> f64 {
+    if rate == 0.0 {
+        return -(present + future) / periods;
+    }
+    let growth = (1.0 + rate).powf(periods);
+    let start = if at_start { 1.0 + rate } else { 1.0 };
+    -rate * (present * growth + future) / (start * (growth - 1.0))
+}
+
+pub const VARIADIC : usize = usize::MAX;
+
+pub const FUNCTIONS : &[Function] = &[
+    Function { name : "abs", min_args : 1, max_args : 1, angles : Angles::None, units : Units::Same, apply : |a| a[0].abs() },
+    Function { name : "sqrt", min_args : 1, max_args : 1, angles : Angles::None, units : Units::Root(2), apply : |a| a[0].sqrt() },
+    Function { name : "cbrt", min_args : 1, max_args : 1, angles : Angles::None, units : Units::Root(3), apply : |a| a[0].cbrt() },
+    Function { name : "exp", min_args : 1, max_args : 1, angles : Angles::None, units : Units::None, apply : |a| a[0].exp() },
+    Function { name : "ln", min_args : 1, max_args : 1, angles : Angles::None, units : Units::None, apply : |a| a[0].ln() },
+    // natural logarithm, or to the base given as second argument
+    Function { name : "log", min_args : 1, max_args : 2, angles : Angles::None, units : Units::None, apply : |a| if a.len() == 2 { a[0].log(a[1]) } else { a[0].ln() } },
+    Function { name : "log2", min_args : 1, max_args : 1, angles : Angles::None, units : Units::None, apply : |a| a[0].log2() },
+    Function { name : "log10", min_args : 1, max_args : 1, angles : Angles::None, units : Units::None, apply : |a| a[0].log10() },
+    Function { name : "floor", min_args : 1, max_args : 1, angles : Angles::None, units : Units::Same, apply : |a| a[0].floor() },
+    Function { name : "ceil", min_args : 1, max_args : 1, angles : Angles::None, units : Units::Same, apply : |a| a[0].ceil() },
+    // halfway cases round away from zero; to as many decimal places as the
+    // second argument says, if given, as in spreadsheets
+    Function { name : "round", min_args : 1, max_args : 2, angles : Angles::None, units : Units::Same, apply : |a| if a.len() == 2 { round_to(a[0], a[1]) } else { a[0].round() } },
+    Function { name : "trunc", min_args : 1, max_args : 1, angles : Angles::None, units : Units::Same, apply : |a| a[0].trunc() },
+    Function { name : "sin", min_args : 1, max_args : 1, angles : Angles::Argument, units : Units::None, apply : |a| a[0].sin() },
+    Function { name : "cos", min_args : 1, max_args : 1, angles : Angles::Argument, units : Units::None, apply : |a| a[0].cos() },
+    Function { name : "tan", min_args : 1, max_args : 1, angles : Angles::Argument, units : Units::None, apply : |a| a[0].tan() },
+    Function { name : "asin", min_args : 1, max_args : 1, angles : Angles::Result, units : Units::None, apply : |a| a[0].asin() },
+    Function { name : "acos", min_args : 1, max_args : 1, angles : Angles::Result, units : Units::None, apply : |a| a[0].acos() },
+    Function { name : "atan", min_args : 1, max_args : 1, angles : Angles::Result, units : Units::None, apply : |a| a[0].atan() },
+    Function { name : "atan2", min_args : 2, max_args : 2, angles : Angles::Result, units : Units::Ratio, apply : |a| a[0].atan2(a[1]) },
+    // an angle in degrees or radians, in whichever unit the session uses
+    Function { name : "deg", min_args : 1, max_args : 1, angles : Angles::Result, units : Units::None, apply : |a| a[0].to_radians() },
+    Function { name : "rad", min_args : 1, max_args : 1, angles : Angles::Result, units : Units::None, apply : |a| a[0] },
+    Function { name : "sinh", min_args : 1, max_args : 1, angles : Angles::None, units : Units::None, apply : |a| a[0].sinh() },
+    Function { name : "cosh", min_args : 1, max_args : 1, angles : Angles::None, units : Units::None, apply : |a| a[0].cosh() },
+    Function { name : "tanh", min_args : 1, max_args : 1, angles : Angles::None, units : Units::None, apply : |a| a[0].tanh() },
+    Function { name : "hypot", min_args : 2, max_args : 2, angles : Angles::None, units : Units::Same, apply : |a| a[0].hypot(a[1]) },
+    Function { name : "pow", min_args : 2, max_args : 2, angles : Angles::None, units : Units::None, apply : |a| a[0].powf(a[1]) },
+    // the gap from the argument to the next float away from zero, and the
+    // next float from the first argument towards the second, as in C
+    Function { name : "ulp", min_args : 1, max_args : 1, angles : Angles::None, units : Units::Same, apply : |a| ulp(a[0]) },
+    Function { name : "nextafter", min_args : 2, max_args : 2, angles : Angles::None, units : Units::Same, apply : |a| next_after(a[0], a[1]) },
+    // the error of the first argument relative to the second, |a - b| / |b|
+    // the payment each period that pays off a loan: pmt(rate, periods,
+    // present value, [future value], [1 to pay at the start of periods])
+    Function { name : "pmt", min_args : 3, max_args : 5, angles : Angles::None, units : Units::None, apply : |a| pmt(a[0], a[1], a[2], a.get(3).copied().unwrap_or(0.0), a.get(4).is_some_and(|t| *t != 0.0)) },
+    Function { name : "relerr", min_args : 2, max_args : 2, angles : Angles::None, units : Units::Ratio, apply : |a| ((a[0] - a[1]) / a[1]).abs() },
+    // the start of the current day, and the current time to the second, in UTC
+    Function { name : "today", min_args : 0, max_args : 0, angles : Angles::None, units : Units::Date, apply : |_| date::today() as f64 },
+    Function { name : "now", min_args : 0, max_args : 0, angles : Angles::None, units : Units::Date, apply : |_| date::now() as f64 },
+];
+
+// Beyond this many factors a product is taken to be infinite rather than
+// worked out.
+const MAX_FACTORS : u64 = 10_000;
+
+pub struct IntegerFunction {
+    pub name : &'static str,
+    pub min_args : usize,
+    pub max_args : usize,
+    // None for a result too large to work out
+    pub apply : fn(&[Natural]) -> Option<Natural>
+}
+
+// n * (n - 1) * ... * (n - k + 1)
+fn falling(n : u64, k : u64) -> Option<Natural> {
+    if k > MAX_FACTORS {
+        return None;
+    }
+    if k > n {
+        return Some(Natural::zero());
+    }
+    Some((n - k + 1..=n).fold(Natural::small(1), |product, i| product.product(&Natural::from_u64(i))))
+}
+
+fn factorial(n : &Natural) -> Option<Natural> {
+    let n = n.to_u64()?;
+    falling(n, n)
+}
+
+fn permutations(n : &Natural, k : &Natural) -> Option<Natural> {
+    falling(n.to_u64()?, k.to_u64()?)
+}
+
+fn combinations(n : &Natural, k : &Natural) -> Option<Natural> {
+    let (n, k) = (n.to_u64()?, k.to_u64()?);
+    if k > n {
+        return Some(Natural::zero());
+    }
+    let k = k.min(n - k);
+    Some(falling(n, k)?.div_rem(&factorial(&Natural::from_u64(k))?).0)
+}
+
+fn lcm(a : &Natural, b : &Natural) -> Natural {
+    if a.is_zero() || b.is_zero() {
+        return Natural::zero();
+    }
+    a.div_rem(&a.gcd(b)).0.product(b)
+}
+
+pub const INTEGER_FUNCTIONS : &[IntegerFunction] = &[
+    IntegerFunction { name : "factorial", min_args : 1, max_args : 1, apply : |a| factorial(&a[0]) },
+    IntegerFunction { name : "nCr", min_args : 2, max_args : 2, apply : |a| combinations(&a[0], &a[1]) },
+    IntegerFunction { name : "nPr", min_args : 2, max_args : 2, apply : |a| permutations(&a[0], &a[1]) },
+    IntegerFunction { name : "gcd", min_args : 1, max_args : VARIADIC, apply : |a| Some(a.iter().fold(Natural::zero(), |acc, n| acc.gcd(n))) },
+    IntegerFunction { name : "lcm", min_args : 1, max_args : VARIADIC, apply : |a| Some(a.iter().fold(Natural::small(1), |acc, n| lcm(&acc, n))) },
+];
+
+// What a list function computes of its numbers. The variance and standard
+// deviation are those of a sample.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Statistic {
+    Len,
+    Sum,
+    Mean,
+    Median,
+    Var,
+    Stddev,
+    Min,
+    Max,
+    Sort
+}
+
+pub struct ListFunction {
+    pub name : &'static str,
+    pub statistic : Statistic
+}
+
+pub const LIST_FUNCTIONS : &[ListFunction] = &[
+    ListFunction { name : "len", statistic : Statistic::Len },
+    ListFunction { name : "sum", statistic : Statistic::Sum },
+    ListFunction { name : "mean", statistic : Statistic::Mean },
+    ListFunction { name : "median", statistic : Statistic::Median },
+    ListFunction { name : "var", statistic : Statistic::Var },
+    ListFunction { name : "stddev", statistic : Statistic::Stddev },
+    ListFunction { name : "min", statistic : Statistic::Min },
+    ListFunction { name : "max", statistic : Statistic::Max },
+    ListFunction { name : "sort", statistic : Statistic::Sort },
+];
+
+// What a matrix function does; matmul takes two arguments, the others one.
+#[derive(Clone, Copy, PartialEq)]
+pub enum MatrixOperation {
+    Multiply,
+    Transpose,
+    Determinant,
+    Inverse
+}
+
+pub struct MatrixFunction {
+    pub name : &'static str,
+    pub args : usize,
+    pub operation : MatrixOperation
+}
+
+pub const MATRIX_FUNCTIONS : &[MatrixFunction] = &[
+    MatrixFunction { name : "matmul", args : 2, operation : MatrixOperation::Multiply },
+    MatrixFunction { name : "transpose", args : 1, operation : MatrixOperation::Transpose },
+    MatrixFunction { name : "det", args : 1, operation : MatrixOperation::Determinant },
+    MatrixFunction { name : "inv", args : 1, operation : MatrixOperation::Inverse },
+];
+
+// What a random function draws; the bounds and parameters, if any, are its
+// arguments.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Distribution {
+    // in [0, 1) or [a, b)
+    Uniform,
+    // an integer from a to b, both included
+    Integer,
+    // with mean mu and standard deviation sigma
+    Normal
+}
+
+pub struct RandomFunction {
+    pub name : &'static str,
+    // how many arguments it takes; rand takes none or two
+    pub args : &'static [usize],
+    pub distribution : Distribution
+}
+
+pub const RANDOM_FUNCTIONS : &[RandomFunction] = &[
+    RandomFunction { name : "rand", args : &[0, 2], distribution : Distribution::Uniform },
+    RandomFunction { name : "randint", args : &[2], distribution : Distribution::Integer },
+    RandomFunction { name : "normal", args : &[2], distribution : Distribution::Normal },
+];
+
+/*
+A built-in function taking values, of whichever table it is in. A compiled
+program finds it once by name, so calling it for each term of a sum or
+point of an integral is no search of the tables.
+*/
+#[derive(Clone, Copy)]
+pub enum Builtin {
+    Function(&'static Function),
+    Integer(&'static IntegerFunction),
+    List(&'static ListFunction),
+    Matrix(&'static MatrixFunction),
+    Random(&'static RandomFunction)
+}
+
+impl Builtin {
+    pub fn name(self) -> &'static str {
+        match self {
+            Builtin::Function(function) => function.name,
+            Builtin::Integer(function) => function.name,
+            Builtin::List(function) => function.name,
+            Builtin::Matrix(function) => function.name,
+            Builtin::Random(function) => function.name
+        }
+    }
+}
+
+pub fn builtin(name : &str) -> Option<Builtin> {
+    find_list(name).map(Builtin::List)
+        .or_else(|| find_matrix(name).map(Builtin::Matrix))
+        .or_else(|| find_integer(name).map(Builtin::Integer))
+        .or_else(|| find_random(name).map(Builtin::Random))
+        .or_else(|| find(name).map(Builtin::Function))
+}
+
+pub fn find(name : &str) -> Option<&'static Function> {
+    FUNCTIONS.iter().find(|f| f.name == name)
+}
+
+pub fn find_integer(name : &str) -> Option<&'static IntegerFunction> {
+    INTEGER_FUNCTIONS.iter().find(|f| f.name == name)
+}
+
+pub fn find_list(name : &str) -> Option<&'static ListFunction> {
+    LIST_FUNCTIONS.iter().find(|f| f.name == name)
+}
+
+pub fn find_matrix(name : &str) -> Option<&'static MatrixFunction> {
+    MATRIX_FUNCTIONS.iter().find(|f| f.name == name)
+}
+
+pub fn find_random(name : &str) -> Option<&'static RandomFunction> {
+    RANDOM_FUNCTIONS.iter().find(|f| f.name == name)
+}
+
+// The name of every built-in function, of each kind in turn.
+pub fn names() -> impl Iterator<Item = &'static str> {
+    let integer = INTEGER_FUNCTIONS.iter().map(|f| f.name);
+    let list = LIST_FUNCTIONS.iter().map(|f| f.name);
+    let matrix = MATRIX_FUNCTIONS.iter().map(|f| f.name);
+    let random = RANDOM_FUNCTIONS.iter().map(|f| f.name);
+    FUNCTIONS.iter().map(|f| f.name).chain(integer).chain(list).chain(matrix).chain(random)
+}
+
+// Whether it's one of the functions taking values, of any kind.
+pub fn is_builtin(name : &str) -> bool {
+    builtin(name).is_some()
+}
+
+pub fn constant(name : &str) -> Option<f64> {
+    CONSTANTS.iter().find(|c| c.name == name).map(|c| c.value)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::evaluate;
+    use crate::evaluate_with;
+    use crate::CalcError;
+    use crate::Context;
+    use crate::Dialect;
+    use crate::Rational;
+    use crate::Value;
+
+    use super::names;
+
+    #[test]
+    fn calls() {
+        assert_eq!(evaluate("sqrt(16) + abs(-3)"), Ok(Value::number(7.0)));
+        assert_eq!(evaluate("log(100, 10) + log(8, 2)"), Ok(Value::number(5.0)));
+        assert_eq!(evaluate("ln(e) + exp(0)"), Ok(Value::number(2.0)));
+        assert_eq!(evaluate("[floor(-2.5), ceil(-2.5), round(-2.5), round(3.14159, 2)]"), evaluate("[-3, -2, -3, 3.14]"));
+        assert_eq!(evaluate("hypot(3, 4)"), Ok(Value::number(5.0)));
+        assert_eq!(evaluate("max(3, 1, 2) - min(3, 1, 2)"), Ok(Value::number(2.0)));
+        assert_eq!(evaluate("2 * sqrt(1 + sin(0)^2)"), Ok(Value::number(2.0)));
+    }
+
+    #[test]
+    fn call_errors() {
+        assert_eq!(evaluate("sqrt(1, 2)"), Err(CalcError::ArgumentCount("sqrt".to_string(), 2, 0)));
+        assert_eq!(evaluate("1 + foo(1)"), Err(CalcError::UnknownFunction("foo".to_string(), 4)));
+        assert_eq!(evaluate("sqrt + 1"), Err(CalcError::UndefinedVariable("sqrt".to_string(), 0)));
+        assert_eq!(evaluate("(-1)!"), Err(CalcError::Domain("factorial".to_string(), 4)));
+    }
+
+    #[test]
+    fn integers() {
+        let exact = |input| evaluate_with(input, Dialect::Standard, &Context::<Rational>::default());
+        assert_eq!(exact("25!"), exact("15511210043330985984000000"));
+        assert_eq!(exact("nCr(60, 30)"), exact("118264581564861424"));
+        assert_eq!(exact("[nCr(3, 5), nPr(5, 2), gcd(12, 18, 8), lcm(4, 6)]"), exact("[0, 20, 2, 12]"));
+        assert_eq!(evaluate("factorial(2.5)"), Err(CalcError::Domain("factorial".to_string(), 0)));
+    }
+
+    #[test]
+    fn lists() {
+        assert_eq!(evaluate("[mean([1, 2, 3, 4]), median([3, 1, 2, 10]), var([1, 2, 3]), len([1, 2, 3])]"), evaluate("[2.5, 2.5, 1, 3]"));
+        assert_eq!(evaluate("stddev([2, 4, 4, 4, 5, 5, 7, 9])"), Ok(Value::number(8f64.sqrt() * (4.0f64 / 7.0).sqrt())));
+        assert_eq!(evaluate("sort([3, 1, 2])"), evaluate("[1, 2, 3]"));
+        assert_eq!(evaluate("sum([1, 2, 3]) + min([3, 1, 2])"), Ok(Value::number(7.0)));
+        assert_eq!(evaluate("[1, 2, 3] * 2 + [1, 1, 1]"), evaluate("[3, 5, 7]"));
+        assert_eq!(evaluate("[1, 2, 3][2]"), Ok(Value::number(3.0)));
+        assert_eq!(evaluate("[1, 2, 3][3]"), Err(CalcError::Index(9)));
+        assert_eq!(evaluate("[1, 2] + [3]"), Err(CalcError::Shape(7)));
+    }
+
+    #[test]
+    fn every_name_is_found() {
+        for name in names() {
+            assert!(super::is_builtin(name), "{}", name);
+        }
+    }
+}
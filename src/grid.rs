@@ -0,0 +1,401 @@
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::VecDeque;
+use std::io;
+use std::io::BufRead;
+use std::io::IsTerminal;
+use std::io::Write;
+use std::process;
+
+use calculator::format;
+use calculator::AngleUnit;
+use calculator::CalcError;
+use calculator::Context;
+use calculator::Dialect;
+use calculator::Token;
+use calculator::Value;
+
+use crate::cli::Options;
+use crate::editor;
+use crate::editor::Key;
+use crate::messages;
+use crate::messages::Msg;
+
+/*
+A spreadsheet of cells A1 to Z999, each holding an expression that may
+name other cells, as in A1 * 2, or ranges of them, as in sum(A1:A3), which
+is the list [A1, A2, A3]; A1:B2 goes along the rows, [A1, B1, A2, B2]. An
+empty cell is 0, as in spreadsheets.
+
+Every change recalculates the sheet, each cell after those it names: a
+cell is taken once all of its references have their values, so whatever
+is left when no more can be taken is on a cycle or depends on one, and is
+an error rather than a value. So is a cell that names one with an error.
+
+At a terminal the sheet is shown to move around in with the arrow keys.
+Enter or typing edits the cell under the cursor, Enter again stores it and
+Ctrl-C drops the edit; Delete or Backspace empties the cell, and Ctrl-D
+quits. Otherwise lines such as `A1 = 2 * B1` are read from stdin, and the
+value of every cell is printed at the end.
+*/
+
+const COLUMNS : usize = 26;
+const ROWS : usize = 999;
+const WIDTH : usize = 12;
+
+// (column, row), from 0
+type Position = (usize, usize);
+
+pub fn name((column, row) : Position) -> String {
+    format!("{}{}", (b'A' + column as u8) as char, row + 1)
+}
+
+pub fn position(name : &str) -> Option<Position> {
+    let mut chars = name.chars();
+    let column = chars.next().filter(char::is_ascii_uppercase)? as usize - 'A' as usize;
+    let digits = chars.as_str();
+    if digits.starts_with('0') || digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let row = digits.parse::<usize>().ok().filter(|row| *row <= ROWS)?;
+    Some((column, row - 1))
+}
+
+pub struct Sheet {
+    dialect : Dialect,
+    angle : AngleUnit,
+    formulas : BTreeMap<Position, String>,
+    values : BTreeMap<Position, Result<Value<f64>, String>>
+}
+
+impl Sheet {
+    pub fn new(dialect : Dialect, angle : AngleUnit) -> Sheet {
+        Sheet { dialect, angle, formulas : BTreeMap::new(), values : BTreeMap::new() }
+    }
+
+    pub fn formula(&self, at : Position) -> Option<&str> {
+        self.formulas.get(&at).map(String::as_str)
+    }
+
+    pub fn value(&self, at : Position) -> Option<&Result<Value<f64>, String>> {
+        self.values.get(&at)
+    }
+
+    // Puts `formula` in the cell at `at`, or empties it if there is none,
+    // and recalculates.
+    pub fn set(&mut self, at : Position, formula : &str) {
+        let formula = formula.trim();
+        let formula = formula.strip_prefix('=').unwrap_or(formula).trim();
+        if formula.is_empty() {
+            self.formulas.remove(&at);
+        }
+        else {
+            self.formulas.insert(at, formula.to_string());
+        }
+        self.recalculate();
+    }
+
+    // `formula` with its ranges written as lists, and the cells it names.
+    fn references(&self, formula : &str) -> (String, BTreeSet<Position>) {
+        let tokens : Vec<_> = calculator::tokenize(formula, self.dialect).collect();
+        let mut expanded = String::new();
+        let mut cells = BTreeSet::new();
+        let mut end = 0;
+        let mut i = 0;
+        while i < tokens.len() {
+            let cell = |i : usize| match tokens.get(i).map(|t| &t.token) {
+                Some(Token::Ident(name)) => position(name),
+                _ => None
+            };
+            if let Some(from) = cell(i) {
+                if let (Some(Token::Colon), Some(to)) = (tokens.get(i + 1).map(|t| &t.token), cell(i + 2)) {
+                    let rows = from.1.min(to.1)..=from.1.max(to.1);
+                    let range : Vec<_> = rows.flat_map(|row| (from.0.min(to.0)..=from.0.max(to.0)).map(move |column| (column, row))).collect();
+                    expanded.push_str(&formula[end..tokens[i].start]);
+                    expanded.push_str(&format!("[{}]", range.iter().map(|at| name(*at)).collect::<Vec<_>>().join(", ")));
+                    cells.extend(range);
+                    end = tokens[i + 2].end;
+                    i += 3;
+                    continue;
+                }
+                cells.insert(from);
+            }
+            i += 1;
+        }
+        expanded.push_str(&formula[end..]);
+        (expanded, cells)
+    }
+
+    fn recalculate(&mut self) {
+        let cells : BTreeMap<Position, (String, BTreeSet<Position>)> = self.formulas.iter().map(|(at, formula)| (*at, self.references(formula))).collect();
+
+        // the cells each one is named by, and how many of those it names
+        // are still to be worked out
+        let mut dependents : BTreeMap<Position, Vec<Position>> = BTreeMap::new();
+        let mut waiting : BTreeMap<Position, usize> = BTreeMap::new();
+        for (at, (_, references)) in &cells {
+            let pending = references.iter().filter(|reference| cells.contains_key(reference)).count();
+            waiting.insert(*at, pending);
+            for reference in references.iter().filter(|reference| cells.contains_key(reference)) {
+                dependents.entry(*reference).or_default().push(*at);
+            }
+        }
+
+        self.values.clear();
+        let mut ctx = Context::<f64> { angle : self.angle, ..Context::default() };
+        let mut ready : VecDeque<Position> = waiting.iter().filter(|(_, n)| **n == 0).map(|(at, _)| *at).collect();
+        while let Some(at) = ready.pop_front() {
+            let (formula, references) = &cells[&at];
+            let failed = references.iter().find(|reference| matches!(self.values.get(reference), Some(Err(_))));
+            let value = match failed {
+                Some(reference) => Err(Msg::CellError(&name(at), &name(*reference)).to_string()),
+                None => {
+                    for reference in references.iter().filter(|reference| !cells.contains_key(reference)) {
+                        ctx.variables.insert(name(*reference), Value::number(0.0));
+                    }
+                    calculator::evaluate_with(formula, self.dialect, &ctx).map_err(|e| error(&e, &name(at), formula))
+                }
+            };
+            if let Ok(value) = &value {
+                ctx.variables.insert(name(at), value.clone());
+            }
+            self.values.insert(at, value);
+
+            for dependent in dependents.get(&at).into_iter().flatten() {
+                if let Some(n) = waiting.get_mut(dependent) {
+                    *n -= 1;
+                    if *n == 0 {
+                        ready.push_back(*dependent);
+                    }
+                }
+            }
+        }
+
+        for at in cells.keys() {
+            self.values.entry(*at).or_insert_with(|| Err(Msg::CellCycle(&name(*at)).to_string()));
+        }
+    }
+}
+
+fn error(e : &CalcError, cell : &str, formula : &str) -> String {
+    Msg::AtOption(cell, e.column(formula), &messages::error(e).to_string()).to_string()
+}
+
+fn shown(value : &Result<Value<f64>, String>, options : &Options) -> String {
+    match value {
+        Ok(value) => format::format_value(value, options.numbers.notation(), options.style),
+        Err(_) => "#ERROR".to_string()
+    }
+}
+
+// Returns the process exit code.
+pub fn run(options : &Options) -> i32 {
+    let raw = if io::stdin().is_terminal() && io::stdout().is_terminal() { editor::RawMode::enable() } else { None };
+    match raw {
+        Some(raw) => {
+            interact(options);
+            drop(raw);
+            0
+        }
+        None => run_lines(options)
+    }
+}
+
+// Each line of stdin sets a cell; then every cell is printed.
+fn run_lines(options : &Options) -> i32 {
+    let mut sheet = Sheet::new(options.dialect, options.angle);
+    let mut failed = false;
+    for (n, line) in io::stdin().lock().lines().map_while(Result::ok).enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match line.split_once('=').and_then(|(cell, formula)| Some((position(cell.trim())?, formula))) {
+            Some((at, formula)) => sheet.set(at, formula),
+            None => {
+                eprintln!("{}", Msg::AtRecord(n + 1, &Msg::NotACell.to_string()));
+                failed = true;
+            }
+        }
+    }
+
+    for (at, value) in &sheet.values {
+        match value {
+            Ok(_) => println!("{} = {}", name(*at), shown(value, options)),
+            Err(e) => {
+                eprintln!("{}", e);
+                failed = true;
+            }
+        }
+    }
+    if failed { 1 } else { 0 }
+}
+
+// The rows and columns of the terminal, 24 by 80 if it doesn't say.
+fn size() -> (usize, usize) {
+    let output = process::Command::new("stty").arg("size").stdin(process::Stdio::inherit()).output().ok();
+    let text = output.and_then(|output| String::from_utf8(output.stdout).ok()).unwrap_or_default();
+    let mut numbers = text.split_whitespace().filter_map(|n| n.parse().ok());
+    match (numbers.next(), numbers.next()) {
+        (Some(rows), Some(columns)) => (rows, columns),
+        _ => (24, 80)
+    }
+}
+
+// `text` cut or padded to `width` characters.
+fn fit(text : &str, width : usize) -> String {
+    if text.chars().count() > width {
+        format!("{}…", text.chars().take(width - 1).collect::<String>())
+    }
+    else {
+        format!("{:>width$}", text, width = width)
+    }
+}
+
+struct View {
+    cursor : Position,
+    // the cell in the top left corner
+    corner : Position,
+    // the formula being typed, if editing
+    editing : Option<String>
+}
+
+fn draw(out : &mut impl Write, sheet : &Sheet, view : &mut View, options : &Options) -> io::Result<()> {
+    let (rows, columns) = size();
+    // the row labels, and three lines for the cell under the cursor
+    let visible_columns = ((columns.saturating_sub(5)) / (WIDTH + 1)).max(1);
+    let visible_rows = rows.saturating_sub(5).max(1);
+    let (column, row) = view.cursor;
+    view.corner.0 = view.corner.0.min(column).max((column + 1).saturating_sub(visible_columns));
+    view.corner.1 = view.corner.1.min(row).max((row + 1).saturating_sub(visible_rows));
+
+    write!(out, "\x1b[H\x1b[2J    ")?;
+    for c in view.corner.0..(view.corner.0 + visible_columns).min(COLUMNS) {
+        write!(out, " {:^width$}", (b'A' + c as u8) as char, width = WIDTH)?;
+    }
+    write!(out, "\r\n")?;
+    for r in view.corner.1..(view.corner.1 + visible_rows).min(ROWS) {
+        write!(out, "{:>4}", r + 1)?;
+        for c in view.corner.0..(view.corner.0 + visible_columns).min(COLUMNS) {
+            let text = sheet.value((c, r)).map_or(String::new(), |value| shown(value, options));
+            if (c, r) == view.cursor {
+                write!(out, " \x1b[7m{}\x1b[0m", fit(&text, WIDTH))?;
+            }
+            else {
+                write!(out, " {}", fit(&text, WIDTH))?;
+            }
+        }
+        write!(out, "\r\n")?;
+    }
+
+    let here = name(view.cursor);
+    match &view.editing {
+        Some(formula) => write!(out, "\r\n{}> {}", here, formula)?,
+        None => {
+            let formula = sheet.formula(view.cursor).unwrap_or("");
+            let detail = match sheet.value(view.cursor) {
+                Some(Err(e)) => e.clone(),
+                Some(value) => shown(value, options),
+                None => String::new()
+            };
+            write!(out, "\r\n{}: {}\r\n{}\r\n{}", here, formula, detail, Msg::GridKeys)?;
+        }
+    }
+    out.flush()
+}
+
+fn interact(options : &Options) {
+    let mut sheet = Sheet::new(options.dialect, options.angle);
+    let mut view = View { cursor : (0, 0), corner : (0, 0), editing : None };
+    let mut stdin = io::stdin().lock();
+    let mut stdout = io::stdout();
+    // a screen of its own, given back on the way out
+    let _ = write!(stdout, "\x1b[?1049h");
+
+    loop {
+        if draw(&mut stdout, &sheet, &mut view, options).is_err() {
+            break;
+        }
+        let key = editor::read_key(&mut stdin);
+        let (column, row) = view.cursor;
+        if let Some(formula) = &mut view.editing {
+            match key {
+                Key::Char(c) => formula.push(c),
+                Key::Backspace => { formula.pop(); }
+                Key::Enter => {
+                    sheet.set(view.cursor, formula);
+                    view.editing = None;
+                    view.cursor.1 = (row + 1).min(ROWS - 1);
+                }
+                Key::Interrupt => { view.editing = None; }
+                Key::Closed => break,
+                _ => {}
+            }
+            continue;
+        }
+        match key {
+            Key::Up => { view.cursor.1 = row.saturating_sub(1); }
+            Key::Down => { view.cursor.1 = (row + 1).min(ROWS - 1); }
+            Key::Left => { view.cursor.0 = column.saturating_sub(1); }
+            Key::Right => { view.cursor.0 = (column + 1).min(COLUMNS - 1); }
+            Key::Home => { view.cursor.0 = 0; }
+            Key::Enter => { view.editing = Some(sheet.formula(view.cursor).unwrap_or("").to_string()); }
+            Key::Char(c) => { view.editing = Some(c.to_string()); }
+            Key::Backspace | Key::Delete => sheet.set(view.cursor, ""),
+            Key::Eof | Key::Interrupt | Key::Closed => break,
+            _ => {}
+        }
+    }
+
+    let _ = write!(stdout, "\x1b[?1049l");
+    let _ = stdout.flush();
+}
+
+#[cfg(test)]
+mod tests {
+    use calculator::AngleUnit;
+    use calculator::Dialect;
+    use calculator::Value;
+
+    use super::position;
+    use super::Sheet;
+
+    fn sheet(cells : &[(&str, &str)]) -> Sheet {
+        let mut sheet = Sheet::new(Dialect::Standard, AngleUnit::Radians);
+        for (cell, formula) in cells {
+            sheet.set(position(cell).expect("a cell"), formula);
+        }
+        sheet
+    }
+
+    fn value(sheet : &Sheet, cell : &str) -> Option<Value<f64>> {
+        sheet.value(position(cell).expect("a cell")).and_then(|value| value.clone().ok())
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(position("A1"), Some((0, 0)));
+        assert_eq!(position("C12"), Some((2, 11)));
+        assert_eq!(position("A0"), None);
+        assert_eq!(position("a1"), None);
+        assert_eq!(position("AB1"), None);
+    }
+
+    #[test]
+    fn recalculation() {
+        // given before the cells they name
+        let mut grid = sheet(&[("C1", "sum(A1:B2)"), ("B1", "A1 * 2"), ("A1", "=3"), ("A2", "1")]);
+        assert_eq!(value(&grid, "B1"), Some(Value::number(6.0)));
+        assert_eq!(value(&grid, "C1"), Some(Value::number(10.0)));
+        grid.set(position("A1").expect("a cell"), "");
+        assert_eq!(value(&grid, "C1"), Some(Value::number(1.0)));
+    }
+
+    #[test]
+    fn cycles() {
+        let grid = sheet(&[("A1", "B1 + 1"), ("B1", "A1"), ("C1", "A1"), ("D1", "1 / 0"), ("E1", "D1"), ("F1", "2")]);
+        for cell in ["A1", "B1", "C1", "D1", "E1"] {
+            assert!(matches!(grid.value(position(cell).expect("a cell")), Some(Err(_))), "{}", cell);
+        }
+        assert_eq!(value(&grid, "F1"), Some(Value::number(2.0)));
+    }
+}
@@ -0,0 +1,74 @@
+use std::env;
+use std::io;
+use std::io::IsTerminal;
+
+use calculator::lexer::Lexer;
+use calculator::Dialect;
+use calculator::Token;
+
+/*
+Colours for the REPL: the line being typed with its numbers, operators
+and brackets told apart, results and errors, and a row of carets under
+the part of the input an error is about. Colour is only used when stdout
+is a terminal and NO_COLOR isn't set to anything, as no-color.org asks.
+*/
+
+const NUMBER : &str = "\x1b[36m";
+const OPERATOR : &str = "\x1b[33m";
+const BRACKET : &str = "\x1b[35m";
+const RESULT : &str = "\x1b[1;32m";
+const ERROR : &str = "\x1b[31m";
+const RESET : &str = "\x1b[0m";
+
+pub fn enabled() -> bool {
+    let no_color = env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+    !no_color && io::stdout().is_terminal()
+}
+
+fn paint(text : &str, color : &str) -> String {
+    format!("{}{}{}", color, text, RESET)
+}
+
+// `line` with each token in the colour of its kind; what isn't a token,
+// such as spaces, stays as it is.
+pub fn expression(line : &str, dialect : Dialect) -> String {
+    let mut shown = String::with_capacity(line.len());
+    let mut end = 0;
+    for token in Lexer::new(line, dialect) {
+        shown.push_str(&line[end..token.start]);
+        let text = &line[token.start..token.end];
+        let color = match token.token {
+            Token::Number(_) | Token::Date(_) | Token::Measurement(..) => NUMBER,
+            Token::LeftBracket | Token::RightBracket | Token::LeftSquare | Token::RightSquare => BRACKET,
+            Token::Invalid(_) | Token::InvalidDate | Token::InvalidExponent => ERROR,
+            Token::Ident(_) | Token::Comma => "",
+            _ => OPERATOR
+        };
+        if color.is_empty() {
+            shown.push_str(text);
+        }
+        else {
+            shown.push_str(&paint(text, color));
+        }
+        end = token.end;
+    }
+    shown.push_str(&line[end..]);
+    shown
+}
+
+pub fn result(text : &str) -> String {
+    paint(text, RESULT)
+}
+
+pub fn error(text : &str) -> String {
+    paint(text, ERROR)
+}
+
+// Carets under the token of `input` starting at `offset`, or just one
+// where there is none, as at the end of the input.
+pub fn carets(input : &str, offset : usize, dialect : Dialect) -> String {
+    let width = Lexer::new(input, dialect)
+        .find(|token| token.start <= offset && offset < token.end)
+        .map_or(1, |token| input[offset..token.end].chars().count());
+    "^".repeat(width.max(1))
+}
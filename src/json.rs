@@ -0,0 +1,235 @@
+use std::fmt;
+use std::iter;
+use std::str;
+
+const MAX_DEPTH : usize = 128;
+
+#[derive(Clone)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>)
+}
+
+impl Value {
+    pub fn get(&self, key : &str) -> Option<&Value> {
+        match self {
+            Value::Object(members) => {
+                members.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+            }
+            _ => None
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None
+        }
+    }
+
+    pub fn object(members : Vec<(&str, Value)>) -> Value {
+        Value::Object(members.into_iter().map(|(k, v)| (k.to_string(), v)).collect())
+    }
+
+    pub fn string(s : &str) -> Value {
+        Value::String(s.to_string())
+    }
+}
+
+fn write_string(f : &mut fmt::Formatter<'_>, s : &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?
+        }
+    }
+    write!(f, "\"")
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Null => write!(f, "null"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Number(v) if v.is_finite() => write!(f, "{}", v),
+            Value::Number(_) => write!(f, "null"),
+            Value::String(s) => write_string(f, s),
+            Value::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            Value::Object(members) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+type Chars<'a> = iter::Peekable<str::Chars<'a>>;
+
+fn skip_whitespace(iter : &mut Chars) {
+    while iter.next_if(|c| c.is_ascii_whitespace()).is_some() {}
+}
+
+fn expect_word(iter : &mut Chars, word : &str, value : Value) -> Option<Value> {
+    for expected in word.chars() {
+        if iter.next()? != expected {
+            return None;
+        }
+    }
+    Some(value)
+}
+
+fn parse_hex4(iter : &mut Chars) -> Option<u32> {
+    let mut code = 0;
+    for _ in 0..4 {
+        code = code * 16 + iter.next()?.to_digit(16)?;
+    }
+    Some(code)
+}
+
+fn parse_string(iter : &mut Chars) -> Option<String> {
+    let mut s = String::new();
+    loop {
+        match iter.next()? {
+            '"' => { return Some(s); }
+            '\\' => {
+                let c = match iter.next()? {
+                    '"' => '"',
+                    '\\' => '\\',
+                    '/' => '/',
+                    'b' => '\u{8}',
+                    'f' => '\u{c}',
+                    'n' => '\n',
+                    'r' => '\r',
+                    't' => '\t',
+                    'u' => {
+                        let high = parse_hex4(iter)?;
+                        if (0xd800..0xdc00).contains(&high) {
+                            if iter.next()? != '\\' || iter.next()? != 'u' {
+                                return None;
+                            }
+                            let low = parse_hex4(iter)?;
+                            if !(0xdc00..0xe000).contains(&low) {
+                                return None;
+                            }
+                            char::from_u32(0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00))?
+                        }
+                        else {
+                            char::from_u32(high)?
+                        }
+                    }
+                    _ => { return None; }
+                };
+                s.push(c);
+            }
+            c if (c as u32) < 0x20 => { return None; }
+            c => s.push(c)
+        }
+    }
+}
+
+fn parse_number(iter : &mut Chars) -> Option<Value> {
+    let mut num = String::with_capacity(8);
+    while let Some(c) = iter.next_if(|c| c.is_ascii_digit() || "+-.eE".contains(*c)) {
+        num.push(c);
+    }
+    num.parse::<f64>().ok().map(Value::Number)
+}
+
+fn parse_value(iter : &mut Chars, depth : usize) -> Option<Value> {
+    if depth > MAX_DEPTH {
+        return None;
+    }
+
+    skip_whitespace(iter);
+    let value = match *iter.peek()? {
+        'n' => expect_word(iter, "null", Value::Null)?,
+        't' => expect_word(iter, "true", Value::Bool(true))?,
+        'f' => expect_word(iter, "false", Value::Bool(false))?,
+        '"' => {
+            iter.next();
+            Value::String(parse_string(iter)?)
+        }
+        '[' => {
+            iter.next();
+            let mut items = Vec::new();
+            skip_whitespace(iter);
+            if iter.next_if_eq(&']').is_none() {
+                loop {
+                    items.push(parse_value(iter, depth + 1)?);
+                    skip_whitespace(iter);
+                    match iter.next()? {
+                        ',' => {}
+                        ']' => { break; }
+                        _ => { return None; }
+                    }
+                }
+            }
+            Value::Array(items)
+        }
+        '{' => {
+            iter.next();
+            let mut members = Vec::new();
+            skip_whitespace(iter);
+            if iter.next_if_eq(&'}').is_none() {
+                loop {
+                    skip_whitespace(iter);
+                    if iter.next()? != '"' {
+                        return None;
+                    }
+                    let key = parse_string(iter)?;
+                    skip_whitespace(iter);
+                    if iter.next()? != ':' {
+                        return None;
+                    }
+                    members.push((key, parse_value(iter, depth + 1)?));
+                    skip_whitespace(iter);
+                    match iter.next()? {
+                        ',' => {}
+                        '}' => { break; }
+                        _ => { return None; }
+                    }
+                }
+            }
+            Value::Object(members)
+        }
+        _ => parse_number(iter)?
+    };
+
+    Some(value)
+}
+
+pub fn parse(s : &str) -> Option<Value> {
+    let mut iter = s.chars().peekable();
+    let value = parse_value(&mut iter, 0)?;
+    skip_whitespace(&mut iter);
+    match iter.next() {
+        None => Some(value),
+        Some(_) => None
+    }
+}
@@ -0,0 +1,733 @@
+use std::fs;
+use std::io;
+use std::io::BufReader;
+use std::io::Read;
+use std::io::Write;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::process;
+use std::str;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::SystemTime;
+
+use calculator::date;
+use calculator::functions;
+use calculator::lexer;
+use calculator::Context;
+use calculator::Dialect;
+use calculator::Outcome;
+
+use crate::batch;
+use crate::cli::Options;
+use crate::json::Value;
+use crate::messages::Msg;
+use crate::serve;
+
+/*
+A Jupyter kernel, so that notebooks can be written in the calculator's
+language. Jupyter starts it with the connection file it writes, as a
+kernelspec's kernel.json says:
+
+    {"argv": ["calculator", "--jupyter-kernel", "{connection_file}"],
+     "display_name": "Calculator", "language": "calculator"}
+
+Each notebook has a kernel, and so a session, of its own: what one cell
+assigns or defines the cells run after it can use. A cell is run as a
+script is: one statement per line, or several separated by `;`, where each
+expression shows its value and the first error stops the cell. The
+error's traceback shows the line with the place marked.
+
+Jupyter talks over ZeroMQ, which this speaks itself (ZMTP 3.0 over TCP,
+without security, as Jupyter on one machine uses it): shell and control
+are ROUTER sockets, iopub is PUB, heartbeat is REP, and stdin is there
+only to be connected to, since the calculator never asks for input. Every
+message is signed with HMAC-SHA256 of the connection file's key, and one
+whose signature doesn't match is ignored. Numbers are f64, in the dialect
+and angle unit given on the command line.
+*/
+
+const DELIMITER : &[u8] = b"<IDS|MSG>";
+const PROTOCOL_VERSION : &str = "5.3";
+
+// Flags of ZMTP frames.
+const MORE : u8 = 0x01;
+const LONG : u8 = 0x02;
+const COMMAND : u8 = 0x04;
+
+fn sha256(message : &[u8]) -> [u8; 32] {
+    const K : [u32; 64] = [
+        0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+        0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+        0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+        0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+        0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+        0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+        0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+        0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2
+    ];
+    let mut h : [u32; 8] = [0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19];
+    let mut padded = message.to_vec();
+    padded.push(0x80);
+    while padded.len() % 64 != 56 {
+        padded.push(0);
+    }
+    padded.extend_from_slice(&(message.len() as u64 * 8).to_be_bytes());
+
+    for block in padded.chunks(64) {
+        let mut w = [0u32; 64];
+        for i in 0..16 {
+            w[i] = u32::from_be_bytes([block[4 * i], block[4 * i + 1], block[4 * i + 2], block[4 * i + 3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut hh] = h;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let choice = (e & f) ^ (!e & g);
+            let t1 = hh.wrapping_add(s1).wrapping_add(choice).wrapping_add(K[i]).wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let majority = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(majority);
+            hh = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (h, x) in h.iter_mut().zip([a, b, c, d, e, f, g, hh]) {
+            *h = h.wrapping_add(x);
+        }
+    }
+
+    let mut digest = [0; 32];
+    for (i, word) in h.iter().enumerate() {
+        digest[4 * i..4 * i + 4].copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+fn hmac_sha256(key : &[u8], parts : &[&[u8]]) -> [u8; 32] {
+    let mut block = [0u8; 64];
+    if key.len() > 64 {
+        block[..32].copy_from_slice(&sha256(key));
+    }
+    else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = block.iter().map(|byte| byte ^ 0x36).collect::<Vec<_>>();
+    for part in parts {
+        inner.extend_from_slice(part);
+    }
+    let mut outer = block.iter().map(|byte| byte ^ 0x5c).collect::<Vec<_>>();
+    outer.extend_from_slice(&sha256(&inner));
+    sha256(&outer)
+}
+
+fn hex(bytes : &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn write_frame(writer : &mut impl Write, flags : u8, body : &[u8]) -> io::Result<()> {
+    if body.len() > u8::MAX as usize {
+        writer.write_all(&[flags | LONG])?;
+        writer.write_all(&(body.len() as u64).to_be_bytes())?;
+    }
+    else {
+        writer.write_all(&[flags, body.len() as u8])?;
+    }
+    writer.write_all(body)
+}
+
+fn write_message(writer : &mut impl Write, frames : &[Vec<u8>]) -> io::Result<()> {
+    for (i, frame) in frames.iter().enumerate() {
+        write_frame(writer, if i + 1 < frames.len() { MORE } else { 0 }, frame)?;
+    }
+    writer.flush()
+}
+
+// The next frame, with its flags.
+fn read_frame(reader : &mut impl Read) -> io::Result<(u8, Vec<u8>)> {
+    let mut flags = [0];
+    reader.read_exact(&mut flags)?;
+    let length = if flags[0] & LONG != 0 {
+        let mut length = [0; 8];
+        reader.read_exact(&mut length)?;
+        u64::from_be_bytes(length)
+    }
+    else {
+        let mut length = [0];
+        reader.read_exact(&mut length)?;
+        length[0] as u64
+    };
+    let mut body = Vec::new();
+    reader.take(length).read_to_end(&mut body)?;
+    if body.len() as u64 != length {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok((flags[0], body))
+}
+
+// The frames of the next message, passing over commands.
+fn read_message(reader : &mut impl Read) -> io::Result<Vec<Vec<u8>>> {
+    let mut frames = Vec::new();
+    loop {
+        let (flags, body) = read_frame(reader)?;
+        if flags & COMMAND != 0 {
+            continue;
+        }
+        frames.push(body);
+        if flags & MORE == 0 {
+            return Ok(frames);
+        }
+    }
+}
+
+// Greets the peer as a socket of `kind`, and waits for it to be ready.
+fn handshake(stream : &mut (impl Read + Write), kind : &str) -> io::Result<()> {
+    let mut greeting = [0u8; 64];
+    greeting[0] = 0xff;
+    greeting[9] = 0x7f;
+    greeting[10] = 3;
+    greeting[12..16].copy_from_slice(b"NULL");
+    stream.write_all(&greeting)?;
+    stream.flush()?;
+
+    let mut peer = [0u8; 64];
+    stream.read_exact(&mut peer)?;
+    if peer[0] != 0xff || peer[9] & 1 == 0 || peer[10] < 3 || &peer[12..16] != b"NULL" {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a ZMTP 3 peer without security"));
+    }
+
+    let mut ready = vec![5];
+    ready.extend_from_slice(b"READY");
+    ready.push(11);
+    ready.extend_from_slice(b"Socket-Type");
+    ready.extend_from_slice(&(kind.len() as u32).to_be_bytes());
+    ready.extend_from_slice(kind.as_bytes());
+    write_frame(stream, COMMAND, &ready)?;
+    stream.flush()?;
+
+    loop {
+        let (flags, body) = read_frame(stream)?;
+        if flags & COMMAND != 0 && body.get(1..6) == Some(b"READY") {
+            return Ok(());
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Channel {
+    // back to whoever sent the request
+    Reply,
+    // to every subscriber of iopub
+    Publish
+}
+
+struct Session {
+    ctx : Context<f64>,
+    execution_count : usize
+}
+
+struct Kernel {
+    key : Vec<u8>,
+    id : String,
+    dialect : Dialect,
+    session : Mutex<Session>,
+    subscribers : Mutex<Vec<TcpStream>>,
+    sent : AtomicU64
+}
+
+// A message from a client, with the raw header so that replies can name
+// it as their parent.
+struct Request {
+    identities : Vec<Vec<u8>>,
+    header : Vec<u8>,
+    msg_type : String,
+    content : Value
+}
+
+fn timestamp() -> String {
+    let now = date::format(date::now());
+    if now.contains('T') { format!("{}Z", now) } else { format!("{}T00:00Z", now) }
+}
+
+fn object(members : Vec<(&str, Value)>) -> Vec<u8> {
+    Value::object(members).to_string().into_bytes()
+}
+
+impl Kernel {
+    fn sign(&self, parts : &[&[u8]]) -> Vec<u8> {
+        if self.key.is_empty() {
+            return Vec::new();
+        }
+        hex(&hmac_sha256(&self.key, parts)).into_bytes()
+    }
+
+    // The request in `frames`, if it is a signed Jupyter message.
+    fn request(&self, mut frames : Vec<Vec<u8>>) -> Option<Request> {
+        let delimiter = frames.iter().position(|frame| frame == DELIMITER)?;
+        let parts = frames.split_off(delimiter);
+        let [_, signature, header, parent, metadata, content, ..] = parts.as_slice() else { return None; };
+        if *signature != self.sign(&[header, parent, metadata, content]) {
+            eprintln!("{}", Msg::BadSignature);
+            return None;
+        }
+        let msg_type = crate::json::parse(str::from_utf8(header).ok()?)?.get("msg_type")?.as_str()?.to_string();
+        let content = crate::json::parse(str::from_utf8(content).ok()?)?;
+        Some(Request { identities : frames, header : header.clone(), msg_type, content })
+    }
+
+    // The frames of a message of `msg_type` in answer to `parent`.
+    fn message(&self, prefix : Vec<Vec<u8>>, parent : &Request, msg_type : &str, content : Value) -> Vec<Vec<u8>> {
+        let header = object(vec![
+            ("msg_id", Value::string(&format!("{}-{}", self.id, self.sent.fetch_add(1, Ordering::SeqCst)))),
+            ("session", Value::string(&self.id)),
+            ("username", Value::string("calculator")),
+            ("date", Value::string(&timestamp())),
+            ("msg_type", Value::string(msg_type)),
+            ("version", Value::string(PROTOCOL_VERSION)),
+        ]);
+        let metadata = b"{}".to_vec();
+        let content = content.to_string().into_bytes();
+        let signature = self.sign(&[&header, &parent.header, &metadata, &content]);
+        let mut frames = prefix;
+        frames.extend([DELIMITER.to_vec(), signature, header, parent.header.clone(), metadata, content]);
+        frames
+    }
+
+    fn reply(&self, request : &Request, msg_type : &str, content : Value) -> (Channel, Vec<Vec<u8>>) {
+        (Channel::Reply, self.message(request.identities.clone(), request, msg_type, content))
+    }
+
+    fn publish(&self, request : &Request, msg_type : &str, content : Value) -> (Channel, Vec<Vec<u8>>) {
+        let topic = format!("kernel.{}.{}", self.id, msg_type).into_bytes();
+        (Channel::Publish, self.message(vec![topic], request, msg_type, content))
+    }
+
+    fn status(&self, request : &Request, state : &str) -> (Channel, Vec<Vec<u8>>) {
+        self.publish(request, "status", Value::object(vec![("execution_state", Value::string(state))]))
+    }
+
+    // Runs the cell `code` in the session, giving what it shows and, if a
+    // statement failed, the members of the error with its traceback.
+    fn run(&self, code : &str) -> (Vec<String>, Option<Vec<(String, Value)>>) {
+        let mut session = self.session.lock().unwrap_or_else(|e| e.into_inner());
+        let mut shown = Vec::new();
+        for (n, line) in code.lines().enumerate() {
+            for (start, statement) in batch::statements(line, self.dialect) {
+                if statement.trim().is_empty() {
+                    continue;
+                }
+                let value = match calculator::execute(statement, self.dialect, &mut session.ctx) {
+                    Ok(Outcome::Value(value)) => value,
+                    Ok(Outcome::Expression(expr)) => {
+                        shown.push(expr.to_string());
+                        continue;
+                    }
+                    Ok(_) => { continue; }
+                    Err(e) => {
+                        let prefix = format!("line {}: ", n + 1);
+                        let before = prefix.chars().count() + line[..start].chars().count() + e.column(statement) - 1;
+                        let traceback = vec![
+                            Value::string(&format!("{}{}", prefix, line)),
+                            Value::string(&format!("{}\x1b[31m^\x1b[0m", " ".repeat(before))),
+                            Value::string(&format!("\x1b[31m{}\x1b[0m", e)),
+                        ];
+                        let error = vec![
+                            ("ename".to_string(), Value::string(e.kind())),
+                            ("evalue".to_string(), Value::string(&e.to_string())),
+                            ("traceback".to_string(), Value::Array(traceback)),
+                        ];
+                        return (shown, Some(error));
+                    }
+                };
+                shown.push(serve::shown(&value, statement));
+                session.ctx.results.push(value);
+            }
+        }
+        (shown, None)
+    }
+
+    fn execute(&self, request : &Request, sent : &mut Vec<(Channel, Vec<Vec<u8>>)>) -> Value {
+        let code = request.content.get("code").and_then(|code| code.as_str()).unwrap_or("");
+        let silent = matches!(request.content.get("silent"), Some(Value::Bool(true)));
+        let count = {
+            let mut session = self.session.lock().unwrap_or_else(|e| e.into_inner());
+            if !silent {
+                session.execution_count += 1;
+            }
+            session.execution_count
+        };
+        let execution_count = || Value::Number(count as f64);
+        if !silent {
+            sent.push(self.publish(request, "execute_input", Value::object(vec![("code", Value::string(code)), ("execution_count", execution_count())])));
+        }
+
+        let (shown, error) = self.run(code);
+        if !shown.is_empty() && !silent {
+            let data = Value::object(vec![("text/plain", Value::string(&shown.join("\n")))]);
+            sent.push(self.publish(request, "execute_result", Value::object(vec![("execution_count", execution_count()), ("data", data), ("metadata", Value::object(vec![]))])));
+        }
+        match error {
+            Some(error) => {
+                if !silent {
+                    sent.push(self.publish(request, "error", Value::Object(error.clone())));
+                }
+                let mut members = error;
+                members.insert(0, ("status".to_string(), Value::string("error")));
+                members.push(("execution_count".to_string(), execution_count()));
+                Value::Object(members)
+            }
+            None => Value::object(vec![
+                ("status", Value::string("ok")),
+                ("execution_count", execution_count()),
+                ("user_expressions", Value::object(vec![])),
+                ("payload", Value::Array(Vec::new())),
+            ])
+        }
+    }
+
+    fn complete(&self, request : &Request) -> Value {
+        let code = request.content.get("code").and_then(|code| code.as_str()).unwrap_or("");
+        let cursor = match request.content.get("cursor_pos") {
+            Some(Value::Number(n)) => *n as usize,
+            _ => code.chars().count()
+        };
+        let before = code.chars().take(cursor).collect::<Vec<_>>();
+        let start = before.iter().rposition(|c| !(c.is_alphanumeric() || *c == '_')).map_or(0, |i| i + 1);
+        let prefix = before[start..].iter().collect::<String>();
+
+        let session = self.session.lock().unwrap_or_else(|e| e.into_inner());
+        let mut matches = functions::names().map(str::to_string)
+            .chain(functions::CONSTANTS.iter().map(|c| c.name.to_string()))
+            .chain(session.ctx.functions.keys().cloned())
+            .chain(session.ctx.variables.keys().cloned())
+            .filter(|name| name.starts_with(&prefix))
+            .collect::<Vec<_>>();
+        matches.sort();
+        matches.dedup();
+        Value::object(vec![
+            ("status", Value::string("ok")),
+            ("matches", Value::Array(matches.iter().map(|name| Value::string(name)).collect())),
+            ("cursor_start", Value::Number(start as f64)),
+            ("cursor_end", Value::Number(cursor as f64)),
+            ("metadata", Value::object(vec![])),
+        ])
+    }
+
+    // What to send for a request on shell or control, in order.
+    fn handle(&self, request : &Request) -> Vec<(Channel, Vec<Vec<u8>>)> {
+        let mut sent = vec![self.status(request, "busy")];
+        let ok = |mut members : Vec<(&str, Value)>| {
+            members.insert(0, ("status", Value::string("ok")));
+            Value::object(members)
+        };
+        let reply = match request.msg_type.as_str() {
+            "kernel_info_request" => Some(("kernel_info_reply", ok(vec![
+                ("protocol_version", Value::string(PROTOCOL_VERSION)),
+                ("implementation", Value::string("calculator")),
+                ("implementation_version", Value::string(env!("CARGO_PKG_VERSION"))),
+                ("language_info", Value::object(vec![
+                    ("name", Value::string("calculator")),
+                    ("version", Value::string(env!("CARGO_PKG_VERSION"))),
+                    ("mimetype", Value::string("text/x-calculator")),
+                    ("file_extension", Value::string(".calc")),
+                ])),
+                ("banner", Value::string(&format!("calculator {}", env!("CARGO_PKG_VERSION")))),
+                ("help_links", Value::Array(Vec::new())),
+            ]))),
+            "execute_request" => Some(("execute_reply", self.execute(request, &mut sent))),
+            "complete_request" => Some(("complete_reply", self.complete(request))),
+            "is_complete_request" => {
+                let code = request.content.get("code").and_then(|code| code.as_str()).unwrap_or("");
+                let ending = lexer::ending(code, self.dialect);
+                let status = if ending.open.is_empty() && !ending.operator { "complete" } else { "incomplete" };
+                let mut members = vec![("status", Value::string(status))];
+                if status == "incomplete" {
+                    members.push(("indent", Value::string("")));
+                }
+                Some(("is_complete_reply", Value::object(members)))
+            }
+            "inspect_request" => Some(("inspect_reply", ok(vec![("found", Value::Bool(false)), ("data", Value::object(vec![])), ("metadata", Value::object(vec![]))]))),
+            "history_request" => Some(("history_reply", ok(vec![("history", Value::Array(Vec::new()))]))),
+            "comm_info_request" => Some(("comm_info_reply", ok(vec![("comms", Value::object(vec![]))]))),
+            "shutdown_request" => {
+                let restart = matches!(request.content.get("restart"), Some(Value::Bool(true)));
+                Some(("shutdown_reply", ok(vec![("restart", Value::Bool(restart))])))
+            }
+            _ => None
+        };
+        if let Some((msg_type, content)) = reply {
+            sent.push(self.reply(request, msg_type, content));
+        }
+        sent.push(self.status(request, "idle"));
+        sent
+    }
+
+    fn broadcast(&self, frames : &[Vec<u8>]) {
+        let mut subscribers = self.subscribers.lock().unwrap_or_else(|e| e.into_inner());
+        subscribers.retain_mut(|stream| write_message(stream, frames).is_ok());
+    }
+}
+
+// Serves a shell or control connection.
+fn requests(kernel : &Kernel, mut stream : TcpStream) -> io::Result<()> {
+    handshake(&mut stream, "ROUTER")?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    loop {
+        let request = match kernel.request(read_message(&mut reader)?) {
+            Some(request) => request,
+            None => { continue; }
+        };
+        for (channel, frames) in kernel.handle(&request) {
+            match channel {
+                Channel::Reply => write_message(&mut stream, &frames)?,
+                Channel::Publish => kernel.broadcast(&frames)
+            }
+        }
+        if request.msg_type == "shutdown_request" {
+            process::exit(0);
+        }
+    }
+}
+
+fn connection(kernel : &Kernel, kind : &str, mut stream : TcpStream) -> io::Result<()> {
+    match kind {
+        "shell" | "control" => requests(kernel, stream),
+        "iopub" => {
+            handshake(&mut stream, "PUB")?;
+            let mut reader = BufReader::new(stream.try_clone()?);
+            kernel.subscribers.lock().unwrap_or_else(|e| e.into_inner()).push(stream);
+            // what to subscribe to; every subscriber is sent everything
+            loop {
+                read_message(&mut reader)?;
+            }
+        }
+        "hb" => {
+            handshake(&mut stream, "REP")?;
+            let mut reader = BufReader::new(stream.try_clone()?);
+            loop {
+                let frames = read_message(&mut reader)?;
+                write_message(&mut stream, &frames)?;
+            }
+        }
+        _ => {
+            handshake(&mut stream, "ROUTER")?;
+            loop {
+                read_message(&mut stream)?;
+            }
+        }
+    }
+}
+
+// Returns the process exit code.
+pub fn run(options : &Options, path : &str) -> i32 {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            return 1;
+        }
+    };
+    let config = match crate::json::parse(&text) {
+        Some(config) if config.get("transport").and_then(|t| t.as_str()).unwrap_or("tcp") == "tcp" => config,
+        _ => {
+            eprintln!("{}", Msg::BadConnectionFile(path));
+            return 1;
+        }
+    };
+    let key = config.get("key").and_then(|key| key.as_str()).unwrap_or("");
+    let scheme = config.get("signature_scheme").and_then(|scheme| scheme.as_str()).unwrap_or("hmac-sha256");
+    let ip = config.get("ip").and_then(|ip| ip.as_str()).unwrap_or("127.0.0.1");
+    if scheme != "hmac-sha256" && !key.is_empty() {
+        eprintln!("{}", Msg::BadConnectionFile(path));
+        return 1;
+    }
+
+    let mut listeners = Vec::new();
+    for kind in ["shell", "control", "iopub", "hb", "stdin"] {
+        let port = match config.get(&format!("{}_port", kind)) {
+            Some(Value::Number(port)) => *port,
+            _ => {
+                eprintln!("{}", Msg::BadConnectionFile(path));
+                return 1;
+            }
+        };
+        let address = format!("{}:{}", ip, port);
+        match TcpListener::bind(&address) {
+            Ok(listener) => listeners.push((kind, listener)),
+            Err(e) => {
+                eprintln!("{}: {}", address, e);
+                return 1;
+            }
+        }
+    }
+
+    let started = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map_or(0, |since| since.as_nanos());
+    let kernel = Arc::new(Kernel {
+        key : key.as_bytes().to_vec(),
+        id : format!("{:x}-{:x}", process::id(), started),
+        dialect : options.dialect,
+        session : Mutex::new(Session { ctx : Context { angle : options.angle, ..Context::default() }, execution_count : 0 }),
+        subscribers : Mutex::new(Vec::new()),
+        sent : AtomicU64::new(0)
+    });
+
+    let threads = listeners.into_iter().map(|(kind, listener)| {
+        let kernel = Arc::clone(&kernel);
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let kernel = Arc::clone(&kernel);
+                thread::spawn(move || connection(&kernel, kind, stream));
+            }
+        })
+    }).collect::<Vec<_>>();
+    for thread in threads {
+        let _ = thread.join();
+    }
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::io::Read;
+    use std::io::Write;
+    use std::sync::atomic::AtomicU64;
+    use std::sync::Mutex;
+
+    use calculator::Context;
+    use calculator::Dialect;
+
+    use super::handshake;
+    use super::hex;
+    use super::hmac_sha256;
+    use super::read_message;
+    use super::sha256;
+    use super::write_message;
+    use super::Channel;
+    use super::Kernel;
+    use super::Session;
+    use super::DELIMITER;
+
+    // What a peer says, and what it is told.
+    struct Peer<'a> {
+        input : &'a [u8],
+        output : Vec<u8>
+    }
+
+    impl Read for Peer<'_> {
+        fn read(&mut self, buf : &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Peer<'_> {
+        fn write(&mut self, buf : &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn kernel() -> Kernel {
+        let session = Session { ctx : Context::default(), execution_count : 0 };
+        Kernel { key : b"secret".to_vec(), id : "test".to_string(), dialect : Dialect::Standard, session : Mutex::new(session), subscribers : Mutex::new(Vec::new()), sent : AtomicU64::new(0) }
+    }
+
+    // A request as a client sends it, signed with the kernel's key.
+    fn request(kernel : &Kernel, msg_type : &str, content : &str) -> Vec<Vec<u8>> {
+        let header = format!(r#"{{"msg_id": "1", "session": "client", "username": "u", "msg_type": "{}", "version": "5.3"}}"#, msg_type).into_bytes();
+        let (parent, metadata, content) = (b"{}".to_vec(), b"{}".to_vec(), content.as_bytes().to_vec());
+        let signature = kernel.sign(&[&header, &parent, &metadata, &content]);
+        vec![b"client".to_vec(), DELIMITER.to_vec(), signature, header, parent, metadata, content]
+    }
+
+    // The type and content of each message sent for a request.
+    fn messages(kernel : &Kernel, frames : Vec<Vec<u8>>) -> Vec<(Channel, String, String)> {
+        let request = kernel.request(frames).expect("a signed request");
+        kernel.handle(&request).into_iter().map(|(channel, frames)| {
+            let start = frames.iter().position(|frame| frame == DELIMITER).expect("a delimiter");
+            let header = crate::json::parse(&String::from_utf8_lossy(&frames[start + 2])).expect("a header");
+            let msg_type = header.get("msg_type").and_then(|t| t.as_str()).unwrap_or("").to_string();
+            (channel, msg_type, String::from_utf8_lossy(&frames[start + 5]).into_owned())
+        }).collect()
+    }
+
+    #[test]
+    fn signatures() {
+        assert_eq!(hex(&sha256(b"abc")), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        // RFC 4231, test cases 2 and 6
+        assert_eq!(hex(&hmac_sha256(b"Jefe", &[b"what do ya want ", b"for nothing?"])), "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843");
+        assert_eq!(hex(&hmac_sha256(&[0xaa; 131], &[b"Test Using Larger Than Block-Size Key - Hash Key First"])), "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54");
+
+        let kernel = kernel();
+        let mut frames = request(&kernel, "kernel_info_request", "{}");
+        assert!(kernel.request(frames.clone()).is_some());
+        frames[2] = b"0000".to_vec();
+        assert!(kernel.request(frames).is_none());
+    }
+
+    #[test]
+    fn zmtp() {
+        let frames = vec![b"a".to_vec(), vec![7; 300], Vec::new()];
+        let mut written = Vec::new();
+        write_message(&mut written, &frames).expect("written");
+        assert_eq!(&written[..4], &[0x01, 1, b'a', 0x03]);
+        assert_eq!(read_message(&mut &written[..]).expect("a message"), frames);
+
+        // a greeting, then READY, from a peer without security
+        let mut greeting = vec![0xff, 0, 0, 0, 0, 0, 0, 0, 1, 0x7f, 3, 0];
+        greeting.extend_from_slice(b"NULL");
+        greeting.resize(64, 0);
+        greeting.extend_from_slice(&[0x04, 6, 5, b'R', b'E', b'A', b'D', b'Y']);
+        let mut peer = Peer { input : &greeting, output : Vec::new() };
+        handshake(&mut peer, "ROUTER").expect("a handshake");
+        assert_eq!(&peer.output[..11], &[0xff, 0, 0, 0, 0, 0, 0, 0, 0, 0x7f, 3]);
+        assert_eq!(&peer.output[73..84], b"Socket-Type");
+        assert_eq!(&peer.output[88..], b"ROUTER");
+    }
+
+    #[test]
+    fn cells() {
+        let kernel = kernel();
+        let sent = messages(&kernel, request(&kernel, "execute_request", r#"{"code": "x = 6\nx * 7; x + 1", "silent": false}"#));
+        let types = sent.iter().map(|(_, msg_type, _)| msg_type.as_str()).collect::<Vec<_>>();
+        assert_eq!(types, ["status", "execute_input", "execute_result", "execute_reply", "status"]);
+        assert!(sent[2].2.contains(r#""data":{"text/plain":"42\n7"}"#));
+        assert!(sent[3].0 == Channel::Reply);
+        assert_eq!(sent[3].2, r#"{"status":"ok","execution_count":1,"user_expressions":{},"payload":[]}"#);
+
+        // the session goes on, and an error stops the cell
+        let sent = messages(&kernel, request(&kernel, "execute_request", r#"{"code": "x / 0\nx", "silent": false}"#));
+        let types = sent.iter().map(|(_, msg_type, _)| msg_type.as_str()).collect::<Vec<_>>();
+        assert_eq!(types, ["status", "execute_input", "error", "execute_reply", "status"]);
+        assert!(sent[3].2.starts_with(r#"{"status":"error","ename":"division_by_zero""#));
+        assert!(sent[3].2.contains(r#""traceback":["line 1: x / 0","          \u001b[31m^"#));
+        assert!(sent[3].2.ends_with(r#""execution_count":2}"#));
+    }
+
+    #[test]
+    fn completion() {
+        let kernel = kernel();
+        let _ = messages(&kernel, request(&kernel, "execute_request", r#"{"code": "sqrt2 = 1", "silent": false}"#));
+        let sent = messages(&kernel, request(&kernel, "complete_request", r#"{"code": "1 + sqr", "cursor_pos": 7}"#));
+        assert_eq!(sent[1].2, r#"{"status":"ok","matches":["sqrt","sqrt2"],"cursor_start":4,"cursor_end":7,"metadata":{}}"#);
+        let sent = messages(&kernel, request(&kernel, "is_complete_request", r#"{"code": "max(1,"}"#));
+        assert_eq!(sent[1].2, r#"{"status":"incomplete","indent":""}"#);
+    }
+}
@@ -0,0 +1,1022 @@
+use std::fmt;
+use std::iter;
+use std::str;
+
+use crate::date;
+use crate::natural::Natural;
+use crate::number::Number;
+use crate::rational::Rational;
+use crate::unicode;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Token {
+    // exactly as written, whatever the evaluator computes in
+    Number(Rational),
+    // a date and time such as 2024-03-01T12:30, in seconds since 1970
+    Date(i64),
+    // 5'10" in inches or 45°30' in degrees, with the name of the unit
+    Measurement(Rational, &'static str),
+    // 1:45, a duration of hours and minutes, with the two of them
+    Clock(Rational, Rational),
+    // a literal of a date the calendar doesn't have, such as 2024-02-30
+    InvalidDate,
+    // the `e` of an exponent without digits, as in 1e or 1e+
+    InvalidExponent,
+    Ident(String),
+    LeftBracket,
+    RightBracket,
+    // of lists and indexing
+    LeftSquare,
+    RightSquare,
+    Comma,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    FloorDiv,
+    Mod,
+    Pow,
+    BitAnd,
+    BitOr,
+    // `xor`, or `^` in the Python dialect
+    Xor,
+    ShiftLeft,
+    ShiftRight,
+    BitNot,
+    // `√`, the square root of what follows
+    Root,
+    // postfix `!`
+    Factorial,
+    // postfix `%`
+    Percent,
+    ApproxEq,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    // the keywords `and`, `or` and `not`
+    And,
+    Or,
+    Not,
+    // of `cond ? a : b`
+    Question,
+    Colon,
+    // `in` or `to`, converting to a unit
+    In,
+    Assign,
+    // a minus sign binding tighter than `^`, as Excel has it: =-2^2 is 4
+    Negate,
+    // a `%` that only divides by 100, also as in Excel: =200+10% is 200.1
+    PlainPercent,
+    Invalid(char)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Dialect {
+    Standard,
+    Python,
+    // standard, but with a decimal comma, perhaps with thousands grouped by
+    // `.` as in 1.000,5, and `;` between arguments
+    European,
+    // formulas as a spreadsheet cell takes them; see `Lexer::excel_name`
+    Excel,
+    // as GNU bc reads it: `%` is the remainder, `&&`, `||` and `!` are the
+    // logical operators, a leading minus binds tighter than `^`, and names
+    // are taken as written
+    Bc
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Number(v) => write!(f, "{}", v.to_decimal_string()),
+            Token::Date(seconds) => write!(f, "{}", date::format(*seconds)),
+            Token::Measurement(v, unit) => write!(f, "{} {}", v.to_decimal_string(), unit),
+            Token::Clock(hours, minutes) => write!(f, "{}:{:0>2}", hours.to_decimal_string(), minutes.to_decimal_string()),
+            Token::InvalidDate => write!(f, "InvalidDate"),
+            Token::InvalidExponent => write!(f, "InvalidExponent"),
+            Token::Ident(name) => write!(f, "{}", name),
+            Token::LeftBracket => write!(f, "("),
+            Token::RightBracket => write!(f, ")"),
+            Token::LeftSquare => write!(f, "["),
+            Token::RightSquare => write!(f, "]"),
+            Token::Comma => write!(f, ","),
+            Token::Add => write!(f, "+"),
+            Token::Sub => write!(f, "-"),
+            Token::Mul => write!(f, "*"),
+            Token::Div => write!(f, "/"),
+            Token::FloorDiv => write!(f, "//"),
+            Token::Mod => write!(f, "%"),
+            Token::Pow => write!(f, "**"),
+            Token::BitAnd => write!(f, "&"),
+            Token::BitOr => write!(f, "|"),
+            Token::Xor => write!(f, "xor"),
+            Token::ShiftLeft => write!(f, "<<"),
+            Token::ShiftRight => write!(f, ">>"),
+            Token::BitNot => write!(f, "~"),
+            Token::Root => write!(f, "√"),
+            Token::Factorial => write!(f, "!"),
+            Token::Percent => write!(f, "%"),
+            Token::ApproxEq => write!(f, "~="),
+            Token::Equal => write!(f, "=="),
+            Token::NotEqual => write!(f, "!="),
+            Token::Less => write!(f, "<"),
+            Token::LessEqual => write!(f, "<="),
+            Token::Greater => write!(f, ">"),
+            Token::GreaterEqual => write!(f, ">="),
+            Token::And => write!(f, "and"),
+            Token::Or => write!(f, "or"),
+            Token::Not => write!(f, "not"),
+            Token::Question => write!(f, "?"),
+            Token::Colon => write!(f, ":"),
+            Token::In => write!(f, "in"),
+            Token::Assign => write!(f, "="),
+            Token::Negate => write!(f, "-"),
+            Token::PlainPercent => write!(f, "%"),
+            Token::Invalid(c) => write!(f, "Invalid({})", c),
+        }
+    }
+}
+
+// A token together with the byte range of the input it was read from.
+pub struct SpannedToken {
+    pub token : Token,
+    pub start : usize,
+    pub end : usize
+}
+
+/*
+Tokens are produced on demand, so the evaluator can pull them straight
+from the input line without collecting them first.
+*/
+pub struct Lexer<'a> {
+    input : &'a str,
+    iter : iter::Peekable<str::CharIndices<'a>>,
+    dialect : Dialect,
+    pending : Option<SpannedToken>,
+    // the last token ended an operand, so a `-` now subtracts
+    operand : bool
+}
+
+fn superscript_digit(c : char) -> Option<u32> {
+    match c {
+        '⁰' => Some(0),
+        '¹' => Some(1),
+        '²' => Some(2),
+        '³' => Some(3),
+        '⁴'..='⁹' => Some(c as u32 - '⁴' as u32 + 4),
+        _ => None
+    }
+}
+
+fn is_superscript(c : char) -> bool {
+    superscript_digit(c).is_some() || c == '⁻' || c == '⁺'
+}
+
+// Binary exponents of hex literals up to this are worked out exactly; far
+// beyond what an f64 can hold, in case the numbers are exact.
+const MAX_BINARY_EXPONENT : u32 = 10_000;
+// The same for the decimal exponents of scientific notation.
+const MAX_DECIMAL_EXPONENT : u32 = 10_000;
+
+// x * 2^exp, scaled in steps so that intermediate powers don't overflow
+fn ldexp(mut x : f64, mut exp : i32) -> f64 {
+    while exp > 1000 {
+        x *= 2f64.powi(1000);
+        exp -= 1000;
+    }
+    while exp < -1000 {
+        x *= 2f64.powi(-1000);
+        exp += 1000;
+    }
+    x * 2f64.powi(exp)
+}
+
+// Some digits, and one point at most, as in 12.5, 12. or .5
+fn is_decimal(literal : &str, point : char) -> bool {
+    literal.chars().any(|c| c.is_ascii_digit()) && literal.matches(point).count() <= 1
+}
+
+// Excel's functions by what this calculator calls them, where it has one
+// that works out the same. STDEV and VAR are of a sample, as here.
+const EXCEL_NAMES : &[(&str, &str)] = &[
+    ("SUM", "sum"), ("AVERAGE", "mean"), ("MEDIAN", "median"), ("MIN", "min"), ("MAX", "max"),
+    ("COUNT", "len"), ("STDEV", "stddev"), ("VAR", "var"),
+    ("ABS", "abs"), ("SQRT", "sqrt"), ("EXP", "exp"), ("LN", "ln"), ("LOG10", "log10"),
+    ("INT", "floor"), ("ROUND", "round"), ("POWER", "pow"), ("FACT", "factorial"), ("GCD", "gcd"), ("LCM", "lcm"),
+    ("SIN", "sin"), ("COS", "cos"), ("TAN", "tan"), ("ASIN", "asin"), ("ACOS", "acos"), ("ATAN", "atan"),
+    ("SINH", "sinh"), ("COSH", "cosh"), ("TANH", "tanh"),
+    ("RAND", "rand"), ("IF", "if"), ("PMT", "pmt"),
+];
+
+// Letters that are symbols of their own, so 2πr is 2 * pi * r.
+fn is_symbol(c : char) -> bool {
+    c == 'π'
+}
+
+// Superscripts are numeric, but after a name they are an exponent: x².
+// Marks go with the letter before them, as in x̄.
+fn is_name_char(c : char) -> bool {
+    (c.is_alphanumeric() && !is_superscript(c) && !is_symbol(c)) || c == '_' || unicode::is_mark(c)
+}
+
+fn is_prime(c : char) -> bool {
+    c == '\'' || c == '′'
+}
+
+fn is_double_prime(c : char) -> bool {
+    c == '"' || c == '″'
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(input : &'a str, dialect : Dialect) -> Lexer<'a> {
+        let mut lexer = Lexer { input, iter : input.char_indices().peekable(), dialect, pending : None, operand : false };
+        // a formula may start as it does in the cell
+        if dialect == Dialect::Excel {
+            while lexer.next_if(|c| c.is_whitespace()).is_some() {}
+            lexer.next_if(|c| c == '=');
+        }
+        lexer
+    }
+
+    fn offset(&mut self) -> usize {
+        match self.iter.peek() {
+            Some((i, _)) => *i,
+            None => self.input.len()
+        }
+    }
+
+    fn next_if(&mut self, f : impl Fn(char) -> bool) -> Option<char> {
+        self.iter.next_if(|(_, c)| f(*c)).map(|(_, c)| c)
+    }
+
+    // An integer in another base: 0xff, 0o17 or 0b1010. A hex literal with a
+    // point or binary exponent is left to `hex_float`.
+    fn radix_integer(&mut self) -> Option<Token> {
+        let mut ahead = self.iter.clone();
+        ahead.next_if(|(_, c)| *c == '0')?;
+        let (_, prefix) = ahead.next()?;
+        let radix = match prefix {
+            'x' | 'X' => 16,
+            'o' | 'O' => 8,
+            'b' | 'B' => 2,
+            _ => { return None; }
+        };
+
+        let start = ahead.peek().map_or(self.input.len(), |(i, _)| *i);
+        while ahead.next_if(|(_, c)| c.is_digit(radix)).is_some() {
+            Lexer::digit_separator(&mut ahead, |c| c.is_digit(radix));
+        }
+        let (end, next) = ahead.peek().map_or((self.input.len(), None), |(i, c)| (*i, Some(*c)));
+        if radix == 16 && matches!(next, Some('.' | 'p' | 'P')) {
+            return None;
+        }
+        self.iter = ahead;
+
+        let digits = self.input[start..end].replace('_', "");
+        if digits.is_empty() {
+            return Some(Token::Invalid(prefix));
+        }
+        // a digit too large for the base, as in 0b102
+        if let Some(c) = next.filter(|c| c.is_ascii_alphanumeric()) {
+            return Some(Token::Invalid(c));
+        }
+        Some(Token::Number(Rational::from_parts(false, Natural::from_radix(&digits, radix), Natural::small(1))))
+    }
+
+    // C99 hexadecimal floating point: 0x1.8p3 is 1.5 * 2^3. As in C the
+    // binary exponent is required. The digits are kept exactly, however
+    // many there are, so the value is only rounded when it is made a float.
+    fn hex_float(&mut self) -> Option<Token> {
+        let mut ahead = self.iter.clone();
+        ahead.next_if(|(_, c)| *c == '0')?;
+        ahead.next_if(|(_, c)| *c == 'x' || *c == 'X')?;
+        self.iter = ahead;
+
+        let mut mantissa = String::new();
+        let mut exponent : i32 = 0;
+        let mut fraction = false;
+        while let Some(c) = self.next_if(|c| c.is_ascii_hexdigit() || (c == '.' && !fraction)) {
+            if c == '.' {
+                fraction = true;
+                continue;
+            }
+            mantissa.push(c);
+            if fraction {
+                exponent = exponent.saturating_sub(4);
+            }
+        }
+        let digits = mantissa.len();
+        let mantissa = Natural::from_radix(&mantissa, 16);
+
+        if digits == 0 || self.next_if(|c| c == 'p' || c == 'P').is_none() {
+            return Some(Token::Invalid('x'));
+        }
+
+        let negative = self.next_if(|c| c == '-').is_some();
+        if !negative {
+            self.next_if(|c| c == '+');
+        }
+        let mut power : i32 = 0;
+        let mut power_digits = 0;
+        while let Some(c) = self.next_if(|c| c.is_ascii_digit()) {
+            power = power.saturating_mul(10).saturating_add(c.to_digit(10).unwrap_or(0) as i32);
+            power_digits += 1;
+        }
+
+        if power_digits == 0 {
+            return Some(Token::Invalid('p'));
+        }
+
+        let power = if negative { -power } else { power };
+        let exponent = exponent.saturating_add(power);
+        let mantissa = Rational::from_parts(false, mantissa, Natural::small(1));
+        if exponent.unsigned_abs() > MAX_BINARY_EXPONENT {
+            return Some(Token::Number(Rational::exactly(ldexp(mantissa.to_f64(), exponent))));
+        }
+        Some(Token::Number(mantissa.mul(&Rational::binary(1, exponent))))
+    }
+
+    // `width` digits after `separator`, as in the fields of a date.
+    fn field(iter : &mut iter::Peekable<str::CharIndices<'a>>, separator : Option<char>, width : usize) -> Option<u32> {
+        if let Some(separator) = separator {
+            iter.next_if(|(_, c)| *c == separator)?;
+        }
+        let mut value = 0;
+        for _ in 0..width {
+            let (_, c) = iter.next_if(|(_, c)| c.is_ascii_digit())?;
+            value = value * 10 + c.to_digit(10)?;
+        }
+        Some(value)
+    }
+
+    // The time of day after the T of a date, as in T12:30 or T12:30:15.
+    // Nothing is consumed if it isn't there.
+    fn clock(iter : &mut iter::Peekable<str::CharIndices<'a>>) -> Option<(u32, u32, u32)> {
+        let mut ahead = iter.clone();
+        ahead.next_if(|(_, c)| *c == 'T')?;
+        let hour = Lexer::field(&mut ahead, None, 2)?;
+        let minute = Lexer::field(&mut ahead, Some(':'), 2)?;
+        let mut seconds = ahead.clone();
+        let second = match Lexer::field(&mut seconds, Some(':'), 2) {
+            Some(second) => {
+                ahead = seconds;
+                second
+            }
+            None => 0
+        };
+        *iter = ahead;
+        Some((hour, minute, second))
+    }
+
+    // A date as 2024-03-01, with the time of day after a T as in
+    // 2024-03-01T12:30 or 2024-03-01T12:30:15. Nothing is consumed unless
+    // all the digits are there, so 2024-3-1 is still a subtraction.
+    fn date(&mut self) -> Option<Token> {
+        let mut ahead = self.iter.clone();
+        let year = Lexer::field(&mut ahead, None, 4)?;
+        let month = Lexer::field(&mut ahead, Some('-'), 2)?;
+        let day = Lexer::field(&mut ahead, Some('-'), 2)?;
+
+        let (hour, minute, second) = Lexer::clock(&mut ahead).unwrap_or((0, 0, 0));
+        if ahead.peek().is_some_and(|(_, c)| c.is_ascii_digit()) {
+            return None;
+        }
+        self.iter = ahead;
+
+        match date::seconds(year as i64, month, day, hour, minute, second) {
+            Some(seconds) => Some(Token::Date(seconds)),
+            None => Some(Token::InvalidDate)
+        }
+    }
+
+    // The literal is parsed directly from the input slice, without copying,
+    // though its value allocates: the numerator and denominator of the
+    // Rational, and more while working them out if it has over 19 digits.
+    fn get_first_number(&mut self) -> Option<Token> {
+        if let Some(token) = self.date() {
+            return Some(token);
+        }
+        if let Some(token) = self.radix_integer() {
+            return Some(token);
+        }
+        if let Some(token) = self.hex_float() {
+            return Some(token);
+        }
+
+        let start = self.offset();
+        let point = self.point();
+        let grouped = self.dialect == Dialect::European;
+        // digits since the start or the last `.` of thousands, while before
+        // the point
+        let mut group = Some(0);
+        while let Some(c) = self.next_if(|c| c.is_ascii_digit() || c == point) {
+            if c == point {
+                group = None;
+                continue;
+            }
+            Lexer::digit_separator(&mut self.iter, |c| c.is_ascii_digit());
+            group = group.map(|n| n + 1);
+            if grouped && group.is_some_and(|n| n <= 3) && Lexer::thousands_separator(&mut self.iter) {
+                group = Some(0);
+            }
+        }
+        let literal = &self.input[start..self.offset()];
+
+        if literal.is_empty() {
+            return None;
+        }
+
+        if !is_decimal(literal, point) {
+            return literal.chars().next().map(Token::Invalid);
+        }
+        let value = Rational::from_literal(literal, point);
+
+        match self.exponent() {
+            // too far out for an exact value; only here is the literal copied
+            // for f64 to parse
+            Some(Ok(power)) if power.unsigned_abs() > MAX_DECIMAL_EXPONENT => {
+                let literal = literal.replace('_', "").replace(point, ".");
+                let value = format!("{}e{}", literal, power).parse::<f64>().unwrap_or(f64::NAN);
+                return Some(Token::Number(Rational::from_f64(value)));
+            }
+            Some(Ok(power)) => {
+                let scale = Natural::small(1).shifted(power.unsigned_abs() as usize);
+                let scale = if power >= 0 {
+                    Rational::from_parts(false, scale, Natural::small(1))
+                }
+                else {
+                    Rational::from_parts(false, Natural::small(1), scale)
+                };
+                return Some(Token::Number(value.mul(&scale)));
+            }
+            // the literal is still a number, for the error to point at the `e`
+            Some(Err(start)) => {
+                self.pending = Some(SpannedToken { token : Token::InvalidExponent, start, end : self.offset() });
+                return Some(Token::Number(value));
+            }
+            None => {}
+        }
+
+        if literal.bytes().all(|b| b.is_ascii_digit() || b == b'_') {
+            if let Some(fraction) = self.mixed_fraction() {
+                return Some(Token::Number(value.add(&fraction)));
+            }
+        }
+
+        Some(Token::Number(value))
+    }
+
+    // The exponent of scientific notation after a literal, as in 1.5e-3 or
+    // 6.022E23. Nothing is consumed if the `e` starts a name instead; Err
+    // with where the `e` is if the digits are missing, as in 1e or 1e+.
+    fn exponent(&mut self) -> Option<Result<i32, usize>> {
+        let mut ahead = self.iter.clone();
+        let (e, _) = ahead.next_if(|(_, c)| *c == 'e' || *c == 'E')?;
+        let negative = ahead.next_if(|(_, c)| *c == '-').is_some();
+        let signed = negative || ahead.next_if(|(_, c)| *c == '+').is_some();
+
+        let start = ahead.peek().map_or(self.input.len(), |(i, _)| *i);
+        let digits = Lexer::digits(&mut ahead);
+        if digits == 0 && !signed && ahead.peek().is_some_and(|(_, c)| c.is_alphanumeric() || *c == '_') {
+            return None;
+        }
+        self.iter = ahead;
+
+        if digits == 0 {
+            return Some(Err(e));
+        }
+        let power = self.input[start..self.offset()].parse::<i32>().unwrap_or(i32::MAX);
+        Some(Ok(if negative { -power } else { power }))
+    }
+
+    // What separates the integer part of a literal from its fraction.
+    fn point(&self) -> char {
+        if self.dialect == Dialect::European { ',' } else { '.' }
+    }
+
+    // Skips a `_` between two digits, as in 1_000_000 or 0xffff_ffff.
+    fn digit_separator(iter : &mut iter::Peekable<str::CharIndices<'a>>, digit : impl Fn(char) -> bool) {
+        let mut ahead = iter.clone();
+        if ahead.next_if(|(_, c)| *c == '_').is_some() && ahead.peek().is_some_and(|(_, c)| digit(*c)) {
+            *iter = ahead;
+        }
+    }
+
+    // Skips a `.` grouping thousands in the European dialect, as in
+    // 1.000.000,5, if exactly three digits follow it.
+    fn thousands_separator(iter : &mut iter::Peekable<str::CharIndices<'a>>) -> bool {
+        let mut ahead = iter.clone();
+        if ahead.next_if(|(_, c)| *c == '.').is_none() || Lexer::digits(&mut ahead.clone()) != 3 {
+            return false;
+        }
+        iter.next();
+        true
+    }
+
+    fn digits(iter : &mut iter::Peekable<str::CharIndices<'a>>) -> usize {
+        let mut count = 0;
+        while iter.next_if(|(_, c)| c.is_ascii_digit()).is_some() {
+            count += 1;
+        }
+        count
+    }
+
+    // After a whole number, a fraction written as `3/4` and separated by
+    // whitespace makes a mixed number: `2 3/4` is 2.75. Nothing is consumed
+    // unless the whole pattern is there.
+    fn mixed_fraction(&mut self) -> Option<Rational> {
+        let mut ahead = self.iter.clone();
+
+        ahead.next_if(|(_, c)| *c == ' ' || *c == '\t')?;
+        while ahead.next_if(|(_, c)| *c == ' ' || *c == '\t').is_some() {}
+
+        let (start, _) = *ahead.peek()?;
+        if Lexer::digits(&mut ahead) == 0 || ahead.next_if(|(_, c)| *c == '/').is_none() {
+            return None;
+        }
+        let (slash, _) = *ahead.peek()?;
+        if Lexer::digits(&mut ahead) == 0 {
+            return None;
+        }
+        let end = ahead.peek().map_or(self.input.len(), |(i, _)| *i);
+
+        // `2 3/4.5` is not a fraction
+        if ahead.peek().is_some_and(|(_, c)| *c == '.') {
+            return None;
+        }
+
+        let numerator = Rational::from_decimal(&self.input[start..slash - 1]);
+        let fraction = numerator.div(&Rational::from_decimal(&self.input[slash..end]), 0)?;
+
+        self.iter = ahead;
+        Some(fraction)
+    }
+
+    // A plain number directly followed by one of the `marks`, e.g. the 10" in
+    // 5'10". Nothing is consumed if the mark is missing.
+    fn marked_number(&mut self, mark : fn(char) -> bool) -> Option<Rational> {
+        let mut ahead = self.iter.clone();
+        let (start, _) = *ahead.peek()?;
+        while ahead.next_if(|(_, c)| c.is_ascii_digit() || *c == '.').is_some() {}
+        let (end, c) = *ahead.peek()?;
+
+        let literal = &self.input[start..end];
+        if !mark(c) || !is_decimal(literal, '.') {
+            return None;
+        }
+        let value = Rational::from_decimal(literal);
+
+        ahead.next();
+        self.iter = ahead;
+        Some(value)
+    }
+
+    // Carpentry and surveying notation after the number `value`: 5'10" and
+    // 3" are lengths in inches, 45°30'15" is an angle in degrees. None if
+    // there is no mark after it.
+    fn measurement(&mut self, value : &Rational) -> Option<Token> {
+        let zero = || Rational::ratio(0, 1);
+        if self.next_if(|c| c == '°').is_some() {
+            let minutes = self.marked_number(is_prime).unwrap_or_else(zero);
+            let seconds = self.marked_number(is_double_prime).unwrap_or_else(zero);
+            let degrees = value.add(&minutes.mul(&Rational::ratio(1, 60))).add(&seconds.mul(&Rational::ratio(1, 3600)));
+            return Some(Token::Measurement(degrees, "deg"));
+        }
+
+        if self.next_if(is_prime).is_some() {
+            let inches = self.marked_number(is_double_prime).unwrap_or_else(zero);
+            return Some(Token::Measurement(value.mul(&Rational::ratio(12, 1)).add(&inches), "inch"));
+        }
+
+        self.next_if(is_double_prime)?;
+        Some(Token::Measurement(value.clone(), "inch"))
+    }
+
+    // 1:45 or 17:00 after the whole number `value`, hours and then exactly
+    // two digits of minutes with nothing in between, so that c ? 1 : 30 is
+    // still a conditional. Excel has none, where 1:10 is rows 1 to 10.
+    fn clock_time(&mut self, value : &Rational) -> Option<Token> {
+        if self.dialect == Dialect::Excel || value.to_integer().is_none_or(|hours| hours < 0) {
+            return None;
+        }
+        let mut ahead = self.iter.clone();
+        let minutes = Lexer::field(&mut ahead, Some(':'), 2)?;
+        if minutes >= 60 || ahead.peek().is_some_and(|(_, c)| c.is_ascii_digit() || *c == '.') {
+            return None;
+        }
+        self.iter = ahead;
+        Some(Token::Clock(value.clone(), Rational::ratio(minutes as u64, 1)))
+    }
+
+    // A superscript run such as ² or ⁻¹ stands for `** n`. The power token is
+    // returned right away and the exponent is queued behind it.
+    fn superscript(&mut self, start : usize) -> SpannedToken {
+        let sign = self.next_if(|c| c == '⁻' || c == '⁺');
+
+        let mut exponent : u64 = 0;
+        let mut digits = 0;
+        while let Some(c) = self.next_if(|c| superscript_digit(c).is_some()) {
+            exponent = exponent.saturating_mul(10).saturating_add(superscript_digit(c).unwrap_or(0) as u64);
+            digits += 1;
+        }
+        let end = self.offset();
+
+        if digits == 0 {
+            return SpannedToken { token : Token::Invalid(sign.unwrap_or('⁻')), start, end };
+        }
+
+        let mut exponent = Rational::ratio(exponent, 1);
+        if sign == Some('⁻') {
+            exponent = exponent.neg();
+        }
+        self.pending = Some(SpannedToken { token : Token::Number(exponent), start, end });
+        SpannedToken { token : Token::Pow, start, end : start }
+    }
+
+    // A name such as `sqrt`, `x_1` or `Δt`: a letter or underscore, then
+    // letters, marks, digits and underscores, in NFC so that é is the same
+    // name however it was typed. `#3`, naming the third result, counts as
+    // a name too. `xor`, `and`, `or` and `not` are operators.
+    fn identifier(&mut self) -> Option<Token> {
+        let start = self.offset();
+        let mut ahead = self.iter.clone();
+        if ahead.next_if(|(_, c)| *c == '#').is_some() {
+            if Lexer::digits(&mut ahead) == 0 {
+                return None;
+            }
+            self.iter = ahead;
+        }
+        else {
+            self.next_if(|c| (c.is_alphabetic() && !is_symbol(c)) || c == '_')?;
+            while self.next_if(is_name_char).is_some() {}
+        }
+
+        if self.dialect == Dialect::Excel {
+            let name = unicode::nfc(&self.input[start..self.offset()]);
+            return Some(self.excel_name(&name));
+        }
+        if self.dialect == Dialect::Bc {
+            // what GNU bc calls the last value printed, and the functions of
+            // its math library, which share their names with variables
+            let called = self.iter.peek().is_some_and(|(_, c)| *c == '(');
+            let name = match (&self.input[start..self.offset()], called) {
+                ("last", false) => "ans",
+                ("s", true) => "sin",
+                ("c", true) => "cos",
+                ("a", true) => "atan",
+                ("l", true) => "ln",
+                ("e", true) => "exp",
+                (name, _) => name
+            };
+            return Some(Token::Ident(name.to_string()));
+        }
+        match unicode::nfc(&self.input[start..self.offset()]).as_str() {
+            "xor" => Some(Token::Xor),
+            "and" => Some(Token::And),
+            "or" => Some(Token::Or),
+            "not" => Some(Token::Not),
+            "in" | "to" => Some(Token::In),
+            name => Some(Token::Ident(name.to_string()))
+        }
+    }
+
+    /*
+    A name in a formula, taken without regard to case, as a spreadsheet
+    does: the Excel functions that have a counterpart here are read as it,
+    so SUM is sum and AVERAGE is mean, and TRUE and FALSE are 1 and 0. Any
+    other name is kept as written, for a variable or for an error naming
+    it; there are no word operators, as Excel spells them as functions. The
+    empty brackets that PI(), TRUE() and FALSE() are called with are
+    dropped, since here they are values.
+    */
+    fn excel_name(&mut self, name : &str) -> Token {
+        let mut ahead = self.iter.clone();
+        let called = ahead.next_if(|(_, c)| *c == '(').is_some() && ahead.next_if(|(_, c)| *c == ')').is_some();
+        let token = match name.to_ascii_uppercase().as_str() {
+            "TRUE" => Token::Number(Rational::ratio(1, 1)),
+            "FALSE" => Token::Number(Rational::ratio(0, 1)),
+            "PI" => Token::Ident("pi".to_string()),
+            upper => {
+                let name = EXCEL_NAMES.iter().find(|(excel, _)| *excel == upper).map_or(name, |(_, name)| *name);
+                return Token::Ident(name.to_string());
+            }
+        };
+        if called {
+            self.iter = ahead;
+        }
+        token
+    }
+
+    // Whether what follows can't begin an operand, so a `%` before it is a
+    // percentage rather than the remainder: the end, a bracket, a separator
+    // or a binary operator. A `+` or `-` after a space, with a space or
+    // nothing after it, is taken as binary too, so 10% + 5 is 5.1. Anything
+    // else that could begin an operand keeps the remainder reading `%` had
+    // before percentages, a joined sign included, so 7 % -3 is still -2. A
+    // second `%` isn't taken as a percentage of a percentage, so 2 % % 3 is
+    // an error rather than a guess.
+    fn ends_operand(&mut self) -> bool {
+        let offset = self.offset();
+        let rest = self.input[offset..].trim_start();
+        let spaced = rest.len() < self.input.len() - offset;
+        let mut chars = rest.chars();
+        match chars.next() {
+            None => true,
+            // `~=` and `!=` compare, a lone `~` complements what follows
+            Some('~') => chars.next() == Some('='),
+            Some('+' | '-' | '−') => spaced && chars.next().is_none_or(char::is_whitespace),
+            Some(c) => ")],;*×/÷^&|<>=!?:".contains(c)
+        }
+    }
+
+    fn get_token(&mut self) -> Option<Token> {
+        if let Some(token) = self.get_first_number() {
+            return match token {
+                Token::Number(value) => Some(self.measurement(&value).or_else(|| self.clock_time(&value)).unwrap_or(Token::Number(value))),
+                other => Some(other)
+            };
+        }
+
+        if let Some(token) = self.identifier() {
+            return Some(token);
+        }
+
+        let (_, c) = self.iter.next()?;
+        let python = self.dialect == Dialect::Python;
+        let european = self.dialect == Dialect::European;
+        let excel = self.dialect == Dialect::Excel;
+        let bc = self.dialect == Dialect::Bc;
+
+        let token = match c {
+            '(' => Token::LeftBracket,
+            ')' => Token::RightBracket,
+            '[' => Token::LeftSquare,
+            ']' => Token::RightSquare,
+            ';' if european => Token::Comma,
+            ',' if !european => Token::Comma,
+            '+' => Token::Add,
+            '-' | '−' if (excel || bc) && !self.operand => Token::Negate,
+            // the minus sign as well as the hyphen, as pasted from documents
+            '-' | '−' => Token::Sub,
+            '*' if python && self.next_if(|c| c == '*').is_some() => Token::Pow,
+            '*' | '×' => Token::Mul,
+            '/' if !bc && self.next_if(|c| c == '/').is_some() => Token::FloorDiv,
+            '/' | '÷' => Token::Div,
+            '√' => Token::Root,
+            '!' if self.next_if(|c| c == '=').is_some() => Token::NotEqual,
+            '!' if bc => Token::Not,
+            '!' => Token::Factorial,
+            'π' => Token::Ident("pi".to_string()),
+            '%' if excel => Token::PlainPercent,
+            '%' if !python && !bc && self.ends_operand() => Token::Percent,
+            '%' => Token::Mod,
+            // in Python `^` is exclusive or
+            '^' if python => Token::Xor,
+            '^' => Token::Pow,
+            // in Excel `&` joins text, which there is none of
+            '&' if excel => Token::Invalid('&'),
+            '&' if bc && self.next_if(|c| c == '&').is_some() => Token::And,
+            '|' if bc && self.next_if(|c| c == '|').is_some() => Token::Or,
+            '&' | '|' if bc => Token::Invalid(c),
+            '&' => Token::BitAnd,
+            '|' => Token::BitOr,
+            '<' if excel && self.next_if(|c| c == '>').is_some() => Token::NotEqual,
+            '<' if self.next_if(|c| c == '<').is_some() => Token::ShiftLeft,
+            '<' if self.next_if(|c| c == '=').is_some() => Token::LessEqual,
+            '<' => Token::Less,
+            '>' if self.next_if(|c| c == '>').is_some() => Token::ShiftRight,
+            '>' if self.next_if(|c| c == '=').is_some() => Token::GreaterEqual,
+            '>' => Token::Greater,
+            '~' if self.next_if(|c| c == '=').is_some() => Token::ApproxEq,
+            '~' => Token::BitNot,
+            '=' if excel => Token::Equal,
+            '=' if self.next_if(|c| c == '=').is_some() => Token::Equal,
+            '=' => Token::Assign,
+            '?' => Token::Question,
+            ':' => Token::Colon,
+            other => Token::Invalid(other)
+        };
+
+        Some(token)
+    }
+}
+
+impl Iterator for Lexer<'_> {
+    type Item = SpannedToken;
+
+    fn next(&mut self) -> Option<SpannedToken> {
+        if let Some(token) = self.pending.take() {
+            return Some(token);
+        }
+
+        while self.next_if(|c| c.is_whitespace()).is_some() {}
+
+        let start = self.offset();
+        if self.iter.peek().is_some_and(|(_, c)| is_superscript(*c)) {
+            return Some(self.superscript(start));
+        }
+
+        let token = self.get_token()?;
+        let end = self.offset();
+        self.operand = matches!(token,
+            Token::Number(_) | Token::Date(_) | Token::Measurement(..) | Token::Clock(..) | Token::Ident(_)
+            | Token::RightBracket | Token::RightSquare | Token::Factorial | Token::Percent | Token::PlainPercent
+        );
+
+        Some(SpannedToken { token, start, end })
+    }
+}
+
+// How an input leaves off, for the REPL to tell whether it goes on on the
+// next line.
+pub struct Ending {
+    // the brackets still open, innermost last; none when a bracket is closed
+    // that was never opened or the wrong kind is closed, since appending
+    // can't fix that
+    pub open : Vec<Token>,
+    // the last token is one that has to be followed by an operand
+    pub operator : bool
+}
+
+impl Ending {
+    pub fn squares(&self) -> bool {
+        self.open.contains(&Token::LeftSquare)
+    }
+
+    // What closes the open brackets, innermost first.
+    pub fn closing(&self) -> String {
+        self.open.iter().rev().map(|t| if *t == Token::LeftSquare { ']' } else { ')' }).collect()
+    }
+}
+
+pub fn ending(input : &str, dialect : Dialect) -> Ending {
+    let mut ending = Ending { open : Vec::new(), operator : false };
+    let mut last = None;
+    for token in tokens(input, dialect) {
+        match token {
+            Token::LeftBracket | Token::LeftSquare => { ending.open.push(token.clone()); }
+            Token::RightBracket if ending.open.last() == Some(&Token::LeftBracket) => { ending.open.pop(); }
+            Token::RightSquare if ending.open.last() == Some(&Token::LeftSquare) => { ending.open.pop(); }
+            Token::RightBracket | Token::RightSquare => {
+                return Ending { open : Vec::new(), operator : false };
+            }
+            _ => {}
+        }
+        last = Some(token);
+    }
+
+    // not `!` or `%`, which are complete after their operand
+    ending.operator = matches!(last, Some(
+        Token::Comma | Token::Add | Token::Sub | Token::Mul | Token::Div | Token::FloorDiv | Token::Mod | Token::Pow
+        | Token::BitAnd | Token::BitOr | Token::Xor | Token::ShiftLeft | Token::ShiftRight | Token::BitNot | Token::Root
+        | Token::ApproxEq | Token::Equal | Token::NotEqual | Token::Less | Token::LessEqual | Token::Greater | Token::GreaterEqual
+        | Token::And | Token::Or | Token::Not | Token::Question | Token::Colon | Token::In | Token::Assign | Token::Negate
+    ));
+    ending
+}
+
+// Just the tokens of `input`, for callers that don't need positions.
+pub fn tokens(input : &str, dialect : Dialect) -> impl Iterator<Item = Token> + '_ {
+    Lexer::new(input, dialect).map(|t| t.token)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ending;
+    use super::Lexer;
+    use super::tokens;
+    use super::Token;
+    use crate::Dialect;
+    use crate::Number;
+
+    // Whether each `%` in `input` was read as a percentage.
+    fn percents(input : &str) -> Vec<bool> {
+        tokens(input, Dialect::Standard)
+            .filter_map(|t| match t {
+                Token::Percent => Some(true),
+                Token::Mod => Some(false),
+                _ => None
+            })
+            .collect()
+    }
+
+    #[test]
+    fn percent_before_anything_but_an_operand() {
+        assert_eq!(percents("50%"), [true]);
+        assert_eq!(percents("(10%)"), [true]);
+        assert_eq!(percents("50% * 2, 5%; 1"), [true, true]);
+        assert_eq!(percents("5% ~= 0.05"), [true]);
+        assert_eq!(percents("5% != 1"), [true]);
+        assert_eq!(percents("10% + 5"), [true]);
+        assert_eq!(percents("50% - 10"), [true]);
+        assert_eq!(percents("50 % − 10"), [true]);
+    }
+
+    #[test]
+    fn remainder_before_an_operand() {
+        assert_eq!(percents("7 % 3"), [false]);
+        assert_eq!(percents("7 % -3"), [false]);
+        assert_eq!(percents("7 % −3"), [false]);
+        assert_eq!(percents("7 % +x"), [false]);
+        assert_eq!(percents("7 %-3"), [false]);
+        assert_eq!(percents("7%- 3"), [false]);
+        assert_eq!(percents("7 % ~3"), [false]);
+        assert_eq!(percents("7 % (3)"), [false]);
+        // a percentage is never followed by another %
+        assert_eq!(percents("2 % % 3"), [false, false]);
+    }
+
+    #[test]
+    fn brackets_close_innermost_first() {
+        assert_eq!(ending("[1, (2", Dialect::Standard).closing(), ")]");
+        assert_eq!(ending("f([1, 2], (3", Dialect::Standard).closing(), "))");
+        assert!(ending("[(1]", Dialect::Standard).open.is_empty());
+        assert!(ending("(1 +", Dialect::Standard).operator);
+    }
+
+    #[test]
+    fn exponent_without_digits() {
+        for input in ["1e", "1e+", "2.5E-"] {
+            let last = Lexer::new(input, Dialect::Standard).last().expect("a token");
+            assert_eq!((last.token, last.start), (Token::InvalidExponent, input.find(['e', 'E']).unwrap()));
+        }
+        // a name after a number is still a name
+        assert_eq!(tokens("2em", Dialect::Standard).last(), Some(Token::Ident("em".to_string())));
+    }
+
+    // as the float nearest the exact value of all the digits, ties to even
+    #[test]
+    fn hex_float_rounding() {
+        let float = |input| match tokens(input, Dialect::Standard).collect::<Vec<_>>().as_slice() {
+            [Token::Number(value)] => value.to_f64(),
+            _ => f64::NAN
+        };
+        let one = 1f64;
+        assert_eq!(float("0x1.8p3"), 12.0);
+        assert_eq!(float("0x1.00000000000008p0"), one);
+        assert_eq!(float("0x1.00000000000018p0"), one.next_up().next_up());
+        assert_eq!(float("0x1.0000000000000800001p0"), one.next_up());
+        assert_eq!(float("0x1.00000000000007ffffffffp0"), one);
+        assert_eq!(float("0x1p-1074"), f64::from_bits(1));
+        assert_eq!(float("0x1p-1075"), 0.0);
+        assert_eq!(float("0x1.0000000000001p-1075"), f64::from_bits(1));
+    }
+
+    #[test]
+    fn hex_float_syntax() {
+        let float = |input| match tokens(input, Dialect::Standard).collect::<Vec<_>>().as_slice() {
+            [Token::Number(value)] => value.to_f64(),
+            _ => f64::NAN
+        };
+        assert_eq!(float("0x.8p1"), 1.0);
+        assert_eq!(float("0XAP-1"), 5.0);
+        assert_eq!(float("0x1p+2"), 4.0);
+        // the binary exponent is required, and has digits
+        assert_eq!(tokens("0x1.8", Dialect::Standard).collect::<Vec<_>>(), [Token::Invalid('x')]);
+        assert_eq!(tokens("0x1p", Dialect::Standard).collect::<Vec<_>>(), [Token::Invalid('p')]);
+        assert_eq!(tokens("0x.p1", Dialect::Standard).next(), Some(Token::Invalid('x')));
+    }
+
+    #[test]
+    fn european_thousands() {
+        let number = |input| match tokens(input, Dialect::European).collect::<Vec<_>>().as_slice() {
+            [Token::Number(value)] => Some(value.to_string()),
+            _ => None
+        };
+        assert_eq!(number("1.000.000,5"), Some("2000001/2".to_string()));
+        assert_eq!(number("12.345"), Some("12345".to_string()));
+        // only groups of three
+        assert_eq!(number("1.00"), None);
+        assert_eq!(number("1234.567"), None);
+        assert_eq!(number("1,000.000"), None);
+    }
+
+    #[test]
+    fn python_never_has_percentages() {
+        let python : Vec<Token> = tokens("7 %", Dialect::Python).collect();
+        assert_eq!(python.last(), Some(&Token::Mod));
+    }
+
+    #[test]
+    fn unicode_names() {
+        let names = |input| tokens(input, Dialect::Standard).collect::<Vec<_>>();
+        assert_eq!(names("Δt"), [Token::Ident("Δt".to_string())]);
+        // é typed as one letter, or as e with a combining acute
+        assert_eq!(names("e\u{301}"), [Token::Ident("\u{e9}".to_string())]);
+        assert_eq!(names("x\u{304}²"), [Token::Ident("x\u{304}".to_string()), Token::Pow, Token::Number(crate::Rational::integer(2))]);
+    }
+
+    #[test]
+    fn clock_times() {
+        let clock = |hours, minutes| Token::Clock(crate::Rational::integer(hours), crate::Rational::integer(minutes));
+        assert_eq!(tokens("17:05", Dialect::Standard).collect::<Vec<_>>(), [clock(17, 5)]);
+        // one digit, three, 60 minutes or spaces are no time
+        for input in ["1:5", "1:455", "1:60", "1 : 30", "1.5:30"] {
+            assert!(!tokens(input, Dialect::Standard).any(|t| matches!(t, Token::Clock(..))), "{}", input);
+        }
+        assert!(!tokens("1:10", Dialect::Excel).any(|t| matches!(t, Token::Clock(..))));
+    }
+}
@@ -0,0 +1,709 @@
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+pub mod ast;
+pub mod calculus;
+pub mod compile;
+pub mod date;
+pub mod decimal;
+pub mod eval;
+pub mod ffi;
+pub mod format;
+pub mod functions;
+pub mod lexer;
+pub mod matrix;
+mod natural;
+pub mod number;
+pub mod parser;
+pub mod profile;
+pub mod random;
+pub mod rational;
+pub mod stack;
+pub mod stats;
+pub mod symbolic;
+pub mod trace;
+mod unicode;
+pub mod units;
+pub mod value;
+
+pub use decimal::Decimal;
+pub use lexer::Dialect;
+pub use lexer::Token;
+pub use number::Number;
+pub use rational::Rational;
+pub use units::Quantity;
+pub use value::Value;
+
+/*
+The calculator as a library: `tokenize` turns a line into tokens, the
+parser builds an expression tree from those, `eval` works out its value,
+and `evaluate` does all of it for the common case. Reading a terminal, a
+file or a socket is left to the caller, such as the binary built on it.
+*/
+
+pub const DEFAULT_EPSILON : f64 = 1e-9;
+pub const DEFAULT_MAX_DEPTH : usize = 256;
+// The most `:set depth` allows. Recursion that runs out of the stack
+// evaluation runs on stops with an error whatever the depth (see stack.rs);
+// a function calling itself once a level, as in f(n) = 1 + f(n - 1), takes
+// about 20 KiB of it a level in a debug build, so this many fit with room
+// to spare.
+pub const DEPTH_LIMIT : usize = 1000;
+pub const DEFAULT_PRECISION : usize = 50;
+pub const PRECISION_LIMIT : usize = 1000;
+
+// A function defined in the session, e.g. `f(x) = x^2 + 1`.
+#[derive(Clone)]
+pub struct UserFunction {
+    pub params : Vec<String>,
+    pub body : ast::Expr
+}
+
+// A function the host program registered with `Context::register_fn`. It
+// takes plain numbers as f64, as the built-in functions do, and is applied
+// to each element of list arguments.
+pub type NativeFn = dyn Fn(&[f64]) -> f64 + Send + Sync;
+
+#[derive(Clone)]
+pub struct NativeFunction {
+    pub arity : usize,
+    pub apply : Arc<NativeFn>
+}
+
+// Why a name couldn't be registered.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RegisterError {
+    // not read as a single name, such as `2x` or `and`
+    InvalidName(String),
+    // the name of a built-in function or constant, which can't be replaced
+    Builtin(String)
+}
+
+impl fmt::Display for RegisterError {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegisterError::InvalidName(name) => write!(f, "'{}' isn't a name", name),
+            RegisterError::Builtin(name) => write!(f, "'{}' is built in", name)
+        }
+    }
+}
+
+impl error::Error for RegisterError {}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum AngleUnit {
+    Radians,
+    Degrees
+}
+
+// Settings the evaluator consults while evaluating, and the variables and
+// functions defined so far. `N` is what the numbers are computed in.
+#[derive(Clone)]
+pub struct Context<N = f64> {
+    // tolerance of `~=`
+    pub epsilon : f64,
+    // how deeply calls of user functions may nest
+    pub max_depth : usize,
+    // how many steps one evaluation may take, and for how long, for input
+    // that isn't trusted; each call of a function, and each term of a sum
+    // or point a derivative, integral or root is worked out from, is a step
+    pub max_steps : Option<u64>,
+    pub time_limit : Option<Duration>,
+    // what sin, cos, tan and their inverses work in
+    pub angle : AngleUnit,
+    // digits after the point kept of a quotient that doesn't terminate,
+    // for number types that can compute one exactly at all
+    pub precision : usize,
+    // work as bc does, with `precision` as its scale: see eval::scaled
+    pub truncate : bool,
+    pub variables : HashMap<String, Value<N>>,
+    pub functions : HashMap<String, UserFunction>,
+    // registered by the host program, and not defined in the session
+    pub native : HashMap<String, NativeFunction>,
+    pub constants : HashMap<String, f64>,
+    // earlier results, which `ans`, `ans1`, `ans2`, ... and `#1`, `#2`, ...
+    // refer to; the caller decides what goes in here
+    pub results : Vec<Value<N>>,
+    // what rand, randint and normal draw from, seeded anew for each
+    // context unless given a seed
+    pub random : random::Random
+}
+
+impl<N> Default for Context<N> {
+    fn default() -> Context<N> {
+        Context {
+            epsilon : DEFAULT_EPSILON,
+            max_depth : DEFAULT_MAX_DEPTH,
+            max_steps : None,
+            time_limit : None,
+            angle : AngleUnit::Radians,
+            precision : DEFAULT_PRECISION,
+            truncate : false,
+            variables : HashMap::new(),
+            functions : HashMap::new(),
+            native : HashMap::new(),
+            constants : HashMap::new(),
+            results : Vec::new(),
+            random : random::Random::default()
+        }
+    }
+}
+
+// What may be registered: a single name, other than those built in. The
+// conditional `if` and the functions that bind a variable count as built in.
+fn registrable(name : &str) -> Result<(), RegisterError> {
+    let mut tokens = lexer::tokens(name, Dialect::Standard);
+    match (tokens.next(), tokens.next()) {
+        (Some(Token::Ident(ident)), None) if ident == name => {}
+        _ => { return Err(RegisterError::InvalidName(name.to_string())); }
+    }
+    let reserved = matches!(name, "if" | "sum" | "Σ" | "prod" | "Π" | "diff" | "integrate" | "solve" | "solveprop");
+    if reserved || functions::is_builtin(name) || functions::constant(name).is_some() {
+        return Err(RegisterError::Builtin(name.to_string()));
+    }
+    Ok(())
+}
+
+impl<N> Context<N> {
+    /*
+    Makes `name` a function of `arity` numbers for the expressions evaluated
+    in this context, e.g. `register_fn("tax", 1, |args| args[0] * 1.19)`.
+    It is called with exactly that many arguments, and a call with any other
+    number is an error. Registering a name again replaces the function, and
+    a function defined in the session under the same name takes precedence,
+    as it does over the built-in ones.
+    */
+    pub fn register_fn(&mut self, name : &str, arity : usize, function : impl Fn(&[f64]) -> f64 + Send + Sync + 'static) -> Result<(), RegisterError> {
+        registrable(name)?;
+        self.native.insert(name.to_string(), NativeFunction { arity, apply : Arc::new(function) });
+        Ok(())
+    }
+
+    // Makes `name` a constant, which can't be assigned or be the name of a
+    // parameter, as with pi. Registering it again replaces the value.
+    pub fn register_constant(&mut self, name : &str, value : f64) -> Result<(), RegisterError> {
+        registrable(name)?;
+        self.variables.remove(name);
+        self.constants.insert(name.to_string(), value);
+        Ok(())
+    }
+}
+
+impl<N: Number> Context<N> {
+    // The same session computing in `M`, with the values stored so far
+    // converted.
+    pub fn convert<M: Number>(self) -> Context<M> {
+        let precision = self.precision;
+        let convert = |value : Value<N>| value.map(&mut |quantity| Quantity { value : M::from_rational(&quantity.value.to_literal(), precision), dimension : quantity.dimension });
+        Context {
+            epsilon : self.epsilon,
+            max_depth : self.max_depth,
+            max_steps : self.max_steps,
+            time_limit : self.time_limit,
+            angle : self.angle,
+            precision : self.precision,
+            truncate : self.truncate,
+            variables : self.variables.into_iter().map(|(name, value)| (name, convert(value))).collect(),
+            functions : self.functions,
+            native : self.native,
+            constants : self.constants,
+            results : self.results.into_iter().map(convert).collect(),
+            random : self.random
+        }
+    }
+}
+
+// What executing a statement produced.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome<N = f64> {
+    Value(Value<N>),
+    // a variable was set to the value
+    Assigned(String, Value<N>),
+    // a function definition, with the function's name
+    Defined(String),
+    // the result of simplifying or differentiating, as an expression
+    Expression(ast::Expr)
+}
+
+// Why an input couldn't be evaluated. Each carries the byte offset into the
+// input where the problem is.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CalcError {
+    UnexpectedToken(Token, usize),
+    UnexpectedEnd(usize),
+    // an unclosed `(` or an unmatched `)`
+    UnbalancedBracket(usize),
+    InvalidCharacter(char, usize),
+    TrailingInput(usize),
+    DivisionByZero(usize),
+    // an operand of a bitwise operator that isn't an integer of at most
+    // 128 bits, or a result that isn't
+    NotAnInteger(usize),
+    UnknownFunction(String, usize),
+    UndefinedVariable(String, usize),
+    // an assignment to a constant, or a parameter named after one
+    Constant(String, usize),
+    RecursionLimit(String, usize),
+    // more levels of brackets, operators or calls than parser::MAX_NESTING
+    Nesting(usize),
+    // the function and how many arguments it was given
+    ArgumentCount(String, usize, usize),
+    // an argument the function isn't defined for, such as (-1)!
+    Domain(String, usize),
+    // bounds of a sum or product that aren't integers, or too far apart
+    Bounds(String, usize),
+    // bounds of randint that aren't integers with the lower one first, or
+    // are more than 2^64 - 1 apart
+    Range(String, usize),
+    // a part of an expression, as printed, that has no derivative rule
+    Differentiate(String, usize),
+    // a derivative, integral or root that couldn't be found
+    NoConvergence(String, usize),
+    // lists of different lengths combined element by element
+    Shape(usize),
+    // a list where only a single value will do, as in a condition
+    List(usize),
+    // an index that isn't an integer within the list, or of something that
+    // isn't a list
+    Index(usize),
+    // a list that isn't a matrix, or not of a size the operation works on
+    Matrix(usize),
+    // the inverse of a matrix whose determinant is 0
+    Singular(usize),
+    // quantities added or compared that aren't of the same dimension, such
+    // as metres and seconds, or a unit where only a number will do
+    Dimension(usize),
+    // a date with anything but a duration added to it, or multiplied
+    Date(usize),
+    // a literal such as 2024-02-30 of a day the calendar doesn't have
+    InvalidDate(usize),
+    // more steps or time than the context's limits allow
+    Budget(usize),
+    // an exponent such as 1e or 1e+ without its digits
+    InvalidExponent(usize),
+    // an exact result with more digits than can be worked out
+    TooLarge(usize)
+}
+
+impl CalcError {
+    pub fn offset(&self) -> usize {
+        match self {
+            CalcError::UnexpectedToken(_, offset)
+            | CalcError::UnexpectedEnd(offset)
+            | CalcError::UnbalancedBracket(offset)
+            | CalcError::InvalidCharacter(_, offset)
+            | CalcError::TrailingInput(offset)
+            | CalcError::DivisionByZero(offset)
+            | CalcError::NotAnInteger(offset)
+            | CalcError::UnknownFunction(_, offset)
+            | CalcError::UndefinedVariable(_, offset)
+            | CalcError::Constant(_, offset)
+            | CalcError::RecursionLimit(_, offset)
+            | CalcError::Nesting(offset)
+            | CalcError::ArgumentCount(_, _, offset)
+            | CalcError::Domain(_, offset)
+            | CalcError::Bounds(_, offset)
+            | CalcError::Range(_, offset)
+            | CalcError::Differentiate(_, offset)
+            | CalcError::NoConvergence(_, offset)
+            | CalcError::Shape(offset)
+            | CalcError::List(offset)
+            | CalcError::Index(offset)
+            | CalcError::Matrix(offset)
+            | CalcError::Singular(offset)
+            | CalcError::Dimension(offset)
+            | CalcError::Date(offset)
+            | CalcError::InvalidDate(offset)
+            | CalcError::Budget(offset)
+            | CalcError::InvalidExponent(offset)
+            | CalcError::TooLarge(offset) => *offset
+        }
+    }
+
+    // The same error, reported at `offset` instead.
+    pub fn with_offset(self, offset : usize) -> CalcError {
+        match self {
+            CalcError::UnexpectedToken(token, _) => CalcError::UnexpectedToken(token, offset),
+            CalcError::UnexpectedEnd(_) => CalcError::UnexpectedEnd(offset),
+            CalcError::UnbalancedBracket(_) => CalcError::UnbalancedBracket(offset),
+            CalcError::InvalidCharacter(c, _) => CalcError::InvalidCharacter(c, offset),
+            CalcError::TrailingInput(_) => CalcError::TrailingInput(offset),
+            CalcError::DivisionByZero(_) => CalcError::DivisionByZero(offset),
+            CalcError::NotAnInteger(_) => CalcError::NotAnInteger(offset),
+            CalcError::UnknownFunction(name, _) => CalcError::UnknownFunction(name, offset),
+            CalcError::UndefinedVariable(name, _) => CalcError::UndefinedVariable(name, offset),
+            CalcError::Constant(name, _) => CalcError::Constant(name, offset),
+            CalcError::RecursionLimit(name, _) => CalcError::RecursionLimit(name, offset),
+            CalcError::Nesting(_) => CalcError::Nesting(offset),
+            CalcError::ArgumentCount(name, n, _) => CalcError::ArgumentCount(name, n, offset),
+            CalcError::Domain(name, _) => CalcError::Domain(name, offset),
+            CalcError::Bounds(name, _) => CalcError::Bounds(name, offset),
+            CalcError::Range(name, _) => CalcError::Range(name, offset),
+            CalcError::Differentiate(expr, _) => CalcError::Differentiate(expr, offset),
+            CalcError::NoConvergence(name, _) => CalcError::NoConvergence(name, offset),
+            CalcError::Shape(_) => CalcError::Shape(offset),
+            CalcError::List(_) => CalcError::List(offset),
+            CalcError::Index(_) => CalcError::Index(offset),
+            CalcError::Matrix(_) => CalcError::Matrix(offset),
+            CalcError::Singular(_) => CalcError::Singular(offset),
+            CalcError::Dimension(_) => CalcError::Dimension(offset),
+            CalcError::Date(_) => CalcError::Date(offset),
+            CalcError::InvalidDate(_) => CalcError::InvalidDate(offset),
+            CalcError::Budget(_) => CalcError::Budget(offset),
+            CalcError::InvalidExponent(_) => CalcError::InvalidExponent(offset),
+            CalcError::TooLarge(_) => CalcError::TooLarge(offset)
+        }
+    }
+
+    // A name for the kind of error, as in "division_by_zero", which stays
+    // the same whatever the message says, for programs to tell errors apart.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            CalcError::UnexpectedToken(..) => "unexpected_token",
+            CalcError::UnexpectedEnd(..) => "unexpected_end",
+            CalcError::UnbalancedBracket(..) => "unbalanced_bracket",
+            CalcError::InvalidCharacter(..) => "invalid_character",
+            CalcError::TrailingInput(..) => "trailing_input",
+            CalcError::DivisionByZero(..) => "division_by_zero",
+            CalcError::NotAnInteger(..) => "not_an_integer",
+            CalcError::UnknownFunction(..) => "unknown_function",
+            CalcError::UndefinedVariable(..) => "undefined_variable",
+            CalcError::Constant(..) => "constant",
+            CalcError::RecursionLimit(..) => "recursion_limit",
+            CalcError::Nesting(..) => "nesting",
+            CalcError::ArgumentCount(..) => "argument_count",
+            CalcError::Domain(..) => "domain",
+            CalcError::Bounds(..) => "bounds",
+            CalcError::Range(..) => "range",
+            CalcError::Differentiate(..) => "differentiate",
+            CalcError::NoConvergence(..) => "no_convergence",
+            CalcError::Shape(..) => "shape",
+            CalcError::List(..) => "list",
+            CalcError::Index(..) => "index",
+            CalcError::Matrix(..) => "matrix",
+            CalcError::Singular(..) => "singular",
+            CalcError::Dimension(..) => "dimension",
+            CalcError::Date(..) => "date",
+            CalcError::InvalidDate(..) => "invalid_date",
+            CalcError::Budget(..) => "budget",
+            CalcError::InvalidExponent(..) => "invalid_exponent",
+            CalcError::TooLarge(..) => "too_large"
+        }
+    }
+
+    // Whether the parser found it, before anything was evaluated.
+    pub fn is_syntax(&self) -> bool {
+        matches!(self, CalcError::UnexpectedToken(..)
+            | CalcError::UnexpectedEnd(_)
+            | CalcError::UnbalancedBracket(_)
+            | CalcError::InvalidCharacter(..)
+            | CalcError::TrailingInput(_)
+            | CalcError::Nesting(_)
+            | CalcError::InvalidDate(_)
+            | CalcError::InvalidExponent(_))
+    }
+
+    // The position in `input` as a column counted in characters from 1.
+    pub fn column(&self, input : &str) -> usize {
+        input.get(..self.offset()).map_or(input.chars().count(), |s| s.chars().count()) + 1
+    }
+}
+
+impl fmt::Display for CalcError {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CalcError::UnexpectedToken(token, _) => write!(f, "Unexpected '{}'", token),
+            CalcError::UnexpectedEnd(_) => write!(f, "Unexpected end of input"),
+            CalcError::UnbalancedBracket(_) => write!(f, "Unbalanced bracket"),
+            CalcError::InvalidCharacter(c, _) => write!(f, "Invalid character '{}'", c),
+            CalcError::TrailingInput(_) => write!(f, "Unexpected input after the expression"),
+            CalcError::DivisionByZero(_) => write!(f, "Division by zero"),
+            CalcError::NotAnInteger(_) => write!(f, "Bitwise operators need integers of at most 128 bits"),
+            CalcError::UnknownFunction(name, _) => write!(f, "Unknown function '{}'", name),
+            CalcError::UndefinedVariable(name, _) => write!(f, "Undefined variable '{}'", name),
+            CalcError::Constant(name, _) => write!(f, "'{}' is a constant", name),
+            CalcError::RecursionLimit(name, _) => write!(f, "Calls of '{}' nested too deeply", name),
+            CalcError::Nesting(_) => write!(f, "Expression nested too deeply"),
+            CalcError::ArgumentCount(name, n, _) => write!(f, "'{}' can't take {} argument(s)", name, n),
+            CalcError::Domain(name, _) => write!(f, "'{}' needs non-negative integers", name),
+            CalcError::Bounds(name, _) => write!(f, "'{}' needs integer bounds at most {} apart", name, eval::MAX_TERMS),
+            CalcError::Range(name, _) => write!(f, "'{}' needs integer bounds, the lower first", name),
+            CalcError::Differentiate(expr, _) => write!(f, "Can't differentiate '{}'", expr),
+            CalcError::NoConvergence(name, _) => write!(f, "'{}' found no answer", name),
+            CalcError::Shape(_) => write!(f, "Lists of different lengths"),
+            CalcError::List(_) => write!(f, "A list where a single value is needed"),
+            CalcError::Index(_) => write!(f, "No such element"),
+            CalcError::Matrix(_) => write!(f, "Not a matrix of a suitable size"),
+            CalcError::Singular(_) => write!(f, "The matrix is singular"),
+            CalcError::Dimension(_) => write!(f, "Incompatible units"),
+            CalcError::Date(_) => write!(f, "A date only takes a duration added or taken off, or another date taken off"),
+            CalcError::InvalidDate(_) => write!(f, "No such date"),
+            CalcError::Budget(_) => write!(f, "Evaluation took more steps or time than allowed"),
+            CalcError::InvalidExponent(_) => write!(f, "The exponent has no digits"),
+            CalcError::TooLarge(_) => write!(f, "The exact result would have too many digits")
+        }
+    }
+}
+
+impl error::Error for CalcError {}
+
+pub fn tokenize(input : &str, dialect : Dialect) -> lexer::Lexer<'_> {
+    lexer::Lexer::new(input, dialect)
+}
+
+// Evaluates `input` in the standard dialect with default settings.
+pub fn evaluate(input : &str) -> Result<Value<f64>, CalcError> {
+    evaluate_with(input, Dialect::Standard, &Context::default())
+}
+
+// `input` may end with a conversion, as in 60 mph in km/h, which gives the
+// value as plain numbers of that unit.
+pub fn evaluate_with<N: Number>(input : &str, dialect : Dialect, ctx : &Context<N>) -> Result<Value<N>, CalcError> {
+    let (input, unit) = units::split_conversion(input, dialect);
+    let value = eval::evaluate(&parser::parse(tokenize(input, dialect))?, ctx)?;
+    match unit {
+        Some((offset, unit)) => convert(&value, unit, offset, dialect, ctx),
+        None => Ok(value)
+    }
+}
+
+// `value` as plain numbers of the unit written in `unit`, e.g. km/h, which
+// has to be of the same dimension. `offset` is where `unit` starts in the
+// input, for errors.
+pub fn convert<N: Number>(value : &Value<N>, unit : &str, offset : usize, dialect : Dialect, ctx : &Context<N>) -> Result<Value<N>, CalcError> {
+    let shift = |e : CalcError| {
+        let at = e.offset() + offset;
+        e.with_offset(at)
+    };
+    let unit = match evaluate_with(unit, dialect, ctx).map_err(shift)? {
+        Value::Scalar(unit) => unit,
+        Value::List(_) => { return Err(CalcError::List(offset)); }
+    };
+
+    value.clone().try_map(&mut |quantity| {
+        if quantity.dimension != unit.dimension {
+            return Err(CalcError::Dimension(offset));
+        }
+        let number = quantity.value.div(&unit.value, ctx.precision).ok_or(CalcError::DivisionByZero(offset))?;
+        Ok(Quantity::number(number))
+    })
+}
+
+// Like `evaluate_with`, but `input` may also be an assignment such as
+// `x = 3 * 4` or a definition such as `f(x) = x^2 + 1`, which are stored
+// in `ctx`. An assignment ending in a conversion stores the quantity, and
+// gives it converted.
+pub fn execute<N: Number>(input : &str, dialect : Dialect, ctx : &mut Context<N>) -> Result<Outcome<N>, CalcError> {
+    let (input, unit) = units::split_conversion(input, dialect);
+    let outcome = eval::execute(parser::parse_statement(tokenize(input, dialect))?, ctx)?;
+    match (unit, outcome) {
+        (Some((offset, unit)), Outcome::Value(value)) => Ok(Outcome::Value(convert(&value, unit, offset, dialect, ctx)?)),
+        (Some((offset, unit)), Outcome::Assigned(name, value)) => Ok(Outcome::Assigned(name, convert(&value, unit, offset, dialect, ctx)?)),
+        // as in the REPL, which shows neither in a unit
+        (_, outcome) => Ok(outcome)
+    }
+}
+
+// The steps in which `execute` would work out the value of `input`, as
+// `trace::trace` takes them; none for a definition or a symbolic line.
+pub fn trace<N: Number>(input : &str, dialect : Dialect, ctx : &Context<N>) -> Result<Vec<ast::Expr>, CalcError> {
+    match parser::parse_statement(tokenize(input, dialect))? {
+        ast::Statement::Expr(expr) | ast::Statement::Assign(_, _, expr) => trace::trace(&expr, ctx),
+        ast::Statement::Define(..) | ast::Statement::Symbolic(..) => Ok(Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::evaluate;
+    use crate::evaluate_with;
+    use crate::format;
+    use crate::execute;
+    use crate::AngleUnit;
+    use crate::CalcError;
+    use crate::Context;
+    use crate::Dialect;
+    use crate::Outcome;
+    use crate::RegisterError;
+    use crate::Value;
+
+    #[test]
+    fn conversion() {
+        assert_eq!(evaluate("1 km in m"), Ok(Value::number(1000.0)));
+        assert_eq!(evaluate("1 km in s"), Err(CalcError::Dimension(8)));
+        let mut ctx = Context::default();
+        let assigned = execute("x = 2 km to m", Dialect::Standard, &mut ctx);
+        assert_eq!(assigned, Ok(Outcome::Assigned("x".to_string(), Value::number(2000.0))));
+        // the variable keeps its unit
+        assert_eq!(execute("x in km", Dialect::Standard, &mut ctx), Ok(Outcome::Value(Value::number(2.0))));
+        assert_eq!(evaluate("1 mi in km"), Ok(Value::number(1.609344)));
+        assert_eq!(evaluate("1 kWh in J"), Ok(Value::number(3_600_000.0)));
+        assert_eq!(evaluate("90 km/h in m/s"), Ok(Value::number(25.0)));
+    }
+
+    #[test]
+    fn registered_functions() {
+        let mut ctx = Context::default();
+        assert_eq!(ctx.register_fn("tax", 1, |args| args[0] * 1.19), Ok(()));
+        let run = |input, ctx : &mut Context<f64>| execute(input, Dialect::Standard, ctx);
+        assert_eq!(run("tax(100)", &mut ctx), Ok(Outcome::Value(Value::number(119.0))));
+        assert_eq!(run("tax([0, 100])", &mut ctx), Ok(Outcome::Value(Value::List(vec![Value::number(0.0), Value::number(119.0)]))));
+        assert_eq!(run("tax(1, 2)", &mut ctx), Err(CalcError::ArgumentCount("tax".to_string(), 2, 0)));
+        assert_eq!(run("tax(1 m)", &mut ctx), Err(CalcError::Dimension(0)));
+        // registering again replaces it, and a function of the session hides it
+        assert_eq!(ctx.register_fn("tax", 1, |args| args[0] * 2.0), Ok(()));
+        assert_eq!(run("tax(100)", &mut ctx), Ok(Outcome::Value(Value::number(200.0))));
+        assert!(run("tax(x) = x", &mut ctx).is_ok());
+        assert_eq!(run("tax(100)", &mut ctx), Ok(Outcome::Value(Value::number(100.0))));
+
+        assert_eq!(ctx.register_fn("sin", 1, |args| args[0]), Err(RegisterError::Builtin("sin".to_string())));
+        assert_eq!(ctx.register_fn("sum", 4, |args| args[0]), Err(RegisterError::Builtin("sum".to_string())));
+        assert_eq!(ctx.register_fn("2x", 1, |args| args[0]), Err(RegisterError::InvalidName("2x".to_string())));
+        assert_eq!(ctx.register_fn("and", 2, |args| args[0]), Err(RegisterError::InvalidName("and".to_string())));
+    }
+
+    #[test]
+    fn registered_constants() {
+        let mut ctx = Context::default();
+        assert_eq!(ctx.register_constant("g0", 9.80665), Ok(()));
+        assert_eq!(execute("1 + g0", Dialect::Standard, &mut ctx), Ok(Outcome::Value(Value::number(10.80665))));
+        assert_eq!(execute("  g0 = 2", Dialect::Standard, &mut ctx), Err(CalcError::Constant("g0".to_string(), 2)));
+        assert!(ctx.register_constant("pi", 3.0).is_err());
+    }
+
+    #[test]
+    fn measurements() {
+        let shown = |input, notation| evaluate(input).map(|value| format::format_value(&value, notation, format::Style::default()));
+        assert_eq!(shown("5'10\"", format::Notation::Decimal), Ok("1.778 m".to_string()));
+        assert_eq!(shown("5'10\" + 3\"", format::Notation::FeetInches), Ok("6'1\"".to_string()));
+        assert_eq!(shown("45°30'15\"", format::Notation::Degrees), Ok("45°30'15\"".to_string()));
+        assert_eq!(evaluate("5'10\" in inch"), Ok(Value::number(70.0)));
+        assert_eq!(evaluate("5'10\" -> cm"), Ok(Value::number(177.8)));
+        assert_eq!(evaluate("45°30' in deg"), Ok(Value::number(45.5)));
+        assert_eq!(evaluate("5'10\" + 1"), Err(CalcError::Dimension(6)));
+        // in radians whatever the session works in
+        let degrees = Context { angle : AngleUnit::Degrees, ..Context::default() };
+        assert_eq!(evaluate_with("cos(180°)", Dialect::Standard, &degrees), Ok(Value::number(-1.0)));
+        assert_eq!(evaluate_with("cos(180)", Dialect::Standard, &degrees), Ok(Value::number(-1.0)));
+    }
+
+    // whole floats beyond 2^53 are taken exactly, not through their
+    // shortest decimal
+    #[test]
+    fn big_whole_floats() {
+        let big = 2f64.powi(60);
+        assert_eq!(evaluate("gcd(2^60, 2^40)"), Ok(Value::number(2f64.powi(40))));
+        assert_eq!(evaluate("lcm(2^60, 3)"), Ok(Value::number(3.0 * big)));
+        assert_eq!(evaluate("2^60 & 1023"), Ok(Value::number(0.0)));
+        assert_eq!(evaluate("2^60 >> 59"), Ok(Value::number(2.0)));
+        let hex = evaluate("2^60").map(|value| format::format_value(&value, format::Notation::Hex, format::Style::default()));
+        assert_eq!(hex.as_deref(), Ok("0x1000000000000000"));
+        assert_eq!(format::format(&0.1, format::Notation::Fraction, format::Style::default()), "1/10");
+    }
+
+    #[test]
+    fn compensated_statistics() {
+        assert_eq!(evaluate("sum([1e100, 1, -1e100])"), Ok(Value::number(1.0)));
+        assert_eq!(evaluate("var([1e9 + 1, 1e9 + 2, 1e9 + 3])"), Ok(Value::number(1.0)));
+    }
+
+    #[test]
+    fn percent_and_remainder() {
+        assert_eq!(evaluate("50%"), Ok(Value::number(0.5)));
+        assert_eq!(evaluate("200 + 10%"), Ok(Value::number(220.0)));
+        assert_eq!(evaluate("200 - 10%"), Ok(Value::number(180.0)));
+        assert_eq!(evaluate("200 * 10%"), Ok(Value::number(20.0)));
+        assert_eq!(evaluate("7 % 3"), Ok(Value::number(1.0)));
+        assert_eq!(evaluate("-7 % 3"), Ok(Value::number(2.0)));
+        // a spaced + or - after % is binary, a joined sign belongs to the
+        // divisor
+        assert_eq!(evaluate("10% + 5"), Ok(Value::number(5.1)));
+        assert_eq!(evaluate("50% - 10"), Ok(Value::number(-9.5)));
+        assert_eq!(evaluate("7 % -3"), Ok(Value::number(-2.0)));
+        assert_eq!(evaluate("7 % 0"), Err(CalcError::DivisionByZero(2)));
+    }
+
+    #[test]
+    fn bc_scale() {
+        let at = |scale, input| {
+            let ctx = Context::<crate::Decimal> { precision : scale, truncate : true, ..Context::default() };
+            evaluate_with(input, Dialect::Bc, &ctx).map(|value| value.to_string())
+        };
+        assert_eq!(at(0, "10 / 3"), Ok("3".to_string()));
+        assert_eq!(at(2, "-10 / 3"), Ok("-3.33".to_string()));
+        assert_eq!(at(0, "-7 % 3"), Ok("-1".to_string()));
+        assert_eq!(at(0, "1.5 * 1.5"), Ok("2.2".to_string()));
+        assert_eq!(at(5, "1.5 * 1.5"), Ok("2.25".to_string()));
+        assert_eq!(at(0, "-2^2"), Ok("4".to_string()));
+        assert_eq!(at(3, "2^-3"), Ok("0.125".to_string()));
+        assert_eq!(at(2, "sqrt(2)"), Ok("1.41".to_string()));
+        assert_eq!(at(0, "!0 && 2 || 0"), Ok("1".to_string()));
+    }
+
+    #[test]
+    fn excel_formulas() {
+        let excel = |input| evaluate_with(input, Dialect::Excel, &Context::<f64>::default());
+        assert_eq!(excel("=SUM(1,2,3)"), Ok(Value::number(6.0)));
+        assert_eq!(excel("=average(1, 2, 6)"), Ok(Value::number(3.0)));
+        // the sign before a power is part of its base
+        assert_eq!(excel("=-2^2"), Ok(Value::number(4.0)));
+        assert_eq!(excel("=3 - -2^2"), Ok(Value::number(-1.0)));
+        assert_eq!(excel("=200+10%"), Ok(Value::number(200.1)));
+        assert_eq!(excel("=50%*PI()"), Ok(Value::number(std::f64::consts::FRAC_PI_2)));
+        assert_eq!(excel("=ROUND(2.675,2)"), Ok(Value::number(2.68)));
+        assert_eq!(excel("=ROUND(-1250,-2)"), Ok(Value::number(-1300.0)));
+        assert_eq!(excel("=IF(1<>2,PMT(0,10,1000),TRUE)"), Ok(Value::number(-100.0)));
+        assert_eq!(excel("=IF(1=2,0,FALSE())"), Ok(Value::number(0.0)));
+        assert!(excel("=1&2").is_err());
+    }
+
+    #[test]
+    fn proportions() {
+        assert_eq!(evaluate("solveprop(3/4 = x/12)"), Ok(Value::number(9.0)));
+        assert_eq!(evaluate("solveprop(3:4 = 12:x)"), Ok(Value::number(16.0)));
+        assert_eq!(evaluate("solveprop(2 * x + 1 == 7)"), Ok(Value::number(3.0)));
+        assert!(matches!(evaluate("solveprop(x^2 = 4)"), Err(CalcError::NoConvergence(..))));
+        assert!(matches!(evaluate("solveprop(3:4 = 6:8)"), Err(CalcError::NoConvergence(..))));
+        // the `=` is the call's, not an assignment
+        let mut ctx = Context::<f64>::default();
+        assert_eq!(execute("y = solveprop(5 = x + 2)", Dialect::Standard, &mut ctx), Ok(Outcome::Assigned("y".to_string(), Value::number(3.0))));
+    }
+
+    #[test]
+    fn date_arithmetic() {
+        assert_eq!(evaluate("2024-02-28 + 1 day"), evaluate("2024-02-29"));
+        assert_eq!(evaluate("2023-02-28 + 1 day"), evaluate("2023-03-01"));
+        assert_eq!(evaluate("2024-03-01T12:30 + 90 min"), evaluate("2024-03-01T14:00"));
+        assert_eq!(evaluate("2024-03-01 - 2024-02-01 in days"), Ok(Value::number(29.0)));
+        assert!(matches!(evaluate("2024-03-01 + 2024-03-01"), Err(CalcError::Date(_))));
+    }
+
+    #[test]
+    fn durations() {
+        let shown = |input, notation| evaluate(input).map(|value| format::format_value(&value, notation, format::Style::default()));
+        assert_eq!(shown("3h + 45min", format::Notation::Decimal), Ok("3.75 h".to_string()));
+        assert_eq!(shown("3h + 45min", format::Notation::Clock), Ok("03:45".to_string()));
+        assert_eq!(shown("-90 s", format::Notation::Decimal), Ok("-1.5 min".to_string()));
+        assert_eq!(shown("45 s", format::Notation::Decimal), Ok("45 s".to_string()));
+        assert_eq!(shown("2024-03-04 - 2024-03-01", format::Notation::Decimal), Ok("3 day".to_string()));
+    }
+
+    #[test]
+    fn clock_times() {
+        let shown = |input| {
+            let value = evaluate(input)?;
+            let notation = format::keeping_clock(input, Dialect::Standard, &value, format::Notation::Decimal);
+            Ok::<_, CalcError>(format::format_value(&value, notation, format::Style::default()))
+        };
+        assert_eq!(shown("1:45 + 0:30"), Ok("2:15".to_string()));
+        assert_eq!(shown("17:00 - 8:30"), Ok("8:30".to_string()));
+        assert_eq!(shown("8:30 - 17:00"), Ok("-8:30".to_string()));
+        assert_eq!(shown("0:45 * 3 + 10 s"), Ok("2:15:10".to_string()));
+        assert_eq!(shown("1:30 / 0:45"), Ok("2".to_string()));
+        assert_eq!(shown("90 min"), Ok("1.5 h".to_string()));
+        assert_eq!(evaluate("solveprop(3:45 = x:60)"), Ok(Value::number(4.0)));
+    }
+}
@@ -0,0 +1,251 @@
+use calculator::ast::Binary;
+use calculator::ast::Expr;
+use calculator::ast::Statement;
+use calculator::eval;
+use calculator::parser;
+use calculator::units;
+use calculator::Context;
+use calculator::Dialect;
+use calculator::Number;
+use calculator::Token;
+use calculator::Value;
+
+use crate::messages::Msg;
+
+/*
+What :lint and --lint point out in a statement that is otherwise fine,
+each at the column it is about:
+
+- brackets that change nothing, found by parsing the statement again with
+  the two of them blanked out, which keeps every offset where it was, and
+  getting the same tree; brackets of calls and lists are left alone
+- operations on literals alone, such as the 60 * 60 of 60 * 60 * t, which
+  could be written as their value; a quotient of two numbers is left alone
+  as the way to write a fraction, and a sign or percentage as part of the
+  literal
+- comparisons that always come out the same, as x == x does, or 1 < 2
+- names that hide another: a variable of the session taken as the
+  parameter of a function defined or the name sum, prod and the like bind,
+  or an assignment to the name of a unit
+*/
+
+// The column, counted from 1, and what is wrong there.
+pub type Warning = (usize, String);
+
+fn column(input : &str, offset : usize) -> usize {
+    input.get(..offset).map_or(input.chars().count(), |s| s.chars().count()) + 1
+}
+
+fn comparison(op : Binary) -> bool {
+    matches!(op, Binary::Equal | Binary::NotEqual | Binary::ApproxEq | Binary::Less | Binary::LessEqual | Binary::Greater | Binary::GreaterEqual)
+}
+
+// Whether `expr` is made of numbers written out and nothing else.
+fn literal(expr : &Expr) -> bool {
+    match expr {
+        Expr::Number(_) => true,
+        Expr::UnaryOp { operand, .. } => literal(operand),
+        Expr::BinaryOp { lhs, rhs, .. } => literal(lhs) && literal(rhs),
+        _ => false
+    }
+}
+
+// Whether `expr` has a call in it, which may draw a random number.
+fn calls(expr : &Expr) -> bool {
+    match expr {
+        Expr::Number(_) | Expr::Date(_) | Expr::Variable { .. } => false,
+        Expr::Call { .. } => true,
+        Expr::UnaryOp { operand, .. } => calls(operand),
+        Expr::BinaryOp { lhs, rhs, .. } => calls(lhs) || calls(rhs),
+        Expr::List(items) => items.iter().any(calls),
+        Expr::Index { list, index, .. } => calls(list) || calls(index),
+        Expr::Conditional { cond, then, otherwise } => calls(cond) || calls(then) || calls(otherwise)
+    }
+}
+
+struct Linter<'a, N> {
+    input : &'a str,
+    ctx : &'a Context<N>,
+    warnings : Vec<Warning>
+}
+
+impl<N: Number> Linter<'_, N> {
+    fn warn(&mut self, offset : usize, message : Msg<'_>) {
+        self.warnings.push((column(self.input, offset), message.to_string()));
+    }
+
+    fn shown(&self, expr : &Expr) -> String {
+        eval::evaluate(expr, self.ctx).map_or_else(|_| "?".to_string(), |value| value.to_string())
+    }
+
+    fn expr(&mut self, expr : &Expr) {
+        match expr {
+            Expr::BinaryOp { op, lhs, rhs, offset } if comparison(*op) => {
+                let same = lhs.to_string() == rhs.to_string() && !calls(lhs);
+                if same || (literal(lhs) && literal(rhs)) {
+                    let truth = match eval::evaluate(expr, self.ctx) {
+                        Ok(Value::Scalar(quantity)) => eval::is_true(&quantity.value),
+                        _ => false
+                    };
+                    self.warn(*offset, Msg::LintAlways(truth));
+                    return;
+                }
+                self.expr(lhs);
+                self.expr(rhs);
+            }
+            Expr::BinaryOp { op, lhs, rhs, offset } if literal(expr) => {
+                let fraction = *op == Binary::Div && matches!((&**lhs, &**rhs), (Expr::Number(_), Expr::Number(_)));
+                if !fraction {
+                    let value = self.shown(expr);
+                    self.warn(*offset, Msg::LintConstant(&expr.to_string(), &value));
+                }
+            }
+            Expr::Number(_) | Expr::Date(_) | Expr::Variable { .. } => {}
+            Expr::UnaryOp { operand, .. } => self.expr(operand),
+            Expr::BinaryOp { lhs, rhs, .. } => {
+                self.expr(lhs);
+                self.expr(rhs);
+            }
+            Expr::Call { name, args, .. } => {
+                if eval::binds_names(name, args) {
+                    for arg in args.iter().take(2) {
+                        if let Expr::Variable { name, offset } = arg {
+                            self.hides(name, *offset);
+                        }
+                    }
+                }
+                for arg in args {
+                    self.expr(arg);
+                }
+            }
+            Expr::List(items) => {
+                for item in items {
+                    self.expr(item);
+                }
+            }
+            Expr::Index { list, index, .. } => {
+                self.expr(list);
+                self.expr(index);
+            }
+            Expr::Conditional { cond, then, otherwise } => {
+                self.expr(cond);
+                self.expr(then);
+                self.expr(otherwise);
+            }
+        }
+    }
+
+    // `name` bound at `offset`, where it hides a variable of the session.
+    fn hides(&mut self, name : &str, offset : usize) {
+        if self.ctx.variables.contains_key(name) {
+            self.warn(offset, Msg::LintHidesVariable(name));
+        }
+    }
+
+    // Brackets that can go, parsing the same without them.
+    fn brackets(&mut self, statement : &Statement, dialect : Dialect) {
+        let tokens : Vec<_> = calculator::tokenize(self.input, dialect).collect();
+        for (i, token) in tokens.iter().enumerate() {
+            if token.token != Token::LeftBracket {
+                continue;
+            }
+            // those of a call, as in sin(x), or of the parameters defined
+            let call = i > 0 && matches!(tokens[i - 1].token, Token::Ident(_));
+            if call {
+                continue;
+            }
+            let mut depth = 0;
+            let close = tokens[i..].iter().position(|token| {
+                match token.token {
+                    Token::LeftBracket => depth += 1,
+                    Token::RightBracket => depth -= 1,
+                    _ => {}
+                }
+                depth == 0
+            });
+            let close = match close {
+                Some(close) => &tokens[i + close],
+                None => { return; }
+            };
+            let mut blanked = self.input.to_string();
+            blanked.replace_range(token.start..token.end, " ");
+            blanked.replace_range(close.start..close.end, " ");
+            if parser::parse_statement(calculator::tokenize(&blanked, dialect)).is_ok_and(|reparsed| reparsed == *statement) {
+                self.warn(token.start, Msg::LintBrackets);
+            }
+        }
+    }
+}
+
+// What could be better in `input`, which is one statement, against the
+// session in `ctx`; nothing if it doesn't parse.
+pub fn lint<N: Number>(input : &str, dialect : Dialect, ctx : &Context<N>) -> Vec<Warning> {
+    let statement = match parser::parse_statement(calculator::tokenize(input, dialect)) {
+        Ok(statement) => statement,
+        Err(_) => { return Vec::new(); }
+    };
+    let mut linter = Linter { input, ctx, warnings : Vec::new() };
+    linter.brackets(&statement, dialect);
+    match &statement {
+        Statement::Expr(expr) | Statement::Symbolic(_, expr) => linter.expr(expr),
+        Statement::Assign(name, offset, expr) => {
+            if units::find(name).is_some() {
+                linter.warn(*offset, Msg::LintHidesUnit(name));
+            }
+            linter.expr(expr);
+        }
+        Statement::Define(_, params, body) => {
+            // the parameters as they come after the name
+            let tokens : Vec<_> = calculator::tokenize(input, dialect).collect();
+            for param in params {
+                let at = tokens.iter().skip(1).find(|token| token.token == Token::Ident(param.clone()));
+                if let Some(token) = at {
+                    linter.hides(param, token.start);
+                }
+            }
+            linter.expr(body);
+        }
+    }
+    linter.warnings.sort_by_key(|(column, _)| *column);
+    linter.warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use calculator::Context;
+    use calculator::Dialect;
+    use calculator::Value;
+
+    use super::lint;
+
+    fn warnings(input : &str) -> Vec<String> {
+        let mut ctx = Context::<f64>::default();
+        ctx.variables.insert("x".to_string(), Value::number(2.0));
+        lint(input, Dialect::Standard, &ctx).into_iter().map(|(column, warning)| format!("{}: {}", column, warning)).collect()
+    }
+
+    #[test]
+    fn brackets() {
+        assert_eq!(warnings("(x + 1) * 2"), Vec::<String>::new());
+        assert_eq!(warnings("x + (y * 2)"), ["5: Brackets that change nothing"]);
+        assert_eq!(warnings("((x + y)) * 2"), ["1: Brackets that change nothing", "2: Brackets that change nothing"]);
+        assert_eq!(warnings("sin(x) + [1, 2]"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn constants() {
+        assert_eq!(warnings("60 * 60 * t"), ["4: 60 * 60 could be written as 3600"]);
+        assert_eq!(warnings("1/3 * t - 5% + -2"), Vec::<String>::new());
+        assert_eq!(warnings("x == x"), ["3: Always true"]);
+        assert_eq!(warnings("1 < 2 ? y : x"), ["3: Always true"]);
+        assert_eq!(warnings("rand() == rand()"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(warnings("f(x) = x + 1"), ["3: x hides the variable of the session"]);
+        assert_eq!(warnings("sum(x, 1, 3, x)"), ["5: x hides the variable of the session"]);
+        assert_eq!(warnings("km = 3"), ["1: km hides the unit"]);
+        assert_eq!(warnings("y = x + 1"), Vec::<String>::new());
+    }
+}
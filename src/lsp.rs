@@ -0,0 +1,497 @@
+use std::collections::HashMap;
+use std::io;
+use std::io::BufRead;
+use std::io::Write;
+
+use calculator::ast::Statement;
+use calculator::functions;
+use calculator::lexer::Lexer;
+use calculator::parser;
+use calculator::units;
+use calculator::AngleUnit;
+use calculator::CalcError;
+use calculator::Context;
+use calculator::Dialect;
+use calculator::Token;
+
+use crate::batch;
+use crate::cli::Options;
+use crate::json::Value;
+use crate::mcp;
+use crate::serve;
+
+/*
+A language server (LSP) on stdio for .calc scripts, the files --file runs,
+so that an editor can check them as they are written:
+
+    diagnostics   each statement that fails, as the script would at that
+                  statement, though later lines are still checked
+    hover         the value of the innermost bracket or call around the
+                  cursor that has one, using what earlier lines define, or
+                  else of the whole expression
+    completion    the built-in functions and constants, and the variables
+                  and functions the lines before define
+    formatting    every statement as :pretty writes it, comments kept
+
+Documents are kept whole, as the client sends them on each change. Numbers
+are f64 in the dialect and angle unit given on the command line, and
+evaluation is limited as in the HTTP server, since it runs as the user
+types. Formatting is only offered in the standard dialect, the one that
+expressions are written in. Columns are UTF-16 code units, as the
+protocol counts them unless told otherwise.
+*/
+
+const SERVER_NOT_INITIALIZED : i32 = -32002;
+
+// Kinds of diagnostics and completion items, as the protocol numbers them.
+const ERROR : f64 = 1.0;
+const FUNCTION : f64 = 3.0;
+const VARIABLE : f64 = 6.0;
+const CONSTANT : f64 = 21.0;
+
+// A statement that failed, with the line it is on and its span there.
+struct Problem {
+    line : usize,
+    start : usize,
+    end : usize,
+    error : CalcError
+}
+
+struct Server {
+    dialect : Dialect,
+    angle : AngleUnit,
+    documents : HashMap<String, String>,
+    initialized : bool,
+    shut_down : bool
+}
+
+// The character of `byte` in `line`, and the other way round.
+fn character(line : &str, byte : usize) -> usize {
+    line[..byte.min(line.len())].encode_utf16().count()
+}
+
+fn byte(line : &str, character : usize) -> usize {
+    let mut units = 0;
+    for (i, c) in line.char_indices() {
+        if units >= character {
+            return i;
+        }
+        units += c.len_utf16();
+    }
+    line.len()
+}
+
+fn position(line : usize, character : usize) -> Value {
+    Value::object(vec![("line", Value::Number(line as f64)), ("character", Value::Number(character as f64))])
+}
+
+fn range(n : usize, line : &str, start : usize, end : usize) -> Value {
+    Value::object(vec![("start", position(n, character(line, start))), ("end", position(n, character(line, end)))])
+}
+
+fn number(value : Option<&Value>) -> Option<usize> {
+    match value {
+        Some(Value::Number(n)) if *n >= 0.0 => Some(*n as usize),
+        _ => None
+    }
+}
+
+fn notification(method : &str, params : Value) -> Value {
+    Value::object(vec![("jsonrpc", Value::string("2.0")), ("method", Value::string(method)), ("params", params)])
+}
+
+// The statement `statement` as :pretty writes it, with what converts it
+// kept as it is. None if it doesn't parse.
+fn pretty(statement : &str, dialect : Dialect) -> Option<String> {
+    let (expr, conversion) = units::split_conversion(statement, dialect);
+    let shown = match parser::parse_statement(calculator::tokenize(expr, dialect)).ok()? {
+        Statement::Expr(expr) => expr.to_string(),
+        Statement::Assign(name, _, expr) => format!("{} = {}", name, expr),
+        Statement::Define(name, params, expr) => format!("{}({}) = {}", name, params.join(", "), expr),
+        Statement::Symbolic(..) => expr.trim().to_string()
+    };
+    match conversion {
+        Some(_) => Some(format!("{} {}", shown, statement[expr.len()..].trim())),
+        None => Some(shown)
+    }
+}
+
+// The expressions around `at` in `statement` that hover may show, the
+// innermost first: each bracket holding `at`, with the name before it if
+// it is a call, then what the statement computes.
+fn around(statement : &str, at : usize, dialect : Dialect) -> Vec<(usize, usize)> {
+    let tokens = Lexer::new(statement, dialect).collect::<Vec<_>>();
+    let mut spans = Vec::new();
+    let mut open = Vec::new();
+    let mut whole = 0;
+    for (i, token) in tokens.iter().enumerate() {
+        match token.token {
+            Token::LeftBracket => {
+                let call = i > 0 && matches!(tokens[i - 1].token, Token::Ident(_)) && tokens[i - 1].end == token.start;
+                open.push(if call { tokens[i - 1].start } else { token.start });
+            }
+            Token::RightBracket => {
+                if let Some(start) = open.pop() {
+                    if start <= at && at < token.end {
+                        spans.push((start, token.end));
+                    }
+                }
+            }
+            Token::Assign if open.is_empty() => { whole = token.end; }
+            _ => {}
+        }
+    }
+    spans.push((whole, statement.len()));
+    spans
+}
+
+impl Server {
+    fn context(&self) -> Context<f64> {
+        Context { angle : self.angle, max_steps : Some(serve::MAX_STEPS), time_limit : Some(serve::EVALUATION_TIME), ..Context::default() }
+    }
+
+    // Runs the lines of `text` before `until`, giving the session they
+    // leave and the statements that failed on the way.
+    fn run(&self, text : &str, until : usize) -> (Context<f64>, Vec<Problem>) {
+        let mut ctx = self.context();
+        let mut errors = Vec::new();
+        for (n, line) in text.lines().enumerate().take(until) {
+            for (start, statement) in batch::statements(line, self.dialect) {
+                if statement.trim().is_empty() {
+                    continue;
+                }
+                match calculator::execute(statement, self.dialect, &mut ctx) {
+                    Ok(calculator::Outcome::Value(value)) => ctx.results.push(value),
+                    Ok(_) => {}
+                    Err(e) => {
+                        let at = (start + e.offset()).min(line.len());
+                        let end = line[at..].chars().next().map_or(at, |c| at + c.len_utf8());
+                        errors.push(Problem { line : n, start : at, end, error : e });
+                    }
+                }
+            }
+        }
+        (ctx, errors)
+    }
+
+    fn diagnostics(&self, uri : &str) -> Value {
+        let text = self.documents.get(uri).map_or("", |text| text.as_str());
+        let lines = text.lines().collect::<Vec<_>>();
+        let diagnostics = self.run(text, lines.len()).1.into_iter().map(|problem| Value::object(vec![
+            ("range", range(problem.line, lines[problem.line], problem.start, problem.end)),
+            ("severity", Value::Number(ERROR)),
+            ("code", Value::string(problem.error.kind())),
+            ("source", Value::string("calculator")),
+            ("message", Value::string(&problem.error.to_string())),
+        ])).collect();
+        notification("textDocument/publishDiagnostics", Value::object(vec![("uri", Value::string(uri)), ("diagnostics", Value::Array(diagnostics))]))
+    }
+
+    // The text of the document a request is about, and the line and the
+    // byte on it of its position.
+    fn at<'a>(&'a self, params : Option<&Value>) -> Option<(&'a str, usize, usize)> {
+        let uri = params?.get("textDocument")?.get("uri")?.as_str()?;
+        let text = self.documents.get(uri)?;
+        let position = params?.get("position")?;
+        let n = number(position.get("line"))?;
+        let line = text.lines().nth(n).unwrap_or("");
+        Some((text, n, byte(line, number(position.get("character"))?)))
+    }
+
+    fn hover(&self, params : Option<&Value>) -> Value {
+        let (text, n, at) = match self.at(params) {
+            Some(at) => at,
+            None => { return Value::Null; }
+        };
+        let line = text.lines().nth(n).unwrap_or("");
+        let ctx = self.run(text, n).0;
+        let statement = batch::statements(line, self.dialect).into_iter().find(|(start, statement)| *start <= at && at <= start + statement.len());
+        let (offset, statement) = match statement {
+            Some(statement) => statement,
+            None => { return Value::Null; }
+        };
+
+        let expr = units::split_conversion(statement, self.dialect).0;
+        for (start, end) in around(expr, at - offset, self.dialect) {
+            let shown = expr[start..end].trim();
+            if shown.is_empty() {
+                continue;
+            }
+            if let Ok(value) = calculator::evaluate_with(shown, self.dialect, &ctx) {
+                let contents = Value::object(vec![("kind", Value::string("markdown")), ("value", Value::string(&format!("`{}` = {}", shown, serve::shown(&value, shown))))]);
+                return Value::object(vec![("contents", contents), ("range", range(n, line, offset + start, offset + end))]);
+            }
+        }
+        Value::Null
+    }
+
+    fn completion(&self, params : Option<&Value>) -> Value {
+        let (text, n, _) = match self.at(params) {
+            Some(at) => at,
+            None => { return Value::Array(Vec::new()); }
+        };
+        let ctx = self.run(text, n).0;
+
+        let mut items = functions::names().map(|name| (name.to_string(), FUNCTION, None)).collect::<Vec<_>>();
+        items.extend(functions::CONSTANTS.iter().map(|c| (c.name.to_string(), CONSTANT, None)));
+        items.extend(ctx.functions.iter().map(|(name, function)| (name.clone(), FUNCTION, Some(format!("{}({})", name, function.params.join(", "))))));
+        items.extend(ctx.variables.keys().map(|name| (name.clone(), VARIABLE, None)));
+        items.sort_by(|a, b| a.0.cmp(&b.0));
+
+        Value::Array(items.into_iter().map(|(label, kind, detail)| {
+            let mut members = vec![("label", Value::string(&label)), ("kind", Value::Number(kind))];
+            if let Some(detail) = detail {
+                members.push(("detail", Value::string(&detail)));
+            }
+            Value::object(members)
+        }).collect())
+    }
+
+    fn formatting(&self, params : Option<&Value>) -> Value {
+        let text = match params.and_then(|p| p.get("textDocument")).and_then(|d| d.get("uri")).and_then(|uri| uri.as_str()) {
+            Some(uri) if self.dialect == Dialect::Standard => self.documents.get(uri).map_or("", |text| text.as_str()),
+            _ => { return Value::Null; }
+        };
+
+        let mut formatted = Vec::new();
+        for line in text.lines() {
+            let statements = batch::statements(line, self.dialect);
+            let mut shown = Vec::new();
+            for (_, statement) in &statements {
+                if !statement.trim().is_empty() {
+                    // a script that doesn't parse is left as it is
+                    match pretty(statement, self.dialect) {
+                        Some(pretty) => shown.push(pretty),
+                        None => { return Value::Null; }
+                    }
+                }
+            }
+            let (start, last) = statements.last().copied().unwrap_or((0, ""));
+            let comment = line[start + last.len()..].trim();
+            formatted.push(match (shown.is_empty(), comment.is_empty()) {
+                (true, _) => comment.to_string(),
+                (false, true) => shown.join("; "),
+                (false, false) => format!("{}  {}", shown.join("; "), comment)
+            });
+        }
+        let mut formatted = formatted.join("\n");
+        if text.ends_with('\n') {
+            formatted.push('\n');
+        }
+
+        let end = position(text.lines().count(), 0);
+        let edit = Value::object(vec![
+            ("range", Value::object(vec![("start", position(0, 0)), ("end", end)])),
+            ("newText", Value::string(&formatted)),
+        ]);
+        Value::Array(vec![edit])
+    }
+
+    fn open(&mut self, params : Option<&Value>, text : Option<&str>) -> Vec<Value> {
+        match (params.and_then(|p| p.get("textDocument")).and_then(|d| d.get("uri")).and_then(|uri| uri.as_str()), text) {
+            (Some(uri), Some(text)) => {
+                self.documents.insert(uri.to_string(), text.to_string());
+                vec![self.diagnostics(uri)]
+            }
+            _ => Vec::new()
+        }
+    }
+
+    // What to send for the message `message`: the response to a request,
+    // and notifications of diagnostics for documents it changed.
+    fn handle(&mut self, message : &str) -> Vec<Value> {
+        let request = match crate::json::parse(message) {
+            Some(request) => request,
+            None => { return vec![mcp::error_response(Value::Null, mcp::PARSE_ERROR, "Parse error")]; }
+        };
+        let params = request.get("params");
+        let method = request.get("method").and_then(|m| m.as_str()).unwrap_or("");
+        let id = match request.get("id") {
+            Some(Value::Number(n)) => Some(Value::Number(*n)),
+            Some(Value::String(s)) => Some(Value::string(s)),
+            _ => None
+        };
+
+        let id = match (id, method) {
+            (Some(id), "initialize") => {
+                self.initialized = true;
+                let capabilities = Value::object(vec![
+                    // the whole document on each change
+                    ("textDocumentSync", Value::Number(1.0)),
+                    ("hoverProvider", Value::Bool(true)),
+                    ("completionProvider", Value::object(vec![])),
+                    ("documentFormattingProvider", Value::Bool(self.dialect == Dialect::Standard)),
+                ]);
+                let info = Value::object(vec![("name", Value::string("calculator")), ("version", Value::string(env!("CARGO_PKG_VERSION")))]);
+                return vec![mcp::response(id, Value::object(vec![("capabilities", capabilities), ("serverInfo", info)]))];
+            }
+            (Some(id), _) if !self.initialized => {
+                return vec![mcp::error_response(id, SERVER_NOT_INITIALIZED, "Server not initialized")];
+            }
+            (Some(id), _) => id,
+            (None, "textDocument/didOpen") => {
+                let text = params.and_then(|p| p.get("textDocument")).and_then(|d| d.get("text")).and_then(|text| text.as_str());
+                return self.open(params, text);
+            }
+            (None, "textDocument/didChange") => {
+                // with the whole document in each change, the last is all
+                // that counts
+                let text = match params.and_then(|p| p.get("contentChanges")) {
+                    Some(Value::Array(changes)) => changes.last().and_then(|change| change.get("text")).and_then(|text| text.as_str()),
+                    _ => None
+                };
+                return self.open(params, text);
+            }
+            (None, "textDocument/didClose") => {
+                if let Some(uri) = params.and_then(|p| p.get("textDocument")).and_then(|d| d.get("uri")).and_then(|uri| uri.as_str()) {
+                    self.documents.remove(uri);
+                    return vec![self.diagnostics(uri)];
+                }
+                return Vec::new();
+            }
+            // notifications otherwise need nothing said, and `exit` is seen
+            // to by `run`
+            (None, _) => { return Vec::new(); }
+        };
+
+        vec![match method {
+            "shutdown" => {
+                self.shut_down = true;
+                mcp::response(id, Value::Null)
+            }
+            "textDocument/hover" => mcp::response(id, self.hover(params)),
+            "textDocument/completion" => mcp::response(id, self.completion(params)),
+            "textDocument/formatting" => mcp::response(id, self.formatting(params)),
+            _ => mcp::error_response(id, mcp::METHOD_NOT_FOUND, "Method not found")
+        }]
+    }
+}
+
+// The body of the next message, after a Content-Length header; None at
+// the end of the input.
+fn read_message(input : &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut length = None;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        if line.trim().is_empty() {
+            if length.is_some() {
+                break;
+            }
+            continue;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+
+    let mut body = vec![0; length.unwrap_or(0)];
+    input.read_exact(&mut body)?;
+    Ok(Some(String::from_utf8_lossy(&body).into_owned()))
+}
+
+// Returns the process exit code: 0 if the client shut the server down
+// before telling it to exit, as it should, and 1 otherwise.
+pub fn run(options : &Options) -> i32 {
+    let mut server = Server { dialect : options.dialect, angle : options.angle, documents : HashMap::new(), initialized : false, shut_down : false };
+    let mut stdin = io::stdin().lock();
+    let mut stdout = io::stdout();
+
+    while let Some(message) = read_message(&mut stdin).expect("Something wrong") {
+        if crate::json::parse(&message).and_then(|m| m.get("method").and_then(|m| m.as_str()).map(|m| m == "exit")) == Some(true) {
+            break;
+        }
+        for reply in server.handle(&message) {
+            let reply = reply.to_string();
+            write!(stdout, "Content-Length: {}\r\n\r\n{}", reply.len(), reply).expect("Something wrong");
+        }
+        stdout.flush().expect("Something wrong");
+    }
+
+    if server.shut_down { 0 } else { 1 }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use calculator::AngleUnit;
+    use calculator::Dialect;
+
+    use super::read_message;
+    use super::Server;
+
+    const SCRIPT : &str = "x = 2*(3 + 4)  // seven twice\\ny = x +\\nsqrt( x+2 )";
+
+    fn server() -> Server {
+        let mut server = Server { dialect : Dialect::Standard, angle : AngleUnit::Radians, documents : HashMap::new(), initialized : false, shut_down : false };
+        server.handle(r#"{"jsonrpc": "2.0", "id": 0, "method": "initialize", "params": {}}"#);
+        server.handle(&format!(r#"{{"jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {{"textDocument": {{"uri": "a.calc", "text": "{}"}}}}}}"#, SCRIPT));
+        server
+    }
+
+    fn request(server : &mut Server, method : &str, line : usize, character : usize) -> String {
+        let message = format!(r#"{{"jsonrpc": "2.0", "id": 1, "method": "{}", "params": {{"textDocument": {{"uri": "a.calc"}}, "position": {{"line": {}, "character": {}}}}}}}"#, method, line, character);
+        server.handle(&message).iter().map(|reply| reply.to_string()).collect()
+    }
+
+    #[test]
+    fn diagnostics() {
+        let mut server = Server { dialect : Dialect::Standard, angle : AngleUnit::Radians, documents : HashMap::new(), initialized : false, shut_down : false };
+        assert!(request(&mut server, "textDocument/hover", 0, 0).contains("-32002"));
+        server.handle(r#"{"jsonrpc": "2.0", "id": 0, "method": "initialize"}"#);
+        let opened = server.handle(&format!(r#"{{"jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {{"textDocument": {{"uri": "a.calc", "text": "{}"}}}}}}"#, SCRIPT));
+        assert_eq!(opened.len(), 1);
+        let opened = opened[0].to_string();
+        assert!(opened.contains(r#""uri":"a.calc""#));
+        assert!(opened.contains(r#""range":{"start":{"line":1,"character":7},"end":{"line":1,"character":7}}"#));
+        assert!(opened.contains(r#""code":"unexpected_end""#));
+    }
+
+    #[test]
+    fn hover() {
+        let mut server = server();
+        // inside the bracket, then outside it
+        assert!(request(&mut server, "textDocument/hover", 0, 8).contains(r#""value":"`(3 + 4)` = 7""#));
+        assert!(request(&mut server, "textDocument/hover", 0, 4).contains(r#""value":"`2*(3 + 4)` = 14""#));
+        // a call, with x from the line before
+        let hover = request(&mut server, "textDocument/hover", 2, 7);
+        assert!(hover.contains(r#""value":"`sqrt( x+2 )` = 4""#));
+        assert!(hover.contains(r#""range":{"start":{"line":2,"character":0},"end":{"line":2,"character":11}}"#));
+    }
+
+    #[test]
+    fn completion() {
+        let mut server = server();
+        let before = request(&mut server, "textDocument/completion", 0, 0);
+        assert!(before.contains(r#"{"label":"sqrt","kind":3}"#));
+        assert!(before.contains(r#"{"label":"pi","kind":21}"#));
+        assert!(!before.contains(r#""label":"x""#));
+        assert!(request(&mut server, "textDocument/completion", 2, 0).contains(r#"{"label":"x","kind":6}"#));
+    }
+
+    #[test]
+    fn formatting() {
+        let mut server = server();
+        let edits = server.handle(r#"{"jsonrpc": "2.0", "id": 2, "method": "textDocument/formatting", "params": {"textDocument": {"uri": "a.calc"}}}"#);
+        // the second line doesn't parse
+        assert_eq!(edits[0].to_string(), r#"{"jsonrpc":"2.0","id":2,"result":null}"#);
+
+        server.handle(r#"{"jsonrpc": "2.0", "method": "textDocument/didChange", "params": {"textDocument": {"uri": "a.calc"}, "contentChanges": [{"text": "x = 2*(3 + 4)  // seven twice\nsqrt( x+2 ) in km;(1)\n"}]}}"#);
+        let edits = server.handle(r#"{"jsonrpc": "2.0", "id": 3, "method": "textDocument/formatting", "params": {"textDocument": {"uri": "a.calc"}}}"#);
+        assert!(edits[0].to_string().contains(r#""newText":"x = 2 * (3 + 4)  // seven twice\nsqrt(x + 2) in km; 1\n""#));
+    }
+
+    #[test]
+    fn framing() {
+        let mut input = "Content-Length: 2\r\n\r\n{}Content-Length: 4\r\nContent-Type: application/vscode-jsonrpc\r\n\r\nnull".as_bytes();
+        assert_eq!(read_message(&mut input).ok().flatten().as_deref(), Some("{}"));
+        assert_eq!(read_message(&mut input).ok().flatten().as_deref(), Some("null"));
+        assert_eq!(read_message(&mut input).ok().flatten(), None);
+    }
+}
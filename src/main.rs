@@ -1,222 +1,113 @@
-use std::str;
-use std::fmt;
 use std::io;
-use std::io::Write;
-use std::iter;
-
-enum Token {
-    Number(f64),
-    LeftBracket,
-    RightBracket,
-    Add,
-    Sub,
-    Mul,
-    Div,
-    Invalid(char)
+use std::io::IsTerminal;
+use std::process;
+
+use calculator::Dialect;
+
+mod aggregate;
+mod ast_json;
+mod batch;
+mod bc;
+mod cli;
+mod crosscheck;
+mod csv;
+mod editor;
+mod equiv;
+mod fields;
+mod grid;
+mod highlight;
+mod json;
+mod jupyter;
+mod lint;
+mod lsp;
+mod mapped;
+mod mcp;
+mod messages;
+mod metrics;
+mod repl;
+mod report;
+mod rpc;
+mod rpn;
+mod serve;
+mod session_json;
+mod ws;
+
+// All of it on the stack the library evaluates on, rather than starting a
+// thread of that for each line.
+fn main() {
+    calculator::stack::run(|_| run());
 }
 
-impl fmt::Display for Token {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Token::Number(v) => write!(f, "{}", v),
-            Token::LeftBracket => write!(f, "("),
-            Token::RightBracket => write!(f, ")"),
-            Token::Add => write!(f, "+"),
-            Token::Sub => write!(f, "-"),
-            Token::Mul => write!(f, "*"),
-            Token::Div => write!(f, "/"),
-            Token::Invalid(c) => write!(f, "Invalid({})", c),
+fn run() {
+    messages::init_from_env();
+
+    let options = match cli::parse_args() {
+        Ok(cli::Command::Run(options)) => *options,
+        Ok(cli::Command::Help) => {
+            print!("{}", cli::usage());
+            return;
         }
-    }
-}
+        Ok(cli::Command::Completions(shell)) => {
+            print!("{}", cli::completions(&shell));
+            return;
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("{}", messages::Msg::TryHelp);
+            process::exit(2);
+        }
+    };
 
-fn get_first_number(iter : &mut iter::Peekable<str::Chars>) -> Option<f64> {
-    let mut num = String::with_capacity(8);
-   
-    while let Some(c) = iter.next_if(|c| c.is_numeric() || *c == '.') {
-        num.push(c);
+    if options.mcp {
+        mcp::run();
+        return;
     }
 
-    if let Ok(value) = num.parse::<f64>() {
-        Some(value)
+    if options.rpc {
+        rpc::run(&options);
+        return;
     }
-    else {
-        None
-    }
-}
 
-fn get_token(iter : &mut iter::Peekable<str::Chars>) -> Option<Token> {
-    loop {
-        if let None = iter.next_if(|c| c.is_whitespace()) {
-            break;
-        }
+    if options.lsp {
+        process::exit(lsp::run(&options));
     }
 
-    if let Some(value) = get_first_number(iter) {
-        return Some(Token::Number(value));
+    if let Some(address) = &options.serve {
+        process::exit(serve::run(address));
     }
 
-    let c = match iter.next() {
-        Some(v) => v,
-        None => { return None; }
-    };
-
-    let token = match c {
-        '(' => Token::LeftBracket,
-        ')' => Token::RightBracket,
-        '+' => Token::Add,
-        '-' => Token::Sub,
-        '*' => Token::Mul,
-        '/' => Token::Div,
-        other => Token::Invalid(other)
-    };
-
-    Some(token)
-}
-
-fn tokenize(s : String) -> Vec<Token> {
-    let mut tokens = Vec::<Token>::new();
-    let mut iter = s.chars().peekable();
-    loop {
-        let token = get_token(&mut iter);
-        match token {
-            Some(t) => { tokens.push(t); }
-            None => { break; }
-        };
+    if let Some(address) = &options.ws {
+        process::exit(ws::run(address));
     }
 
-    tokens
-}
-
-/*
-grammar:
-    factor = number
-    factor = (expression)
-    factor = + factor
-    factor = - factor
-*/
-fn evaluate_factor<T>(tokens : &mut iter::Peekable<T>) -> Option<f64>
-where T: Iterator<Item = Token>
-{
-    if let Some(token) = tokens.next() {
-        match token {
-            Token::Number(value) => Some(value),
-            Token::LeftBracket => {
-                let value = evaluate_expression(tokens)?;
-                if let Some(token) = tokens.next() {
-                    match token {
-                        Token::RightBracket => Some(value),
-                        _ => None
-                    }
-                }
-                else {
-                    None
-                }
-            }
-            Token::Add => evaluate_factor(tokens),
-            Token::Sub => {
-                match evaluate_factor(tokens) {
-                    Some(value) => Some(-value),
-                    None => None
-                }
-            }
-            _ => None
-        }
-    }
-    else {
-        None
+    if let Some(path) = &options.jupyter {
+        process::exit(jupyter::run(&options, path));
     }
-}
 
-/*
-grammar:
-    term = factor
-    term' = * factor term'
-          | / factor term
-          | nothing
-*/
-fn evaluate_term<T>(tokens : &mut iter::Peekable<T>) -> Option<f64>
-where T: Iterator<Item = Token>
-{
-    let mut value = evaluate_factor(tokens)?;
-
-    loop {
-        if let Some(token) = tokens.peek() {
-            match token {
-                Token::Mul => {
-                    tokens.next()?;
-                    value *= evaluate_factor(tokens)?;
-                }
-                Token::Div => {
-                    tokens.next()?;
-                    value /= evaluate_factor(tokens)?;
-                }
-                _ => { return Some(value); }
-            }
-        }
-        else {
-            return Some(value);
-        }
+    if options.grid {
+        process::exit(grid::run(&options));
     }
-}
 
-/*
-grammar:
-    expression = term expression'
-    expression' = + term expression'
-                | - term expression'
-                | nothing
-*/
-fn evaluate_expression<T>(tokens : &mut iter::Peekable<T>) -> Option<f64>
-where T: Iterator<Item = Token>
-{
-    let mut value = evaluate_term(tokens)?;
-
-    loop {
-        if let Some(token) = tokens.peek() {
-            match token {
-                Token::Add => {
-                    tokens.next()?;
-                    value += evaluate_term(tokens)?;
-                }
-                Token::Sub => {
-                    tokens.next()?;
-                    value -= evaluate_term(tokens)?;
-                }
-                _ => { return Some(value); }
-            }
-        }
-        else {
-            return Some(value);
-        }
+    if let Some(aggregates) = &options.aggregate {
+        process::exit(aggregate::run(aggregates, options.dialect, options.angle, options.profile));
     }
-}
 
-fn evaluate<T>(tokens : T) -> Option<f64> where T: iter::Iterator<Item = Token> {
-    let mut tokens = tokens.into_iter().peekable();
-    let val = evaluate_expression(&mut tokens)?;
-    match tokens.next() {
-        None => Some(val),
-        Some(_) => None
+    if let (Some(path), Some(expr)) = (&options.csv, &options.expr) {
+        process::exit(csv::run(&options, path, expr));
     }
-}
-
-fn main() {
-    loop {
-        let mut input = String::new();
-
-        print!(">> ");
-        if let Err(e) = io::stdout().flush() {
-            panic!("Flush error: {}", e);
-        }
 
-        io::stdin().read_line(&mut input).expect("Something wrong");
+    if let Some(expr) = &options.fields {
+        process::exit(fields::run(&options, expr));
+    }
 
-        let tokens = tokenize(input).into_iter();
+    if let Some(path) = &options.file {
+        process::exit(batch::run_file(&options, path));
+    }
 
-        match evaluate(tokens) {
-            Some(value) => println!("{}", value),
-            None => println!("Syntax error")
-        }
+    // bc reads its input line by line, at a terminal too
+    if !options.expressions.is_empty() || !io::stdin().is_terminal() || options.dialect == Dialect::Bc {
+        process::exit(batch::run(&options));
     }
+
+    repl::run(&options);
 }
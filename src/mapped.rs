@@ -0,0 +1,128 @@
+use std::fs;
+use std::io;
+use std::str;
+
+/*
+A script file for --file, mapped into memory rather than read into a String,
+so that one generated to hundreds of megabytes costs no more memory than
+its longest line while it is evaluated: the pages are the file's own, read
+in as the lines get to them and dropped again by the system when it needs
+the memory, as nothing is written to them. The system is told the file is
+read front to back, so it reads ahead and lets go of what is behind.
+
+That is mmap on Unix, called directly as the crate has no dependencies.
+Elsewhere, and for a file that can't be mapped such as a pipe, the file is
+read whole after all. An empty file isn't mapped either, as mmap refuses a
+length of 0.
+*/
+
+#[cfg(unix)]
+mod unix {
+    use std::ffi::c_int;
+    use std::ffi::c_long;
+    use std::ffi::c_void;
+
+    pub const PROT_READ : c_int = 1;
+    pub const MAP_PRIVATE : c_int = 2;
+    pub const MADV_SEQUENTIAL : c_int = 2;
+
+    extern "C" {
+        pub fn mmap(addr : *mut c_void, len : usize, prot : c_int, flags : c_int, fd : c_int, offset : c_long) -> *mut c_void;
+        pub fn munmap(addr : *mut c_void, len : usize) -> c_int;
+        pub fn madvise(addr : *mut c_void, len : usize, advice : c_int) -> c_int;
+    }
+}
+
+enum Bytes {
+    #[cfg(unix)]
+    Mapped(*const u8, usize),
+    Read(Vec<u8>)
+}
+
+pub struct Script {
+    bytes : Bytes
+}
+
+impl Script {
+    pub fn open(path : &str) -> io::Result<Script> {
+        let mut file = fs::File::open(path)?;
+        #[cfg(unix)]
+        {
+            use std::os::fd::AsRawFd;
+
+            let len = file.metadata()?.len() as usize;
+            if len > 0 {
+                // a private, read-only map of the whole file, which stays
+                // when the file is closed until drop unmaps it
+                let addr = unsafe { unix::mmap(std::ptr::null_mut(), len, unix::PROT_READ, unix::MAP_PRIVATE, file.as_raw_fd(), 0) };
+                if addr as isize != -1 {
+                    // only advice, which can go unheeded
+                    unsafe { unix::madvise(addr, len, unix::MADV_SEQUENTIAL) };
+                    return Ok(Script { bytes : Bytes::Mapped(addr as *const u8, len) });
+                }
+            }
+        }
+        let mut bytes = Vec::new();
+        io::Read::read_to_end(&mut file, &mut bytes)?;
+        Ok(Script { bytes : Bytes::Read(bytes) })
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        match &self.bytes {
+            #[cfg(unix)]
+            Bytes::Mapped(addr, len) => unsafe { std::slice::from_raw_parts(*addr, *len) },
+            Bytes::Read(bytes) => bytes
+        }
+    }
+}
+
+impl Drop for Script {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Bytes::Mapped(addr, len) = self.bytes {
+            unsafe { unix::munmap(addr as *mut _, len) };
+        }
+    }
+}
+
+// The lines of `bytes` without their \n or \r\n, as str::lines has them,
+// each as text or the column, counted from 1, where it stops being UTF-8.
+pub fn lines(bytes : &[u8]) -> impl Iterator<Item = Result<&str, usize>> {
+    let lines = (!bytes.is_empty()).then(|| bytes.strip_suffix(b"\n").unwrap_or(bytes).split(|b| *b == b'\n'));
+    lines.into_iter().flatten().map(|line| {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        str::from_utf8(line).map_err(|e| str::from_utf8(&line[..e.valid_up_to()]).map_or(0, |valid| valid.chars().count()) + 1)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::lines;
+    use super::Script;
+
+    #[test]
+    fn mapped() {
+        let path = std::env::temp_dir().join(format!("calculator-mapped-{}.calc", std::process::id()));
+        fs::write(&path, "x = 1\r\nx + 1\n").unwrap();
+        let script = Script::open(path.to_str().unwrap()).unwrap();
+        assert_eq!(script.bytes(), b"x = 1\r\nx + 1\n");
+        drop(script);
+
+        fs::write(&path, "").unwrap();
+        assert_eq!(Script::open(path.to_str().unwrap()).unwrap().bytes(), b"");
+        fs::remove_file(&path).unwrap();
+        assert!(Script::open(path.to_str().unwrap()).is_err());
+    }
+
+    #[test]
+    fn split() {
+        let text = |bytes : &[u8]| lines(bytes).map(|line| line.map(str::to_string)).collect::<Vec<_>>();
+        assert_eq!(text(b"a\r\nb\n\nc"), [Ok("a".to_string()), Ok("b".to_string()), Ok(String::new()), Ok("c".to_string())]);
+        assert_eq!(text(b""), []);
+        assert_eq!(text(b"\n"), [Ok(String::new())]);
+        assert_eq!(text("√2 \u{d7}".as_bytes()), [Ok("√2 \u{d7}".to_string())]);
+        assert_eq!(text(b"\xe2\x88\x9a2 +\xff 2"), [Err(5)]);
+    }
+}
@@ -0,0 +1,222 @@
+use crate::ast::Binary;
+use crate::eval;
+use crate::functions::MatrixOperation;
+use crate::number::Number;
+use crate::units::Dimension;
+use crate::units::Quantity;
+use crate::CalcError;
+use crate::Rational;
+use crate::Value;
+
+/*
+A matrix is a list of rows of the same length, as in [[1, 2], [3, 4]]; a
+list of single values is a matrix of one row. Multiplied by a matrix, such
+a list is taken as a column on the right, so matmul(m, [1, 2]) is again a
+list. Element-wise arithmetic needs nothing from here, [[1, 2], [3, 4]] * 2
+already works on lists.
+
+It's all computed in the context's number type, so with rationals an
+inverse comes out exact. The elements of a matrix being multiplied may be
+of any dimensions that add up; for a determinant or inverse they all have
+to be of one.
+*/
+
+type Rows<N> = Vec<Vec<Quantity<N>>>;
+
+// The rows of `value`, and whether it was a plain list of values.
+fn rows<N>(value : Value<N>, offset : usize) -> Result<(Rows<N>, bool), CalcError> {
+    let items = match value {
+        Value::List(items) if !items.is_empty() => items,
+        _ => { return Err(CalcError::Matrix(offset)); }
+    };
+    if items.iter().all(|item| matches!(item, Value::Scalar(_))) {
+        let row = items.into_iter().flat_map(Value::flatten).collect();
+        return Ok((vec![row], true));
+    }
+
+    let mut rows = Vec::with_capacity(items.len());
+    for item in items {
+        let row = match item {
+            Value::List(row) if !row.is_empty() => row,
+            _ => { return Err(CalcError::Matrix(offset)); }
+        };
+        let row = row.into_iter().map(|element| match element {
+            Value::Scalar(quantity) => Ok(quantity),
+            Value::List(_) => Err(CalcError::Matrix(offset))
+        });
+        rows.push(row.collect::<Result<Vec<_>, _>>()?);
+    }
+    if rows.iter().any(|row| row.len() != rows[0].len()) {
+        return Err(CalcError::Matrix(offset));
+    }
+    Ok((rows, false))
+}
+
+fn to_value<N>(rows : Rows<N>) -> Value<N> {
+    Value::List(rows.into_iter().map(|row| Value::List(row.into_iter().map(Value::Scalar).collect())).collect())
+}
+
+fn transpose<N>(rows : Rows<N>) -> Rows<N> {
+    let mut columns = (0..rows[0].len()).map(|_| Vec::with_capacity(rows.len())).collect::<Vec<_>>();
+    for row in rows {
+        for (column, element) in columns.iter_mut().zip(row) {
+            column.push(element);
+        }
+    }
+    columns
+}
+
+fn multiply<N: Number>(lhs : Value<N>, rhs : Value<N>, precision : usize, offset : usize) -> Result<Value<N>, CalcError> {
+    let (lhs, row) = rows(lhs, offset)?;
+    let (rhs, column) = rows(rhs, offset)?;
+    let columns = if column { rhs } else { transpose(rhs) };
+    if lhs[0].len() != columns[0].len() {
+        return Err(CalcError::Matrix(offset));
+    }
+
+    let mut product = Vec::with_capacity(lhs.len());
+    for row in &lhs {
+        let mut elements = Vec::with_capacity(columns.len());
+        for column in &columns {
+            let mut element = eval::apply(Binary::Mul, &row[0], &column[0], precision, offset)?;
+            for (a, b) in row.iter().zip(column).skip(1) {
+                let term = eval::apply(Binary::Mul, a, b, precision, offset)?;
+                element = eval::apply(Binary::Add, &element, &term, precision, offset)?;
+            }
+            elements.push(element);
+        }
+        product.push(elements);
+    }
+
+    // a row times a column is a single value, and otherwise a list stays one
+    match (row, column) {
+        (true, true) => Ok(Value::Scalar(product.swap_remove(0).swap_remove(0))),
+        (true, false) => Ok(Value::List(product.swap_remove(0).into_iter().map(Value::Scalar).collect())),
+        (false, true) => Ok(Value::List(product.into_iter().flatten().map(Value::Scalar).collect())),
+        (false, false) => Ok(to_value(product))
+    }
+}
+
+// The numbers of a square matrix and the dimension they share.
+fn square<N>(value : Value<N>, offset : usize) -> Result<(Vec<Vec<N>>, Dimension), CalcError> {
+    let (rows, _) = rows(value, offset)?;
+    if rows.len() != rows[0].len() {
+        return Err(CalcError::Matrix(offset));
+    }
+    let dimension = rows[0][0].dimension;
+    if rows.iter().flatten().any(|element| element.dimension != dimension) {
+        return Err(CalcError::Dimension(offset));
+    }
+    Ok((rows.into_iter().map(|row| row.into_iter().map(|element| element.value).collect()).collect(), dimension))
+}
+
+/*
+Gauss-Jordan elimination on `rows` and the rows of `augmented` alongside.
+The pivot of each column is the first that isn't 0 rather than the
+largest, which for the small integer matrices typed into a calculator
+keeps the steps exact more often than it costs precision. Gives the
+determinant, which is 0 as soon as a column has no pivot, in which case the
+elimination stops there.
+*/
+fn eliminate<N: Number>(rows : &mut [Vec<N>], augmented : &mut [Vec<N>], precision : usize) -> N {
+    let zero = N::from_integer(0);
+    let mut determinant = N::from_integer(1);
+    for col in 0..rows.len() {
+        let pivot = match (col..rows.len()).find(|row| rows[*row][col] != zero) {
+            Some(pivot) => pivot,
+            None => { return zero; }
+        };
+        if pivot != col {
+            rows.swap(pivot, col);
+            augmented.swap(pivot, col);
+            determinant = determinant.neg();
+        }
+        determinant = determinant.mul(&rows[col][col]);
+
+        let (pivot_row, pivot_augmented) = (rows[col].clone(), augmented[col].clone());
+        for row in 0..rows.len() {
+            if row == col {
+                continue;
+            }
+            let factor = match rows[row][col].div(&pivot_row[col], precision) {
+                Some(factor) if factor != zero => factor,
+                _ => continue
+            };
+            for (value, pivot) in rows[row].iter_mut().zip(&pivot_row) {
+                *value = value.sub(&factor.mul(pivot));
+            }
+            for (value, pivot) in augmented[row].iter_mut().zip(&pivot_augmented) {
+                *value = value.sub(&factor.mul(pivot));
+            }
+        }
+    }
+    determinant
+}
+
+fn determinant<N: Number>(value : Value<N>, precision : usize, offset : usize) -> Result<Value<N>, CalcError> {
+    let (mut rows, dimension) = square(value, offset)?;
+    let n = rows.len();
+    let mut augmented = vec![Vec::new(); n];
+    let value = eliminate(&mut rows, &mut augmented, precision);
+    let dimension = dimension.pow(&Rational::integer(n as i128)).ok_or(CalcError::Dimension(offset))?;
+    Ok(Value::Scalar(Quantity { value, dimension }))
+}
+
+// Gauss-Jordan: whatever turns the matrix into the identity turns the
+// identity into its inverse.
+fn inverse<N: Number>(value : Value<N>, precision : usize, offset : usize) -> Result<Value<N>, CalcError> {
+    let (mut rows, dimension) = square(value, offset)?;
+    let n = rows.len();
+    let mut inverse = (0..n).map(|i| (0..n).map(|j| N::from_integer((i == j) as i128)).collect()).collect::<Vec<Vec<N>>>();
+    if eliminate(&mut rows, &mut inverse, precision) == N::from_integer(0) {
+        return Err(CalcError::Singular(offset));
+    }
+
+    let dimension = Dimension::NONE.quotient(dimension).ok_or(CalcError::Dimension(offset))?;
+    let mut result = Vec::with_capacity(n);
+    for (row, pivot) in inverse.into_iter().zip(rows.iter().enumerate().map(|(i, row)| &row[i])) {
+        let row = row.iter().map(|value| value.div(pivot, precision).map(|value| Quantity { value, dimension }));
+        result.push(row.collect::<Option<Vec<_>>>().ok_or(CalcError::Singular(offset))?);
+    }
+    Ok(to_value(result))
+}
+
+pub fn apply<N: Number>(operation : MatrixOperation, mut args : Vec<Value<N>>, precision : usize, offset : usize) -> Result<Value<N>, CalcError> {
+    let first = args.remove(0);
+    match operation {
+        MatrixOperation::Multiply => multiply(first, args.remove(0), precision, offset),
+        MatrixOperation::Transpose => rows(first, offset).map(|(rows, _)| to_value(transpose(rows))),
+        MatrixOperation::Determinant => determinant(first, precision, offset),
+        MatrixOperation::Inverse => inverse(first, precision, offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::evaluate;
+    use crate::evaluate_with;
+    use crate::CalcError;
+    use crate::Context;
+    use crate::Dialect;
+    use crate::Rational;
+
+    #[test]
+    fn operations() {
+        assert_eq!(evaluate("matmul([[1, 2], [3, 4]], [[5, 6], [7, 8]])"), evaluate("[[19, 22], [43, 50]]"));
+        assert_eq!(evaluate("matmul([[1, 2], [3, 4]], [1, 1])"), evaluate("[3, 7]"));
+        assert_eq!(evaluate("transpose([[1, 2, 3], [4, 5, 6]])"), evaluate("[[1, 4], [2, 5], [3, 6]]"));
+        assert_eq!(evaluate("det([[1, 2], [3, 4]])"), evaluate("-2"));
+        assert_eq!(evaluate("[[1, 2], [3, 4]] * 2 - 1"), evaluate("[[1, 3], [5, 7]]"));
+        let exact = |input| evaluate_with(input, Dialect::Standard, &Context::<Rational>::default());
+        assert_eq!(exact("inv([[1, 2], [3, 4]])"), exact("[[-2, 1], [3/2, -1/2]]"));
+        assert_eq!(exact("matmul(inv([[2, 1], [1, 3]]), [[2, 1], [1, 3]])"), exact("[[1, 0], [0, 1]]"));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(evaluate("inv([[1, 2], [2, 4]])"), Err(CalcError::Singular(0)));
+        assert_eq!(evaluate("det([[1, 2, 3]])"), Err(CalcError::Matrix(0)));
+        assert_eq!(evaluate("matmul([[1, 2]], [[1, 2]])"), Err(CalcError::Matrix(0)));
+        assert_eq!(evaluate("transpose([[1, 2], [3]])"), Err(CalcError::Matrix(0)));
+    }
+}
@@ -0,0 +1,248 @@
+use std::io;
+use std::io::BufRead;
+use std::io::Write;
+
+use calculator::Context;
+use calculator::Dialect;
+
+use crate::json::Value;
+use crate::serve;
+
+/*
+Model Context Protocol server over stdio: one JSON-RPC 2.0 message per line
+on stdin, one response per line on stdout. The only tool is `calculate`,
+which evaluates a single expression. Nothing else is reachable from here,
+in particular no file access.
+
+An expression is evaluated within the same limits of steps and time as
+in the HTTP server. A failed calculation is a tool error, with the message
+as text and, as structured content, {"error": {"code": ..., "message":
+..., "column": ...}}, where the code is CalcError::kind or "too_long" for
+an expression over MAX_EXPRESSION_LEN.
+*/
+
+const SUPPORTED_VERSIONS : [&str; 3] = ["2025-06-18", "2025-03-26", "2024-11-05"];
+
+const MAX_EXPRESSION_LEN : usize = 1000;
+
+pub const PARSE_ERROR : i32 = -32700;
+const INVALID_REQUEST : i32 = -32600;
+pub const METHOD_NOT_FOUND : i32 = -32601;
+pub const INVALID_PARAMS : i32 = -32602;
+
+pub fn response(id : Value, result : Value) -> Value {
+    Value::object(vec![
+        ("jsonrpc", Value::string("2.0")),
+        ("id", id),
+        ("result", result),
+    ])
+}
+
+pub fn error_response(id : Value, code : i32, message : &str) -> Value {
+    Value::object(vec![
+        ("jsonrpc", Value::string("2.0")),
+        ("id", id),
+        ("error", Value::object(vec![
+            ("code", Value::Number(code as f64)),
+            ("message", Value::string(message)),
+        ])),
+    ])
+}
+
+fn initialize(params : Option<&Value>) -> Value {
+    let requested = params
+        .and_then(|p| p.get("protocolVersion"))
+        .and_then(|v| v.as_str());
+    let version = match requested {
+        Some(v) if SUPPORTED_VERSIONS.contains(&v) => v,
+        _ => SUPPORTED_VERSIONS[0]
+    };
+
+    Value::object(vec![
+        ("protocolVersion", Value::string(version)),
+        ("capabilities", Value::object(vec![("tools", Value::object(vec![]))])),
+        ("serverInfo", Value::object(vec![
+            ("name", Value::string(env!("CARGO_PKG_NAME"))),
+            ("version", Value::string(env!("CARGO_PKG_VERSION"))),
+        ])),
+    ])
+}
+
+fn list_tools() -> Value {
+    let expression = Value::object(vec![
+        ("type", Value::string("string")),
+        ("description", Value::string("Arithmetic expression using numbers, + - * / // % ^, brackets and functions such as sqrt(x) or log(x, base)")),
+    ]);
+    let tool = Value::object(vec![
+        ("name", Value::string("calculate")),
+        ("description", Value::string("Evaluate an arithmetic expression and return the result")),
+        ("inputSchema", Value::object(vec![
+            ("type", Value::string("object")),
+            ("properties", Value::object(vec![("expression", expression)])),
+            ("required", Value::Array(vec![Value::string("expression")])),
+        ])),
+    ]);
+
+    Value::object(vec![("tools", Value::Array(vec![tool]))])
+}
+
+fn tool_result(text : &str, is_error : bool) -> Vec<(&'static str, Value)> {
+    let content = Value::object(vec![
+        ("type", Value::string("text")),
+        ("text", Value::string(text)),
+    ]);
+    vec![
+        ("content", Value::Array(vec![content])),
+        ("isError", Value::Bool(is_error)),
+    ]
+}
+
+fn tool_error(code : &str, message : &str, column : Option<usize>) -> Value {
+    let mut error = vec![("code", Value::string(code)), ("message", Value::string(message))];
+    if let Some(column) = column {
+        error.push(("column", Value::Number(column as f64)));
+    }
+    let text = match column {
+        Some(column) => format!("{} at column {}", message, column),
+        None => message.to_string()
+    };
+    let mut result = tool_result(&text, true);
+    result.push(("structuredContent", Value::object(vec![("error", Value::object(error))])));
+    Value::object(result)
+}
+
+fn calculate(expression : &str) -> Value {
+    if expression.chars().count() > MAX_EXPRESSION_LEN {
+        return tool_error("too_long", &format!("Expression longer than {} characters", MAX_EXPRESSION_LEN), None);
+    }
+
+    let ctx = Context::<f64> { max_steps : Some(serve::MAX_STEPS), time_limit : Some(serve::EVALUATION_TIME), ..Context::default() };
+    match calculator::evaluate_with(expression, Dialect::Standard, &ctx) {
+        Ok(value) => Value::object(tool_result(&serve::shown(&value, expression), false)),
+        Err(e) => tool_error(e.kind(), &e.to_string(), Some(e.column(expression)))
+    }
+}
+
+fn call_tool(id : Value, params : Option<&Value>) -> Value {
+    let name = params.and_then(|p| p.get("name")).and_then(|v| v.as_str());
+    if name != Some("calculate") {
+        return error_response(id, INVALID_PARAMS, "Unknown tool");
+    }
+
+    let expression = params
+        .and_then(|p| p.get("arguments"))
+        .and_then(|a| a.get("expression"))
+        .and_then(|v| v.as_str());
+    match expression {
+        Some(expression) => response(id, calculate(expression)),
+        None => error_response(id, INVALID_PARAMS, "Missing string argument 'expression'")
+    }
+}
+
+// The reply to the JSON-RPC message `message`, which `reply` gives from
+// the method, id and params of a request; None for a notification or a
+// response.
+pub fn dispatch(message : &str, reply : impl FnOnce(&str, Value, Option<&Value>) -> Value) -> Option<Value> {
+    let request = match crate::json::parse(message) {
+        Some(v) => v,
+        None => { return Some(error_response(Value::Null, PARSE_ERROR, "Parse error")); }
+    };
+
+    let method = match request.get("method").and_then(|m| m.as_str()) {
+        Some(m) => m,
+        None => {
+            // A response from the client to a request we never sent; ignore it.
+            if request.get("result").is_some() || request.get("error").is_some() {
+                return None;
+            }
+            return Some(error_response(Value::Null, INVALID_REQUEST, "Invalid request"));
+        }
+    };
+
+    // Requests without an id are notifications and never get a reply.
+    let id = match request.get("id") {
+        Some(Value::Number(n)) => Value::Number(*n),
+        Some(Value::String(s)) => Value::string(s),
+        _ => { return None; }
+    };
+
+    Some(reply(method, id, request.get("params")))
+}
+
+fn handle(message : &str) -> Option<Value> {
+    dispatch(message, |method, id, params| match method {
+        "initialize" => response(id, initialize(params)),
+        "ping" => response(id, Value::object(vec![])),
+        "tools/list" => response(id, list_tools()),
+        "tools/call" => call_tool(id, params),
+        _ => error_response(id, METHOD_NOT_FOUND, "Method not found")
+    })
+}
+
+// Answers each line of stdin with what `handle` makes of it, if anything.
+pub fn serve(mut handle : impl FnMut(&str) -> Option<Value>) {
+    let mut stdin = io::stdin().lock();
+    let mut stdout = io::stdout();
+    let mut line = String::new();
+
+    loop {
+        line.clear();
+        if stdin.read_line(&mut line).expect("Something wrong") == 0 {
+            break;
+        }
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        if let Some(reply) = handle(&line) {
+            writeln!(stdout, "{}", reply).expect("Something wrong");
+            stdout.flush().expect("Something wrong");
+        }
+    }
+}
+
+pub fn run() {
+    serve(handle);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::handle;
+
+    fn reply(message : &str) -> Option<String> {
+        handle(message).map(|reply| reply.to_string())
+    }
+
+    #[test]
+    fn handshake() {
+        let initialized = reply(r#"{"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {"protocolVersion": "2025-03-26"}}"#).unwrap_or_default();
+        assert!(initialized.contains(r#""protocolVersion":"2025-03-26""#), "{}", initialized);
+        let unknown = reply(r#"{"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {"protocolVersion": "1999-01-01"}}"#).unwrap_or_default();
+        assert!(unknown.contains(r#""protocolVersion":"2025-06-18""#), "{}", unknown);
+        assert_eq!(reply(r#"{"jsonrpc": "2.0", "method": "notifications/initialized"}"#), None);
+        assert!(reply(r#"{"jsonrpc": "2.0", "id": "a", "method": "tools/list"}"#).unwrap_or_default().contains(r#""name":"calculate""#));
+    }
+
+    #[test]
+    fn calculations() {
+        let call = |expression : &str| reply(&format!(r#"{{"jsonrpc": "2.0", "id": 7, "method": "tools/call", "params": {{"name": "calculate", "arguments": {{"expression": "{}"}}}}}}"#, expression)).unwrap_or_default();
+        assert_eq!(call("2 * (3 + 4)"), r#"{"jsonrpc":"2.0","id":7,"result":{"content":[{"type":"text","text":"14"}],"isError":false}}"#);
+        assert!(call("1 km in m").contains(r#""text":"1000 m""#));
+        let failed = call("1 / 0");
+        assert!(failed.contains(r#""isError":true"#) && failed.contains(r#""code":"division_by_zero""#), "{}", failed);
+        assert!(call("1 +").contains(r#""column":4"#));
+        assert!(call(&"1+".repeat(600)).contains(r#""code":"too_long""#));
+    }
+
+    #[test]
+    fn errors() {
+        assert!(reply("{").unwrap_or_default().contains("-32700"));
+        assert!(reply(r#"{"jsonrpc": "2.0", "id": 1}"#).unwrap_or_default().contains("-32600"));
+        assert!(reply(r#"{"jsonrpc": "2.0", "id": 1, "method": "resources/list"}"#).unwrap_or_default().contains("-32601"));
+        assert!(reply(r#"{"jsonrpc": "2.0", "id": 1, "method": "tools/call", "params": {"name": "shell"}}"#).unwrap_or_default().contains("Unknown tool"));
+        assert!(reply(r#"{"jsonrpc": "2.0", "id": 1, "method": "tools/call", "params": {"name": "calculate", "arguments": {}}}"#).unwrap_or_default().contains("Missing string argument"));
+        // a response to the client's own request is passed over
+        assert_eq!(reply(r#"{"jsonrpc": "2.0", "id": 1, "result": {}}"#), None);
+    }
+}
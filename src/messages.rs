@@ -0,0 +1,673 @@
+use std::env;
+use std::fmt;
+use std::sync::atomic::AtomicU8;
+use std::sync::atomic::Ordering;
+
+use calculator::CalcError;
+use calculator::Token;
+
+/*
+Every user-facing string goes through `Msg`, whose Display impl picks the
+text for the current language. Each language is one exhaustive match, so a
+new message doesn't compile until it has been translated everywhere.
+
+Output meant for programs (MCP, the JSON-RPC error texts) stays English.
+*/
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Lang {
+    En,
+    De
+}
+
+pub const LANGUAGES : &[&str] = &["en", "de"];
+
+static LANG : AtomicU8 = AtomicU8::new(0);
+
+impl Lang {
+    pub fn from_code(code : &str) -> Option<Lang> {
+        // "de", "de_DE.UTF-8", "de-AT", ...
+        let language = code.split(['_', '-', '.', '@']).next()?;
+        match language {
+            "en" | "C" | "POSIX" => Some(Lang::En),
+            "de" => Some(Lang::De),
+            _ => None
+        }
+    }
+}
+
+pub fn set_lang(lang : Lang) {
+    LANG.store(lang as u8, Ordering::Relaxed);
+}
+
+fn lang() -> Lang {
+    match LANG.load(Ordering::Relaxed) {
+        1 => Lang::De,
+        _ => Lang::En
+    }
+}
+
+// The usual POSIX precedence: LC_ALL, then LC_MESSAGES, then LANG.
+pub fn init_from_env() {
+    for var in ["LC_ALL", "LC_MESSAGES", "LANG"] {
+        if let Ok(value) = env::var(var) {
+            if !value.is_empty() {
+                set_lang(Lang::from_code(&value).unwrap_or(Lang::En));
+                return;
+            }
+        }
+    }
+}
+
+// The message for an error reported by the library, whose own Display
+// is English only.
+pub fn error(e : &CalcError) -> Msg<'_> {
+    match e {
+        CalcError::UnexpectedToken(token, _) => Msg::UnexpectedToken(token),
+        CalcError::UnexpectedEnd(_) => Msg::UnexpectedEnd,
+        CalcError::UnbalancedBracket(_) => Msg::UnbalancedBracket,
+        CalcError::InvalidCharacter(c, _) => Msg::InvalidCharacter(*c),
+        CalcError::TrailingInput(_) => Msg::TrailingInput,
+        CalcError::DivisionByZero(_) => Msg::DivisionByZero,
+        CalcError::NotAnInteger(_) => Msg::NotAnInteger,
+        CalcError::UnknownFunction(name, _) => Msg::UnknownFunction(name),
+        CalcError::UndefinedVariable(name, _) => Msg::UndefinedVariable(name),
+        CalcError::Constant(name, _) => Msg::Constant(name),
+        CalcError::RecursionLimit(name, _) => Msg::RecursionLimit(name),
+        CalcError::Nesting(_) => Msg::Nesting,
+        CalcError::ArgumentCount(name, n, _) => Msg::ArgumentCount(name, *n),
+        CalcError::Domain(name, _) => Msg::Domain(name),
+        CalcError::Bounds(name, _) => Msg::Bounds(name),
+        CalcError::Range(name, _) => Msg::Range(name),
+        CalcError::Differentiate(expr, _) => Msg::Differentiate(expr),
+        CalcError::NoConvergence(name, _) => Msg::NoConvergence(name),
+        CalcError::Shape(_) => Msg::Shape,
+        CalcError::List(_) => Msg::List,
+        CalcError::Index(_) => Msg::Index,
+        CalcError::Matrix(_) => Msg::Matrix,
+        CalcError::Singular(_) => Msg::Singular,
+        CalcError::Dimension(_) => Msg::Dimension,
+        CalcError::Date(_) => Msg::Date,
+        CalcError::InvalidDate(_) => Msg::InvalidDate,
+        CalcError::Budget(_) => Msg::Budget,
+        CalcError::InvalidExponent(_) => Msg::InvalidExponent,
+        CalcError::TooLarge(_) => Msg::TooLarge
+    }
+}
+
+pub enum Msg<'a> {
+    UnexpectedToken(&'a Token),
+    UnexpectedEnd,
+    UnbalancedBracket,
+    InvalidCharacter(char),
+    TrailingInput,
+    DivisionByZero,
+    NotAnInteger,
+    UnknownFunction(&'a str),
+    UndefinedVariable(&'a str),
+    Constant(&'a str),
+    RecursionLimit(&'a str),
+    Nesting,
+    Defined(&'a str),
+    ArgumentCount(&'a str, usize),
+    Domain(&'a str),
+    Bounds(&'a str),
+    Range(&'a str),
+    Differentiate(&'a str),
+    NoConvergence(&'a str),
+    Shape,
+    List,
+    Index,
+    Matrix,
+    Singular,
+    Dimension,
+    Date,
+    InvalidDate,
+    TooLarge,
+    InvalidExponent,
+    Budget,
+    AtLine(usize, usize, &'a str),
+    AtArgument(usize, usize, &'a str),
+    AtFile(&'a str, usize, usize, &'a str),
+    // a line of a script that isn't UTF-8 from that column on
+    NotUtf8,
+    // an error in the expression given with an option, at a column of it
+    AtOption(&'a str, usize, &'a str),
+    // an error evaluating a line of input, without a column of its own
+    AtRecord(usize, &'a str),
+    // the field or column, and its text
+    NotANumber(&'a str, &'a str),
+    StackUnderflow(&'a str),
+    InvalidWord(&'a str),
+    AddedBrackets(usize, &'a str),
+
+    UnknownCommand(&'a str),
+    UnknownSetting(&'a str),
+    ExpectedOnOff(&'a str),
+    InvalidTolerance(&'a str),
+    InvalidDepth(&'a str),
+    InvalidPrecision(&'a str),
+    InvalidSeed(&'a str),
+    InvalidFormat(&'a str),
+    UnknownDisplay(&'a str),
+    ExportUsage,
+    AstUsage,
+    RenderUsage(&'a str),
+    EquivUsage,
+    LintUsage,
+    // of :lint and --lint
+    LintBrackets,
+    LintConstant(&'a str, &'a str),
+    LintAlways(bool),
+    LintHidesVariable(&'a str),
+    LintHidesUnit(&'a str),
+    LintClean,
+    AtColumn(usize, &'a str),
+    EquivalentBySimplifying,
+    // equal at that many points
+    EquivalentAt(usize),
+    // where, if anywhere, and the values of the two sides
+    Counterexample(&'a str, &'a str, &'a str),
+    InvalidAst,
+    UnknownExportFormat(&'a str),
+    Exported(usize, &'a str),
+    SessionTitle,
+    ReplHelp(&'a str),
+    NoVariables,
+    NoAliases,
+    SavedConfig(&'a str),
+    ConfigTitle,
+    SavedSession(&'a str),
+    LoadedSession(&'a str),
+    InvalidSession(&'a str),
+    NoSessionPath,
+    NoConfigPath,
+
+    UnknownOption(&'a str),
+    MissingValue(&'a str),
+    InvalidValue(&'a str, &'a str, &'a str),
+    InvalidDelimiter(&'a str),
+    // an option only used with another
+    Requires(&'a str, &'a str),
+    RequiresEither(&'a str, &'a str, &'a str),
+    Conflicts(&'a str, &'a str),
+    UnexpectedArgument(&'a str),
+    UnknownAggregate(&'a str),
+    // of the Jupyter kernel
+    BadConnectionFile(&'a str),
+    BadSignature,
+    // a bc setting given a value it can't take
+    BadSetting(&'a str, u32, u32),
+    // of --grid: a cell on a cycle of references, and one naming a cell
+    // that has no value
+    CellCycle(&'a str),
+    CellError(&'a str, &'a str),
+    NotACell,
+    GridKeys,
+    // what each way of evaluating gave, for --crosscheck
+    Disagree(&'a str),
+    TryHelp,
+    ProfileInputs(u64),
+
+    Usage,
+    Options,
+    Commands,
+    HelpHelp,
+    HelpRpn,
+    HelpDialect,
+    HelpNumbers,
+    HelpAngle,
+    HelpFormat,
+    HelpTrim,
+    HelpSeparators,
+    HelpAggregate,
+    HelpProfile,
+    HelpAstJson,
+    HelpEmit,
+    HelpFromAstJson,
+    HelpFile,
+    HelpConfig,
+    HelpLang,
+    HelpMcp,
+    HelpRpc,
+    HelpLsp,
+    HelpServe,
+    HelpWs,
+    HelpJupyter,
+    HelpGrid,
+    HelpCrosscheck,
+    HelpLint,
+    HelpFields,
+    HelpDelimiter,
+    HelpCsv,
+    HelpExpr,
+    HelpOut,
+    HelpCompletions
+}
+
+impl fmt::Display for Msg<'_> {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        match lang() {
+            Lang::En => match self {
+                Msg::UnexpectedToken(t) => write!(f, "Unexpected '{}'", t),
+                Msg::UnexpectedEnd => write!(f, "Unexpected end of input"),
+                Msg::UnbalancedBracket => write!(f, "Unbalanced bracket"),
+                Msg::InvalidCharacter(c) => write!(f, "Invalid character '{}'", c),
+                Msg::TrailingInput => write!(f, "Unexpected input after the expression"),
+                Msg::DivisionByZero => write!(f, "Division by zero"),
+                Msg::NotAnInteger => write!(f, "Bitwise operators need integers of at most 128 bits"),
+                Msg::UnknownFunction(name) => write!(f, "Unknown function '{}'", name),
+                Msg::UndefinedVariable(name) => write!(f, "Undefined variable '{}'", name),
+                Msg::Constant(name) => write!(f, "'{}' is a constant and can't be assigned", name),
+                Msg::RecursionLimit(name) => write!(f, "Calls of '{}' nested too deeply", name),
+                Msg::Nesting => write!(f, "Expression nested too deeply"),
+                Msg::Defined(name) => write!(f, "Defined {}", name),
+                Msg::ArgumentCount(name, n) => write!(f, "'{}' can't take {} argument(s)", name, n),
+                Msg::Domain(name) => write!(f, "'{}' needs non-negative integers", name),
+                Msg::Bounds(name) => write!(f, "'{}' needs integer bounds at most {} apart", name, calculator::eval::MAX_TERMS),
+                Msg::Range(name) => write!(f, "'{}' needs integer bounds, the lower first", name),
+                Msg::Differentiate(expr) => write!(f, "Can't differentiate '{}'", expr),
+                Msg::NoConvergence(name) => write!(f, "'{}' found no answer", name),
+                Msg::Shape => write!(f, "Lists of different lengths"),
+                Msg::List => write!(f, "A list where a single value is needed"),
+                Msg::Index => write!(f, "No such element"),
+                Msg::Matrix => write!(f, "Not a matrix of a suitable size"),
+                Msg::Singular => write!(f, "The matrix is singular"),
+                Msg::Dimension => write!(f, "Incompatible units"),
+                Msg::Date => write!(f, "A date only takes a duration added or taken off, or another date taken off"),
+                Msg::InvalidDate => write!(f, "No such date"),
+                Msg::TooLarge => write!(f, "The exact result would have too many digits"),
+                Msg::InvalidExponent => write!(f, "The exponent has no digits"),
+                Msg::Budget => write!(f, "Evaluation took more steps or time than allowed"),
+                Msg::AtLine(n, c, e) => write!(f, "line {}, column {}: {}", n, c, e),
+                Msg::AtArgument(n, c, e) => write!(f, "argument {}, column {}: {}", n, c, e),
+                Msg::AtFile(path, n, c, e) => write!(f, "{}:{}:{}: {}", path, n, c, e),
+                Msg::NotUtf8 => write!(f, "The text isn't UTF-8"),
+                Msg::AtOption(name, c, e) => write!(f, "{}, column {}: {}", name, c, e),
+                Msg::AtRecord(n, e) => write!(f, "line {}: {}", n, e),
+                Msg::NotANumber(name, text) => write!(f, "{} is not a number: '{}'", name, text),
+                Msg::StackUnderflow(w) => write!(f, "Stack underflow at '{}'", w),
+                Msg::InvalidWord(w) => write!(f, "Invalid word '{}'", w),
+                Msg::AddedBrackets(n, e) => write!(f, "Warning: added {} closing bracket(s): {}", n, e),
+
+                Msg::UnknownCommand(c) => write!(f, "Unknown command '{}'", c),
+                Msg::UnknownSetting(s) => write!(f, "Unknown setting '{}'", s),
+                Msg::ExpectedOnOff(v) => write!(f, "Expected 'on' or 'off', got '{}'", v),
+                Msg::InvalidTolerance(v) => write!(f, "Invalid tolerance '{}'", v),
+                Msg::InvalidDepth(v) => write!(f, "Invalid recursion depth '{}'", v),
+                Msg::InvalidPrecision(v) => write!(f, "Invalid precision '{}'", v),
+                Msg::InvalidSeed(v) => write!(f, "Invalid seed '{}' (expected a whole number from 0 to {})", v, u64::MAX),
+                Msg::InvalidFormat(v) => write!(f, "Invalid format '{}' (expected plain, fixed N, sig N, sci N or eng N)", v),
+                Msg::UnknownDisplay(d) => write!(f, "Unknown display '{}' (expected decimal, fraction, mixed, hex, oct, bin, ftin, dms, hms, hm or latex)", d),
+                Msg::ExportUsage => write!(f, "Usage: :export md <file>"),
+                Msg::AstUsage => write!(f, "Usage: :ast [dot] <statement>"),
+                Msg::RenderUsage(command) => write!(f, "Usage: {} <expression>", command),
+                Msg::EquivUsage => write!(f, "Usage: :equiv <expression> == <expression>"),
+                Msg::LintUsage => write!(f, "Usage: :lint <statement>"),
+                Msg::LintBrackets => write!(f, "Brackets that change nothing"),
+                Msg::LintConstant(expr, value) => write!(f, "{} could be written as {}", expr, value),
+                Msg::LintAlways(truth) => write!(f, "Always {}", if *truth { "true" } else { "false" }),
+                Msg::LintHidesVariable(name) => write!(f, "{} hides the variable of the session", name),
+                Msg::LintHidesUnit(name) => write!(f, "{} hides the unit", name),
+                Msg::LintClean => write!(f, "Nothing to point out"),
+                Msg::AtColumn(c, e) => write!(f, "column {}: {}", c, e),
+                Msg::EquivalentBySimplifying => write!(f, "Equivalent: the difference simplifies to 0"),
+                Msg::EquivalentAt(n) => write!(f, "Equal at {} points tried, so most likely equivalent", n),
+                Msg::Counterexample("", left, right) => write!(f, "Not equivalent: the left side is {} and the right side {}", left, right),
+                Msg::Counterexample(point, left, right) => write!(f, "Not equivalent: at {} the left side is {} and the right side {}", point, left, right),
+                Msg::InvalidAst => write!(f, "Not a parse tree in JSON"),
+                Msg::UnknownExportFormat(x) => write!(f, "Unknown export format '{}'", x),
+                Msg::Exported(n, p) => write!(f, "Exported {} entries to {}", n, p),
+                Msg::SessionTitle => write!(f, "Calculator session"),
+                Msg::ReplHelp(functions) => write!(f, "\
+Operators:  + - * / // % ^ (** in the python dialect), ~= and brackets;
+            also × ÷ − and √ for sqrt, and 5! for factorial(5)
+Percent:    50% is 0.5, 200 + 10% is 220; % before an operand, as in 7 % 3 or 7 % -3, is the remainder
+Bitwise:    & | xor (^ in the python dialect) << >> ~ on integers
+Logic:      == != < <= > >= and or not give 1 or 0;
+            c ? a : b and if(c, a, b) pick a unless c is 0
+Numbers:    1.5  1.5e-3  1_000  0xff  0o17  0b1010  0x1.8p3  2 3/4  5'10\"  45°30'  x²
+Variables:  x = 3 * 4, then x; ans, ans1, #1 for earlier results
+Output:     255 -> hex shows one result in another notation, 5'10\" -> cm in a unit
+Units:      5 km + 300 m, 60 mph in km/h (or to), 2 h * 30 W; m g s A K mol cd,
+            N J W Pa Hz C V ohm and more, with SI prefixes as in ms or kWh
+Constants:  pi (or π) e tau phi inf
+Angles:     sin cos tan and their inverses work in the unit set with :mode;
+            deg(30) and rad(pi/6) are the same angle in either, and so is
+            30°, which is in rad like 30 deg
+Lists:      [1, 2, 3] * 2, xs[0] (from 0, xs[-1] is the last); len sum mean
+            median var stddev min max sort take numbers and lists
+Matrices:   [[1, 2], [3, 4]] as rows; matmul(a, b) transpose det inv
+Series:     sum(i, 1, 100, i^2) and prod(k, 1, 10, k), also as Σ and Π
+Random:     rand() in [0, 1), rand(a, b), randint(1, 6), normal(mu, sigma)
+Dates:      2024-03-01 + 45 days, today() - 1999-12-31 in day, now() in UTC;
+            2024-03-01T12:30; 3 h + 45 min is 3.75 h, and -> hms shows 03:45;
+            1:45 + 0:30 is 2:15, as -> hm shows it
+Calculus:   diff(x^3, x, 2), integrate(sin(x), x, 0, pi), solve(x^2 - 2, x, 1)
+Ratios:     solveprop(3:4 = x:12) or solveprop(3/4 = x/12) is 9
+Symbolic:   simplify(2*x + 3*x), expand((x + 1)^2), d/dx(x^3), as a whole line
+Lines:      a line ending in an operator or \\, or inside brackets, goes on at ..;
+            a :command ends it, and Ctrl-C drops it
+Functions:  f(x) = x^2 + 1 defines one; built in are
+            {}
+
+Commands:
+  :quit, :exit                 Leave the calculator
+  :help                        Show this help
+  :vars                        List variables and functions
+  :clear                       Forget all variables and functions
+  :mode rpn | infix            Input mode
+  :mode deg | rad              Angle unit of trigonometric functions
+  :mode float | decimal | rational
+                               Compute in floating point, exact decimals or fractions
+  :mode hex | oct | bin        Show results in another base
+  :mode ftin | dms | hms | hm  Show results in feet and inches, degrees or hours
+  :mode latex                  Show results as LaTeX, e.g. \\frac{{1}}{{3}}
+  :set display <notation>      How results are shown: decimal, fraction, mixed,
+                               hex, oct, bin, ftin, dms, hms, hm or latex
+  :format fixed <places>       Round results to places after the point; also
+                               sig, sci or eng <digits>, and plain to stop
+  :trace on | off              Show each step of working out a result
+  :ast <statement>             Show the parse tree as JSON
+  :ast dot <statement>         Show the parse tree as a Graphviz graph
+  :latex <expression>          Write the expression as LaTeX
+  :pretty <expression>         Write the expression with only the brackets needed
+  :canon <expression>          Write the expression in canonical form: operands
+                               sorted, constants folded
+  :equiv <a> == <b>            Whether the two are equal for all values of their
+                               variables, or where they aren't
+  :lint <statement>            Point out brackets that change nothing, constant
+                               parts, comparisons that can't fail, hidden names
+  :set autoclose on | off      Close brackets left open
+  :set trim on | off           Drop trailing zeros of rounded results
+  :set separators on | off     Group the digits of results in thousands
+  :set epsilon <tolerance>     Tolerance of ~=
+  :set depth <calls>           How deeply function calls may nest
+  :set precision <digits>      Decimal places of quotients that don't terminate
+  :seed [<n>]                  Draw the same random numbers again for the same n;
+                               without n, numbers that can't be foreseen
+  :export md <file>            Save the session as Markdown
+  :alias <name> <text>         Have a word at the start of a line stand for text;
+                               without text, forget it; without a name, list them
+  :save-config                 Save settings, aliases, variables and functions
+                               to the init script run at startup
+  :save [<file>]               Save the whole session as JSON, as leaving does
+  :load [<file>]               Carry on with a saved session, by default the last one", functions),
+                Msg::NoVariables => write!(f, "No variables or functions defined"),
+                Msg::NoAliases => write!(f, "No aliases defined"),
+                Msg::SavedConfig(p) => write!(f, "Saved the settings to {}", p),
+                Msg::ConfigTitle => write!(f, "Run when the calculator starts; written by :save-config"),
+                Msg::SavedSession(p) => write!(f, "Saved the session to {}", p),
+                Msg::LoadedSession(p) => write!(f, "Loaded the session from {}", p),
+                Msg::InvalidSession(p) => write!(f, "{} doesn't hold a saved session", p),
+                Msg::NoSessionPath => write!(f, "No home directory to save the session in; give a file"),
+                Msg::NoConfigPath => write!(f, "No home directory to save the settings in; use --config"),
+
+                Msg::UnknownOption(o) => write!(f, "Unknown option '{}'", o),
+                Msg::MissingValue(o) => write!(f, "Missing value for {}", o),
+                Msg::InvalidValue(v, o, c) => write!(f, "Invalid value '{}' for {} (expected one of: {})", v, o, c),
+                Msg::InvalidDelimiter(d) => write!(f, "The delimiter of a CSV file must be one character, not '{}'", d),
+                Msg::Requires(a, b) => write!(f, "{} needs {}", a, b),
+                Msg::RequiresEither(a, b, c) => write!(f, "{} needs {} or {}", a, b, c),
+                Msg::Conflicts(a, b) => write!(f, "{} can't be used with {}", a, b),
+                Msg::UnexpectedArgument(a) => write!(f, "Unexpected argument '{}'", a),
+                Msg::UnknownAggregate(a) => write!(f, "Unknown aggregate '{}'", a),
+                Msg::BadConnectionFile(path) => write!(f, "{}: not a Jupyter connection file over TCP signed with hmac-sha256", path),
+                Msg::BadSignature => write!(f, "Ignored a message with a wrong signature"),
+                Msg::BadSetting(name, min, max) => write!(f, "{} must be a whole number from {} to {}", name, min, max),
+                Msg::CellCycle(cell) => write!(f, "{} is on a cycle of references, or names a cell that is", cell),
+                Msg::CellError(cell, other) => write!(f, "{} names {}, which has no value", cell, other),
+                Msg::NotACell => write!(f, "Expected a cell and its formula, as in A1 = 2 * B1"),
+                Msg::GridKeys => write!(f, "Arrows move, Enter edits, Delete empties, Ctrl-D quits"),
+                Msg::Disagree(values) => write!(f, "The ways of evaluating disagree: {}", values),
+                Msg::TryHelp => write!(f, "Try '--help' for more information."),
+                Msg::ProfileInputs(n) => write!(f, "profile over {} inputs:", n),
+
+                Msg::Usage => write!(f, "Usage:"),
+                Msg::Options => write!(f, "Options:"),
+                Msg::Commands => write!(f, "Commands:"),
+                Msg::HelpHelp => write!(f, "Print this help"),
+                Msg::HelpRpn => write!(f, "Start in postfix (RPN) input mode"),
+                Msg::HelpDialect => write!(f, "Expression syntax to accept"),
+                Msg::HelpNumbers => write!(f, "Compute in binary floating point, exact decimals or fractions"),
+                Msg::HelpAngle => write!(f, "Angle unit of trigonometric functions, radians by default"),
+                Msg::HelpFormat => write!(f, "Round results: plain, fixed:N, sig:N, sci:N or eng:N; mathml prints each statement as MathML instead"),
+                Msg::HelpTrim => write!(f, "Drop trailing zeros of rounded results"),
+                Msg::HelpSeparators => write!(f, "Group the digits of results in thousands"),
+                Msg::HelpAggregate => write!(f, "Aggregate the numbers read from stdin, e.g. sum,mean,max"),
+                Msg::HelpProfile => write!(f, "Report time and allocations per evaluation phase"),
+                Msg::HelpAstJson => write!(f, "Print the parse tree of each statement as JSON instead"),
+                Msg::HelpEmit => write!(f, "Print each statement instead: its parse tree as an S-expression, a Graphviz graph or JSON, or the statement as MathML"),
+                Msg::HelpFromAstJson => write!(f, "Read each statement as a parse tree in JSON"),
+                Msg::HelpFile => write!(f, "Run the statements in a script file"),
+                Msg::HelpConfig => write!(f, "Start the REPL with this init script instead of ~/.config/calculator/init.calc"),
+                Msg::HelpLang => write!(f, "Language of messages (default from LANG)"),
+                Msg::HelpMcp => write!(f, "Serve a calculate tool over stdio (Model Context Protocol)"),
+                Msg::HelpRpc => write!(f, "Keep a session over JSON-RPC 2.0 on stdio"),
+                Msg::HelpLsp => write!(f, "Check scripts in an editor, as a language server on stdio"),
+                Msg::HelpServe => write!(f, "Evaluate JSON requests over HTTP, e.g. 127.0.0.1:8080"),
+                Msg::HelpWs => write!(f, "Keep a session per WebSocket connection, e.g. 8080"),
+                Msg::HelpJupyter => write!(f, "Run as a Jupyter kernel with the connection file given"),
+                Msg::HelpGrid => write!(f, "Edit a spreadsheet of cells that name each other, as A1 * 2"),
+                Msg::HelpCrosscheck => write!(f, "Evaluate every expression in several ways and report where they disagree"),
+                Msg::HelpLint => write!(f, "Point out what could be written better in each statement, as :lint does"),
+                Msg::HelpFields => write!(f, "Evaluate the expression for each line of stdin, its fields bound as f1, f2, ..."),
+                Msg::HelpDelimiter => write!(f, "Split lines into fields on this rather than on whitespace, or CSV on it rather than on commas"),
+                Msg::HelpCsv => write!(f, "Add a column worked out by --expr to a CSV file, or stdin for -"),
+                Msg::HelpExpr => write!(f, "The expression evaluated for each row, its columns bound by name"),
+                Msg::HelpOut => write!(f, "The header of the new column, the expression by default"),
+                Msg::HelpCompletions => write!(f, "Print a shell completion script"),
+            },
+            Lang::De => match self {
+                Msg::UnexpectedToken(t) => write!(f, "Unerwartetes '{}'", t),
+                Msg::UnexpectedEnd => write!(f, "Unerwartetes Ende der Eingabe"),
+                Msg::UnbalancedBracket => write!(f, "Unausgeglichene Klammer"),
+                Msg::InvalidCharacter(c) => write!(f, "Ungültiges Zeichen '{}'", c),
+                Msg::TrailingInput => write!(f, "Unerwartete Eingabe nach dem Ausdruck"),
+                Msg::DivisionByZero => write!(f, "Division durch null"),
+                Msg::NotAnInteger => write!(f, "Bitoperatoren brauchen ganze Zahlen mit höchstens 128 Bit"),
+                Msg::UnknownFunction(name) => write!(f, "Unbekannte Funktion '{}'", name),
+                Msg::UndefinedVariable(name) => write!(f, "Undefinierte Variable '{}'", name),
+                Msg::Constant(name) => write!(f, "'{}' ist eine Konstante und kann nicht zugewiesen werden", name),
+                Msg::RecursionLimit(name) => write!(f, "Aufrufe von '{}' zu tief verschachtelt", name),
+                Msg::Nesting => write!(f, "Ausdruck zu tief verschachtelt"),
+                Msg::Defined(name) => write!(f, "{} definiert", name),
+                Msg::ArgumentCount(name, n) => write!(f, "'{}' nimmt keine {} Argument(e)", name, n),
+                Msg::Domain(name) => write!(f, "'{}' braucht nicht negative ganze Zahlen", name),
+                Msg::Bounds(name) => write!(f, "'{}' braucht ganzzahlige Grenzen, höchstens {} auseinander", name, calculator::eval::MAX_TERMS),
+                Msg::Range(name) => write!(f, "'{}' braucht ganzzahlige Grenzen, die untere zuerst", name),
+                Msg::Differentiate(expr) => write!(f, "'{}' lässt sich nicht ableiten", expr),
+                Msg::NoConvergence(name) => write!(f, "'{}' hat kein Ergebnis gefunden", name),
+                Msg::Shape => write!(f, "Listen verschiedener Länge"),
+                Msg::List => write!(f, "Eine Liste, wo ein einzelner Wert nötig ist"),
+                Msg::Index => write!(f, "Kein solches Element"),
+                Msg::Matrix => write!(f, "Keine Matrix passender Größe"),
+                Msg::Singular => write!(f, "Die Matrix ist singulär"),
+                Msg::Dimension => write!(f, "Unverträgliche Einheiten"),
+                Msg::Date => write!(f, "Zu einem Datum lässt sich nur eine Dauer addieren, und abziehen eine Dauer oder ein anderes Datum"),
+                Msg::InvalidDate => write!(f, "Dieses Datum gibt es nicht"),
+                Msg::TooLarge => write!(f, "Das genaue Ergebnis hätte zu viele Ziffern"),
+                Msg::InvalidExponent => write!(f, "Der Exponent hat keine Ziffern"),
+                Msg::Budget => write!(f, "Die Auswertung brauchte mehr Schritte oder Zeit als erlaubt"),
+                Msg::AtLine(n, c, e) => write!(f, "Zeile {}, Spalte {}: {}", n, c, e),
+                Msg::AtArgument(n, c, e) => write!(f, "Argument {}, Spalte {}: {}", n, c, e),
+                Msg::AtFile(path, n, c, e) => write!(f, "{}:{}:{}: {}", path, n, c, e),
+                Msg::NotUtf8 => write!(f, "Der Text ist kein UTF-8"),
+                Msg::AtOption(name, c, e) => write!(f, "{}, Spalte {}: {}", name, c, e),
+                Msg::AtRecord(n, e) => write!(f, "Zeile {}: {}", n, e),
+                Msg::NotANumber(name, text) => write!(f, "{} ist keine Zahl: '{}'", name, text),
+                Msg::StackUnderflow(w) => write!(f, "Stapelunterlauf bei '{}'", w),
+                Msg::InvalidWord(w) => write!(f, "Ungültiges Wort '{}'", w),
+                Msg::AddedBrackets(n, e) => write!(f, "Warnung: {} schließende Klammer(n) ergänzt: {}", n, e),
+
+                Msg::UnknownCommand(c) => write!(f, "Unbekannter Befehl '{}'", c),
+                Msg::UnknownSetting(s) => write!(f, "Unbekannte Einstellung '{}'", s),
+                Msg::ExpectedOnOff(v) => write!(f, "'on' oder 'off' erwartet, '{}' erhalten", v),
+                Msg::InvalidTolerance(v) => write!(f, "Ungültige Toleranz '{}'", v),
+                Msg::InvalidDepth(v) => write!(f, "Ungültige Rekursionstiefe '{}'", v),
+                Msg::InvalidPrecision(v) => write!(f, "Ungültige Genauigkeit '{}'", v),
+                Msg::InvalidSeed(v) => write!(f, "Ungültiger Startwert '{}' (erwartet: eine ganze Zahl von 0 bis {})", v, u64::MAX),
+                Msg::InvalidFormat(v) => write!(f, "Ungültiges Format '{}' (erwartet: plain, fixed N, sig N, sci N oder eng N)", v),
+                Msg::UnknownDisplay(d) => write!(f, "Unbekannte Anzeige '{}' (erwartet: decimal, fraction, mixed, hex, oct, bin, ftin, dms, hms, hm oder latex)", d),
+                Msg::ExportUsage => write!(f, "Aufruf: :export md <Datei>"),
+                Msg::AstUsage => write!(f, "Aufruf: :ast [dot] <Anweisung>"),
+                Msg::RenderUsage(command) => write!(f, "Aufruf: {} <Ausdruck>", command),
+                Msg::EquivUsage => write!(f, "Aufruf: :equiv <Ausdruck> == <Ausdruck>"),
+                Msg::LintUsage => write!(f, "Aufruf: :lint <Anweisung>"),
+                Msg::LintBrackets => write!(f, "Klammern, die nichts ändern"),
+                Msg::LintConstant(expr, value) => write!(f, "{} ließe sich als {} schreiben", expr, value),
+                Msg::LintAlways(truth) => write!(f, "Immer {}", if *truth { "wahr" } else { "falsch" }),
+                Msg::LintHidesVariable(name) => write!(f, "{} verdeckt die Variable der Sitzung", name),
+                Msg::LintHidesUnit(name) => write!(f, "{} verdeckt die Einheit", name),
+                Msg::LintClean => write!(f, "Nichts anzumerken"),
+                Msg::AtColumn(c, e) => write!(f, "Spalte {}: {}", c, e),
+                Msg::EquivalentBySimplifying => write!(f, "Gleichwertig: die Differenz vereinfacht sich zu 0"),
+                Msg::EquivalentAt(n) => write!(f, "Gleich an {} versuchten Stellen, also sehr wahrscheinlich gleichwertig", n),
+                Msg::Counterexample("", left, right) => write!(f, "Nicht gleichwertig: die linke Seite ist {} und die rechte {}", left, right),
+                Msg::Counterexample(point, left, right) => write!(f, "Nicht gleichwertig: bei {} ist die linke Seite {} und die rechte {}", point, left, right),
+                Msg::InvalidAst => write!(f, "Kein Syntaxbaum in JSON"),
+                Msg::UnknownExportFormat(x) => write!(f, "Unbekanntes Exportformat '{}'", x),
+                Msg::Exported(n, p) => write!(f, "{} Einträge nach {} exportiert", n, p),
+                Msg::SessionTitle => write!(f, "Rechnersitzung"),
+                Msg::ReplHelp(functions) => write!(f, "\
+Operatoren: + - * / // % ^ (** im Python-Dialekt), ~= und Klammern;
+            auch × ÷ − und √ für sqrt, und 5! für factorial(5)
+Prozent:    50% ist 0.5, 200 + 10% ist 220; % vor einem Operanden, wie in 7 % 3 oder 7 % -3, ist der Rest
+Bitweise:   & | xor (^ im Python-Dialekt) << >> ~ auf ganzen Zahlen
+Logik:      == != < <= > >= and or not ergeben 1 oder 0;
+            c ? a : b und if(c, a, b) wählen a, außer c ist 0
+Zahlen:     1.5  1.5e-3  1_000  0xff  0o17  0b1010  0x1.8p3  2 3/4  5'10\"  45°30'  x²
+Variablen:  x = 3 * 4, danach x; ans, ans1, #1 für frühere Ergebnisse
+Ausgabe:    255 -> hex zeigt ein Ergebnis in anderer Darstellung, 5'10\" -> cm in einer Einheit
+Einheiten:  5 km + 300 m, 60 mph in km/h (oder to), 2 h * 30 W; m g s A K mol cd,
+            N J W Pa Hz C V ohm und mehr, mit SI-Präfixen wie in ms oder kWh
+Konstanten: pi (oder π) e tau phi inf
+Winkel:     sin cos tan und ihre Umkehrungen rechnen in der mit :mode
+            gewählten Einheit; deg(30) und rad(pi/6) sind in beiden derselbe Winkel,
+            ebenso 30°, das wie 30 deg in rad ist
+Listen:     [1, 2, 3] * 2, xs[0] (ab 0, xs[-1] ist das letzte); len sum mean
+            median var stddev min max sort nehmen Zahlen und Listen
+Matrizen:   [[1, 2], [3, 4]] zeilenweise; matmul(a, b) transpose det inv
+Reihen:     sum(i, 1, 100, i^2) und prod(k, 1, 10, k), auch als Σ und Π
+Zufall:     rand() in [0, 1), rand(a, b), randint(1, 6), normal(mu, sigma)
+Daten:      2024-03-01 + 45 days, today() - 1999-12-31 in day, now() in UTC;
+            2024-03-01T12:30; 3 h + 45 min ist 3.75 h, und -> hms zeigt 03:45;
+            1:45 + 0:30 ist 2:15, wie -> hm es zeigt
+Analysis:   diff(x^3, x, 2), integrate(sin(x), x, 0, pi), solve(x^2 - 2, x, 1)
+Verhältnis: solveprop(3:4 = x:12) oder solveprop(3/4 = x/12) ist 9
+Symbolisch: simplify(2*x + 3*x), expand((x + 1)^2), d/dx(x^3), als ganze Zeile
+Zeilen:     eine Zeile, die auf einen Operator oder \\ oder in Klammern endet,
+            geht bei .. weiter; ein :Befehl beendet sie, Strg-C verwirft sie
+Funktionen: f(x) = x^2 + 1 definiert eine; eingebaut sind
+            {}
+
+Befehle:
+  :quit, :exit                 Den Rechner beenden
+  :help                        Diese Hilfe anzeigen
+  :vars                        Variablen und Funktionen auflisten
+  :clear                       Alle Variablen und Funktionen vergessen
+  :mode rpn | infix            Eingabemodus
+  :mode deg | rad              Winkeleinheit der Winkelfunktionen
+  :mode float | decimal | rational
+                               In Gleitkomma, exakt dezimal oder mit Brüchen rechnen
+  :mode hex | oct | bin        Ergebnisse in einer anderen Basis zeigen
+  :mode ftin | dms | hms | hm  Ergebnisse in Fuß und Zoll, Grad oder Stunden zeigen
+  :mode latex                  Ergebnisse als LaTeX zeigen, z. B. \\frac{{1}}{{3}}
+  :set display <Darstellung>   Darstellung der Ergebnisse: decimal, fraction,
+                               mixed, hex, oct, bin, ftin, dms, hms, hm oder latex
+  :format fixed <Stellen>      Ergebnisse auf Nachkommastellen runden; ebenso
+                               sig, sci oder eng <Ziffern>, und plain zum Beenden
+  :trace on | off              Jeden Schritt zum Ergebnis zeigen
+  :ast <Anweisung>             Den Syntaxbaum als JSON zeigen
+  :ast dot <Anweisung>         Den Syntaxbaum als Graphviz-Graph zeigen
+  :latex <Ausdruck>            Den Ausdruck als LaTeX schreiben
+  :pretty <Ausdruck>           Den Ausdruck mit nur den nötigen Klammern schreiben
+  :canon <Ausdruck>            Den Ausdruck in kanonischer Form schreiben:
+                               Operanden sortiert, Konstanten ausgewertet
+  :equiv <a> == <b>            Ob beide für alle Werte ihrer Variablen gleich
+                               sind, oder wo nicht
+  :lint <Anweisung>            Unnötige Klammern, konstante Teile, Vergleiche
+                               mit festem Ergebnis und verdeckte Namen zeigen
+  :set autoclose on | off      Offene Klammern schließen
+  :set trim on | off           Nullen am Ende gerundeter Ergebnisse weglassen
+  :set separators on | off     Die Ziffern der Ergebnisse in Tausender gruppieren
+  :set epsilon <Toleranz>      Toleranz von ~=
+  :set depth <Aufrufe>         Verschachtelungstiefe von Funktionsaufrufen
+  :set precision <Stellen>     Nachkommastellen nicht abbrechender Quotienten
+  :seed [<n>]                  Für dasselbe n wieder dieselben Zufallszahlen ziehen;
+                               ohne n nicht vorhersehbare
+  :export md <Datei>           Die Sitzung als Markdown speichern
+  :alias <Name> <Text>         Ein Wort am Zeilenanfang für Text stehen lassen;
+                               ohne Text vergessen, ohne Namen alle auflisten
+  :save-config                 Einstellungen, Aliase, Variablen und Funktionen
+                               im Startskript speichern
+  :save [<Datei>]              Die ganze Sitzung als JSON speichern, wie beim Beenden
+  :load [<Datei>]              Eine gespeicherte Sitzung fortsetzen, sonst die letzte", functions),
+                Msg::NoVariables => write!(f, "Keine Variablen oder Funktionen definiert"),
+                Msg::NoAliases => write!(f, "Keine Aliase definiert"),
+                Msg::SavedConfig(p) => write!(f, "Einstellungen in {} gespeichert", p),
+                Msg::ConfigTitle => write!(f, "Beim Start des Rechners ausgeführt; von :save-config geschrieben"),
+                Msg::SavedSession(p) => write!(f, "Sitzung in {} gespeichert", p),
+                Msg::LoadedSession(p) => write!(f, "Sitzung aus {} geladen", p),
+                Msg::InvalidSession(p) => write!(f, "{} enthält keine gespeicherte Sitzung", p),
+                Msg::NoSessionPath => write!(f, "Kein Home-Verzeichnis zum Speichern der Sitzung; eine Datei angeben"),
+                Msg::NoConfigPath => write!(f, "Kein Home-Verzeichnis zum Speichern der Einstellungen; --config verwenden"),
+
+                Msg::UnknownOption(o) => write!(f, "Unbekannte Option '{}'", o),
+                Msg::MissingValue(o) => write!(f, "Fehlender Wert für {}", o),
+                Msg::InvalidValue(v, o, c) => write!(f, "Ungültiger Wert '{}' für {} (erwartet: {})", v, o, c),
+                Msg::InvalidDelimiter(d) => write!(f, "Das Trennzeichen einer CSV-Datei muss ein Zeichen sein, nicht '{}'", d),
+                Msg::Requires(a, b) => write!(f, "{} braucht {}", a, b),
+                Msg::RequiresEither(a, b, c) => write!(f, "{} braucht {} oder {}", a, b, c),
+                Msg::Conflicts(a, b) => write!(f, "{} geht nicht zusammen mit {}", a, b),
+                Msg::UnexpectedArgument(a) => write!(f, "Unerwartetes Argument '{}'", a),
+                Msg::UnknownAggregate(a) => write!(f, "Unbekannte Aggregatfunktion '{}'", a),
+                Msg::BadConnectionFile(path) => write!(f, "{}: keine Jupyter-Verbindungsdatei für TCP mit hmac-sha256", path),
+                Msg::BadSignature => write!(f, "Nachricht mit falscher Signatur ignoriert"),
+                Msg::BadSetting(name, min, max) => write!(f, "{} muss eine ganze Zahl von {} bis {} sein", name, min, max),
+                Msg::CellCycle(cell) => write!(f, "{} steht in einem Kreis von Bezügen oder nennt eine Zelle darin", cell),
+                Msg::CellError(cell, other) => write!(f, "{} nennt {}, das keinen Wert hat", cell, other),
+                Msg::NotACell => write!(f, "Erwartet eine Zelle und ihre Formel, wie in A1 = 2 * B1"),
+                Msg::GridKeys => write!(f, "Pfeile bewegen, Enter bearbeitet, Entf leert, Strg-D beendet"),
+                Msg::Disagree(values) => write!(f, "Die Arten der Auswertung sind uneins: {}", values),
+                Msg::TryHelp => write!(f, "Weitere Informationen mit '--help'."),
+                Msg::ProfileInputs(n) => write!(f, "Profil über {} Eingaben:", n),
+
+                Msg::Usage => write!(f, "Aufruf:"),
+                Msg::Options => write!(f, "Optionen:"),
+                Msg::Commands => write!(f, "Befehle:"),
+                Msg::HelpHelp => write!(f, "Diese Hilfe anzeigen"),
+                Msg::HelpRpn => write!(f, "In der Postfix-Eingabe (UPN) starten"),
+                Msg::HelpDialect => write!(f, "Syntax der Ausdrücke"),
+                Msg::HelpNumbers => write!(f, "In binärem Gleitkomma, mit exakten Dezimalzahlen oder Brüchen rechnen"),
+                Msg::HelpAngle => write!(f, "Winkeleinheit der Winkelfunktionen, standardmäßig Bogenmaß"),
+                Msg::HelpFormat => write!(f, "Ergebnisse runden: plain, fixed:N, sig:N, sci:N oder eng:N; mathml gibt stattdessen jede Anweisung als MathML aus"),
+                Msg::HelpTrim => write!(f, "Nullen am Ende gerundeter Ergebnisse weglassen"),
+                Msg::HelpSeparators => write!(f, "Die Ziffern der Ergebnisse in Tausender gruppieren"),
+                Msg::HelpAggregate => write!(f, "Von stdin gelesene Zahlen zusammenfassen, z. B. sum,mean,max"),
+                Msg::HelpProfile => write!(f, "Zeit und Speicheranforderungen je Auswertungsphase ausgeben"),
+                Msg::HelpAstJson => write!(f, "Stattdessen den Syntaxbaum jeder Anweisung als JSON ausgeben"),
+                Msg::HelpEmit => write!(f, "Stattdessen jede Anweisung ausgeben: ihren Syntaxbaum als S-Ausdruck, Graphviz-Graph oder JSON, oder die Anweisung als MathML"),
+                Msg::HelpFromAstJson => write!(f, "Jede Anweisung als Syntaxbaum in JSON lesen"),
+                Msg::HelpFile => write!(f, "Die Anweisungen einer Skriptdatei ausführen"),
+                Msg::HelpConfig => write!(f, "Die REPL mit diesem Startskript statt ~/.config/calculator/init.calc beginnen"),
+                Msg::HelpLang => write!(f, "Sprache der Meldungen (Standard aus LANG)"),
+                Msg::HelpMcp => write!(f, "Ein calculate-Werkzeug über stdio anbieten (Model Context Protocol)"),
+                Msg::HelpRpc => write!(f, "Eine Sitzung über JSON-RPC 2.0 auf stdio führen"),
+                Msg::HelpLsp => write!(f, "Skripte im Editor prüfen, als Language Server auf stdio"),
+                Msg::HelpServe => write!(f, "JSON-Anfragen über HTTP auswerten, z. B. 127.0.0.1:8080"),
+                Msg::HelpWs => write!(f, "Eine Sitzung je WebSocket-Verbindung führen, z. B. 8080"),
+                Msg::HelpJupyter => write!(f, "Als Jupyter-Kernel mit der angegebenen Verbindungsdatei laufen"),
+                Msg::HelpGrid => write!(f, "Eine Tabelle von Zellen bearbeiten, die sich nennen, wie A1 * 2"),
+                Msg::HelpCrosscheck => write!(f, "Jeden Ausdruck auf mehrere Arten auswerten und melden, wo sie uneins sind"),
+                Msg::HelpLint => write!(f, "Zeigen, was sich in jeder Anweisung besser schreiben ließe, wie :lint"),
+                Msg::HelpFields => write!(f, "Den Ausdruck für jede Zeile von stdin auswerten, mit ihren Feldern als f1, f2, ..."),
+                Msg::HelpDelimiter => write!(f, "Zeilen an diesem Text statt an Leerraum in Felder teilen, oder CSV statt an Kommas"),
+                Msg::HelpCsv => write!(f, "Einer CSV-Datei eine mit --expr berechnete Spalte anfügen, oder stdin für -"),
+                Msg::HelpExpr => write!(f, "Der für jede Zeile ausgewertete Ausdruck, mit ihren Spalten nach Namen"),
+                Msg::HelpOut => write!(f, "Die Überschrift der neuen Spalte, standardmäßig der Ausdruck"),
+                Msg::HelpCompletions => write!(f, "Ein Skript zur Shell-Vervollständigung ausgeben"),
+            }
+        }
+    }
+}
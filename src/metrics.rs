@@ -0,0 +1,114 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use calculator::CalcError;
+
+/*
+What the servers have done, for Prometheus to scrape from GET /metrics in
+its text format: requests answered by the HTTP server, by status code;
+messages the WebSocket server answered; expressions that didn't parse and
+those that parsed but had no value; and how long evaluations took, as a
+histogram. The counts start at 0 when the server does. No server keeps a
+cache, so there is no hit rate to report.
+*/
+
+// The upper bounds of the histogram's buckets, in seconds, up to
+// serve::EVALUATION_TIME.
+const BUCKETS : [f64; 7] = [0.0001, 0.001, 0.01, 0.1, 0.5, 1.0, 2.0];
+
+#[derive(Default)]
+pub struct Metrics {
+    requests : Mutex<BTreeMap<&'static str, u64>>,
+    messages : AtomicU64,
+    parse_errors : AtomicU64,
+    evaluation_errors : AtomicU64,
+    // evaluations that took at most each bound, but more than the one before;
+    // the last holds those over every bound
+    latency : [AtomicU64; BUCKETS.len() + 1],
+    latency_nanos : AtomicU64
+}
+
+impl Metrics {
+    // Counts an HTTP response of `status`, such as "200 OK".
+    pub fn request(&self, status : &'static str) {
+        let code = status.split(' ').next().unwrap_or(status);
+        *self.requests.lock().unwrap_or_else(|e| e.into_inner()).entry(code).or_insert(0) += 1;
+    }
+
+    pub fn message(&self) {
+        self.messages.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Runs `evaluate`, counting how long it took and whether it failed.
+    pub fn evaluate<T>(&self, evaluate : impl FnOnce() -> Result<T, CalcError>) -> Result<T, CalcError> {
+        let start = Instant::now();
+        let result = evaluate();
+        let taken = start.elapsed();
+
+        let bucket = BUCKETS.iter().position(|bound| taken.as_secs_f64() <= *bound).unwrap_or(BUCKETS.len());
+        self.latency[bucket].fetch_add(1, Ordering::Relaxed);
+        self.latency_nanos.fetch_add(taken.as_nanos() as u64, Ordering::Relaxed);
+        match &result {
+            Err(e) if e.is_syntax() => { self.parse_errors.fetch_add(1, Ordering::Relaxed); }
+            Err(_) => { self.evaluation_errors.fetch_add(1, Ordering::Relaxed); }
+            Ok(_) => {}
+        }
+        result
+    }
+
+    // Everything counted, in Prometheus' text format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let mut counter = |name : &str, help : &str, values : &[(String, u64)]| {
+            let _ = writeln!(out, "# HELP {} {}\n# TYPE {} counter", name, help, name);
+            for (labels, value) in values {
+                let _ = writeln!(out, "{}{} {}", name, labels, value);
+            }
+        };
+
+        let requests = self.requests.lock().unwrap_or_else(|e| e.into_inner()).iter().map(|(code, n)| (format!("{{code=\"{}\"}}", code), *n)).collect::<Vec<_>>();
+        counter("calculator_http_requests_total", "HTTP requests answered, by status code.", &requests);
+        counter("calculator_websocket_messages_total", "WebSocket messages answered.", &[(String::new(), self.messages.load(Ordering::Relaxed))]);
+        counter("calculator_parse_errors_total", "Expressions that didn't parse.", &[(String::new(), self.parse_errors.load(Ordering::Relaxed))]);
+        counter("calculator_evaluation_errors_total", "Expressions that parsed but had no value.", &[(String::new(), self.evaluation_errors.load(Ordering::Relaxed))]);
+
+        let name = "calculator_evaluation_seconds";
+        let _ = writeln!(out, "# HELP {} How long evaluations took.\n# TYPE {} histogram", name, name);
+        let mut count = 0;
+        for (i, n) in self.latency.iter().enumerate() {
+            count += n.load(Ordering::Relaxed);
+            let bound = BUCKETS.get(i).map_or("+Inf".to_string(), |bound| bound.to_string());
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, count);
+        }
+        let _ = writeln!(out, "{}_sum {}", name, self.latency_nanos.load(Ordering::Relaxed) as f64 / 1e9);
+        let _ = writeln!(out, "{}_count {}", name, count);
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Metrics;
+
+    #[test]
+    fn counts() {
+        let metrics = Metrics::default();
+        metrics.request("200 OK");
+        metrics.request("200 OK");
+        metrics.request("422 Unprocessable Content");
+        assert!(metrics.evaluate(|| calculator::evaluate("1 + 1")).is_ok());
+        assert!(metrics.evaluate(|| calculator::evaluate("1 +")).is_err());
+        assert!(metrics.evaluate(|| calculator::evaluate("1 / 0")).is_err());
+
+        let shown = metrics.render();
+        assert!(shown.contains("calculator_http_requests_total{code=\"200\"} 2\ncalculator_http_requests_total{code=\"422\"} 1\n"));
+        assert!(shown.contains("# TYPE calculator_parse_errors_total counter\ncalculator_parse_errors_total 1\n"));
+        assert!(shown.contains("calculator_evaluation_errors_total 1\n"));
+        assert!(shown.contains("calculator_evaluation_seconds_bucket{le=\"+Inf\"} 3\n"));
+        assert!(shown.ends_with("calculator_evaluation_seconds_count 3\n"));
+    }
+}
@@ -0,0 +1,265 @@
+use std::cmp::Ordering;
+
+/*
+Arbitrary size natural numbers for the exact number types. Only what they
+need is here, and done the schoolbook way, which is plenty for numbers of
+a few hundred digits.
+*/
+
+const BASE : u64 = 1_000_000_000;
+pub const BASE_DIGITS : usize = 9;
+
+// A natural number in base 10^9, least significant limb first and without
+// high zero limbs, so zero has none.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Natural(pub Vec<u32>);
+
+impl Natural {
+    pub fn zero() -> Natural {
+        Natural(Vec::new())
+    }
+
+    // For `n` below the base.
+    pub fn small(n : u32) -> Natural {
+        Natural(vec![n]).trim()
+    }
+
+    pub fn from_u64(n : u64) -> Natural {
+        Natural(vec![(n % BASE) as u32, (n / BASE % BASE) as u32, (n / BASE / BASE) as u32]).trim()
+    }
+
+    // None if it doesn't fit.
+    pub fn to_u64(&self) -> Option<u64> {
+        if self.0.len() > 3 {
+            return None;
+        }
+        self.0.iter().rev().try_fold(0u64, |n, limb| n.checked_mul(BASE)?.checked_add(*limb as u64))
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn trim(mut self) -> Natural {
+        while self.0.last() == Some(&0) {
+            self.0.pop();
+        }
+        self
+    }
+
+    // From a string of ASCII digits.
+    pub fn from_digits(digits : &str) -> Natural {
+        let mut limbs = Vec::with_capacity(digits.len() / BASE_DIGITS + 1);
+        let mut end = digits.len();
+        while end > 0 {
+            let start = end.saturating_sub(BASE_DIGITS);
+            limbs.push(digits[start..end].parse::<u32>().unwrap_or(0));
+            end = start;
+        }
+        Natural(limbs).trim()
+    }
+
+    // From the decimal digits among `chars`, skipping anything else, as the
+    // `_` in 1_000. Taken nine at a time, so nothing is collected first.
+    pub fn from_decimal_chars(chars : impl Iterator<Item = char>) -> Natural {
+        let (mut n, mut limb, mut scale) = (Natural::zero(), 0, 1);
+        for digit in chars.filter_map(|c| c.to_digit(10)) {
+            limb = limb * 10 + digit;
+            scale *= 10;
+            if scale == BASE as u32 {
+                n = n.times_small(scale).sum(&Natural::small(limb));
+                (limb, scale) = (0, 1);
+            }
+        }
+        n.times_small(scale).sum(&Natural::small(limb))
+    }
+
+    // From digits in base `radix`, at most 16, as in a hex literal.
+    pub fn from_radix(digits : &str, radix : u32) -> Natural {
+        digits.chars().fold(Natural::zero(), |n, c| n.times_small(radix).sum(&Natural::small(c.to_digit(radix).unwrap_or(0))))
+    }
+
+    // The digits in base `radix`, at most 16, in lower case.
+    pub fn to_radix(&self, radix : u32) -> String {
+        let mut digits = Vec::new();
+        let mut n = self.clone();
+        while !n.is_zero() {
+            let (quotient, rem) = n.div_rem_small(radix);
+            digits.push(char::from_digit(rem, radix).unwrap_or('0'));
+            n = quotient;
+        }
+
+        if digits.is_empty() {
+            return "0".to_string();
+        }
+        digits.iter().rev().collect()
+    }
+
+    pub fn digits(&self) -> String {
+        match self.0.split_last() {
+            None => "0".to_string(),
+            Some((high, rest)) => {
+                let mut s = high.to_string();
+                for limb in rest.iter().rev() {
+                    s.push_str(&format!("{:09}", limb));
+                }
+                s
+            }
+        }
+    }
+
+    pub fn sum(&self, other : &Natural) -> Natural {
+        let len = self.0.len().max(other.0.len());
+        let mut limbs = Vec::with_capacity(len + 1);
+        let mut carry = 0;
+        for i in 0..len {
+            let s = carry + *self.0.get(i).unwrap_or(&0) as u64 + *other.0.get(i).unwrap_or(&0) as u64;
+            limbs.push((s % BASE) as u32);
+            carry = s / BASE;
+        }
+        if carry > 0 {
+            limbs.push(carry as u32);
+        }
+        Natural(limbs)
+    }
+
+    // self - other, for other <= self
+    pub fn difference(&self, other : &Natural) -> Natural {
+        let mut limbs = Vec::with_capacity(self.0.len());
+        let mut borrow = 0;
+        for (i, limb) in self.0.iter().enumerate() {
+            let mut d = *limb as i64 - borrow - *other.0.get(i).unwrap_or(&0) as i64;
+            borrow = 0;
+            if d < 0 {
+                d += BASE as i64;
+                borrow = 1;
+            }
+            limbs.push(d as u32);
+        }
+        Natural(limbs).trim()
+    }
+
+    pub fn product(&self, other : &Natural) -> Natural {
+        if self.is_zero() || other.is_zero() {
+            return Natural::zero();
+        }
+
+        let mut limbs = vec![0u64; self.0.len() + other.0.len()];
+        for (i, a) in self.0.iter().enumerate() {
+            let mut carry = 0;
+            for (j, b) in other.0.iter().enumerate() {
+                let t = limbs[i + j] + *a as u64 * *b as u64 + carry;
+                limbs[i + j] = t % BASE;
+                carry = t / BASE;
+            }
+            let mut k = i + other.0.len();
+            while carry > 0 {
+                let t = limbs[k] + carry;
+                limbs[k] = t % BASE;
+                carry = t / BASE;
+                k += 1;
+            }
+        }
+        Natural(limbs.into_iter().map(|limb| limb as u32).collect()).trim()
+    }
+
+    pub fn times_small(&self, m : u32) -> Natural {
+        let mut limbs = Vec::with_capacity(self.0.len() + 1);
+        let mut carry = 0;
+        for limb in &self.0 {
+            let t = *limb as u64 * m as u64 + carry;
+            limbs.push((t % BASE) as u32);
+            carry = t / BASE;
+        }
+        if carry > 0 {
+            limbs.push(carry as u32);
+        }
+        Natural(limbs).trim()
+    }
+
+    // self * 10^n
+    pub fn shifted(&self, n : usize) -> Natural {
+        if self.is_zero() {
+            return Natural::zero();
+        }
+        let mut limbs = vec![0; n / BASE_DIGITS];
+        limbs.extend_from_slice(&self.0);
+        Natural(limbs).times_small(10u32.pow((n % BASE_DIGITS) as u32))
+    }
+
+    // For `d` between 1 and the base.
+    pub fn div_rem_small(&self, d : u32) -> (Natural, u32) {
+        let mut limbs = vec![0; self.0.len()];
+        let mut rem = 0;
+        for i in (0..self.0.len()).rev() {
+            let cur = rem * BASE + self.0[i] as u64;
+            limbs[i] = (cur / d as u64) as u32;
+            rem = cur % d as u64;
+        }
+        (Natural(limbs).trim(), rem as u32)
+    }
+
+    // Long division, one decimal digit of the quotient at a time. For a
+    // non-zero `d`.
+    pub fn div_rem(&self, d : &Natural) -> (Natural, Natural) {
+        if let [small] = d.0[..] {
+            let (quotient, rem) = self.div_rem_small(small);
+            return (quotient, Natural::small(rem));
+        }
+        if self < d {
+            return (Natural::zero(), self.clone());
+        }
+
+        let digits = self.digits();
+        let mut quotient = String::with_capacity(digits.len());
+        let mut rem = Natural::zero();
+        for c in digits.bytes() {
+            rem = rem.times_small(10).sum(&Natural::small((c - b'0') as u32));
+            let mut q = b'0';
+            while rem >= *d {
+                rem = rem.difference(d);
+                q += 1;
+            }
+            quotient.push(q as char);
+        }
+        (Natural::from_digits(&quotient), rem)
+    }
+
+    // Square and multiply.
+    pub fn power(&self, mut exponent : u64) -> Natural {
+        let mut base = self.clone();
+        let mut result = Natural::small(1);
+        while exponent > 0 {
+            if exponent % 2 == 1 {
+                result = result.product(&base);
+            }
+            exponent /= 2;
+            if exponent > 0 {
+                base = base.product(&base);
+            }
+        }
+        result
+    }
+
+    pub fn gcd(&self, other : &Natural) -> Natural {
+        let (mut a, mut b) = (self.clone(), other.clone());
+        while !b.is_zero() {
+            let (_, rem) = a.div_rem(&b);
+            a = b;
+            b = rem;
+        }
+        a
+    }
+}
+
+impl Ord for Natural {
+    fn cmp(&self, other : &Natural) -> Ordering {
+        self.0.len().cmp(&other.0.len()).then_with(|| self.0.iter().rev().cmp(other.0.iter().rev()))
+    }
+}
+
+impl PartialOrd for Natural {
+    fn partial_cmp(&self, other : &Natural) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
@@ -0,0 +1,155 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+use crate::rational::Rational;
+
+/*
+What the evaluator needs of the numbers it computes with. `f64` is the
+default; `decimal::Decimal` computes exactly in decimal and
+`rational::Rational` in fractions instead.
+
+Literals come in exactly, through `from_rational`. The built-in functions
+such as sqrt or sin work in f64 whatever the type, so their results are
+only as precise as an f64. Bitwise operators work in i128, on values that
+are whole.
+*/
+pub trait Number: Clone + PartialEq + fmt::Debug + fmt::Display + Send + Sync {
+    fn from_f64(value : f64) -> Self;
+    fn to_f64(&self) -> f64;
+    // rounded to `precision` digits after the point where it has to be
+    fn from_rational(value : &Rational, precision : usize) -> Self;
+    fn to_rational(&self) -> Rational;
+
+    // The value as it would be typed, which is to_rational but for f64:
+    // the float nearest 0.1 gives 1/10 here, and its exact binary fraction
+    // from to_rational.
+    fn to_literal(&self) -> Rational {
+        self.to_rational()
+    }
+
+    // The number written in decimal, rounded if it has no exact decimal form.
+    fn to_decimal_string(&self) -> String {
+        self.to_string()
+    }
+
+    // None unless the value is whole and fits in an i128.
+    fn to_integer(&self) -> Option<i128> {
+        self.to_rational().to_integer()
+    }
+
+    fn from_integer(value : i128) -> Self {
+        Self::from_rational(&Rational::integer(value), 0)
+    }
+
+    // None if either is NaN.
+    fn compare(&self, rhs : &Self) -> Option<Ordering> {
+        self.to_rational().compare(&rhs.to_rational())
+    }
+
+    fn add(&self, rhs : &Self) -> Self;
+    fn sub(&self, rhs : &Self) -> Self;
+    fn mul(&self, rhs : &Self) -> Self;
+    fn neg(&self) -> Self;
+
+    // None when dividing by zero. A quotient that doesn't come out exactly
+    // is rounded to `precision` digits after the point, if the type has to
+    // round it at all.
+    fn div(&self, rhs : &Self, precision : usize) -> Option<Self>;
+    fn floor_div(&self, rhs : &Self) -> Option<Self>;
+    // the result takes the sign of the divisor, as in Python
+    fn modulo(&self, rhs : &Self) -> Option<Self>;
+
+    // None for zero to a negative power, and for an exact result too
+    // large to work out.
+    fn pow(&self, rhs : &Self, precision : usize) -> Option<Self>;
+}
+
+impl Number for f64 {
+    fn from_f64(value : f64) -> f64 {
+        value
+    }
+
+    fn to_f64(&self) -> f64 {
+        *self
+    }
+
+    fn from_rational(value : &Rational, _precision : usize) -> f64 {
+        value.to_f64()
+    }
+
+    fn to_rational(&self) -> Rational {
+        Rational::exactly(*self)
+    }
+
+    fn to_literal(&self) -> Rational {
+        Rational::from_f64(*self)
+    }
+
+    fn compare(&self, rhs : &f64) -> Option<Ordering> {
+        self.partial_cmp(rhs)
+    }
+
+    fn add(&self, rhs : &f64) -> f64 {
+        self + rhs
+    }
+
+    fn sub(&self, rhs : &f64) -> f64 {
+        self - rhs
+    }
+
+    fn mul(&self, rhs : &f64) -> f64 {
+        self * rhs
+    }
+
+    fn neg(&self) -> f64 {
+        -self
+    }
+
+    fn div(&self, rhs : &f64, _precision : usize) -> Option<f64> {
+        if *rhs == 0.0 { None } else { Some(self / rhs) }
+    }
+
+    fn floor_div(&self, rhs : &f64) -> Option<f64> {
+        if *rhs == 0.0 { None } else { Some((self / rhs).floor()) }
+    }
+
+    fn modulo(&self, rhs : &f64) -> Option<f64> {
+        if *rhs == 0.0 {
+            return None;
+        }
+
+        let r = self % rhs;
+        if r != 0.0 && (r < 0.0) != (*rhs < 0.0) {
+            Some(r + rhs)
+        }
+        else {
+            Some(r)
+        }
+    }
+
+    fn pow(&self, rhs : &f64, _precision : usize) -> Option<f64> {
+        if *self == 0.0 && *rhs < 0.0 {
+            return None;
+        }
+        Some(self.powf(*rhs))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Number;
+
+    #[test]
+    fn float_arithmetic() {
+        assert_eq!(Number::add(&0.5, &0.25), 0.75);
+        assert_ne!(Number::add(&0.1, &0.2), 0.3);
+        assert_eq!(Number::div(&1.0, &0.0, 0), None);
+        assert_eq!((-7.0).floor_div(&2.0), Some(-4.0));
+        // the remainder takes the sign of the divisor
+        assert_eq!((-7.0).modulo(&3.0), Some(2.0));
+        assert_eq!(7.0.modulo(&-3.0), Some(-2.0));
+        assert_eq!(7.0.modulo(&0.0), None);
+        assert_eq!(Number::pow(&2.0, &-2.0, 0), Some(0.25));
+        assert_eq!(Number::pow(&0.0, &-1.0, 0), None);
+    }
+}
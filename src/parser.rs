@@ -0,0 +1,810 @@
+use std::iter;
+
+use crate::ast::Binary;
+use crate::ast::Expr;
+use crate::ast::Statement;
+use crate::ast::Symbolic;
+use crate::ast::Unary;
+use crate::functions;
+use crate::stack;
+use crate::lexer::SpannedToken;
+use crate::lexer::Token;
+use crate::number::Number;
+use crate::rational::Rational;
+use crate::CalcError;
+
+/*
+Recursive descent over the token stream, building the `Expr` that eval.rs
+then walks. Errors in the syntax are all found here; what can only go wrong
+with values, such as undefined names, is left to evaluation.
+
+Everything after the parser recurses over the tree as well, so how deeply
+an expression nests is limited here. Brackets, signs and powers nest as
+the parser recurses into them; a chain such as 1 + 2 + 3 nests as well, in
+the tree, since each operation has the ones before it as its left operand,
+so every operation of a chain counts as a level.
+*/
+
+// How many levels an expression may nest. Parsing runs on a stack of its
+// own, but what walks the tree afterwards, such as printing it, doesn't, and
+// a debug build takes a good part of a small thread's stack for this many.
+pub const MAX_NESTING : usize = 200;
+
+// The token stream, remembering where the last token taken ended so that
+// running out of input can be reported at a position too.
+struct Tokens<T: Iterator<Item = SpannedToken>> {
+    iter : iter::Peekable<T>,
+    end : usize,
+    // how deeply the parser has recursed
+    depth : usize
+}
+
+impl<T: Iterator<Item = SpannedToken>> Tokens<T> {
+    fn new(tokens : T) -> Tokens<T> {
+        Tokens { iter : tokens.peekable(), end : 0, depth : 0 }
+    }
+
+    fn peek(&mut self) -> Option<&Token> {
+        self.iter.peek().map(|t| &t.token)
+    }
+
+    // Where the next token starts, or the end of the input.
+    fn start(&mut self) -> usize {
+        self.iter.peek().map_or(self.end, |t| t.start)
+    }
+
+    fn next(&mut self) -> Option<SpannedToken> {
+        let token = self.iter.next()?;
+        self.end = token.end;
+        Some(token)
+    }
+
+    // The next token, which has to be there.
+    fn expect(&mut self) -> Result<SpannedToken, CalcError> {
+        self.next().ok_or(CalcError::UnexpectedEnd(self.end))
+    }
+
+    // One level deeper, as long as that's within MAX_NESTING.
+    fn deepen(&mut self) -> Result<(), CalcError> {
+        if self.depth >= MAX_NESTING {
+            return Err(CalcError::Nesting(self.start()));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    // `parse`, one level deeper.
+    fn nested(&mut self, parse : fn(&mut Tokens<T>) -> Result<Expr, CalcError>) -> Result<Expr, CalcError> {
+        self.deepen()?;
+        let expr = parse(self);
+        self.depth -= 1;
+        expr
+    }
+}
+
+// The error for a token that can't appear where it was found.
+fn unexpected(token : &SpannedToken) -> CalcError {
+    match &token.token {
+        Token::Invalid(c) => CalcError::InvalidCharacter(*c, token.start),
+        Token::InvalidDate => CalcError::InvalidDate(token.start),
+        Token::InvalidExponent => CalcError::InvalidExponent(token.start),
+        Token::RightBracket => CalcError::UnbalancedBracket(token.start),
+        other => CalcError::UnexpectedToken(other.clone(), token.start)
+    }
+}
+
+// `cond ? then : otherwise`, from the three arguments of `if`.
+fn conditional(cond : Option<Expr>, then : Option<Expr>, otherwise : Option<Expr>) -> Expr {
+    let boxed = |expr : Option<Expr>| Box::new(expr.expect("three arguments"));
+    Expr::Conditional { cond : boxed(cond), then : boxed(then), otherwise : boxed(otherwise) }
+}
+
+// `lhs op rhs`, for a token the caller has checked is a binary operator.
+fn binary(op : &SpannedToken, lhs : Expr, rhs : Expr) -> Expr {
+    let kind = Binary::from_token(&op.token).expect("not a binary operator");
+    Expr::BinaryOp { op : kind, lhs : Box::new(lhs), rhs : Box::new(rhs), offset : op.start }
+}
+
+/*
+grammar:
+    arguments = close
+              | conditional arguments'
+    arguments' = , conditional arguments'
+               | close
+
+`close` is `)` for the arguments of a call and `]` for the elements of a
+list. `open` is where the bracket before them is, for errors.
+*/
+fn parse_arguments<T>(tokens : &mut Tokens<T>, open : usize, close : Token) -> Result<Vec<Expr>, CalcError>
+where T: Iterator<Item = SpannedToken>
+{
+    let mut args = Vec::new();
+    if tokens.peek() == Some(&close) {
+        tokens.expect()?;
+        return Ok(args);
+    }
+
+    loop {
+        args.push(tokens.nested(parse_conditional)?);
+        match tokens.next() {
+            Some(SpannedToken { token : Token::Comma, .. }) => {}
+            Some(SpannedToken { token, .. }) if token == close => { return Ok(args); }
+            Some(other) => { return Err(unexpected(&other)); }
+            None => { return Err(CalcError::UnbalancedBracket(open)); }
+        }
+    }
+}
+
+/*
+grammar:
+    primary = number
+    primary = date
+    primary = (conditional)
+    primary = if(conditional, conditional, conditional)
+    primary = solveprop(proportion)
+    primary = identifier(arguments)
+    primary = identifier
+    primary = [arguments]
+    primary = - primary    (a minus the lexer found binds tighter, in Excel)
+*/
+fn parse_primary<T>(tokens : &mut Tokens<T>) -> Result<Expr, CalcError>
+where T: Iterator<Item = SpannedToken>
+{
+    let token = tokens.expect()?;
+    match token.token {
+        Token::Number(value) => Ok(Expr::Number(value)),
+        Token::Date(seconds) => Ok(Expr::Date(seconds)),
+        // as if written 70 inch, a quantity in the unit
+        Token::Measurement(value, unit) => Ok(Expr::BinaryOp {
+            op : Binary::Mul,
+            lhs : Box::new(Expr::Number(value)),
+            rhs : Box::new(Expr::Variable { name : unit.to_string(), offset : token.start }),
+            offset : token.start
+        }),
+        // as if written (1 * 60 + 45) min
+        Token::Clock(hours, minutes) => Ok(Expr::BinaryOp {
+            op : Binary::Mul,
+            lhs : Box::new(Expr::Number(hours.mul(&Rational::ratio(60, 1)).add(&minutes))),
+            rhs : Box::new(Expr::Variable { name : "min".to_string(), offset : token.start }),
+            offset : token.start
+        }),
+        Token::Ident(name) => {
+            if let (Some(Token::LeftBracket), "solveprop") = (tokens.peek(), name.as_str()) {
+                let open = tokens.expect()?.start;
+                let equation = parse_proportion(tokens, open)?;
+                return Ok(Expr::Call { name, args : vec![equation], offset : token.start });
+            }
+            if let Some(Token::LeftBracket) = tokens.peek() {
+                let open = tokens.expect()?.start;
+                let mut args = parse_arguments(tokens, open, Token::RightBracket)?;
+                if name == "if" {
+                    if args.len() != 3 {
+                        return Err(CalcError::ArgumentCount(name, args.len(), token.start));
+                    }
+                    let (otherwise, then, cond) = (args.pop(), args.pop(), args.pop());
+                    return Ok(conditional(cond, then, otherwise));
+                }
+                return Ok(Expr::Call { name, args, offset : token.start });
+            }
+            Ok(Expr::Variable { name, offset : token.start })
+        }
+        Token::LeftBracket => {
+            let expr = tokens.nested(parse_conditional)?;
+            match tokens.next() {
+                Some(SpannedToken { token : Token::RightBracket, .. }) => Ok(expr),
+                Some(other) => Err(unexpected(&other)),
+                None => Err(CalcError::UnbalancedBracket(token.start))
+            }
+        }
+        Token::LeftSquare => Ok(Expr::List(parse_arguments(tokens, token.start, Token::RightSquare)?)),
+        Token::Negate => Ok(Expr::UnaryOp { op : Unary::Minus, operand : Box::new(tokens.nested(parse_primary)?), offset : token.start }),
+        _ => Err(unexpected(&token))
+    }
+}
+
+// `a:b`, a ratio, as the quotient it is, or just `a`. What the lexer took
+// for a time, as 3:45, is the ratio of its two numbers here.
+fn parse_ratio<T>(tokens : &mut Tokens<T>) -> Result<Expr, CalcError>
+where T: Iterator<Item = SpannedToken>
+{
+    if let Some(Token::Clock(hours, minutes)) = tokens.peek().cloned() {
+        let start = tokens.expect()?.start;
+        return Ok(Expr::BinaryOp { op : Binary::Div, lhs : Box::new(Expr::Number(hours)), rhs : Box::new(Expr::Number(minutes)), offset : start });
+    }
+    let lhs = tokens.nested(parse_bit_or)?;
+    if let Some(Token::Colon) = tokens.peek() {
+        let colon = tokens.expect()?.start;
+        let rhs = tokens.nested(parse_bit_or)?;
+        return Ok(Expr::BinaryOp { op : Binary::Div, lhs : Box::new(lhs), rhs : Box::new(rhs), offset : colon });
+    }
+    Ok(lhs)
+}
+
+/*
+grammar:
+    proportion = ratio = ratio)
+               | ratio == ratio)
+    ratio = bit_or
+          | bit_or : bit_or
+
+What solveprop takes, 3:4 = x:12 or 3/4 = x/12, which is read as the
+comparison 3 / 4 == x / 12. It stands alone, so its `=` is no assignment
+and its `:` no part of a conditional.
+*/
+fn parse_proportion<T>(tokens : &mut Tokens<T>, open : usize) -> Result<Expr, CalcError>
+where T: Iterator<Item = SpannedToken>
+{
+    let lhs = parse_ratio(tokens)?;
+    let equals = match tokens.next() {
+        Some(SpannedToken { token : Token::Assign | Token::Equal, start, .. }) => start,
+        Some(other) => { return Err(unexpected(&other)); }
+        None => { return Err(CalcError::UnbalancedBracket(open)); }
+    };
+    let rhs = parse_ratio(tokens)?;
+    match tokens.next() {
+        Some(SpannedToken { token : Token::RightBracket, .. }) => {}
+        Some(other) => { return Err(unexpected(&other)); }
+        None => { return Err(CalcError::UnbalancedBracket(open)); }
+    }
+    Ok(Expr::BinaryOp { op : Binary::Equal, lhs : Box::new(lhs), rhs : Box::new(rhs), offset : equals })
+}
+
+/*
+grammar:
+    postfix = primary
+            | postfix !
+            | postfix %    (where the lexer found a percentage)
+            | postfix %    (a hundredth, in Excel)
+            | postfix [conditional]
+*/
+fn parse_postfix<T>(tokens : &mut Tokens<T>) -> Result<Expr, CalcError>
+where T: Iterator<Item = SpannedToken>
+{
+    let mut expr = parse_primary(tokens)?;
+
+    let depth = tokens.depth;
+    loop {
+        if let Some(Token::Factorial | Token::Percent | Token::PlainPercent | Token::LeftSquare) = tokens.peek() {
+            tokens.deepen()?;
+        }
+        match tokens.peek() {
+            Some(Token::Factorial) => {
+                let offset = tokens.expect()?.start;
+                expr = Expr::Call { name : "factorial".to_string(), args : vec![expr], offset };
+            }
+            Some(Token::Percent) => {
+                let offset = tokens.expect()?.start;
+                expr = Expr::UnaryOp { op : Unary::Percent, operand : Box::new(expr), offset };
+            }
+            Some(Token::PlainPercent) => {
+                let offset = tokens.expect()?.start;
+                expr = Expr::BinaryOp { op : Binary::Div, lhs : Box::new(expr), rhs : Box::new(Expr::Number(Rational::ratio(100, 1))), offset };
+            }
+            Some(Token::LeftSquare) => {
+                let offset = tokens.expect()?.start;
+                let index = tokens.nested(parse_conditional)?;
+                match tokens.next() {
+                    Some(SpannedToken { token : Token::RightSquare, .. }) => {}
+                    Some(other) => { return Err(unexpected(&other)); }
+                    None => { return Err(CalcError::UnbalancedBracket(offset)); }
+                }
+                expr = Expr::Index { list : Box::new(expr), index : Box::new(index), offset };
+            }
+            _ => {
+                tokens.depth = depth;
+                return Ok(expr);
+            }
+        }
+    }
+}
+
+/*
+grammar:
+    power = postfix
+          | postfix ** factor    (written ^ in the standard dialect)
+          | √ power
+
+Taking a factor as the exponent makes `**` right associative, so 2^3^2 is
+2^9, and lets the exponent carry a sign: 2^-1. A factorial binds tighter,
+so 2^3! is 2^6. A root is a call of sqrt, of the power after it: √4^2
+is 4.
+*/
+fn parse_power<T>(tokens : &mut Tokens<T>) -> Result<Expr, CalcError>
+where T: Iterator<Item = SpannedToken>
+{
+    if let Some(Token::Root) = tokens.peek() {
+        let offset = tokens.expect()?.start;
+        let operand = tokens.nested(parse_power)?;
+        return Ok(Expr::Call { name : "sqrt".to_string(), args : vec![operand], offset });
+    }
+
+    let base = parse_postfix(tokens)?;
+
+    if let Some(Token::Pow) = tokens.peek() {
+        let op = tokens.expect()?;
+        let exponent = tokens.nested(parse_factor)?;
+        return Ok(binary(&op, base, exponent));
+    }
+
+    Ok(base)
+}
+
+/*
+grammar:
+    factor = power
+    factor = + factor
+    factor = - factor
+    factor = ~ factor
+*/
+fn parse_factor<T>(tokens : &mut Tokens<T>) -> Result<Expr, CalcError>
+where T: Iterator<Item = SpannedToken>
+{
+    let op = match tokens.peek() {
+        Some(Token::Add) => Unary::Plus,
+        Some(Token::Sub) => Unary::Minus,
+        Some(Token::BitNot) => Unary::BitNot,
+        _ => { return parse_power(tokens); }
+    };
+
+    let offset = tokens.expect()?.start;
+    Ok(Expr::UnaryOp { op, operand : Box::new(tokens.nested(parse_factor)?), offset })
+}
+
+/*
+grammar:
+    product = factor product'
+    product' = power product'    (starting with an identifier, bracket or √)
+             | nothing
+
+Implicit multiplication as on handheld calculators: 2pi, 2(3 + 4) and
+(1 + 2)(3 + 4). The operand is a power, so 2x^2 is 2 * x^2, and has to
+start with a name, bracket or root, so 2 -x stays a subtraction and 2 3 an
+error. It binds tighter than `*` and `/`, so 100 km / 2 h is a speed and
+1/2x is 1/(2x).
+*/
+fn parse_product<T>(tokens : &mut Tokens<T>) -> Result<Expr, CalcError>
+where T: Iterator<Item = SpannedToken>
+{
+    let mut expr = parse_factor(tokens)?;
+
+    let depth = tokens.depth;
+    while let Some(Token::Ident(_) | Token::LeftBracket | Token::Root) = tokens.peek() {
+        tokens.deepen()?;
+        let offset = tokens.start();
+        let rhs = parse_power(tokens)?;
+        expr = Expr::BinaryOp { op : Binary::Mul, lhs : Box::new(expr), rhs : Box::new(rhs), offset };
+    }
+    tokens.depth = depth;
+
+    Ok(expr)
+}
+
+/*
+grammar:
+    term = product term'
+    term' = * product term'
+          | / product term'
+          | // product term'
+          | % product term'
+          | nothing
+*/
+fn parse_term<T>(tokens : &mut Tokens<T>) -> Result<Expr, CalcError>
+where T: Iterator<Item = SpannedToken>
+{
+    let mut expr = parse_product(tokens)?;
+
+    let depth = tokens.depth;
+    while let Some(Token::Mul | Token::Div | Token::FloorDiv | Token::Mod) = tokens.peek() {
+        tokens.deepen()?;
+        let op = tokens.expect()?;
+        expr = binary(&op, expr, parse_product(tokens)?);
+    }
+    tokens.depth = depth;
+
+    Ok(expr)
+}
+
+/*
+grammar:
+    expression = term expression'
+    expression' = + term expression'
+                | - term expression'
+                | nothing
+*/
+fn parse_expression<T>(tokens : &mut Tokens<T>) -> Result<Expr, CalcError>
+where T: Iterator<Item = SpannedToken>
+{
+    let mut expr = parse_term(tokens)?;
+
+    let depth = tokens.depth;
+    while let Some(Token::Add | Token::Sub) = tokens.peek() {
+        tokens.deepen()?;
+        let op = tokens.expect()?;
+        expr = binary(&op, expr, parse_term(tokens)?);
+    }
+    tokens.depth = depth;
+
+    Ok(expr)
+}
+
+/*
+grammar:
+    shift = expression shift'
+    shift' = << expression shift'
+           | >> expression shift'
+           | nothing
+
+The bitwise operators bind looser than arithmetic, as in Python, so
+1 << 2 + 3 is 1 << 5.
+*/
+fn parse_shift<T>(tokens : &mut Tokens<T>) -> Result<Expr, CalcError>
+where T: Iterator<Item = SpannedToken>
+{
+    let mut expr = parse_expression(tokens)?;
+
+    let depth = tokens.depth;
+    while let Some(Token::ShiftLeft | Token::ShiftRight) = tokens.peek() {
+        tokens.deepen()?;
+        let op = tokens.expect()?;
+        expr = binary(&op, expr, parse_expression(tokens)?);
+    }
+    tokens.depth = depth;
+
+    Ok(expr)
+}
+
+/*
+grammar:
+    bit_and = shift bit_and'
+    bit_and' = & shift bit_and'
+             | nothing
+*/
+fn parse_bit_and<T>(tokens : &mut Tokens<T>) -> Result<Expr, CalcError>
+where T: Iterator<Item = SpannedToken>
+{
+    let mut expr = parse_shift(tokens)?;
+
+    let depth = tokens.depth;
+    while let Some(Token::BitAnd) = tokens.peek() {
+        tokens.deepen()?;
+        let op = tokens.expect()?;
+        expr = binary(&op, expr, parse_shift(tokens)?);
+    }
+    tokens.depth = depth;
+
+    Ok(expr)
+}
+
+/*
+grammar:
+    xor = bit_and xor'
+    xor' = xor bit_and xor'    (^ in the Python dialect)
+         | nothing
+*/
+fn parse_xor<T>(tokens : &mut Tokens<T>) -> Result<Expr, CalcError>
+where T: Iterator<Item = SpannedToken>
+{
+    let mut expr = parse_bit_and(tokens)?;
+
+    let depth = tokens.depth;
+    while let Some(Token::Xor) = tokens.peek() {
+        tokens.deepen()?;
+        let op = tokens.expect()?;
+        expr = binary(&op, expr, parse_bit_and(tokens)?);
+    }
+    tokens.depth = depth;
+
+    Ok(expr)
+}
+
+/*
+grammar:
+    bit_or = xor bit_or'
+    bit_or' = | xor bit_or'
+            | nothing
+*/
+fn parse_bit_or<T>(tokens : &mut Tokens<T>) -> Result<Expr, CalcError>
+where T: Iterator<Item = SpannedToken>
+{
+    let mut expr = parse_xor(tokens)?;
+
+    let depth = tokens.depth;
+    while let Some(Token::BitOr) = tokens.peek() {
+        tokens.deepen()?;
+        let op = tokens.expect()?;
+        expr = binary(&op, expr, parse_xor(tokens)?);
+    }
+    tokens.depth = depth;
+
+    Ok(expr)
+}
+
+/*
+grammar:
+    comparison = bit_or
+               | bit_or ~= bit_or
+               | bit_or == bit_or
+               | bit_or != bit_or
+               | bit_or < bit_or
+               | bit_or <= bit_or
+               | bit_or > bit_or
+               | bit_or >= bit_or
+
+Comparisons don't chain: 1 < 2 < 3 is an error rather than either
+reading of it.
+*/
+fn parse_comparison<T>(tokens : &mut Tokens<T>) -> Result<Expr, CalcError>
+where T: Iterator<Item = SpannedToken>
+{
+    let lhs = parse_bit_or(tokens)?;
+
+    if let Some(Token::ApproxEq | Token::Equal | Token::NotEqual | Token::Less | Token::LessEqual | Token::Greater | Token::GreaterEqual) = tokens.peek() {
+        let op = tokens.expect()?;
+        let rhs = parse_bit_or(tokens)?;
+        return Ok(binary(&op, lhs, rhs));
+    }
+
+    Ok(lhs)
+}
+
+/*
+grammar:
+    not = not not
+        | comparison
+*/
+fn parse_not<T>(tokens : &mut Tokens<T>) -> Result<Expr, CalcError>
+where T: Iterator<Item = SpannedToken>
+{
+    if let Some(Token::Not) = tokens.peek() {
+        let offset = tokens.expect()?.start;
+        return Ok(Expr::UnaryOp { op : Unary::Not, operand : Box::new(tokens.nested(parse_not)?), offset });
+    }
+
+    parse_comparison(tokens)
+}
+
+/*
+grammar:
+    and = not and'
+    and' = and not and'
+         | nothing
+*/
+fn parse_and<T>(tokens : &mut Tokens<T>) -> Result<Expr, CalcError>
+where T: Iterator<Item = SpannedToken>
+{
+    let mut expr = parse_not(tokens)?;
+
+    let depth = tokens.depth;
+    while let Some(Token::And) = tokens.peek() {
+        tokens.deepen()?;
+        let op = tokens.expect()?;
+        expr = binary(&op, expr, parse_not(tokens)?);
+    }
+    tokens.depth = depth;
+
+    Ok(expr)
+}
+
+/*
+grammar:
+    or = and or'
+    or' = or and or'
+        | nothing
+*/
+fn parse_or<T>(tokens : &mut Tokens<T>) -> Result<Expr, CalcError>
+where T: Iterator<Item = SpannedToken>
+{
+    let mut expr = parse_and(tokens)?;
+
+    let depth = tokens.depth;
+    while let Some(Token::Or) = tokens.peek() {
+        tokens.deepen()?;
+        let op = tokens.expect()?;
+        expr = binary(&op, expr, parse_and(tokens)?);
+    }
+    tokens.depth = depth;
+
+    Ok(expr)
+}
+
+/*
+grammar:
+    conditional = or
+                | or ? conditional : conditional
+
+The loosest level, so n <= 1 ? 1 : n * f(n - 1) needs no brackets. Only
+the branch taken is evaluated.
+*/
+fn parse_conditional<T>(tokens : &mut Tokens<T>) -> Result<Expr, CalcError>
+where T: Iterator<Item = SpannedToken>
+{
+    let cond = parse_or(tokens)?;
+
+    if let Some(Token::Question) = tokens.peek() {
+        tokens.expect()?;
+        let then = tokens.nested(parse_conditional)?;
+        match tokens.next() {
+            Some(SpannedToken { token : Token::Colon, .. }) => {}
+            Some(other) => { return Err(unexpected(&other)); }
+            None => { return Err(CalcError::UnexpectedEnd(tokens.end)); }
+        }
+        let otherwise = tokens.nested(parse_conditional)?;
+        return Ok(conditional(Some(cond), Some(then), Some(otherwise)));
+    }
+
+    Ok(cond)
+}
+
+// The whole of `tokens` as one expression; `end` is where to report running
+// out of input if there are no tokens at all.
+fn parse_all<T>(tokens : T, end : usize) -> Result<Expr, CalcError> where T: Iterator<Item = SpannedToken> {
+    let tokens = tokens.collect::<Vec<_>>();
+    stack::run(|_| {
+        let mut tokens = Tokens::new(tokens.into_iter());
+        tokens.end = end;
+        let expr = parse_conditional(&mut tokens)?;
+
+        // all of the input has to be consumed by the time the expression is complete
+        match tokens.next() {
+            None => Ok(expr),
+            Some(token @ SpannedToken { token : Token::Invalid(_) | Token::InvalidDate | Token::InvalidExponent | Token::RightBracket, .. }) => Err(unexpected(&token)),
+            Some(token) => Err(CalcError::TrailingInput(token.start))
+        }
+    })
+}
+
+pub fn parse<T>(tokens : T) -> Result<Expr, CalcError> where T: Iterator<Item = SpannedToken> {
+    parse_all(tokens, 0)
+}
+
+// The parameter names of a definition's left-hand side: `name(a, b)` gives
+// Some(["a", "b"]). None if it doesn't have that shape.
+fn parameters(lhs : &[SpannedToken]) -> Option<Vec<String>> {
+    let mut params = Vec::new();
+    let (last, inner) = lhs.split_last()?;
+    if last.token != Token::RightBracket || inner.first()?.token != Token::LeftBracket {
+        return None;
+    }
+
+    for (i, token) in inner[1..].iter().enumerate() {
+        match (&token.token, i % 2) {
+            (Token::Ident(param), 0) => params.push(param.clone()),
+            (Token::Comma, 1) if i + 2 < inner.len() => {}
+            _ => { return None; }
+        }
+    }
+
+    Some(params)
+}
+
+// `simplify(expr)`, `expand(expr)` or `d/dx(expr)`, which the parser reads
+// as d divided by a call of dx, as the whole line; anything else is an
+// expression to evaluate.
+fn symbolic(expr : Expr) -> Statement {
+    match expr {
+        Expr::Call { name, mut args, .. } if args.len() == 1 && (name == "simplify" || name == "expand") => {
+            let operation = if name == "simplify" { Symbolic::Simplify } else { Symbolic::Expand };
+            Statement::Symbolic(operation, args.remove(0))
+        }
+        Expr::BinaryOp { op : Binary::Div, ref lhs, ref rhs, .. } => match (&**lhs, &**rhs) {
+            (Expr::Variable { name : d, .. }, Expr::Call { name, args, .. }) if d == "d" && args.len() == 1 && name.len() > 1 && name.starts_with('d') => {
+                Statement::Symbolic(Symbolic::Derivative(name[1..].to_string()), args[0].clone())
+            }
+            _ => Statement::Expr(expr)
+        },
+        expr => Statement::Expr(expr)
+    }
+}
+
+/*
+grammar:
+    statement = identifier = comparison
+              | identifier(parameters) = comparison
+              | simplify(comparison) | expand(comparison) | d/didentifier(comparison)
+              | comparison
+    parameters = nothing
+               | identifier parameters'
+    parameters' = , identifier parameters'
+                | nothing
+
+The whole line is read first, since telling a definition `f(x) = ...`
+from a call `f(x) + ...` takes looking as far as the `=`, the first one
+outside brackets, as solveprop(3/4 = x/12) has its own. Neither a
+variable nor a parameter may be named after a constant such as `pi`.
+*/
+pub fn parse_statement<T>(tokens : T) -> Result<Statement, CalcError> where T: Iterator<Item = SpannedToken> {
+    let mut tokens = tokens.collect::<Vec<_>>();
+    let mut depth : usize = 0;
+    let outside = |t : &SpannedToken| {
+        match t.token {
+            Token::LeftBracket => depth += 1,
+            Token::RightBracket => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+        depth == 0 && t.token == Token::Assign
+    };
+    let assign = match tokens.iter().position(outside) {
+        Some(assign) => assign,
+        None => { return parse(tokens.into_iter()).map(symbolic); }
+    };
+
+    let rhs = tokens.split_off(assign + 1);
+    let equals = tokens.pop().map_or(0, |t| t.start);
+    let (name, start, lhs) = match tokens.split_first() {
+        Some((SpannedToken { token : Token::Ident(name), start, .. }, lhs)) => (name.clone(), *start, lhs),
+        _ => { return Err(CalcError::UnexpectedToken(Token::Assign, equals)); }
+    };
+
+    if lhs.is_empty() {
+        if functions::constant(&name).is_some() {
+            return Err(CalcError::Constant(name, start));
+        }
+        return Ok(Statement::Assign(name, start, parse_all(rhs.into_iter(), equals + 1)?));
+    }
+
+    let params = parameters(lhs).ok_or(CalcError::UnexpectedToken(Token::Assign, equals))?;
+    if let Some(param) = params.iter().find(|param| functions::constant(param).is_some()) {
+        let offset = lhs.iter().find(|t| t.token == Token::Ident(param.clone())).map_or(start, |t| t.start);
+        return Err(CalcError::Constant(param.clone(), offset));
+    }
+    Ok(Statement::Define(name, params, parse_all(rhs.into_iter(), equals + 1)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_statement;
+    use super::MAX_NESTING;
+    use crate::ast::Sexp;
+    use crate::tokenize;
+    use crate::CalcError;
+    use crate::Dialect;
+
+    fn sexp(input : &str) -> String {
+        sexp_in(input, Dialect::Standard)
+    }
+
+    fn sexp_in(input : &str, dialect : Dialect) -> String {
+        let statement = parse_statement(tokenize(input, dialect)).expect("parses");
+        Sexp(&statement).to_string()
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(sexp("2 + 3 * 4"), "(+ 2 (* 3 4))");
+        assert_eq!(sexp("2 * 3 ^ 2"), "(* 2 (^ 3 2))");
+        assert_eq!(sexp("-2 ^ 2"), "(- (^ 2 2))");
+        assert_eq!(sexp("1 + 2 == 3"), "(== (+ 1 2) 3)");
+        assert_eq!(sexp("1 | 2 & 3"), "(| 1 (& 2 3))");
+        assert_eq!(sexp("1 < 2 ? 3 : 4"), "(if (< 1 2) 3 4)");
+    }
+
+    #[test]
+    fn associativity() {
+        assert_eq!(sexp("10 - 3 - 2"), "(- (- 10 3) 2)");
+        assert_eq!(sexp("100 / 10 / 2"), "(/ (/ 100 10) 2)");
+        assert_eq!(sexp("2 ^ 3 ^ 2"), "(^ 2 (^ 3 2))");
+        assert_eq!(sexp("2 ^ -1"), "(^ 2 (- 1))");
+    }
+
+    #[test]
+    fn python() {
+        let python = |input| sexp_in(input, Dialect::Python);
+        assert_eq!(python("2 ** 3 ** 2"), "(^ 2 (^ 3 2))");
+        assert_eq!(python("-2 ** 2"), "(- (^ 2 2))");
+        assert_eq!(python("2 ** -1"), "(^ 2 (- 1))");
+        assert_eq!(python("7 // 2 * 3 % 4"), "(% (* (// 7 2) 3) 4)");
+        // ^ is exclusive or, between & and |
+        assert_eq!(python("1 | 2 ^ 3 & 4"), "(| 1 (xor 2 (& 3 4)))");
+        assert_eq!(python("1 << 2 + 3"), "(<< 1 (+ 2 3))");
+        assert_eq!(python("not 1 < 2 and 3"), "(and (not (< 1 2)) 3)");
+    }
+
+    #[test]
+    fn nesting_limit() {
+        let nested = |n : usize| format!("{}1{}", "(".repeat(n), ")".repeat(n));
+        assert!(parse_statement(tokenize(&nested(MAX_NESTING), Dialect::Standard)).is_ok());
+        let deeper = parse_statement(tokenize(&nested(MAX_NESTING + 1), Dialect::Standard));
+        // at the operand one level too deep
+        assert_eq!(deeper.err(), Some(CalcError::Nesting(MAX_NESTING + 1)));
+    }
+}
@@ -0,0 +1,198 @@
+use std::alloc::GlobalAlloc;
+use std::alloc::Layout;
+use std::alloc::System;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use std::time::Instant;
+use std::vec;
+
+use crate::lexer;
+use crate::lexer::SpannedToken;
+use crate::CalcError;
+use crate::Dialect;
+
+/*
+The time and allocations of each phase of an evaluation: tokenizing,
+parsing, compiling and evaluating. Compiling is what Program::compile does
+for the bodies of sums, integrals and the like, working out their constant
+parts once, and is counted apart from the evaluation it happens in.
+
+Allocations are only counted by CountingAlloc, which a program has to make
+its global allocator for them to be; without it they stay at zero. It
+counts only while a measurement is running, so the unprofiled paths pay
+for a single relaxed load.
+*/
+pub struct CountingAlloc;
+
+const TOKENIZE : usize = 0;
+const PARSE : usize = 1;
+const COMPILE : usize = 2;
+const EVALUATE : usize = 3;
+
+static COUNTING : AtomicBool = AtomicBool::new(false);
+// the phase allocations are counted in while COUNTING
+static PHASE : AtomicUsize = AtomicUsize::new(TOKENIZE);
+static ALLOCATIONS : [AtomicU64; 4] = [const { AtomicU64::new(0) }; 4];
+static BYTES : [AtomicU64; 4] = [const { AtomicU64::new(0) }; 4];
+// spent compiling within the evaluation being measured
+static COMPILING : AtomicU64 = AtomicU64::new(0);
+
+fn record(size : usize) {
+    if COUNTING.load(Ordering::Relaxed) {
+        let phase = PHASE.load(Ordering::Relaxed);
+        ALLOCATIONS[phase].fetch_add(1, Ordering::Relaxed);
+        BYTES[phase].fetch_add(size as u64, Ordering::Relaxed);
+    }
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout : Layout) -> *mut u8 {
+        record(layout.size());
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr : *mut u8, layout : Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr : *mut u8, layout : Layout, new_size : usize) -> *mut u8 {
+        record(new_size);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct PhaseStats {
+    pub time : Duration,
+    pub allocations : u64,
+    pub bytes : u64
+}
+
+impl PhaseStats {
+    fn add(&mut self, other : &PhaseStats) {
+        self.time += other.time;
+        self.allocations += other.allocations;
+        self.bytes += other.bytes;
+    }
+
+    // What was counted for `phase`, which is reset.
+    fn take(phase : usize, time : Duration) -> PhaseStats {
+        PhaseStats {
+            time,
+            allocations : ALLOCATIONS[phase].swap(0, Ordering::Relaxed),
+            bytes : BYTES[phase].swap(0, Ordering::Relaxed)
+        }
+    }
+}
+
+fn measure<R>(phase : usize, f : impl FnOnce() -> R) -> (R, Duration) {
+    PHASE.store(phase, Ordering::Relaxed);
+    COUNTING.store(true, Ordering::Relaxed);
+    let start = Instant::now();
+
+    let result = f();
+
+    let time = start.elapsed();
+    COUNTING.store(false, Ordering::Relaxed);
+    (result, time)
+}
+
+// Runs `compile`, counting it in the compile phase if an evaluation is
+// being measured.
+pub fn compiling<R>(compile : impl FnOnce() -> R) -> R {
+    if !COUNTING.load(Ordering::Relaxed) || PHASE.load(Ordering::Relaxed) != EVALUATE {
+        return compile();
+    }
+
+    PHASE.store(COMPILE, Ordering::Relaxed);
+    let start = Instant::now();
+    let result = compile();
+    COMPILING.fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+    PHASE.store(EVALUATE, Ordering::Relaxed);
+    result
+}
+
+// Per-phase cost of evaluating one or more inputs.
+#[derive(Clone, Copy, Default)]
+pub struct EvalReport {
+    pub inputs : u64,
+    pub tokenize : PhaseStats,
+    pub parse : PhaseStats,
+    pub compile : PhaseStats,
+    pub evaluate : PhaseStats
+}
+
+impl EvalReport {
+    pub fn total(&self) -> PhaseStats {
+        let mut total = self.tokenize;
+        total.add(&self.parse);
+        total.add(&self.compile);
+        total.add(&self.evaluate);
+        total
+    }
+
+    pub fn add(&mut self, other : &EvalReport) {
+        self.inputs += other.inputs;
+        self.tokenize.add(&other.tokenize);
+        self.parse.add(&other.parse);
+        self.compile.add(&other.compile);
+        self.evaluate.add(&other.evaluate);
+    }
+}
+
+// The parser normally pulls tokens from the lexer as it goes. To time the
+// phases separately the lexer is first run on its own, then the tokens are
+// collected outside of any measurement and handed to `parse` in a buffer,
+// and what that builds to `eval`. A parse error leaves `compile` and
+// `evaluate` at zero. Evaluations on other threads at the same time are
+// counted too.
+pub fn evaluate<T, R, P, E>(input : &str, dialect : Dialect, parse : P, eval : E) -> (Result<R, CalcError>, EvalReport)
+where P: FnOnce(vec::IntoIter<SpannedToken>) -> Result<T, CalcError>,
+      E: FnOnce(T) -> Result<R, CalcError>
+{
+    let (_, time) = measure(TOKENIZE, || lexer::Lexer::new(input, dialect).count());
+    let tokenize = PhaseStats::take(TOKENIZE, time);
+    let tokens = lexer::Lexer::new(input, dialect).collect::<Vec<_>>();
+    let (tree, time) = measure(PARSE, || parse(tokens.into_iter()));
+    let parse = PhaseStats::take(PARSE, time);
+    let mut report = EvalReport { inputs : 1, tokenize, parse, ..EvalReport::default() };
+
+    match tree {
+        Ok(tree) => {
+            COMPILING.store(0, Ordering::Relaxed);
+            let (value, time) = measure(EVALUATE, || eval(tree));
+            let compiling = Duration::from_nanos(COMPILING.swap(0, Ordering::Relaxed));
+            report.compile = PhaseStats::take(COMPILE, compiling);
+            report.evaluate = PhaseStats::take(EVALUATE, time.saturating_sub(compiling));
+            (value, report)
+        }
+        Err(e) => (Err(e), report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::evaluate;
+    use crate::eval;
+    use crate::parser;
+    use crate::Context;
+    use crate::Dialect;
+
+    #[test]
+    fn phases() {
+        let ctx = Context::<f64>::default();
+        let (value, report) = evaluate("sum(i, 1, 10, i * 2)", Dialect::Standard, parser::parse, |expr| eval::evaluate(&expr, &ctx));
+        assert!(value.is_ok());
+        assert!(report.compile.time > Duration::ZERO);
+        assert_eq!(report.total().time, report.tokenize.time + report.parse.time + report.compile.time + report.evaluate.time);
+
+        let (value, report) = evaluate("1 +", Dialect::Standard, parser::parse, |expr| eval::evaluate(&expr, &ctx));
+        assert!(value.is_err());
+        assert_eq!((report.compile.time, report.evaluate.time), (Duration::ZERO, Duration::ZERO));
+    }
+}
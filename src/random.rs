@@ -0,0 +1,135 @@
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+
+/*
+The numbers rand, randint and normal draw from: splitmix64, which steps
+its state by a constant and scrambles the result. The state is atomic so
+that a context can be evaluated in through a shared reference and still
+move on with each number drawn; what one context draws doesn't repeat in
+another unless they were given the same seed.
+
+Without a seed, the state starts from the keys std picks for hash maps,
+which are different in each process.
+*/
+
+const GAMMA : u64 = 0x9e37_79b9_7f4a_7c15;
+
+pub struct Random {
+    state : AtomicU64
+}
+
+impl Random {
+    pub fn new(seed : u64) -> Random {
+        Random { state : AtomicU64::new(seed) }
+    }
+
+    pub fn next_u64(&self) -> u64 {
+        let mut z = self.state.fetch_add(GAMMA, Ordering::Relaxed).wrapping_add(GAMMA);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // In [0, 1), from the top 53 bits, as many as an f64 holds.
+    pub fn uniform(&self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    // In [0, n], each as likely as the others; draws again rather than
+    // favouring the low numbers when n + 1 doesn't divide 2^64.
+    pub fn up_to(&self, n : u64) -> u64 {
+        if n == u64::MAX {
+            return self.next_u64();
+        }
+        let range = n + 1;
+        let limit = u64::MAX - u64::MAX % range;
+        loop {
+            let x = self.next_u64();
+            if x < limit {
+                return x % range;
+            }
+        }
+    }
+
+    // Normally distributed with mean 0 and standard deviation 1, by the
+    // Box-Muller transform.
+    pub fn normal(&self) -> f64 {
+        let u = 1.0 - self.uniform();
+        let v = self.uniform();
+        (-2.0 * u.ln()).sqrt() * (std::f64::consts::TAU * v).cos()
+    }
+}
+
+impl Default for Random {
+    fn default() -> Random {
+        Random::new(RandomState::new().hash_one(GAMMA))
+    }
+}
+
+impl Clone for Random {
+    fn clone(&self) -> Random {
+        Random::new(self.state.load(Ordering::Relaxed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::evaluate;
+    use crate::evaluate_with;
+    use crate::CalcError;
+    use crate::Context;
+    use crate::Dialect;
+    use crate::Value;
+
+    use super::Random;
+
+    #[test]
+    fn seeded() {
+        let (a, b) = (Random::new(42), Random::new(42));
+        let drawn : Vec<u64> = (0..5).map(|_| a.next_u64()).collect();
+        assert_eq!(drawn, (0..5).map(|_| b.next_u64()).collect::<Vec<_>>());
+        assert_ne!(drawn[0], Random::new(43).next_u64());
+        // a clone goes on from where the original is
+        let c = a.clone();
+        assert_eq!(a.next_u64(), c.next_u64());
+    }
+
+    #[test]
+    fn ranges() {
+        let random = Random::new(7);
+        let mut seen = [0; 6];
+        for _ in 0..6000 {
+            let x = random.uniform();
+            assert!((0.0..1.0).contains(&x));
+            seen[random.up_to(5) as usize] += 1;
+        }
+        assert!(seen.iter().all(|n| (800..1200).contains(n)), "{:?}", seen);
+        assert_eq!(random.up_to(0), 0);
+        let mean = (0..10_000).map(|_| random.normal()).sum::<f64>() / 10_000.0;
+        assert!(mean.abs() < 0.05, "{}", mean);
+    }
+
+    #[test]
+    fn functions() {
+        let seeded = || Context::<f64> { random : Random::new(1), ..Context::default() };
+        let list = "[rand(), rand(2, 4), randint(1, 6), normal(10, 2)]";
+        assert_eq!(evaluate_with(list, Dialect::Standard, &seeded()), evaluate_with(list, Dialect::Standard, &seeded()));
+
+        let ctx = seeded();
+        let draw = |input| match evaluate_with(input, Dialect::Standard, &ctx) {
+            Ok(Value::Scalar(quantity)) => quantity.value,
+            other => panic!("not a number: {:?}", other)
+        };
+        for _ in 0..100 {
+            assert!((0.0..1.0).contains(&draw("rand()")));
+            assert!((2.0..4.0).contains(&draw("rand(2, 4)")));
+            let die = draw("randint(1, 6)");
+            assert!(die.fract() == 0.0 && (1.0..=6.0).contains(&die));
+        }
+        assert_eq!(evaluate_with("rand(2 m, 2 m)", Dialect::Standard, &ctx), evaluate("2 m"));
+        assert_eq!(evaluate_with("randint(6, 1)", Dialect::Standard, &ctx), Err(CalcError::Range("randint".to_string(), 0)));
+        assert_eq!(evaluate_with("rand(1)", Dialect::Standard, &ctx), Err(CalcError::ArgumentCount("rand".to_string(), 1, 0)));
+    }
+}
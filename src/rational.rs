@@ -0,0 +1,521 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+use crate::natural::Natural;
+use crate::number::Number;
+
+/*
+Exact fractions, kept in lowest terms. Everything but powers with a
+fractional exponent is exact; those, and the built-in functions, go through
+f64, and so do infinities and NaN.
+
+The lexer reads every literal as a `Rational`, so `0.1` or `2 1/3` reach
+the evaluator exactly, whatever it then computes in.
+*/
+
+// Integer powers are worked out exactly, by repeated squaring, up to about
+// this many digits; beyond that they are too large to be.
+const MAX_POW_DIGITS : usize = 300_000;
+
+#[derive(Clone, Debug)]
+pub struct Rational(Repr);
+
+// About how many decimal digits `n` has, for a non-zero `n`.
+fn log10(n : &Natural) -> f64 {
+    let digits = n.digits();
+    let lead = &digits[..digits.len().min(15)];
+    lead.parse::<f64>().unwrap_or(1.0).log10() + (digits.len() - lead.len()) as f64
+}
+
+#[derive(Clone, Debug)]
+enum Repr {
+    // in lowest terms; zero is 0/1 and never negative
+    Finite { negative : bool, numerator : Natural, denominator : Natural },
+    // an infinity or NaN
+    Special(f64)
+}
+
+// The sign, numerator and denominator of a finite rational.
+pub type Parts<'a> = (bool, &'a Natural, &'a Natural);
+
+impl Rational {
+    // For a non-zero denominator.
+    pub fn from_parts(negative : bool, numerator : Natural, denominator : Natural) -> Rational {
+        let gcd = numerator.gcd(&denominator);
+        let (numerator, denominator) = if gcd == Natural::small(1) {
+            (numerator, denominator)
+        }
+        else {
+            (numerator.div_rem(&gcd).0, denominator.div_rem(&gcd).0)
+        };
+
+        Rational(Repr::Finite { negative : negative && !numerator.is_zero(), numerator, denominator })
+    }
+
+    // numerator / denominator, for a non-zero denominator
+    pub fn ratio(numerator : u64, denominator : u64) -> Rational {
+        Rational::from_parts(false, Natural::from_u64(numerator), Natural::from_u64(denominator))
+    }
+
+    // A literal such as `12.5`: ASCII digits with at most one point.
+    pub fn from_decimal(literal : &str) -> Rational {
+        Rational::from_literal(literal, '.')
+    }
+
+    // A literal as typed, with `point` before the fraction and its digits
+    // perhaps grouped by `_`, as in 1_000,5 in the European dialect.
+    pub fn from_literal(literal : &str, point : char) -> Rational {
+        let (integer, fraction) = literal.split_once(point).unwrap_or((literal, ""));
+        if let Some(value) = Rational::small_literal(integer, fraction) {
+            return value;
+        }
+        let numerator = Natural::from_decimal_chars(integer.chars().chain(fraction.chars()));
+        let places = fraction.bytes().filter(u8::is_ascii_digit).count();
+        Rational::from_parts(false, numerator, Natural::small(1).shifted(places))
+    }
+
+    // A literal whose digits fit in a u64, reduced in u64 so that only the
+    // numerator and denominator are allocated.
+    fn small_literal(integer : &str, fraction : &str) -> Option<Rational> {
+        let mut numerator : u64 = 0;
+        for digit in integer.chars().chain(fraction.chars()).filter_map(|c| c.to_digit(10)) {
+            numerator = numerator.checked_mul(10)?.checked_add(digit as u64)?;
+        }
+        let places = fraction.bytes().filter(u8::is_ascii_digit).count();
+        let denominator = 10u64.checked_pow(places as u32)?;
+
+        let (mut a, mut b) = (numerator, denominator);
+        while b != 0 {
+            (a, b) = (b, a % b);
+        }
+        let (numerator, denominator) = (Natural::from_u64(numerator / a), Natural::from_u64(denominator / a));
+        Some(Rational(Repr::Finite { negative : false, numerator, denominator }))
+    }
+
+    // mantissa * 2^exponent
+    pub fn binary(mantissa : u64, exponent : i32) -> Rational {
+        let power = Natural::small(2).power(exponent.unsigned_abs() as u64);
+        if exponent >= 0 {
+            Rational::from_parts(false, Natural::from_u64(mantissa).product(&power), Natural::small(1))
+        }
+        else {
+            Rational::from_parts(false, Natural::from_u64(mantissa), power)
+        }
+    }
+
+    // A float exactly, as the binary fraction it is: 0.1 is
+    // 3602879701896397/36028797018963968.
+    pub fn exactly(value : f64) -> Rational {
+        if !value.is_finite() {
+            return Rational(Repr::Special(value));
+        }
+
+        let bits = value.to_bits();
+        let biased = ((bits >> 52) & 0x7ff) as i32;
+        let fraction = bits & ((1 << 52) - 1);
+        let (mantissa, exponent) = if biased == 0 { (fraction, -1074) } else { (fraction | 1 << 52, biased - 1075) };
+        let magnitude = Rational::binary(mantissa, exponent);
+        if value.is_sign_negative() { magnitude.neg() } else { magnitude }
+    }
+
+    pub fn integer(value : i128) -> Rational {
+        Rational::from_parts(value < 0, Natural::from_digits(&value.unsigned_abs().to_string()), Natural::small(1))
+    }
+
+    // The value if it is an integer that fits in an i128.
+    pub fn to_integer(&self) -> Option<i128> {
+        let (negative, numerator, denominator) = self.parts()?;
+        if *denominator != Natural::small(1) {
+            return None;
+        }
+        format!("{}{}", if negative { "-" } else { "" }, numerator.digits()).parse().ok()
+    }
+
+    pub fn parts(&self) -> Option<Parts<'_>> {
+        match &self.0 {
+            Repr::Finite { negative, numerator, denominator } => Some((*negative, numerator, denominator)),
+            Repr::Special(_) => None
+        }
+    }
+
+    fn is_zero(&self) -> bool {
+        self.parts().is_some_and(|(_, numerator, _)| numerator.is_zero())
+    }
+
+    fn via_f64(&self, rhs : &Rational, f : impl Fn(f64, f64) -> f64) -> Rational {
+        Rational::from_f64(f(self.to_f64(), rhs.to_f64()))
+    }
+
+    // None if either is NaN.
+    pub fn compare(&self, other : &Rational) -> Option<Ordering> {
+        let ((an, a, ad), (bn, b, bd)) = match (self.parts(), other.parts()) {
+            (Some(a), Some(b)) => (a, b),
+            _ => { return self.to_f64().partial_cmp(&other.to_f64()); }
+        };
+
+        // zero is never negative, so differing signs decide it
+        let magnitude = a.product(bd).cmp(&b.product(ad));
+        Some(match (an, bn) {
+            (false, false) => magnitude,
+            (true, true) => magnitude.reverse(),
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less
+        })
+    }
+
+    // How many digits after the point the value has written in decimal, if
+    // that terminates: when the denominator has no prime factors but 2 and 5.
+    pub fn decimal_places(&self) -> Option<usize> {
+        let (_, _, denominator) = self.parts()?;
+        let mut rest = denominator.clone();
+        let mut counts = [0; 2];
+        for (count, factor) in counts.iter_mut().zip([2, 5]) {
+            loop {
+                let (quotient, rem) = rest.div_rem_small(factor);
+                if rem != 0 {
+                    break;
+                }
+                rest = quotient;
+                *count += 1;
+            }
+        }
+
+        if rest == Natural::small(1) { Some(counts[0].max(counts[1])) } else { None }
+    }
+
+    // Cut off after `places` decimal places, toward zero.
+    pub fn truncated(&self, places : usize) -> Rational {
+        match self.parts() {
+            Some((negative, numerator, denominator)) => {
+                Rational::from_parts(negative, numerator.shifted(places).div_rem(denominator).0, Natural::small(1).shifted(places))
+            }
+            None => self.clone()
+        }
+    }
+
+    // The exact decimal form, if it terminates.
+    pub fn to_decimal(&self) -> Option<String> {
+        let places = self.decimal_places()?;
+        let (negative, numerator, denominator) = self.parts()?;
+        let mut s = numerator.shifted(places).div_rem(denominator).0.digits();
+        if places > 0 {
+            if s.len() <= places {
+                s = "0".repeat(places + 1 - s.len()) + &s;
+            }
+            s.insert(s.len() - places, '.');
+        }
+        Some(format!("{}{}", if negative { "-" } else { "" }, s))
+    }
+
+    // As a mixed number, e.g. 2 1/3 or -1 1/2.
+    pub fn mixed(&self) -> String {
+        let (negative, numerator, denominator) = match self.parts() {
+            Some(parts) => parts,
+            None => { return self.to_string(); }
+        };
+
+        let (whole, rem) = numerator.div_rem(denominator);
+        if whole.is_zero() || rem.is_zero() {
+            return self.to_string();
+        }
+        format!("{}{} {}/{}", if negative { "-" } else { "" }, whole.digits(), rem.digits(), denominator.digits())
+    }
+}
+
+impl PartialEq for Rational {
+    fn eq(&self, other : &Rational) -> bool {
+        match (&self.0, &other.0) {
+            (Repr::Special(a), Repr::Special(b)) => a == b,
+            (Repr::Finite { .. }, Repr::Finite { .. }) => self.parts() == other.parts(),
+            _ => false
+        }
+    }
+}
+
+impl fmt::Display for Rational {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (negative, numerator, denominator) = match &self.0 {
+            Repr::Finite { negative, numerator, denominator } => (*negative, numerator, denominator),
+            Repr::Special(value) => { return write!(f, "{}", value); }
+        };
+
+        write!(f, "{}{}", if negative { "-" } else { "" }, numerator.digits())?;
+        if *denominator != Natural::small(1) {
+            write!(f, "/{}", denominator.digits())?;
+        }
+        Ok(())
+    }
+}
+
+/*
+numerator / denominator rounded to the nearest float, ties to even. The
+quotient is scaled by a power of two to between 55 and 64 bits, which
+with whether anything was left over is enough to round it once: to 53
+bits, or to fewer where the float would be subnormal.
+*/
+fn nearest_f64(numerator : &Natural, denominator : &Natural) -> f64 {
+    if numerator.is_zero() {
+        return 0.0;
+    }
+
+    // a first guess at the scale from the decimal lengths, corrected until
+    // the quotient has enough bits
+    let lengths = numerator.digits().len() as f64 - denominator.digits().len() as f64;
+    let mut scale = 60 - (lengths * std::f64::consts::LOG2_10) as i32;
+    let (quotient, exact) = loop {
+        let power = Natural::small(2).power(scale.unsigned_abs() as u64);
+        let (quotient, rem) = if scale >= 0 {
+            numerator.product(&power).div_rem(denominator)
+        }
+        else {
+            numerator.div_rem(&denominator.product(&power))
+        };
+        match quotient.to_u64() {
+            Some(q) if q >> 54 != 0 => break (q as u128, rem.is_zero()),
+            Some(_) => scale += 8,
+            None => scale -= 8
+        }
+    };
+
+    // the value is in [2^top, 2^(top + 1))
+    let bits = 128 - quotient.leading_zeros() as i32;
+    let top = bits - 1 - scale;
+    if top > 1023 {
+        return f64::INFINITY;
+    }
+    let keep = if top < -1022 { 53 - (-1022 - top) } else { 53 };
+    if keep < 0 {
+        return 0.0;
+    }
+
+    let drop = bits - keep;
+    let (mut mantissa, rest, half) = (quotient >> drop, quotient & ((1 << drop) - 1), 1 << (drop - 1));
+    if rest > half || (rest == half && (!exact || mantissa & 1 == 1)) {
+        mantissa += 1;
+    }
+    let mut value = mantissa as f64;
+    let mut exponent = drop - scale;
+    while exponent > 1000 {
+        value *= 2f64.powi(1000);
+        exponent -= 1000;
+    }
+    while exponent < -1000 {
+        value *= 2f64.powi(-1000);
+        exponent += 1000;
+    }
+    value * 2f64.powi(exponent)
+}
+
+impl Number for Rational {
+    // Through the shortest decimal that reads back as the same f64, so 0.1
+    // gives 1/10. A whole float is taken exactly instead: the shortest
+    // decimal of 2^60 is 1152921504606847000, which is another integer.
+    fn from_f64(value : f64) -> Rational {
+        if !value.is_finite() || value.fract() == 0.0 {
+            return Rational::exactly(value);
+        }
+
+        let s = value.to_string();
+        match s.strip_prefix('-') {
+            Some(rest) => Rational::from_decimal(rest).neg(),
+            None => Rational::from_decimal(&s)
+        }
+    }
+
+    fn to_f64(&self) -> f64 {
+        let (negative, numerator, denominator) = match &self.0 {
+            Repr::Finite { negative, numerator, denominator } => (*negative, numerator, denominator),
+            Repr::Special(value) => { return *value; }
+        };
+
+        // exact operands give a correctly rounded quotient
+        const EXACT : u64 = 1 << 53;
+        let value = match (numerator.to_u64(), denominator.to_u64()) {
+            (Some(n), Some(d)) if n <= EXACT && d <= EXACT => n as f64 / d as f64,
+            _ => nearest_f64(numerator, denominator)
+        };
+
+        if negative { -value } else { value }
+    }
+
+    fn from_rational(value : &Rational, _precision : usize) -> Rational {
+        value.clone()
+    }
+
+    fn to_rational(&self) -> Rational {
+        self.clone()
+    }
+
+    fn to_decimal_string(&self) -> String {
+        self.to_decimal().unwrap_or_else(|| self.to_f64().to_string())
+    }
+
+    fn add(&self, rhs : &Rational) -> Rational {
+        let ((an, a, ad), (bn, b, bd)) = match (self.parts(), rhs.parts()) {
+            (Some(a), Some(b)) => (a, b),
+            _ => { return self.via_f64(rhs, |a, b| a + b); }
+        };
+
+        let (a, b) = (a.product(bd), b.product(ad));
+        let denominator = ad.product(bd);
+        if an == bn {
+            Rational::from_parts(an, a.sum(&b), denominator)
+        }
+        else if a >= b {
+            Rational::from_parts(an, a.difference(&b), denominator)
+        }
+        else {
+            Rational::from_parts(bn, b.difference(&a), denominator)
+        }
+    }
+
+    fn sub(&self, rhs : &Rational) -> Rational {
+        self.add(&rhs.neg())
+    }
+
+    fn mul(&self, rhs : &Rational) -> Rational {
+        match (self.parts(), rhs.parts()) {
+            (Some((an, a, ad)), Some((bn, b, bd))) => Rational::from_parts(an != bn, a.product(b), ad.product(bd)),
+            _ => self.via_f64(rhs, |a, b| a * b)
+        }
+    }
+
+    fn neg(&self) -> Rational {
+        match &self.0 {
+            Repr::Finite { negative, numerator, denominator } => {
+                Rational(Repr::Finite { negative : !negative && !numerator.is_zero(), numerator : numerator.clone(), denominator : denominator.clone() })
+            }
+            Repr::Special(value) => Rational(Repr::Special(-value))
+        }
+    }
+
+    fn div(&self, rhs : &Rational, _precision : usize) -> Option<Rational> {
+        if rhs.is_zero() {
+            return None;
+        }
+        match (self.parts(), rhs.parts()) {
+            (Some((an, a, ad)), Some((bn, b, bd))) => Some(Rational::from_parts(an != bn, a.product(bd), ad.product(b))),
+            _ => Some(self.via_f64(rhs, |a, b| a / b))
+        }
+    }
+
+    fn floor_div(&self, rhs : &Rational) -> Option<Rational> {
+        if rhs.is_zero() {
+            return None;
+        }
+        let ((an, a, ad), (bn, b, bd)) = match (self.parts(), rhs.parts()) {
+            (Some(a), Some(b)) => (a, b),
+            _ => { return Some(self.via_f64(rhs, |a, b| (a / b).floor())); }
+        };
+
+        // a negative quotient that isn't whole rounds away from zero
+        let negative = an != bn;
+        let (mut quotient, rem) = a.product(bd).div_rem(&ad.product(b));
+        if negative && !rem.is_zero() {
+            quotient = quotient.sum(&Natural::small(1));
+        }
+        Some(Rational::from_parts(negative, quotient, Natural::small(1)))
+    }
+
+    fn modulo(&self, rhs : &Rational) -> Option<Rational> {
+        if rhs.is_zero() {
+            return None;
+        }
+        if self.parts().is_none() || rhs.parts().is_none() {
+            return Some(self.via_f64(rhs, |a, b| a.modulo(&b).unwrap_or(f64::NAN)));
+        }
+        let quotient = self.floor_div(rhs)?;
+        Some(self.sub(&rhs.mul(&quotient)))
+    }
+
+    fn pow(&self, rhs : &Rational, _precision : usize) -> Option<Rational> {
+        let (sign, numerator, denominator, negative, exponent) = match (self.parts(), rhs.parts()) {
+            (Some((sign, numerator, denominator)), Some((negative, exponent, one))) if *one == Natural::small(1) => {
+                (sign, numerator, denominator, negative, exponent)
+            }
+            _ => { return Some(self.via_f64(rhs, f64::powf)); }
+        };
+
+        // 0, 1 and -1 to any power, however large
+        if numerator.is_zero() {
+            return match (negative, exponent.is_zero()) {
+                (true, _) => None,
+                (false, zero) => Some(Rational::integer(zero as i128))
+            };
+        }
+        if numerator == denominator {
+            let odd = exponent.0.first().is_some_and(|limb| limb % 2 == 1);
+            return Some(Rational::integer(if sign && odd { -1 } else { 1 }));
+        }
+
+        let digits = exponent.to_u64().map(|e| e as f64 * (log10(numerator) + log10(denominator)));
+        let exponent = exponent.to_u64().filter(|_| digits.is_some_and(|digits| digits <= MAX_POW_DIGITS as f64))?;
+        // powers of coprime numbers are coprime, so still in lowest terms
+        let (numerator, denominator) = (numerator.power(exponent), denominator.power(exponent));
+        let (numerator, denominator) = if negative { (denominator, numerator) } else { (numerator, denominator) };
+        Some(Rational(Repr::Finite { negative : sign && exponent % 2 == 1, numerator, denominator }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Rational;
+    use crate::number::Number;
+
+    fn pow(base : i128, exponent : i128) -> Option<Rational> {
+        Rational::integer(base).pow(&Rational::integer(exponent), 0)
+    }
+
+    #[test]
+    fn exact_arithmetic() {
+        let (third, sixth) = (Rational::ratio(1, 3), Rational::ratio(1, 6));
+        assert_eq!(third.add(&sixth), Rational::ratio(1, 2));
+        assert_eq!(third.sub(&sixth.mul(&Rational::integer(4))).to_string(), "-1/3");
+        assert_eq!(third.div(&sixth, 0), Some(Rational::integer(2)));
+        assert_eq!(third.div(&Rational::integer(0), 0), None);
+        assert_eq!(Rational::ratio(7, 2).neg().floor_div(&Rational::integer(1)), Some(Rational::integer(-4)));
+        // the remainder takes the sign of the divisor
+        assert_eq!(Rational::ratio(7, 2).neg().modulo(&Rational::integer(2)), Some(Rational::ratio(1, 2)));
+    }
+
+    #[test]
+    fn floats() {
+        assert_eq!(Rational::exactly(0.1), Rational::binary(3602879701896397, -55));
+        assert_eq!(Rational::exactly(-0.5), Rational::ratio(1, 2).neg());
+        assert_eq!(Rational::exactly(f64::from_bits(1)), Rational::binary(1, -1074));
+        assert_eq!(Rational::from_f64(0.1), Rational::ratio(1, 10));
+        assert_eq!(Rational::from_f64(2f64.powi(60)), Rational::binary(1, 60));
+    }
+
+    #[test]
+    fn nearest_floats() {
+        let one = Rational::integer(1);
+        let tie = one.add(&Rational::binary(1, -53));
+        assert_eq!(tie.to_f64(), 1.0);
+        assert_eq!(tie.add(&Rational::binary(1, -80)).to_f64(), 1f64.next_up());
+        let huge = Rational::integer(10).pow(&Rational::integer(400), 0).expect("exact");
+        let third = huge.add(&one).div(&huge.mul(&Rational::integer(3)), 0).expect("not zero");
+        assert_eq!(third.to_f64(), 1.0 / 3.0);
+        assert_eq!(huge.to_f64(), f64::INFINITY);
+        assert_eq!(one.div(&huge, 0).map(|tiny| tiny.to_f64()), Some(0.0));
+    }
+
+    #[test]
+    fn integer_powers_are_exact() {
+        assert_eq!(pow(0, -1), None);
+        assert_eq!(pow(0, 0), Some(Rational::integer(1)));
+        assert_eq!(pow(-2, 3), Some(Rational::integer(-8)));
+        assert_eq!(pow(2, -2), Some(Rational::ratio(1, 4)));
+        let big = pow(2, 100_000).expect("exact");
+        assert_eq!(big.parts().map(|(_, n, _)| n.digits().len()), Some(30_103));
+        let small = pow(10, -100_000).expect("exact");
+        assert_eq!(small.parts().map(|(_, _, d)| d.digits().len()), Some(100_001));
+    }
+
+    #[test]
+    fn powers_of_one_and_beyond_the_limit() {
+        let huge = Rational::integer(10).pow(&Rational::integer(40), 0).expect("exact");
+        assert_eq!(Rational::integer(-1).pow(&huge.add(&Rational::integer(1)), 0), Some(Rational::integer(-1)));
+        assert_eq!(Rational::integer(2).pow(&huge, 0), None);
+    }
+}
@@ -0,0 +1,1063 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::env;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+use calculator::ast::Binary;
+use calculator::ast::Dot;
+use calculator::ast::Expr;
+use calculator::ast::Latex;
+use calculator::date;
+use calculator::eval;
+use calculator::format;
+use calculator::functions;
+use calculator::lexer;
+use calculator::parser;
+use calculator::random::Random;
+use calculator::symbolic;
+use calculator::units;
+use calculator::AngleUnit;
+use calculator::CalcError;
+use calculator::Context;
+use calculator::Decimal;
+use calculator::Dialect;
+use calculator::Number;
+use calculator::Outcome;
+use calculator::Rational;
+use calculator::UserFunction;
+use calculator::Value;
+
+use crate::ast_json;
+use crate::cli::Numbers;
+use crate::cli::Options;
+use crate::editor::Editor;
+use crate::editor::Input;
+use crate::equiv;
+use crate::highlight;
+use crate::json;
+use crate::lint;
+use crate::messages;
+use crate::messages::Msg;
+use crate::report;
+use crate::rpn;
+use crate::session_json;
+
+const PROMPT : &str = ">> ";
+// of the lines an unfinished one goes on on
+const CONTINUATION : &str = ".. ";
+
+/*
+The init script is run when the REPL starts, from --config or else
+~/.config/calculator/init.calc, and holds lines as typed at the prompt:
+commands such as `:mode deg` or `:alias`, assignments and definitions, and
+comments starting with `#`. Only errors in it are shown. As with
+`:save-config`, which writes the settings of the session back to it, the
+statements are in the standard dialect whatever --dialect says.
+*/
+const CONFIG_DIR : &str = "calculator";
+const CONFIG_FILE : &str = "init.calc";
+
+/*
+A whole session, as `:save` writes it and `:load` reads it back: the
+variables, functions, results and what was typed for them, and the
+settings, as one JSON object. Leaving the REPL saves it to this file in
+the home directory for `:load` without a file name, unless nothing was
+done in it.
+*/
+const SESSION_FILE : &str = ".calculator_session.json";
+
+struct Settings {
+    autoclose : bool,
+    // print the steps of each evaluation
+    trace : bool,
+    display : format::Notation,
+    style : format::Style,
+    // a word at the start of a line and what it stands for
+    aliases : Vec<(String, String)>,
+    // of results, errors and the markers under them
+    color : bool
+}
+
+// An evaluated line and what was printed for it.
+struct Entry {
+    input : String,
+    output : String
+}
+
+struct Repl<'a, N> {
+    options : &'a Options,
+    numbers : Numbers,
+    settings : Settings,
+    ctx : Context<N>,
+    rpn : Option<rpn::Rpn<N>>,
+    history : Vec<Entry>
+}
+
+enum Flow {
+    Continue,
+    Quit,
+    // to computing in other numbers
+    Switch(Numbers),
+    // the session saved in the file, which may compute in other numbers
+    Load(PathBuf)
+}
+
+// The session in whichever numbers it computes in at the moment.
+enum Session<'a> {
+    Float(Repl<'a, f64>),
+    Decimal(Repl<'a, Decimal>),
+    Rational(Repl<'a, Rational>)
+}
+
+impl<'a> Session<'a> {
+    fn new(options : &'a Options) -> Session<'a> {
+        let repl = Repl {
+            options,
+            numbers : Numbers::Float,
+            settings : Settings { autoclose : false, trace : false, display : format::Notation::Decimal, style : options.style, aliases : Vec::new(), color : highlight::enabled() },
+            ctx : Context { angle : options.angle, ..Context::default() },
+            rpn : if options.rpn { Some(rpn::Rpn::new()) } else { None },
+            history : Vec::new()
+        };
+        Session::Float(repl).switch(options.numbers)
+    }
+
+    fn switch(self, numbers : Numbers) -> Session<'a> {
+        match (self, numbers) {
+            (Session::Float(repl), Numbers::Float) => Session::Float(repl),
+            (Session::Float(repl), Numbers::Decimal) => Session::Decimal(repl.convert(Numbers::Float, numbers)),
+            (Session::Float(repl), Numbers::Rational) => Session::Rational(repl.convert(Numbers::Float, numbers)),
+            (Session::Decimal(repl), Numbers::Decimal) => Session::Decimal(repl),
+            (Session::Decimal(repl), Numbers::Float) => Session::Float(repl.convert(Numbers::Decimal, numbers)),
+            (Session::Decimal(repl), Numbers::Rational) => Session::Rational(repl.convert(Numbers::Decimal, numbers)),
+            (Session::Rational(repl), Numbers::Rational) => Session::Rational(repl),
+            (Session::Rational(repl), Numbers::Float) => Session::Float(repl.convert(Numbers::Rational, numbers)),
+            (Session::Rational(repl), Numbers::Decimal) => Session::Decimal(repl.convert(Numbers::Rational, numbers))
+        }
+    }
+
+    fn line(&mut self, input : &str) -> Flow {
+        match self {
+            Session::Float(repl) => repl.line(input),
+            Session::Decimal(repl) => repl.line(input),
+            Session::Rational(repl) => repl.line(input)
+        }
+    }
+
+    // The session saved in `path`, or this one if it can't be read.
+    fn restore(self, path : &Path) -> Session<'a> {
+        let shown = path.display().to_string();
+        let saved = match fs::read_to_string(path) {
+            Ok(saved) => saved,
+            Err(e) => {
+                println!("{}: {}", shown, e);
+                return self;
+            }
+        };
+        let saved = json::parse(&saved);
+        let numbers = match saved.as_ref().and_then(|saved| Numbers::from_name(saved.get("numbers")?.as_str()?)) {
+            Some(numbers) => numbers,
+            None => {
+                println!("{}", Msg::InvalidSession(&shown));
+                return self;
+            }
+        };
+
+        // all of the file is read before anything changes, so that this
+        // session goes on untouched, in its own numbers, if any of it is bad
+        let restored = saved.and_then(|saved| match numbers {
+            Numbers::Float => Saved::read(&saved).map(Restored::Float),
+            Numbers::Decimal => Saved::read(&saved).map(Restored::Decimal),
+            Numbers::Rational => Saved::read(&saved).map(Restored::Rational)
+        });
+        let restored = match restored {
+            Some(restored) => restored,
+            None => {
+                println!("{}", Msg::InvalidSession(&shown));
+                return self;
+            }
+        };
+
+        println!("{}", Msg::LoadedSession(&shown));
+        match (self.switch(numbers), restored) {
+            (Session::Float(mut repl), Restored::Float(saved)) => { repl.restore(saved); Session::Float(repl) }
+            (Session::Decimal(mut repl), Restored::Decimal(saved)) => { repl.restore(saved); Session::Decimal(repl) }
+            (Session::Rational(mut repl), Restored::Rational(saved)) => { repl.restore(saved); Session::Rational(repl) }
+            _ => unreachable!("switched to the numbers the session was read in")
+        }
+    }
+
+    fn unfinished(&self, input : &str) -> bool {
+        match self {
+            Session::Float(repl) => repl.unfinished(input),
+            Session::Decimal(repl) => repl.unfinished(input),
+            Session::Rational(repl) => repl.unfinished(input)
+        }
+    }
+
+    // Saves the session to SESSION_FILE if anything was done in it.
+    fn autosave(&self) {
+        let saved = match self {
+            Session::Float(repl) => repl.autosave(),
+            Session::Decimal(repl) => repl.autosave(),
+            Session::Rational(repl) => repl.autosave()
+        };
+        if let Err(e) = saved {
+            println!("{}", e);
+        }
+    }
+
+    // Runs the init script at `path`, if there is one.
+    fn load(mut self, path : &Path) -> Session<'a> {
+        let script = match fs::read_to_string(path) {
+            Ok(script) => script,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => { return self; }
+            Err(e) => {
+                println!("{}: {}", path.display(), e);
+                return self;
+            }
+        };
+
+        let name = path.display().to_string();
+        for (n, line) in script.lines().enumerate() {
+            let flow = match &mut self {
+                Session::Float(repl) => repl.run_quietly(line),
+                Session::Decimal(repl) => repl.run_quietly(line),
+                Session::Rational(repl) => repl.run_quietly(line)
+            };
+            match flow {
+                Ok(Flow::Switch(numbers)) => { self = self.switch(numbers); }
+                Ok(Flow::Load(path)) => { self = self.restore(&path); }
+                Ok(_) => {}
+                Err((column, e)) => println!("{}", Msg::AtFile(&name, n + 1, column, &e))
+            }
+        }
+        self
+    }
+}
+
+fn session_path() -> Option<PathBuf> {
+    Some(PathBuf::from(env::var_os("HOME")?).join(SESSION_FILE))
+}
+
+// The file named after a command such as `:save`, or else the session
+// file saved on leaving.
+fn session_file(words : &[&str]) -> Result<PathBuf, String> {
+    if words.is_empty() {
+        session_path().ok_or_else(|| Msg::NoSessionPath.to_string())
+    }
+    else {
+        Ok(PathBuf::from(words.join(" ")))
+    }
+}
+
+fn config_path(options : &Options) -> Option<PathBuf> {
+    if let Some(path) = &options.config {
+        return Some(PathBuf::from(path));
+    }
+    let dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config")
+    };
+    Some(dir.join(CONFIG_DIR).join(CONFIG_FILE))
+}
+
+fn parse_switch(value : &str) -> Result<bool, String> {
+    match value {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err(Msg::ExpectedOnOff(value).to_string())
+    }
+}
+
+// `value` written so that reading it back gives it exactly, with a
+// fraction in brackets before a unit, as 1/3 m would be 1/(3 m).
+fn exact<N: Number>(value : &Value<N>, notation : format::Notation) -> String {
+    match value {
+        Value::Scalar(quantity) => {
+            if let Some(date) = quantity.date() {
+                return date::format(date);
+            }
+            let number = format::format(&quantity.value, notation, format::Style::default());
+            if quantity.dimension.is_none() {
+                number
+            }
+            else if number.contains('/') {
+                format!("({}) {}", number, quantity.dimension)
+            }
+            else {
+                format!("{} {}", number, quantity.dimension)
+            }
+        }
+        Value::List(items) => format!("[{}]", items.iter().map(|item| exact(item, notation)).collect::<Vec<_>>().join(", "))
+    }
+}
+
+fn write_markdown(history : &[Entry], out : &mut impl Write) -> io::Result<()> {
+    writeln!(out, "# {}", Msg::SessionTitle)?;
+    for entry in history {
+        writeln!(out)?;
+        writeln!(out, "```")?;
+        writeln!(out, "{}", entry.input)?;
+        writeln!(out, "```")?;
+        writeln!(out)?;
+        writeln!(out, "> {}", entry.output)?;
+    }
+
+    Ok(())
+}
+
+// A session as read from a file by :load, for the numbers it was saved in.
+struct Saved<N> {
+    angle : AngleUnit,
+    display : format::Notation,
+    rounding : format::Rounding,
+    epsilon : f64,
+    max_depth : usize,
+    precision : usize,
+    rpn : bool,
+    trim : bool,
+    separators : bool,
+    autoclose : bool,
+    trace : bool,
+    aliases : Vec<(String, String)>,
+    variables : HashMap<String, Value<N>>,
+    functions : HashMap<String, UserFunction>,
+    results : Vec<Value<N>>,
+    history : Vec<Entry>
+}
+
+enum Restored {
+    Float(Saved<f64>),
+    Decimal(Saved<Decimal>),
+    Rational(Saved<Rational>)
+}
+
+impl<N: Number> Saved<N> {
+    fn read(saved : &json::Value) -> Option<Saved<N>> {
+        let switch = |key : &str| match saved.get(key)? {
+            json::Value::Bool(on) => Some(*on),
+            _ => None
+        };
+        let whole = |key : &str, limit : usize| match saved.get(key)? {
+            json::Value::Number(n) if n.fract() == 0.0 && *n >= 0.0 && *n <= limit as f64 => Some(*n as usize),
+            _ => None
+        };
+        let members = |key : &str| match saved.get(key)? {
+            json::Value::Object(members) => Some(members),
+            _ => None
+        };
+        let items = |key : &str| match saved.get(key)? {
+            json::Value::Array(items) => Some(items),
+            _ => None
+        };
+        let text = |value : &json::Value| value.as_str().map(str::to_string);
+
+        let angle = match saved.get("angle")?.as_str()? {
+            "deg" => AngleUnit::Degrees,
+            "rad" => AngleUnit::Radians,
+            _ => { return None; }
+        };
+        let display = format::Notation::from_name(saved.get("display")?.as_str()?)?;
+        let mut words = saved.get("format")?.as_str()?.split_whitespace();
+        let rounding = format::Rounding::from_words(words.next()?, words.next())?;
+        let epsilon = match saved.get("epsilon")? {
+            json::Value::Number(epsilon) if *epsilon >= 0.0 => *epsilon,
+            _ => { return None; }
+        };
+        let max_depth = whole("depth", calculator::DEPTH_LIMIT)?;
+        let precision = whole("precision", calculator::PRECISION_LIMIT)?;
+        let (rpn, trim, separators, autoclose, trace) = (switch("rpn")?, switch("trim")?, switch("separators")?, switch("autoclose")?, switch("trace")?);
+
+        let aliases = members("aliases")?.iter().map(|(name, alias)| Some((name.clone(), text(alias)?))).collect::<Option<Vec<_>>>()?;
+        let variables = members("variables")?.iter().map(|(name, value)| Some((name.clone(), session_json::value_from_json(value, precision)?))).collect::<Option<HashMap<_, _>>>()?;
+        let functions = members("functions")?.iter().map(|(name, function)| {
+            let params = match function.get("params")? {
+                json::Value::Array(params) => params.iter().map(text).collect::<Option<Vec<_>>>()?,
+                _ => { return None; }
+            };
+            Some((name.clone(), UserFunction { params, body : ast_json::expr_from_json(function.get("body")?)? }))
+        }).collect::<Option<HashMap<_, _>>>()?;
+        let results = items("results")?.iter().map(|value| session_json::value_from_json(value, precision)).collect::<Option<Vec<_>>>()?;
+        let history = items("history")?.iter().map(|entry| Some(Entry { input : text(entry.get("input")?)?, output : text(entry.get("output")?)? })).collect::<Option<Vec<_>>>()?;
+
+        Some(Saved {
+            angle, display, rounding, epsilon, max_depth, precision,
+            rpn, trim, separators, autoclose, trace,
+            aliases, variables, functions, results, history
+        })
+    }
+}
+
+impl<'a, N: Number> Repl<'a, N> {
+    // The same session, with the values so far converted to `M`. The display
+    // follows along if it was the default for the numbers before.
+    fn convert<M: Number>(self, from : Numbers, to : Numbers) -> Repl<'a, M> {
+        let mut settings = self.settings;
+        if settings.display == from.notation() {
+            settings.display = to.notation();
+        }
+
+        Repl {
+            options : self.options,
+            numbers : to,
+            settings,
+            rpn : self.rpn.map(|rpn| rpn.convert(self.ctx.precision)),
+            ctx : self.ctx.convert(),
+            history : self.history
+        }
+    }
+
+    // Marks the part of the input the error is about, under the input as
+    // echoed after the prompt, and returns the message.
+    fn mark_error(&self, e : &CalcError, input : &str) -> String {
+        let carets = highlight::carets(input, e.offset(), self.options.dialect);
+        let carets = if self.settings.color { highlight::error(&carets) } else { carets };
+        println!("{}{}", " ".repeat(PROMPT.len() + e.column(input) - 1), carets);
+        messages::error(e).to_string()
+    }
+
+    // Variables sorted by name, then functions.
+    fn print_vars(&self) {
+        if self.ctx.variables.is_empty() && self.ctx.functions.is_empty() {
+            println!("{}", Msg::NoVariables);
+            return;
+        }
+
+        let mut variables = self.ctx.variables.iter().collect::<Vec<_>>();
+        variables.sort_by(|a, b| a.0.cmp(b.0));
+        for (name, value) in variables {
+            println!("{} = {}", name, format::format_value(value, self.settings.display, self.settings.style));
+        }
+
+        let mut functions = self.ctx.functions.iter().collect::<Vec<_>>();
+        functions.sort_by(|a, b| a.0.cmp(b.0));
+        for (name, function) in functions {
+            println!("{}({}) = {}", name, function.params.join(", "), function.body);
+        }
+    }
+
+    // The line with an alias at its start replaced by what it stands for.
+    fn expand<'l>(&self, line : &'l str) -> Cow<'l, str> {
+        let start = line.len() - line.trim_start().len();
+        let word = line[start..].split_whitespace().next().unwrap_or_default();
+        match self.settings.aliases.iter().find(|(name, _)| name == word) {
+            Some((_, text)) => Cow::Owned(format!("{}{}", text, &line[start + word.len()..])),
+            None => Cow::Borrowed(line)
+        }
+    }
+
+    // The session as an init script that restores it.
+    fn config(&self) -> String {
+        let settings = &self.settings;
+        let switch = |on : bool| if on { "on" } else { "off" };
+        let mut script = String::new();
+        let _ = writeln!(script, "# {}", Msg::ConfigTitle);
+        let _ = writeln!(script, ":mode {}", self.numbers.name());
+        let _ = writeln!(script, ":mode {}", if self.ctx.angle == AngleUnit::Degrees { "deg" } else { "rad" });
+        let _ = writeln!(script, ":mode {}", if self.rpn.is_some() { "rpn" } else { "infix" });
+        let _ = writeln!(script, ":set display {}", settings.display.name());
+        let _ = writeln!(script, ":format {}", settings.style.rounding.words());
+        let _ = writeln!(script, ":set trim {}", switch(settings.style.trim));
+        let _ = writeln!(script, ":set separators {}", switch(settings.style.separators));
+        let _ = writeln!(script, ":set autoclose {}", switch(settings.autoclose));
+        let _ = writeln!(script, ":trace {}", switch(settings.trace));
+        let _ = writeln!(script, ":set epsilon {}", self.ctx.epsilon);
+        let _ = writeln!(script, ":set depth {}", self.ctx.max_depth);
+        let _ = writeln!(script, ":set precision {}", self.ctx.precision);
+        for (name, text) in &settings.aliases {
+            let _ = writeln!(script, ":alias {} {}", name, text);
+        }
+
+        // in the standard dialect the script is read in
+        let mut variables = self.ctx.variables.iter().collect::<Vec<_>>();
+        variables.sort_by(|a, b| a.0.cmp(b.0));
+        for (name, value) in variables {
+            let _ = writeln!(script, "{} = {}", name, exact(value, self.numbers.notation()));
+        }
+        let mut functions = self.ctx.functions.iter().collect::<Vec<_>>();
+        functions.sort_by(|a, b| a.0.cmp(b.0));
+        for (name, function) in functions {
+            let _ = writeln!(script, "{}({}) = {}", name, function.params.join(", "), function.body);
+        }
+        script
+    }
+
+    // Whether `input` goes on on the next line: it ends with `\`, or with an
+    // operator or inside brackets, short of the `(` autoclose would close.
+    // A command is always one line.
+    fn unfinished(&self, input : &str) -> bool {
+        let input = input.trim();
+        if input.starts_with(':') {
+            return false;
+        }
+        if input.ends_with('\\') {
+            return true;
+        }
+        if self.rpn.is_some() || input.is_empty() {
+            return false;
+        }
+        let ending = lexer::ending(input, self.options.dialect);
+        ending.operator || ending.squares() || (!ending.open.is_empty() && !self.settings.autoclose)
+    }
+
+    // Everything :load needs to carry on with the session.
+    fn snapshot(&self) -> json::Value {
+        let settings = &self.settings;
+        let mut variables = self.ctx.variables.iter().collect::<Vec<_>>();
+        variables.sort_by(|a, b| a.0.cmp(b.0));
+        let mut functions = self.ctx.functions.iter().collect::<Vec<_>>();
+        functions.sort_by(|a, b| a.0.cmp(b.0));
+
+        let variables = variables.into_iter().map(|(name, value)| (name.clone(), session_json::value_to_json(value))).collect();
+        let functions = functions.into_iter().map(|(name, function)| {
+            let params = function.params.iter().map(|param| json::Value::string(param)).collect();
+            (name.clone(), json::Value::object(vec![("params", json::Value::Array(params)), ("body", ast_json::expr_to_json(&function.body))]))
+        });
+        let aliases = settings.aliases.iter().map(|(name, text)| (name.clone(), json::Value::string(text))).collect();
+        let history = self.history.iter().map(|entry| json::Value::object(vec![("input", json::Value::string(&entry.input)), ("output", json::Value::string(&entry.output))]));
+
+        json::Value::object(vec![
+            ("numbers", json::Value::string(self.numbers.name())),
+            ("angle", json::Value::string(if self.ctx.angle == AngleUnit::Degrees { "deg" } else { "rad" })),
+            ("rpn", json::Value::Bool(self.rpn.is_some())),
+            ("display", json::Value::string(settings.display.name())),
+            ("format", json::Value::string(&settings.style.rounding.words())),
+            ("trim", json::Value::Bool(settings.style.trim)),
+            ("separators", json::Value::Bool(settings.style.separators)),
+            ("autoclose", json::Value::Bool(settings.autoclose)),
+            ("trace", json::Value::Bool(settings.trace)),
+            ("epsilon", json::Value::Number(self.ctx.epsilon)),
+            ("depth", json::Value::Number(self.ctx.max_depth as f64)),
+            ("precision", json::Value::Number(self.ctx.precision as f64)),
+            ("aliases", json::Value::Object(aliases)),
+            ("variables", json::Value::Object(variables)),
+            ("functions", json::Value::Object(functions.collect())),
+            ("results", json::Value::Array(self.ctx.results.iter().map(session_json::value_to_json).collect())),
+            ("history", json::Value::Array(history.collect()))
+        ])
+    }
+
+    // Replaces the session by the one `saved` describes, in the numbers it
+    // is in already. None, changing nothing, if `saved` isn't a snapshot.
+    fn restore(&mut self, saved : Saved<N>) {
+        let style = format::Style { rounding : saved.rounding, trim : saved.trim, separators : saved.separators, ..self.settings.style };
+        self.settings = Settings { autoclose : saved.autoclose, trace : saved.trace, display : saved.display, style, aliases : saved.aliases, color : self.settings.color };
+        let Saved { epsilon, max_depth, angle, precision, variables, functions, results, .. } = saved;
+        self.ctx = Context { epsilon, max_depth, angle, precision, variables, functions, results, ..self.ctx.clone() };
+        self.rpn = if saved.rpn { self.rpn.take().or_else(|| Some(rpn::Rpn::new())) } else { None };
+        self.history = saved.history;
+    }
+
+    fn save(&self, path : &Path) -> Result<(), String> {
+        fs::write(path, self.snapshot().to_string() + "\n").map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    fn autosave(&self) -> Result<(), String> {
+        let empty = self.history.is_empty() && self.ctx.variables.is_empty() && self.ctx.functions.is_empty();
+        match session_path() {
+            Some(path) if !empty => self.save(&path),
+            _ => Ok(())
+        }
+    }
+
+    // A line of the init script, with the column of an error in it. Nothing
+    // is printed but what commands print.
+    fn run_quietly(&mut self, line : &str) -> Result<Flow, (usize, String)> {
+        let line = self.expand(line);
+        let line = line.as_ref();
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            return Ok(Flow::Continue);
+        }
+        if line.trim_start().starts_with(':') {
+            return self.command(line).map_err(|e| (1, e));
+        }
+        match calculator::execute(line, Dialect::Standard, &mut self.ctx) {
+            Ok(_) => Ok(Flow::Continue),
+            Err(e) => Err((e.column(line), messages::error(&e).to_string()))
+        }
+    }
+
+    /*
+    commands:
+        :quit | :exit
+        :help
+        :vars
+        :clear
+        :mode rpn | infix
+        :mode deg | rad
+        :mode float | decimal | rational
+        :mode <notation>
+        :format plain | fixed <places> | sig <digits> | sci <digits> | eng <digits>
+        :trace on | off
+        :ast [dot] <statement>
+        :latex <expression>
+        :pretty <expression>
+        :canon <expression>
+        :equiv <expression> == <expression>
+        :lint <statement>
+        :set autoclose on | off
+        :set trim on | off
+        :set separators on | off
+        :set epsilon <tolerance>
+        :set depth <calls>
+        :set precision <digits>
+        :seed [<n>]
+        :set display decimal | fraction | mixed | hex | oct | bin | ftin | dms | hms | hm | latex
+        :export md <file>
+        :alias [<name> [<text>]]
+        :save-config
+        :save [<file>]
+        :load [<file>]
+    */
+    fn command(&mut self, line : &str) -> Result<Flow, String> {
+        let words = line.split_whitespace().collect::<Vec<_>>();
+
+        match words.as_slice() {
+            [":quit" | ":exit"] => {
+                return Ok(Flow::Quit);
+            }
+            [":help"] => {
+                let names = functions::names().collect::<Vec<_>>();
+                println!("{}", Msg::ReplHelp(&names.join(" ")));
+            }
+            [":vars"] => {
+                self.print_vars();
+            }
+            [":clear"] => {
+                self.ctx.variables.clear();
+                self.ctx.functions.clear();
+            }
+            [":mode", "deg"] => {
+                self.ctx.angle = AngleUnit::Degrees;
+            }
+            [":mode", "rad"] => {
+                self.ctx.angle = AngleUnit::Radians;
+            }
+            [":mode", name] if Numbers::from_name(name).is_some() => {
+                return Ok(Flow::Switch(Numbers::from_name(name).unwrap_or(Numbers::Float)));
+            }
+            [":mode", name] if format::Notation::from_name(name).is_some() => {
+                self.settings.display = format::Notation::from_name(name).unwrap_or(format::Notation::Decimal);
+            }
+            [":mode", "rpn"] => {
+                self.rpn.get_or_insert_with(rpn::Rpn::new);
+            }
+            [":mode", "infix"] => {
+                self.rpn = None;
+            }
+            [":format", name, digits @ ..] if digits.len() <= 1 => {
+                self.settings.style.rounding = match format::Rounding::from_words(name, digits.first().copied()) {
+                    Some(rounding) => rounding,
+                    None => { return Err(Msg::InvalidFormat(&words[1..].join(" ")).to_string()); }
+                };
+            }
+            [":trace", value] => {
+                self.settings.trace = parse_switch(value)?;
+            }
+            [":ast"] | [":ast", "dot"] => {
+                return Err(Msg::AstUsage.to_string());
+            }
+            [name @ (":latex" | ":pretty" | ":canon")] => {
+                return Err(Msg::RenderUsage(name).to_string());
+            }
+            [name @ (":ast" | ":latex" | ":pretty" | ":canon"), rest @ ..] => {
+                // the input is what follows the command, word breaks and all
+                let mut start = line.find(name).map_or(0, |i| i + name.len());
+                let dot = *name == ":ast" && rest[0] == "dot";
+                if dot {
+                    start += line[start..].find("dot").map_or(0, |i| i + "dot".len());
+                }
+                let tokens = calculator::tokenize(&line[start..], self.options.dialect);
+                let shown = match *name {
+                    ":ast" if dot => parser::parse_statement(tokens).map(|statement| Dot(&statement).to_string()),
+                    ":ast" => parser::parse_statement(tokens).map(|statement| ast_json::to_json(&statement).to_string()),
+                    ":latex" => parser::parse(tokens).map(|expr| Latex(&expr).to_string()),
+                    ":canon" => parser::parse(tokens).map(|expr| symbolic::canonical(&expr).to_string()),
+                    _ => parser::parse(tokens).map(|expr| expr.to_string())
+                };
+                match shown {
+                    Ok(shown) => println!("{}", shown),
+                    Err(e) => {
+                        let at = e.offset() + start;
+                        return Err(self.mark_error(&e.with_offset(at), line));
+                    }
+                }
+            }
+            [":equiv"] => {
+                return Err(Msg::EquivUsage.to_string());
+            }
+            [":equiv", ..] => {
+                let start = line.find(":equiv").map_or(0, |i| i + ":equiv".len());
+                let (lhs, rhs) = match parser::parse(calculator::tokenize(&line[start..], self.options.dialect)) {
+                    Ok(Expr::BinaryOp { op : Binary::Equal, lhs, rhs, .. }) => (lhs, rhs),
+                    Ok(_) => { return Err(Msg::EquivUsage.to_string()); }
+                    Err(e) => {
+                        let at = e.offset() + start;
+                        return Err(self.mark_error(&e.with_offset(at), line));
+                    }
+                };
+                match equiv::check(&lhs, &rhs, &self.ctx) {
+                    equiv::Verdict::Simplified => println!("{}", Msg::EquivalentBySimplifying),
+                    equiv::Verdict::Sampled(n) => println!("{}", Msg::EquivalentAt(n)),
+                    equiv::Verdict::Counterexample(point, left, right) => {
+                        let point = point.iter().map(|(name, x)| format!("{} = {}", name, x)).collect::<Vec<_>>().join(", ");
+                        println!("{}", Msg::Counterexample(&point, &left, &right));
+                    }
+                }
+            }
+            [":lint"] => {
+                return Err(Msg::LintUsage.to_string());
+            }
+            [":lint", ..] => {
+                let start = line.find(":lint").map_or(0, |i| i + ":lint".len());
+                let input = &line[start..];
+                if let Err(e) = parser::parse_statement(calculator::tokenize(input, self.options.dialect)) {
+                    let at = e.offset() + start;
+                    return Err(self.mark_error(&e.with_offset(at), line));
+                }
+                let warnings = lint::lint(input, self.options.dialect, &self.ctx);
+                if warnings.is_empty() {
+                    println!("{}", Msg::LintClean);
+                }
+                // counted in the line, command and all
+                let before = line[..start].chars().count();
+                for (column, warning) in warnings {
+                    println!("{}", Msg::AtColumn(before + column, &warning));
+                }
+            }
+            [":set", "autoclose", value] => {
+                self.settings.autoclose = parse_switch(value)?;
+            }
+            [":set", "trim", value] => {
+                self.settings.style.trim = parse_switch(value)?;
+            }
+            [":set", "separators", value] => {
+                self.settings.style.separators = parse_switch(value)?;
+            }
+            [":set", "epsilon", value] => {
+                self.ctx.epsilon = match value.parse::<f64>() {
+                    Ok(epsilon) if epsilon >= 0.0 && epsilon.is_finite() => epsilon,
+                    _ => { return Err(Msg::InvalidTolerance(value).to_string()); }
+                };
+            }
+            [":set", "depth", value] => {
+                self.ctx.max_depth = match value.parse::<usize>() {
+                    Ok(depth) if depth <= calculator::DEPTH_LIMIT => depth,
+                    _ => { return Err(Msg::InvalidDepth(value).to_string()); }
+                };
+            }
+            [":set", "precision", value] => {
+                self.ctx.precision = match value.parse::<usize>() {
+                    Ok(precision) if precision <= calculator::PRECISION_LIMIT => precision,
+                    _ => { return Err(Msg::InvalidPrecision(value).to_string()); }
+                };
+            }
+            [":seed"] => {
+                self.ctx.random = Random::default();
+            }
+            [":seed", value] => {
+                let seed = value.parse::<u64>().map_err(|_| Msg::InvalidSeed(value).to_string())?;
+                self.ctx.random = Random::new(seed);
+            }
+            [":set", "display", name] => {
+                self.settings.display = match format::Notation::from_name(name) {
+                    Some(notation) => notation,
+                    None => { return Err(Msg::UnknownDisplay(name).to_string()); }
+                };
+            }
+            [":set", name, ..] => {
+                return Err(Msg::UnknownSetting(name).to_string());
+            }
+            [":export", "md", path @ ..] if !path.is_empty() => {
+                let path = path.join(" ");
+                let mut file = fs::File::create(&path).map_err(|e| format!("{}: {}", path, e))?;
+                write_markdown(&self.history, &mut file).map_err(|e| format!("{}: {}", path, e))?;
+                println!("{}", Msg::Exported(self.history.len(), &path));
+            }
+            [":export", "md"] => {
+                return Err(Msg::ExportUsage.to_string());
+            }
+            [":export", format, ..] => {
+                return Err(Msg::UnknownExportFormat(format).to_string());
+            }
+            [":alias"] => {
+                if self.settings.aliases.is_empty() {
+                    println!("{}", Msg::NoAliases);
+                }
+                for (name, text) in &self.settings.aliases {
+                    println!("{} = {}", name, text);
+                }
+            }
+            [":alias", name, ..] => {
+                // the text is the rest of the line after the name
+                let rest = line.trim_start()[":alias".len()..].trim_start();
+                let text = rest[name.len()..].trim();
+                self.settings.aliases.retain(|(known, _)| known != name);
+                if !text.is_empty() {
+                    self.settings.aliases.push((name.to_string(), text.to_string()));
+                }
+            }
+            [":save", path @ ..] => {
+                let path = session_file(path)?;
+                self.save(&path)?;
+                println!("{}", Msg::SavedSession(&path.display().to_string()));
+            }
+            [":load", path @ ..] => {
+                return Ok(Flow::Load(session_file(path)?));
+            }
+            [":save-config"] => {
+                let path = config_path(self.options).ok_or_else(|| Msg::NoConfigPath.to_string())?;
+                let shown = path.display().to_string();
+                if let Some(dir) = path.parent() {
+                    fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+                }
+                fs::write(&path, self.config()).map_err(|e| format!("{}: {}", shown, e))?;
+                println!("{}", Msg::SavedConfig(&shown));
+            }
+            _ => {
+                return Err(Msg::UnknownCommand(line.trim()).to_string());
+            }
+        }
+
+        Ok(Flow::Continue)
+    }
+
+    // Returns the text to show for the result, or the error; warnings and
+    // --profile reports are printed directly.
+    fn evaluate(&mut self, input : &str) -> Result<String, String> {
+        let dialect = self.options.dialect;
+        let (input, display) = match format::split_conversion(input) {
+            Ok((input, conversion)) => (input, conversion),
+            Err(name) => { return Err(Msg::UnknownDisplay(name).to_string()); }
+        };
+        let line = input;
+        let (input, unit) = units::split_conversion(input, dialect);
+
+        // With autoclose on, brackets still open at the end of the line are
+        // closed there, and the expression actually evaluated is shown.
+        let closed;
+        let mut input = input;
+        if self.settings.autoclose {
+            let missing = lexer::ending(input, dialect).closing();
+            if !missing.is_empty() {
+                closed = format!("{}{}", input.trim_end(), missing);
+                println!("{}", Msg::AddedBrackets(missing.len(), &closed));
+                input = &closed;
+            }
+        }
+
+        // an error is left for the evaluation to report
+        if self.settings.trace {
+            for step in calculator::trace(input, dialect, &self.ctx).unwrap_or_default() {
+                println!("= {}", step);
+            }
+        }
+
+        let (result, report) = if self.options.profile {
+            let (result, report) = calculator::profile::evaluate(input, dialect, parser::parse_statement, |statement| eval::execute(statement, &mut self.ctx));
+            (result, Some(report))
+        }
+        else {
+            (calculator::execute(input, dialect, &mut self.ctx), None)
+        };
+
+        if let Some(report) = report {
+            eprint!("{}", report::Summary(&report));
+        }
+
+        match result {
+            Ok(Outcome::Value(value) | Outcome::Assigned(_, value)) => {
+                let shown = match unit {
+                    Some((offset, unit)) => match calculator::convert(&value, unit, offset, dialect, &self.ctx) {
+                        Ok(number) => format!("{} {}", format::format_value(&number, display.unwrap_or(self.settings.display), self.settings.style), unit),
+                        Err(e) => { return Err(self.mark_error(&e, line)); }
+                    },
+                    None => {
+                        let display = display.unwrap_or_else(|| format::keeping_clock(input, dialect, &value, self.settings.display));
+                        format::format_value(&value, display, self.settings.style)
+                    }
+                };
+                let output = format!("#{} = {}", self.ctx.results.len() + 1, shown);
+                self.ctx.results.push(value);
+                Ok(output)
+            }
+            Ok(Outcome::Defined(name)) => Ok(Msg::Defined(&name).to_string()),
+            Ok(Outcome::Expression(expr)) => Ok(expr.to_string()),
+            Err(e) => Err(self.mark_error(&e, input))
+        }
+    }
+
+    // Handles one line read at the prompt.
+    fn line(&mut self, input : &str) -> Flow {
+        let expanded = self.expand(input);
+        let input = expanded.as_ref();
+        if input.trim_start().starts_with(':') {
+            return match self.command(input) {
+                Ok(flow) => flow,
+                Err(e) => {
+                    println!("{}", self.paint(Err(&e)));
+                    Flow::Continue
+                }
+            };
+        }
+
+        if input.trim().is_empty() {
+            return Flow::Continue;
+        }
+
+        let output = match &mut self.rpn {
+            Some(rpn) => match rpn.execute(input, &mut self.ctx) {
+                Ok(()) => Ok(rpn.to_string()),
+                Err(e) => Err(e.to_string())
+            },
+            None => self.evaluate(input)
+        };
+
+        println!("{}", self.paint(output.as_deref().map_err(String::as_str)));
+        let output = output.unwrap_or_else(|e| e);
+        self.history.push(Entry { input : input.trim().to_string(), output });
+        Flow::Continue
+    }
+
+    // A result or error in its colour, if colours are on.
+    fn paint(&self, output : Result<&str, &str>) -> String {
+        match output {
+            Ok(result) if self.settings.color => highlight::result(result),
+            Err(e) if self.settings.color => highlight::error(e),
+            Ok(text) | Err(text) => text.to_string()
+        }
+    }
+}
+
+pub fn run(options : &Options) {
+    let mut session = Session::new(options);
+    if let Some(path) = config_path(options) {
+        session = session.load(&path);
+    }
+    let mut editor = Editor::new(if highlight::enabled() { Some(options.dialect) } else { None });
+
+    // One buffer for the whole session, cleared rather than reallocated for
+    // every line, and one for the lines an unfinished one goes on on.
+    let mut input = String::new();
+    let mut more = String::new();
+
+    'session: loop {
+        match editor.read_line(PROMPT, &mut input) {
+            Input::Line => {}
+            Input::Interrupted => continue,
+            Input::End => {
+                println!();
+                break;
+            }
+        }
+
+        // the lines are joined with spaces, a `\` at the end taken off, and
+        // shown as one under the prompt for errors to be marked in. Ctrl-C
+        // drops them all, and a command ends them: what came before is run
+        // as it stands, then the command.
+        let mut continued = false;
+        let mut command = false;
+        let mut interrupted = false;
+        while session.unfinished(&input) {
+            match editor.read_line(CONTINUATION, &mut more) {
+                Input::Line => {}
+                Input::Interrupted => {
+                    interrupted = true;
+                    break;
+                }
+                Input::End => break
+            }
+            if more.trim_start().starts_with(':') {
+                command = true;
+                break;
+            }
+            let len = input.trim_end().len();
+            if input[..len].ends_with('\\') {
+                input.truncate(len - 1);
+            }
+            input.push(' ');
+            input.push_str(&more);
+            continued = true;
+        }
+        if interrupted {
+            continue;
+        }
+        if continued {
+            let shown = if highlight::enabled() { highlight::expression(&input, options.dialect) } else { input.clone() };
+            println!("{}{}", " ".repeat(PROMPT.len()), shown);
+        }
+
+        if command {
+            let len = input.trim_end().len();
+            if input[..len].ends_with('\\') {
+                input.truncate(len - 1);
+            }
+        }
+        let lines = if command { 2 } else { 1 };
+        for line in [&input, &more].into_iter().take(lines) {
+            match session.line(line) {
+                Flow::Continue => {}
+                Flow::Quit => break 'session,
+                Flow::Switch(numbers) => { session = session.switch(numbers); }
+                Flow::Load(path) => { session = session.restore(&path); }
+            }
+        }
+    }
+    session.autosave();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use calculator::AngleUnit;
+    use calculator::Rational;
+    use calculator::Value;
+
+    use crate::cli::Options;
+
+    use super::Flow;
+    use super::Session;
+
+    #[test]
+    fn save_and_load() {
+        let path = std::env::temp_dir().join(format!("calculator-session-{}.json", std::process::id()));
+        let options = Options::default();
+        let mut session = Session::new(&options);
+        for line in [":mode rational", "x = 1/3", "f(t) = t + x", "f(1)", ":mode deg", ":format sig 3"] {
+            if let Flow::Switch(numbers) = session.line(line) {
+                session = session.switch(numbers);
+            }
+        }
+        session.line(&format!(":save {}", path.display()));
+
+        // a file that isn't a session changes nothing
+        fs::write(path.with_extension("bad"), "{\"numbers\": \"rational\"}").unwrap();
+        let mut session = Session::new(&options).restore(&path.with_extension("bad"));
+        fs::remove_file(path.with_extension("bad")).unwrap();
+        assert!(matches!(&session, Session::Float(repl) if repl.ctx.variables.is_empty()));
+
+        let session = match session.line(&format!(":load {}", path.display())) {
+            Flow::Load(saved) => session.restore(&saved),
+            _ => panic!("expected to load {}", path.display())
+        };
+        fs::remove_file(&path).unwrap();
+        match session {
+            Session::Rational(repl) => {
+                assert_eq!(repl.ctx.variables.get("x"), Some(&Value::number(Rational::ratio(1, 3))));
+                assert_eq!(repl.ctx.functions.get("f").map(|f| f.body.to_string()), Some("t + x".to_string()));
+                assert_eq!(repl.ctx.results, [Value::number(Rational::ratio(1, 3)), Value::number(Rational::ratio(4, 3))]);
+                assert!(repl.ctx.angle == AngleUnit::Degrees);
+                assert_eq!(repl.settings.style.rounding.words(), "sig 3");
+                assert_eq!(repl.history.iter().map(|entry| entry.output.as_str()).collect::<Vec<_>>(), ["#1 = 1/3", "Defined f", "#2 = 4/3"]);
+            }
+            _ => panic!("expected rational numbers")
+        }
+    }
+}
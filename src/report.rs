@@ -0,0 +1,33 @@
+use std::fmt;
+
+use calculator::profile::CountingAlloc;
+use calculator::profile::EvalReport;
+use calculator::profile::PhaseStats;
+
+use crate::messages::Msg;
+
+// for --profile to count allocations
+#[global_allocator]
+static GLOBAL : CountingAlloc = CountingAlloc;
+
+// An EvalReport as --profile prints it, a line for each phase.
+pub struct Summary<'a>(pub &'a EvalReport);
+
+fn write_phase(f : &mut fmt::Formatter<'_>, name : &str, stats : &PhaseStats) -> fmt::Result {
+    writeln!(f, "{:<10}{:>12}{:>10} allocs{:>10} B",
+             name, format!("{:?}", stats.time), stats.allocations, stats.bytes)
+}
+
+impl fmt::Display for Summary<'_> {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        let report = self.0;
+        if report.inputs != 1 {
+            writeln!(f, "{}", Msg::ProfileInputs(report.inputs))?;
+        }
+        write_phase(f, "tokenize", &report.tokenize)?;
+        write_phase(f, "parse", &report.parse)?;
+        write_phase(f, "compile", &report.compile)?;
+        write_phase(f, "evaluate", &report.evaluate)?;
+        write_phase(f, "total", &report.total())
+    }
+}
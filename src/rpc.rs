@@ -0,0 +1,166 @@
+use calculator::ast::Statement;
+use calculator::parser;
+use calculator::units;
+use calculator::CalcError;
+use calculator::Context;
+use calculator::Dialect;
+use calculator::Outcome;
+use calculator::Value;
+
+use crate::ast_json;
+use crate::cli::Options;
+use crate::json;
+use crate::mcp;
+use crate::serve;
+
+/*
+JSON-RPC 2.0 over stdio for editors and other programs that keep one
+session open rather than starting the calculator for each expression. As
+with --mcp there is one message per line each way, but the variables and
+functions defined stay until `reset`:
+
+    eval    {"expr": "x + 1"} gives {"result": "4", "value": 4}; the
+            expression may also assign or define, as in the REPL
+    parse   {"expr": "x + 1"} gives the statement as AST JSON (ast_json.rs)
+    define  {"expr": "f(x) = x^2"} gives {"name": "f"}, and takes only an
+            assignment or a definition
+    reset   forgets every variable, function and result
+
+An expression that fails is an error with the code EVALUATION_ERROR and,
+as data, {"code": ..., "message": ..., "column": ...} as in the HTTP
+server. Numbers are f64, in the dialect and angle unit given on the
+command line. The caller is trusted as much as the REPL's user, so there
+are no limits of steps or time.
+*/
+
+const EVALUATION_ERROR : i32 = -32000;
+
+struct Session {
+    dialect : Dialect,
+    ctx : Context<f64>,
+    // what a reset goes back to
+    initial : Context<f64>
+}
+
+fn evaluation_error(id : json::Value, e : &CalcError, expr : &str) -> json::Value {
+    let data = json::Value::object(vec![
+        ("code", json::Value::string(e.kind())),
+        ("message", json::Value::string(&e.to_string())),
+        ("column", json::Value::Number(e.column(expr) as f64)),
+    ]);
+    json::Value::object(vec![
+        ("jsonrpc", json::Value::string("2.0")),
+        ("id", id),
+        ("error", json::Value::object(vec![
+            ("code", json::Value::Number(EVALUATION_ERROR as f64)),
+            ("message", json::Value::string(&e.to_string())),
+            ("data", data),
+        ])),
+    ])
+}
+
+impl Session {
+    fn eval(&mut self, id : json::Value, expr : &str) -> json::Value {
+        let value = match calculator::execute(expr, self.dialect, &mut self.ctx) {
+            Ok(Outcome::Value(value) | Outcome::Assigned(_, value)) => value,
+            Ok(Outcome::Defined(name)) => { return mcp::response(id, json::Value::object(vec![("name", json::Value::string(&name))])); }
+            Ok(Outcome::Expression(expr)) => { return mcp::response(id, json::Value::object(vec![("result", json::Value::string(&expr.to_string()))])); }
+            Err(e) => { return evaluation_error(id, &e, expr); }
+        };
+
+        let mut members = vec![("result", json::Value::string(&serve::shown(&value, expr)))];
+        if let Value::Scalar(quantity) = &value {
+            if quantity.dimension.is_none() {
+                members.push(("value", json::Value::Number(quantity.value)));
+            }
+        }
+        self.ctx.results.push(value);
+        mcp::response(id, json::Value::object(members))
+    }
+
+    fn parse(&self, id : json::Value, expr : &str) -> json::Value {
+        match parser::parse_statement(calculator::tokenize(expr, self.dialect)) {
+            Ok(statement) => mcp::response(id, ast_json::to_json(&statement)),
+            Err(e) => evaluation_error(id, &e, expr)
+        }
+    }
+
+    fn define(&mut self, id : json::Value, expr : &str) -> json::Value {
+        let (statement, _) = units::split_conversion(expr, self.dialect);
+        match parser::parse_statement(calculator::tokenize(statement, self.dialect)) {
+            Ok(Statement::Assign(..) | Statement::Define(..)) => {}
+            Ok(_) => { return mcp::error_response(id, mcp::INVALID_PARAMS, "Not an assignment or a definition"); }
+            Err(e) => { return evaluation_error(id, &e, expr); }
+        }
+        match calculator::execute(expr, self.dialect, &mut self.ctx) {
+            Ok(Outcome::Assigned(name, _) | Outcome::Defined(name)) => mcp::response(id, json::Value::object(vec![("name", json::Value::string(&name))])),
+            Ok(_) => unreachable!("an assignment or a definition"),
+            Err(e) => evaluation_error(id, &e, expr)
+        }
+    }
+
+    fn handle(&mut self, message : &str) -> Option<json::Value> {
+        mcp::dispatch(message, |method, id, params| {
+            let expr = params.and_then(|params| params.get("expr")).and_then(|expr| expr.as_str());
+            match (method, expr) {
+                ("eval", Some(expr)) => self.eval(id, expr),
+                ("parse", Some(expr)) => self.parse(id, expr),
+                ("define", Some(expr)) => self.define(id, expr),
+                ("eval" | "parse" | "define", None) => mcp::error_response(id, mcp::INVALID_PARAMS, "Missing string member 'expr'"),
+                ("reset", _) => {
+                    self.ctx = self.initial.clone();
+                    mcp::response(id, json::Value::Null)
+                }
+                _ => mcp::error_response(id, mcp::METHOD_NOT_FOUND, "Method not found")
+            }
+        })
+    }
+}
+
+pub fn run(options : &Options) {
+    let initial = Context { angle : options.angle, ..Context::default() };
+    let mut session = Session { dialect : options.dialect, ctx : initial.clone(), initial };
+    mcp::serve(|message| session.handle(message));
+}
+
+#[cfg(test)]
+mod tests {
+    use calculator::Context;
+    use calculator::Dialect;
+
+    use super::Session;
+
+    fn replies(messages : &[&str]) -> Vec<String> {
+        let mut session = Session { dialect : Dialect::Standard, ctx : Context::default(), initial : Context::default() };
+        messages.iter().filter_map(|message| session.handle(message)).map(|reply| reply.to_string()).collect()
+    }
+
+    #[test]
+    fn sessions_persist_until_reset() {
+        let replies = replies(&[
+            r#"{"jsonrpc": "2.0", "id": 1, "method": "define", "params": {"expr": "f(x) = x^2"}}"#,
+            r#"{"jsonrpc": "2.0", "id": 2, "method": "eval", "params": {"expr": "f(3) + 1"}}"#,
+            r#"{"jsonrpc": "2.0", "method": "eval", "params": {"expr": "1"}}"#,
+            r#"{"jsonrpc": "2.0", "id": 3, "method": "reset"}"#,
+            r#"{"jsonrpc": "2.0", "id": 4, "method": "eval", "params": {"expr": "f(3)"}}"#,
+        ]);
+        assert_eq!(replies[0], r#"{"jsonrpc":"2.0","id":1,"result":{"name":"f"}}"#);
+        assert_eq!(replies[1], r#"{"jsonrpc":"2.0","id":2,"result":{"result":"10","value":10}}"#);
+        assert_eq!(replies[2], r#"{"jsonrpc":"2.0","id":3,"result":null}"#);
+        assert!(replies[3].contains(r#""code":"unknown_function""#));
+    }
+
+    #[test]
+    fn errors() {
+        let replies = replies(&[
+            r#"{"jsonrpc": "2.0", "id": 1, "method": "parse", "params": {"expr": "1 +"}}"#,
+            r#"{"jsonrpc": "2.0", "id": 2, "method": "define", "params": {"expr": "1 + 2"}}"#,
+            r#"{"jsonrpc": "2.0", "id": 3, "method": "eval"}"#,
+            r#"{"jsonrpc": "2.0", "id": 4, "method": "solve"}"#,
+        ]);
+        assert_eq!(replies[0], r#"{"jsonrpc":"2.0","id":1,"error":{"code":-32000,"message":"Unexpected end of input","data":{"code":"unexpected_end","message":"Unexpected end of input","column":4}}}"#);
+        assert!(replies[1].contains("-32602"));
+        assert!(replies[2].contains("Missing string member 'expr'"));
+        assert!(replies[3].contains("-32601"));
+    }
+}
@@ -0,0 +1,284 @@
+use std::fmt;
+
+use calculator::ast::Binary;
+use calculator::ast::Expr;
+use calculator::ast::Unary;
+use calculator::eval;
+use calculator::functions;
+use calculator::lexer;
+use calculator::parser;
+use calculator::CalcError;
+use calculator::Context;
+use calculator::Dialect;
+use calculator::Number;
+use calculator::Quantity;
+use calculator::Token;
+use calculator::Value;
+
+use crate::messages;
+use crate::messages::Msg;
+
+pub enum Error {
+    StackUnderflow(String),
+    InvalidWord(String),
+    Calc(CalcError)
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::StackUnderflow(word) => write!(f, "{}", Msg::StackUnderflow(word)),
+            Error::InvalidWord(word) => write!(f, "{}", Msg::InvalidWord(word)),
+            Error::Calc(e) => write!(f, "{}", messages::error(e)),
+        }
+    }
+}
+
+/*
+Postfix input operating on a stack, e.g. `3 4 + 5 *`. Words are separated
+by whitespace, but numbers and operators may also be run together as in
+`3 4+`. Numbers are written as in infix mode, `-3`, `1e-3` and `0x1f`
+included. Every binary operator of infix mode takes the two top values,
+`!` and `%` the top one, and a built-in or user function as many as it
+needs at least, so `2 sqrt`, `1 1 atan2` or `[1,2,3] mean`; gcd and lcm
+take two. A list or matrix is written as one word in infix, `[1,2,3]` or
+`[[1,2],[3,4]]`. Other names, variables, constants and units alike, push
+their value, so `5 km *` is 5 km. Operations go through the evaluator on
+the session's numbers, so they follow :mode and the angle unit, and
+compute exactly in rational or decimal mode. Besides those the words are:
+    dup    duplicate the top value
+    swap   exchange the two top values
+    drop   discard the top value
+    neg    change the sign of the top value
+    clear  empty the stack
+    in     the value below in the unit on top, as in `5 km * m in`
+    =x     store the top value in the variable x, leaving it there
+*/
+pub struct Rpn<N> {
+    stack : Vec<Value<N>>
+}
+
+impl<N: Number> fmt::Display for Rpn<N> {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[")?;
+        for (i, value) in self.stack.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{}", value)?;
+        }
+        write!(f, "]")
+    }
+}
+
+// The names the operands of a word are bound to while it's evaluated; ones
+// no input can use.
+const OPERANDS : [&str; 4] = ["$0", "$1", "$2", "$3"];
+
+impl<N: Number> Rpn<N> {
+    pub fn new() -> Rpn<N> {
+        Rpn { stack : Vec::new() }
+    }
+
+    // The same stack in `M`.
+    pub fn convert<M: Number>(self, precision : usize) -> Rpn<M> {
+        let convert = |value : Value<N>| value.map(&mut |quantity| Quantity { value : M::from_rational(&quantity.value.to_literal(), precision), dimension : quantity.dimension });
+        Rpn { stack : self.stack.into_iter().map(convert).collect() }
+    }
+
+    fn pop(&mut self, word : &str) -> Result<Value<N>, Error> {
+        self.stack.pop().ok_or_else(|| Error::StackUnderflow(word.to_string()))
+    }
+
+    // Replaces the top `n` values with the value of `build`, which is given
+    // them as variables, the deepest first.
+    fn apply(&mut self, n : usize, word : &str, ctx : &Context<N>, build : impl FnOnce(Vec<Expr>) -> Expr) -> Result<(), Error> {
+        if self.stack.len() < n || n > OPERANDS.len() {
+            return Err(Error::StackUnderflow(word.to_string()));
+        }
+        let values = self.stack.split_off(self.stack.len() - n);
+        let bound = OPERANDS.iter().copied().zip(values).collect::<Vec<_>>();
+        let operands = bound.iter().map(|(name, _)| Expr::Variable { name : name.to_string(), offset : 0 }).collect();
+        let value = eval::evaluate_bound(&build(operands), &bound, ctx).map_err(Error::Calc)?;
+        self.stack.push(value);
+        Ok(())
+    }
+
+    fn call(&mut self, name : &str, args : usize, ctx : &Context<N>) -> Result<(), Error> {
+        self.apply(args, name, ctx, |args| Expr::Call { name : name.to_string(), args, offset : 0 })
+    }
+
+    // How many values the function `name` takes off the stack, if it is one.
+    fn arguments(name : &str, ctx : &Context<N>) -> Option<usize> {
+        if let Some(function) = ctx.functions.get(name) {
+            return Some(function.params.len());
+        }
+        if functions::find_list(name).is_some() {
+            return Some(1);
+        }
+        if let Some(function) = functions::find_matrix(name) {
+            return Some(function.args);
+        }
+        if let Some(function) = functions::find_integer(name) {
+            return Some(if function.max_args == functions::VARIADIC { 2 } else { function.min_args });
+        }
+        if let Some(function) = functions::find_random(name) {
+            return Some(function.args[0]);
+        }
+        functions::find(name).map(|function| function.min_args)
+    }
+
+    // A function is called; anything else is a variable, a constant or a
+    // unit, and pushed.
+    fn name(&mut self, name : &str, ctx : &Context<N>) -> Result<(), Error> {
+        match Rpn::arguments(name, ctx) {
+            Some(args) => self.call(name, args, ctx),
+            None => self.apply(0, name, ctx, |_| Expr::Variable { name : name.to_string(), offset : 0 })
+        }
+    }
+
+    fn execute_word(&mut self, word : &str, ctx : &mut Context<N>) -> Result<(), Error> {
+        match word {
+            "dup" => {
+                let top = self.pop(word)?;
+                self.stack.push(top.clone());
+                self.stack.push(top);
+            }
+            "swap" => {
+                let top = self.pop(word)?;
+                let below = self.pop(word)?;
+                self.stack.push(top);
+                self.stack.push(below);
+            }
+            "drop" => {
+                self.pop(word)?;
+            }
+            "neg" => {
+                self.apply(1, word, ctx, |mut args| Expr::UnaryOp { op : Unary::Minus, operand : Box::new(args.remove(0)), offset : 0 })?;
+            }
+            "clear" => {
+                self.stack.clear();
+            }
+            "in" | "to" => {
+                // the quotient is a plain number only if the units match
+                self.apply(2, word, ctx, |mut args| {
+                    let (unit, value) = (args.remove(1), args.remove(0));
+                    Expr::BinaryOp { op : Binary::Div, lhs : Box::new(value), rhs : Box::new(unit), offset : 0 }
+                })?;
+                match self.stack.last() {
+                    Some(Value::Scalar(quantity)) if !quantity.dimension.is_none() => {
+                        self.stack.pop();
+                        return Err(Error::Calc(CalcError::Dimension(0)));
+                    }
+                    _ => {}
+                }
+            }
+            _ if word.starts_with('=') && word.len() > 1 => {
+                let name = &word[1..];
+                if ctx.constants.contains_key(name) || functions::constant(name).is_some() {
+                    return Err(Error::Calc(CalcError::Constant(name.to_string(), 0)));
+                }
+                let top = self.stack.last().ok_or_else(|| Error::StackUnderflow(word.to_string()))?;
+                ctx.variables.insert(name.to_string(), top.clone());
+            }
+            // a list or matrix, in infix
+            _ if word.starts_with('[') => {
+                let expr = parser::parse(lexer::Lexer::new(word, Dialect::Standard)).map_err(Error::Calc)?;
+                self.apply(0, word, ctx, |_| expr)?;
+            }
+            _ => {
+                let tokens = lexer::tokens(word, Dialect::Standard).collect::<Vec<_>>();
+                match tokens.as_slice() {
+                    // a signed number, rather than an operator and a number
+                    [sign @ (Token::Sub | Token::Add), Token::Number(value)] => {
+                        let number = Expr::Number(value.clone());
+                        let op = if *sign == Token::Sub { Unary::Minus } else { Unary::Plus };
+                        return self.apply(0, word, ctx, |_| Expr::UnaryOp { op, operand : Box::new(number), offset : 0 });
+                    }
+                    [Token::Ident(name)] => { return self.name(name, ctx); }
+                    _ => {}
+                }
+
+                for token in tokens {
+                    match token {
+                        Token::Number(value) => self.apply(0, word, ctx, |_| Expr::Number(value))?,
+                        Token::Ident(name) => self.name(&name, ctx)?,
+                        Token::Factorial => self.call("factorial", 1, ctx)?,
+                        Token::Percent => {
+                            self.apply(1, word, ctx, |mut args| Expr::UnaryOp { op : Unary::Percent, operand : Box::new(args.remove(0)), offset : 0 })?;
+                        }
+                        _ => {
+                            let op = Binary::from_token(&token).ok_or_else(|| Error::InvalidWord(word.to_string()))?;
+                            self.apply(2, word, ctx, |mut args| {
+                                let rhs = Box::new(args.remove(1));
+                                Expr::BinaryOp { op, lhs : Box::new(args.remove(0)), rhs, offset : 0 }
+                            })?;
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // A line either applies completely or leaves the stack as it was; what
+    // it stores in variables stays. It computes in `ctx`, the session's.
+    pub fn execute(&mut self, line : &str, ctx : &mut Context<N>) -> Result<(), Error> {
+        let saved = self.stack.clone();
+        for word in line.split_whitespace() {
+            if let Err(e) = self.execute_word(word, ctx) {
+                self.stack = saved;
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use calculator::Context;
+    use calculator::Number;
+    use calculator::Rational;
+    use calculator::Value;
+
+    use super::Rpn;
+
+    // The stack after each line, or the error.
+    fn run<N: Number>(lines : &[&str], ctx : &mut Context<N>) -> Vec<String> {
+        let mut rpn = Rpn::new();
+        lines.iter().map(|line| match rpn.execute(line, ctx) {
+            Ok(()) => rpn.to_string(),
+            Err(e) => e.to_string()
+        }).collect()
+    }
+
+    #[test]
+    fn arithmetic() {
+        let mut ctx = Context::<f64>::default();
+        assert_eq!(run(&["3 4 + 5 *", "2 ^", "-3 +", "4+"], &mut ctx), ["[35]", "[1225]", "[1222]", "[1226]"]);
+        assert_eq!(run(&["2 sqrt 2 ^", "1 1 atan2 4 *", "5 ! 10 %"], &mut ctx), ["[2.0000000000000004]", "[2.0000000000000004 3.141592653589793]", "[2.0000000000000004 3.141592653589793 120 0.1]"]);
+        assert_eq!(run(&["[1,2,3,6] mean 12 18 gcd", "5 km * m in"], &mut ctx), ["[3 6]", "[3 6 5000]"]);
+    }
+
+    #[test]
+    fn stack_words() {
+        let mut ctx = Context::<f64>::default();
+        assert_eq!(run(&["1 2", "swap", "dup", "drop neg", "=x clear", "x 1 +"], &mut ctx), ["[1 2]", "[2 1]", "[2 1 1]", "[2 -1]", "[]", "[0]"]);
+        assert_eq!(ctx.variables.get("x"), Some(&Value::number(-1.0)));
+    }
+
+    #[test]
+    fn errors_leave_the_stack() {
+        let mut ctx = Context::<f64>::default();
+        assert_eq!(run(&["1 2", "3 + + +", "1 0 /", "1 $", "=pi", "5 km * s in", ""], &mut ctx)[1..],
+            ["Stack underflow at '+'", "Division by zero", "Invalid word '$'", "'pi' is a constant and can't be assigned", "Incompatible units", "[1 2]"]);
+    }
+
+    #[test]
+    fn exact_numbers() {
+        let mut ctx = Context::<Rational>::default();
+        assert_eq!(run(&["1 3 / 1 6 / +", "0.1 0.2 + 0.3 -"], &mut ctx), ["[1/2]", "[1/2 0]"]);
+    }
+}
@@ -0,0 +1,312 @@
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Read;
+use std::io::Write;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+use calculator::functions;
+use calculator::units;
+use calculator::CalcError;
+use calculator::Context;
+use calculator::Dialect;
+use calculator::Value;
+
+use crate::json;
+use crate::metrics::Metrics;
+
+/*
+Evaluation over HTTP: `POST /eval` (or `/evaluate`) with a body such as
+{"expr": "2*(3+4)", "vars": {"x": 1}} gives {"result": "14", "value": 14},
+or {"error": {"code": ..., "message": ..., "column": ...}} if the
+expression has no value; the code is CalcError::kind. Every request gets a
+session of its own with only the variables it sends, so one client never
+sees what another one assigned. `GET /metrics` gives what the server has
+counted, for Prometheus (see metrics.rs).
+
+Only as much of HTTP/1.1 is understood as that takes: one request per
+connection, a body of a known length and no more than MAX_BODY bytes.
+Each connection is answered on a thread of its own, up to MAX_CONNECTIONS
+at a time, and has TIMEOUT to send its request in, so a client that is
+slow to send holds up no one but itself. An evaluation stops with an
+error after MAX_STEPS steps or EVALUATION_TIME, whichever comes first.
+*/
+
+const MAX_BODY : usize = 64 * 1024;
+const MAX_HEADER_LINES : usize = 100;
+// as in the MCP server, which also takes expressions from anyone
+pub const MAX_EXPRESSION_LEN : usize = 1000;
+pub const MAX_CONNECTIONS : usize = 64;
+const TIMEOUT : Duration = Duration::from_secs(5);
+pub const MAX_STEPS : u64 = 10_000_000;
+pub const EVALUATION_TIME : Duration = Duration::from_secs(2);
+
+const JSON : &str = "application/json";
+// the version of Prometheus' text format
+const METRICS : &str = "text/plain; version=0.0.4";
+
+struct Response {
+    status : &'static str,
+    content_type : &'static str,
+    body : String
+}
+
+fn error(status : &'static str, message : &str) -> Response {
+    let body = json::Value::object(vec![("error", json::Value::object(vec![("message", json::Value::string(message))]))]);
+    Response { status, content_type : JSON, body : body.to_string() }
+}
+
+fn bad_request(message : &str) -> Response {
+    error("400 Bad Request", message)
+}
+
+fn evaluate(request : &json::Value, metrics : &Metrics) -> Response {
+    let expr = match request.get("expr").and_then(|expr| expr.as_str()) {
+        Some(expr) => expr,
+        None => { return bad_request("Missing string member 'expr'"); }
+    };
+    if expr.chars().count() > MAX_EXPRESSION_LEN {
+        return bad_request(&format!("Expression longer than {} characters", MAX_EXPRESSION_LEN));
+    }
+
+    let mut ctx = Context::<f64> { max_steps : Some(MAX_STEPS), time_limit : Some(EVALUATION_TIME), ..Context::default() };
+    match request.get("vars") {
+        None | Some(json::Value::Null) => {}
+        Some(json::Value::Object(vars)) => {
+            for (name, value) in vars {
+                match value {
+                    json::Value::Number(_) if functions::constant(name).is_some() => {
+                        return bad_request(&format!("'{}' is a constant", name));
+                    }
+                    json::Value::Number(value) => { ctx.variables.insert(name.clone(), Value::number(*value)); }
+                    _ => { return bad_request(&format!("Variable '{}' isn't a number", name)); }
+                }
+            }
+        }
+        Some(_) => { return bad_request("'vars' isn't an object"); }
+    }
+
+    let result = metrics.evaluate(|| calculator::evaluate_with(expr, Dialect::Standard, &ctx));
+    let status = if result.is_ok() { "200 OK" } else { "422 Unprocessable Content" };
+    Response { status, content_type : JSON, body : reply(&result, expr).to_string() }
+}
+
+// {"result": ..., "value": ...} for the value of `expr`, the value only if
+// it is a plain number, or {"error": ...}.
+pub fn reply(result : &Result<Value<f64>, CalcError>, expr : &str) -> json::Value {
+    match result {
+        Ok(value) => {
+            let mut members = vec![("result", json::Value::string(&shown(value, expr)))];
+            if let Value::Scalar(quantity) = value {
+                if quantity.dimension.is_none() {
+                    members.push(("value", json::Value::Number(quantity.value)));
+                }
+            }
+            json::Value::object(members)
+        }
+        Err(e) => {
+            let error = json::Value::object(vec![
+                ("code", json::Value::string(e.kind())),
+                ("message", json::Value::string(&e.to_string())),
+                ("column", json::Value::Number(e.column(expr) as f64)),
+            ]);
+            json::Value::object(vec![("error", error)])
+        }
+    }
+}
+
+// `value` as text, followed by the unit `expr` converted it to, if any.
+pub fn shown(value : &Value<f64>, expr : &str) -> String {
+    match units::split_conversion(expr, Dialect::Standard) {
+        (_, Some((_, unit))) => format!("{} {}", value, unit),
+        (_, None) => value.to_string()
+    }
+}
+
+// Reads from `stream` may only wait for what is left until `deadline`.
+fn until(stream : &TcpStream, deadline : Instant) -> io::Result<()> {
+    match deadline.checked_duration_since(Instant::now()) {
+        Some(left) if !left.is_zero() => stream.set_read_timeout(Some(left)),
+        _ => Err(io::ErrorKind::TimedOut.into())
+    }
+}
+
+// The request read from `stream`, as far as answering it takes.
+fn respond(stream : &TcpStream, metrics : &Metrics) -> io::Result<Response> {
+    let deadline = Instant::now() + TIMEOUT;
+    let mut reader = BufReader::new(stream.take(MAX_BODY as u64 * 2));
+    let mut line = String::new();
+    until(stream, deadline)?;
+    reader.read_line(&mut line)?;
+    let mut words = line.split_whitespace();
+    let (method, path) = (words.next().unwrap_or_default().to_string(), words.next().unwrap_or_default().to_string());
+
+    let mut length = None;
+    for _ in 0..MAX_HEADER_LINES {
+        line.clear();
+        until(stream, deadline)?;
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+
+    if path == "/metrics" {
+        if method != "GET" {
+            return Ok(error("405 Method Not Allowed", "Use GET"));
+        }
+        return Ok(Response { status : "200 OK", content_type : METRICS, body : metrics.render() });
+    }
+    if path != "/eval" && path != "/evaluate" {
+        return Ok(error("404 Not Found", "The endpoints are /eval and /metrics"));
+    }
+    if method != "POST" {
+        return Ok(error("405 Method Not Allowed", "Use POST"));
+    }
+    let length = match length {
+        Some(length) if length <= MAX_BODY => length,
+        Some(_) => { return Ok(error("413 Content Too Large", "Request body too large")); }
+        None => { return Ok(error("411 Length Required", "Missing Content-Length")); }
+    };
+
+    let mut body = vec![0; length];
+    let mut read = 0;
+    while read < length {
+        until(stream, deadline)?;
+        match reader.read(&mut body[read..])? {
+            0 => { return Err(io::ErrorKind::UnexpectedEof.into()); }
+            n => read += n
+        }
+    }
+    let request = String::from_utf8(body).ok().and_then(|body| json::parse(&body));
+    match request {
+        Some(request) => Ok(evaluate(&request, metrics)),
+        None => Ok(bad_request("The body isn't JSON"))
+    }
+}
+
+fn handle(mut stream : TcpStream, metrics : &Metrics) -> io::Result<()> {
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let response = respond(&stream, metrics)?;
+    metrics.request(response.status);
+    write!(stream, "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", response.status, response.content_type, response.body.len(), response.body)?;
+    stream.flush()
+}
+
+pub fn run(address : &str) -> i32 {
+    let listener = match TcpListener::bind(address) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("{}: {}", address, e);
+            return 1;
+        }
+    };
+
+    // a client gone wrong only ends its own connection
+    let open = Arc::new(AtomicUsize::new(0));
+    let metrics = Arc::new(Metrics::default());
+    for mut stream in listener.incoming().flatten() {
+        if open.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+            open.fetch_sub(1, Ordering::SeqCst);
+            metrics.request("503 Service Unavailable");
+            let body = error("503 Service Unavailable", "Too many connections").body;
+            let _ = write!(stream, "HTTP/1.1 503 Service Unavailable\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body);
+            continue;
+        }
+        let connections = Arc::clone(&open);
+        let metrics = Arc::clone(&metrics);
+        let spawned = thread::Builder::new().spawn(move || {
+            let _ = handle(stream, &metrics);
+            connections.fetch_sub(1, Ordering::SeqCst);
+        });
+        if spawned.is_err() {
+            open.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::net::TcpStream;
+    use std::thread;
+
+    use crate::json;
+    use crate::metrics::Metrics;
+
+    use super::evaluate;
+    use super::handle;
+
+    fn evaluated(request : &str) -> (&'static str, String) {
+        let response = evaluate(&json::parse(request).expect("JSON"), &Metrics::default());
+        (response.status, response.body)
+    }
+
+    // What the server answers to `request` over a connection of its own.
+    fn exchange(request : &str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            handle(stream, &Metrics::default()).unwrap();
+        });
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        server.join().unwrap();
+        response
+    }
+
+    #[test]
+    fn evaluations() {
+        assert_eq!(evaluated(r#"{"expr": "2*(3+4)"}"#), ("200 OK", r#"{"result":"14","value":14}"#.to_string()));
+        assert_eq!(evaluated(r#"{"expr": "x * y", "vars": {"x": 1.5, "y": 4}}"#), ("200 OK", r#"{"result":"6","value":6}"#.to_string()));
+        assert_eq!(evaluated(r#"{"expr": "2 km in m"}"#), ("200 OK", r#"{"result":"2000 m","value":2000}"#.to_string()));
+        let (status, body) = evaluated(r#"{"expr": "1 / 0"}"#);
+        assert_eq!(status, "422 Unprocessable Content");
+        assert!(body.starts_with(r#"{"error":{"code":"division_by_zero""#), "{}", body);
+    }
+
+    #[test]
+    fn bad_requests() {
+        let status = |request| evaluated(request).0;
+        assert_eq!(status(r#"{"exp": "1"}"#), "400 Bad Request");
+        assert_eq!(status(r#"{"expr": "1", "vars": {"pi": 3}}"#), "400 Bad Request");
+        assert_eq!(status(r#"{"expr": "x", "vars": {"x": "1"}}"#), "400 Bad Request");
+        assert_eq!(status(r#"{"expr": "x", "vars": [1]}"#), "400 Bad Request");
+        assert_eq!(evaluated(&format!(r#"{{"expr": "{}"}}"#, "1+".repeat(600))).1, r#"{"error":{"message":"Expression longer than 1000 characters"}}"#);
+        // each request has only the variables it sends
+        assert_eq!(status(r#"{"expr": "x"}"#), "422 Unprocessable Content");
+    }
+
+    #[test]
+    fn http() {
+        let body = r#"{"expr": "6 * 7"}"#;
+        let response = exchange(&format!("POST /eval HTTP/1.1\r\nHost: localhost\r\ncontent-length: {}\r\n\r\n{}", body.len(), body));
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n"), "{}", response);
+        assert!(response.ends_with("\r\n\r\n{\"result\":\"42\",\"value\":42}"), "{}", response);
+
+        let status = |request : &str| exchange(request).lines().next().unwrap_or_default().to_string();
+        assert_eq!(status("GET /eval HTTP/1.1\r\n\r\n"), "HTTP/1.1 405 Method Not Allowed");
+        assert_eq!(status("POST /other HTTP/1.1\r\n\r\n"), "HTTP/1.1 404 Not Found");
+        assert_eq!(status("POST /evaluate HTTP/1.1\r\n\r\n"), "HTTP/1.1 411 Length Required");
+        assert_eq!(status("POST /eval HTTP/1.1\r\nContent-Length: 1000000\r\n\r\n"), "HTTP/1.1 413 Content Too Large");
+        assert_eq!(status("POST /eval HTTP/1.1\r\nContent-Length: 3\r\n\r\n{]}"), "HTTP/1.1 400 Bad Request");
+        assert!(exchange("GET /metrics HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\n"));
+    }
+}
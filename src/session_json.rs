@@ -0,0 +1,136 @@
+use calculator::units::Dimension;
+use calculator::Number;
+use calculator::Quantity;
+use calculator::Rational;
+use calculator::Value;
+
+use crate::json;
+
+/*
+Values of a session as JSON, exactly whatever the numbers are computed in:
+a plain number is its fraction as a string, such as "-7/3" or "inf", one
+with a unit an object {"value": "5000", "unit": [1, 0, 0, 0, 0, 0, 0, 0]}
+of the powers of the base units m, kg, s, A, K, mol, cd and rad, a date the
+seconds since 1970 as {"value": "1709251200", "date": true}, and a list
+an array of these.
+*/
+
+pub fn value_to_json<N: Number>(value : &Value<N>) -> json::Value {
+    match value {
+        Value::Scalar(quantity) => {
+            let number = json::Value::String(quantity.value.to_literal().to_string());
+            if quantity.dimension.is_none() {
+                return number;
+            }
+            if quantity.dimension.is_date() {
+                return json::Value::object(vec![("value", number), ("date", json::Value::Bool(true))]);
+            }
+            let powers = quantity.dimension.powers().iter().map(|power| json::Value::Number(*power as f64)).collect();
+            json::Value::object(vec![("value", number), ("unit", json::Value::Array(powers))])
+        }
+        Value::List(items) => json::Value::Array(items.iter().map(value_to_json).collect())
+    }
+}
+
+// A number as a fraction from Display of Rational, or inf, -inf or NaN.
+fn rational(s : &str) -> Option<Rational> {
+    let (negative, magnitude) = match s.strip_prefix('-') {
+        Some(magnitude) => (true, magnitude),
+        None => (false, s)
+    };
+    let natural = |s : &str| Some(Rational::from_decimal(s)).filter(|_| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()));
+    let value = match magnitude.split_once('/') {
+        Some((numerator, denominator)) => natural(numerator)?.div(&natural(denominator)?, 0)?,
+        None => match natural(magnitude) {
+            Some(value) => value,
+            None => { return s.parse::<f64>().ok().filter(|value| !value.is_finite()).map(Rational::from_f64); }
+        }
+    };
+    Some(if negative { value.neg() } else { value })
+}
+
+fn dimension(value : &json::Value) -> Option<Dimension> {
+    let powers = match value {
+        json::Value::Array(powers) => powers,
+        _ => { return None; }
+    };
+    // sessions saved before angles had a unit list seven powers
+    let mut result = [0; 8];
+    if powers.len() != result.len() && powers.len() != result.len() - 1 {
+        return None;
+    }
+    for (i, power) in powers.iter().enumerate() {
+        result[i] = match power {
+            json::Value::Number(n) if n.fract() == 0.0 && *n >= i8::MIN as f64 && *n <= i8::MAX as f64 => *n as i8,
+            _ => { return None; }
+        };
+    }
+    Some(Dimension::from_powers(result))
+}
+
+// None if `value` isn't one as value_to_json writes them.
+pub fn value_from_json<N: Number>(value : &json::Value, precision : usize) -> Option<Value<N>> {
+    let number = |s : &str| rational(s).map(|value| N::from_rational(&value, precision));
+    match value {
+        json::Value::String(s) => Some(Value::number(number(s)?)),
+        json::Value::Object(_) => {
+            let dimension = match value.get("date") {
+                Some(json::Value::Bool(true)) => Dimension::DATE,
+                _ => dimension(value.get("unit")?)?
+            };
+            Some(Value::Scalar(Quantity { value : number(value.get("value")?.as_str()?)?, dimension }))
+        }
+        json::Value::Array(items) => items.iter().map(|item| value_from_json(item, precision)).collect::<Option<Vec<_>>>().map(Value::List),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use calculator::units::Dimension;
+    use calculator::Number;
+    use calculator::Quantity;
+    use calculator::Rational;
+    use calculator::Value;
+
+    use crate::json;
+
+    use super::value_from_json;
+    use super::value_to_json;
+
+    fn read<N: Number>(text : &str) -> Option<Value<N>> {
+        value_from_json(&json::parse(text)?, 20)
+    }
+
+    #[test]
+    fn values() {
+        let third = Value::number(Rational::ratio(7, 3).neg());
+        assert_eq!(value_to_json(&third).to_string(), r#""-7/3""#);
+        let distance = Value::Scalar(Quantity { value : 5000.0, dimension : Dimension::LENGTH });
+        assert_eq!(value_to_json(&distance).to_string(), r#"{"value":"5000","unit":[1,0,0,0,0,0,0,0]}"#);
+        let date = Value::Scalar(Quantity { value : 1709251200.0, dimension : Dimension::DATE });
+        assert_eq!(value_to_json(&date).to_string(), r#"{"value":"1709251200","date":true}"#);
+        assert_eq!(value_to_json(&Value::List(vec![Value::number(0.5), Value::number(f64::NEG_INFINITY)])).to_string(), r#"["1/2","-inf"]"#);
+
+        for value in [third.clone(), Value::List(vec![third, Value::number(Rational::integer(0))])] {
+            assert_eq!(read(&value_to_json(&value).to_string()), Some(value));
+        }
+        assert_eq!(read(&value_to_json(&distance).to_string()), Some(distance));
+        assert_eq!(read(&value_to_json(&date).to_string()), Some(date));
+        // as the exact fraction of the float, which reads back as the same
+        assert_eq!(read::<f64>(&value_to_json(&Value::number(0.1)).to_string()), Some(Value::number(0.1)));
+    }
+
+    #[test]
+    fn malformed() {
+        // as saved before angles had a unit
+        assert_eq!(read(r#"{"value": "2", "unit": [1, 0, 0, 0, 0, 0, 0]}"#), Some(Value::Scalar(Quantity { value : 2.0, dimension : Dimension::LENGTH })));
+        assert_eq!(read::<f64>(r#""1.5""#), None);
+        assert_eq!(read::<f64>(r#""1/0""#), None);
+        assert_eq!(read::<f64>(r#""--1""#), None);
+        assert_eq!(read::<f64>("1.5"), None);
+        assert_eq!(read::<f64>(r#"{"value": "2", "unit": [1, 0]}"#), None);
+        assert_eq!(read::<f64>(r#"{"value": "2", "unit": [0.5, 0, 0, 0, 0, 0, 0, 0]}"#), None);
+        assert_eq!(read::<f64>(r#"["1", null]"#), None);
+    }
+}
@@ -0,0 +1,73 @@
+use std::cell::Cell;
+use std::hint;
+use std::panic;
+use std::sync::Mutex;
+use std::thread;
+
+/*
+The parser and the evaluator recurse, and how deep the evaluator goes
+isn't known from the tree: calls of user functions nest their bodies in
+each other, each with sums and the like of its own. So both run on a
+thread with SIZE of stack, whatever thread they are called on, and
+measure how much of it they have used from where they started; going
+beyond what `run` allows is an error rather than an overflow. What is
+left of SIZE is for the recursion between two checks.
+
+Starting a thread for every line adds up, so a program that evaluates
+many can do all of it within one `run`, and the parser and evaluator then
+go on on that thread.
+*/
+
+const SIZE : usize = 64 << 20;
+const BUDGET : usize = 48 << 20;
+// for when no thread can be started, and the stack of the caller, whose
+// size isn't known, has to do
+const FALLBACK_BUDGET : usize = 512 << 10;
+
+// The stack `run` gives, with where it began and how much of it there is.
+#[derive(Clone, Copy)]
+pub struct Stack {
+    base : usize,
+    budget : usize
+}
+
+impl Stack {
+    pub fn exhausted(self) -> bool {
+        position().abs_diff(self.base) > self.budget
+    }
+}
+
+thread_local! {
+    // the stack of the `run` this thread is in, if any
+    static CURRENT : Cell<Option<Stack>> = const { Cell::new(None) };
+}
+
+// Where the stack is now, as the address of a local.
+fn position() -> usize {
+    let marker = 0u8;
+    hint::black_box(&marker) as *const u8 as usize
+}
+
+pub fn run<T: Send>(f : impl FnOnce(Stack) -> T + Send) -> T {
+    if let Some(stack) = CURRENT.get() {
+        return f(stack);
+    }
+
+    // taken by whichever of the two gets to call it
+    let f = Mutex::new(Some(f));
+    let call = |stack| {
+        let f = f.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).take();
+        f.unwrap_or_else(|| unreachable!("called only once"))(stack)
+    };
+    thread::scope(|threads| {
+        let thread = thread::Builder::new().stack_size(SIZE).spawn_scoped(threads, || {
+            let stack = Stack { base : position(), budget : BUDGET };
+            CURRENT.set(Some(stack));
+            call(stack)
+        });
+        match thread {
+            Ok(thread) => thread.join().unwrap_or_else(|panic| panic::resume_unwind(panic)),
+            Err(_) => call(Stack { base : position(), budget : FALLBACK_BUDGET })
+        }
+    })
+}
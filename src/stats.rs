@@ -0,0 +1,134 @@
+use crate::number::Number;
+
+/*
+Sums and moments of many numbers, kept accurate in f64 however many there
+are: the statistics functions of lists and --aggregate both use them. In
+the exact number types the compensation stays zero and costs little.
+*/
+
+/*
+Neumaier's variant of Kahan summation: the low-order bits lost by each
+addition are collected separately and added back at the end, so the error
+doesn't grow with the number of terms.
+*/
+pub struct CompensatedSum<N> {
+    sum : N,
+    compensation : N
+}
+
+impl<N: Number> CompensatedSum<N> {
+    pub fn new() -> CompensatedSum<N> {
+        CompensatedSum { sum : N::from_integer(0), compensation : N::from_integer(0) }
+    }
+
+    pub fn add(&mut self, x : &N) {
+        let t = self.sum.add(x);
+        let lost = if self.sum.to_f64().abs() >= x.to_f64().abs() { self.sum.sub(&t).add(x) } else { x.sub(&t).add(&self.sum) };
+        self.compensation = self.compensation.add(&lost);
+        self.sum = t;
+    }
+
+    pub fn value(&self) -> N {
+        self.sum.add(&self.compensation)
+    }
+}
+
+impl<N: Number> Default for CompensatedSum<N> {
+    fn default() -> CompensatedSum<N> {
+        CompensatedSum::new()
+    }
+}
+
+// The count, sum, mean and variance of numbers added one at a time. The
+// variance uses Welford's update, with the squared deviations summed as
+// above, so it needs no second pass and stays accurate for large values
+// close together.
+pub struct Moments<N> {
+    count : u64,
+    sum : CompensatedSum<N>,
+    mean : N,
+    m2 : CompensatedSum<N>
+}
+
+impl<N: Number> Moments<N> {
+    pub fn new() -> Moments<N> {
+        Moments { count : 0, sum : CompensatedSum::new(), mean : N::from_integer(0), m2 : CompensatedSum::new() }
+    }
+
+    // `precision` is that of the quotient in the running mean.
+    pub fn push(&mut self, x : &N, precision : usize) {
+        self.count += 1;
+        self.sum.add(x);
+
+        let delta = x.sub(&self.mean);
+        if let Some(step) = delta.div(&N::from_integer(self.count as i128), precision) {
+            self.mean = self.mean.add(&step);
+        }
+        self.m2.add(&delta.mul(&x.sub(&self.mean)));
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn sum(&self) -> N {
+        self.sum.value()
+    }
+
+    // None for no numbers.
+    pub fn mean(&self, precision : usize) -> Option<N> {
+        self.sum().div(&N::from_integer(self.count as i128), precision).filter(|_| self.count > 0)
+    }
+
+    // The sample variance, over n - 1; None for fewer than two numbers.
+    pub fn variance(&self, precision : usize) -> Option<N> {
+        self.m2.value().div(&N::from_integer(self.count as i128 - 1), precision).filter(|_| self.count >= 2)
+    }
+}
+
+impl<N: Number> Default for Moments<N> {
+    fn default() -> Moments<N> {
+        Moments::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CompensatedSum;
+    use super::Moments;
+    use crate::rational::Rational;
+
+    #[test]
+    fn compensated() {
+        let mut sum = CompensatedSum::<f64>::new();
+        for x in [1e100, 1.0, -1e100] {
+            sum.add(&x);
+        }
+        assert_eq!(sum.value(), 1.0);
+
+        let mut tenths = CompensatedSum::<f64>::new();
+        for _ in 0..10 {
+            tenths.add(&0.1);
+        }
+        assert_eq!(tenths.value(), 1.0);
+    }
+
+    #[test]
+    fn moments() {
+        let mut moments = Moments::<f64>::new();
+        assert_eq!((moments.mean(0), moments.variance(0)), (None, None));
+        for x in [1e9 + 1.0, 1e9 + 2.0, 1e9 + 3.0] {
+            moments.push(&x, 0);
+        }
+        assert_eq!(moments.count(), 3);
+        assert_eq!(moments.mean(0), Some(1e9 + 2.0));
+        assert_eq!(moments.variance(0), Some(1.0));
+
+        let mut exact = Moments::<Rational>::new();
+        for x in [1, 2, 4] {
+            exact.push(&Rational::integer(x), 0);
+        }
+        assert_eq!(exact.mean(0), Some(Rational::ratio(7, 3)));
+        assert_eq!(exact.variance(0), Some(Rational::ratio(7, 3)));
+    }
+}
@@ -0,0 +1,640 @@
+use std::cmp::Ordering;
+
+use crate::ast::Binary;
+use crate::ast::Expr;
+use crate::ast::Symbolic;
+use crate::ast::Unary;
+use crate::natural::Natural;
+use crate::number::Number;
+use crate::eval;
+use crate::CalcError;
+use crate::Context;
+use crate::Rational;
+use crate::Value;
+
+/*
+Expressions worked on as expressions rather than numbers. An expression is
+brought into a normal form, a sum of terms, each an exact coefficient
+times powers of factors that aren't taken apart any further: variables,
+calls and, unless expanding, sums. Like terms are collected and like
+factors combined on the way, so 2*x + 3*x comes out as 5 * x and x*x/x as
+x. Every name is a symbol, even one with a value in the session.
+
+Derivatives follow the usual rules, with the trigonometric functions taken
+in radians, and are then simplified.
+*/
+
+// How high a power of a sum `expand` multiplies out.
+const MAX_EXPANDED_POWER : i128 = 16;
+
+// `base` to the power of `exponent`; `key` is how the base prints, which is
+// what tells factors apart, offsets aside.
+#[derive(Clone)]
+struct Factor {
+    key : String,
+    base : Expr,
+    exponent : Rational
+}
+
+// A coefficient times factors, which are sorted, variables first.
+#[derive(Clone)]
+struct Term {
+    coefficient : Rational,
+    factors : Vec<Factor>
+}
+
+// Terms that all have different factors, added up. No terms is 0.
+#[derive(Clone)]
+struct Sum(Vec<Term>);
+
+fn number(value : i128) -> Rational {
+    Rational::integer(value)
+}
+
+fn binary(op : Binary, lhs : Expr, rhs : Expr) -> Expr {
+    Expr::BinaryOp { op, lhs : Box::new(lhs), rhs : Box::new(rhs), offset : 0 }
+}
+
+fn power(base : Expr, exponent : &Rational) -> Expr {
+    if *exponent == number(1) { base } else { binary(Binary::Pow, base, Expr::Number(exponent.clone())) }
+}
+
+fn product(factors : Vec<Expr>) -> Expr {
+    factors.into_iter().reduce(|acc, factor| binary(Binary::Mul, acc, factor)).unwrap_or(Expr::Number(number(1)))
+}
+
+impl Factor {
+    fn new(base : Expr, exponent : Rational) -> Factor {
+        Factor { key : base.to_string(), base, exponent }
+    }
+
+    fn order(&self, other : &Factor) -> Ordering {
+        let rank = |factor : &Factor| !matches!(factor.base, Expr::Variable { .. });
+        rank(self).cmp(&rank(other)).then_with(|| self.key.cmp(&other.key))
+    }
+}
+
+impl Term {
+    fn constant(coefficient : Rational) -> Term {
+        Term { coefficient, factors : Vec::new() }
+    }
+
+    fn atom(base : Expr, exponent : Rational) -> Term {
+        Term { coefficient : number(1), factors : vec![Factor::new(base, exponent)] }
+    }
+
+    fn like(&self, other : &Term) -> bool {
+        self.factors.len() == other.factors.len()
+            && self.factors.iter().zip(&other.factors).all(|(a, b)| a.key == b.key && a.exponent == b.exponent)
+    }
+
+    fn degree(&self) -> f64 {
+        self.factors.iter().map(|factor| factor.exponent.to_f64()).sum()
+    }
+
+    // Higher degrees first, and among terms of one degree the higher
+    // powers of the first factors, so a^2*b comes before a*b^2.
+    fn order(&self, other : &Term) -> Ordering {
+        let by_degree = other.degree().partial_cmp(&self.degree()).unwrap_or(Ordering::Equal);
+        let mut keys = self.factors.iter().chain(&other.factors).map(|factor| &factor.key).collect::<Vec<_>>();
+        keys.sort();
+        keys.dedup();
+        let exponent = |term : &Term, key : &str| term.factors.iter().find(|f| f.key == key).map_or(number(0), |f| f.exponent.clone());
+        keys.into_iter().fold(by_degree, |acc, key| acc.then_with(|| exponent(other, key).compare(&exponent(self, key)).unwrap_or(Ordering::Equal)))
+    }
+
+    fn mul(mut self, other : Term) -> Term {
+        self.coefficient = self.coefficient.mul(&other.coefficient);
+        for factor in other.factors {
+            match self.factors.iter_mut().find(|f| f.key == factor.key) {
+                Some(existing) => existing.exponent = existing.exponent.add(&factor.exponent),
+                None => self.factors.push(factor)
+            }
+        }
+        self.factors.retain(|factor| factor.exponent != number(0));
+
+        // a number to a whole power, as from 2^(1/2) * 2^(1/2), is just a number
+        let mut coefficient = self.coefficient;
+        self.factors.retain(|factor| match (&factor.base, factor.exponent.to_integer()) {
+            (Expr::Number(base), Some(exponent)) => match base.pow(&number(exponent), 0) {
+                Some(power) => {
+                    coefficient = coefficient.mul(&power);
+                    false
+                }
+                None => true
+            },
+            _ => true
+        });
+        self.coefficient = coefficient;
+        self.factors.sort_by(Factor::order);
+        self
+    }
+
+    // None for a power of 0 that has no value.
+    fn pow(mut self, exponent : i128) -> Option<Term> {
+        if exponent < 0 && self.coefficient == number(0) {
+            return None;
+        }
+        self.coefficient = self.coefficient.pow(&number(exponent), 0)?;
+        for factor in self.factors.iter_mut() {
+            factor.exponent = factor.exponent.mul(&number(exponent));
+        }
+        Some(self)
+    }
+
+    // The term without its sign, or with it if `signed`, and whether it was
+    // negative.
+    fn to_expr(&self, signed : bool) -> (bool, Expr) {
+        let (negative, numerator, denominator) = match self.coefficient.parts() {
+            Some((negative, numerator, denominator)) => {
+                let whole = |n : &Natural| Rational::from_parts(false, n.clone(), Natural::small(1));
+                (negative, whole(numerator), whole(denominator))
+            }
+            None => (false, self.coefficient.clone(), number(1))
+        };
+
+        let (mut above, mut below) = (Vec::new(), Vec::new());
+        if numerator != number(1) || self.factors.iter().all(|factor| factor.exponent.compare(&number(0)) == Some(Ordering::Less)) {
+            above.push(Expr::Number(numerator));
+        }
+        if denominator != number(1) {
+            below.push(Expr::Number(denominator));
+        }
+        for factor in &self.factors {
+            if factor.exponent.compare(&number(0)) == Some(Ordering::Less) {
+                below.push(power(factor.base.clone(), &factor.exponent.neg()));
+            }
+            else {
+                above.push(power(factor.base.clone(), &factor.exponent));
+            }
+        }
+
+        // the sign goes on the first factor, as in -2 * x
+        if negative && signed {
+            let first = match above.remove(0) {
+                Expr::Number(value) => Expr::Number(value.neg()),
+                first => Expr::UnaryOp { op : Unary::Minus, operand : Box::new(first), offset : 0 }
+            };
+            above.insert(0, first);
+        }
+        let above = product(above);
+        if below.is_empty() {
+            (negative, above)
+        }
+        else {
+            (negative, binary(Binary::Div, above, product(below)))
+        }
+    }
+}
+
+impl Sum {
+    fn constant(value : Rational) -> Sum {
+        if value == number(0) { Sum(Vec::new()) } else { Sum(vec![Term::constant(value)]) }
+    }
+
+    fn as_constant(&self) -> Option<Rational> {
+        match self.0.as_slice() {
+            [] => Some(number(0)),
+            [term] if term.factors.is_empty() => Some(term.coefficient.clone()),
+            _ => None
+        }
+    }
+
+    fn add(mut self, other : Sum) -> Sum {
+        for term in other.0 {
+            match self.0.iter_mut().find(|t| t.like(&term)) {
+                Some(existing) => existing.coefficient = existing.coefficient.add(&term.coefficient),
+                None => self.0.push(term)
+            }
+        }
+        self.0.retain(|term| term.coefficient != number(0));
+        self
+    }
+
+    fn scale(self, factor : &Rational) -> Sum {
+        if *factor == number(0) {
+            return Sum(Vec::new());
+        }
+        Sum(self.0.into_iter().map(|mut term| { term.coefficient = term.coefficient.mul(factor); term }).collect())
+    }
+
+    // A sum of more than one term becomes a single factor.
+    fn into_term(self) -> Term {
+        match self.0.len() {
+            1 => self.0.into_iter().next().unwrap_or_else(|| unreachable!("one term")),
+            _ => Term::atom(self.to_expr(), number(1))
+        }
+    }
+
+    // Expanding multiplies out every product of sums; otherwise only a
+    // number is multiplied into a sum.
+    fn mul(self, other : Sum, expand : bool) -> Sum {
+        if let Some(factor) = self.as_constant() {
+            return other.scale(&factor);
+        }
+        if let Some(factor) = other.as_constant() {
+            return self.scale(&factor);
+        }
+        if !expand && (self.0.len() > 1 || other.0.len() > 1) {
+            return Sum(vec![self.into_term().mul(other.into_term())]);
+        }
+
+        let mut result = Sum(Vec::new());
+        for a in &self.0 {
+            for b in &other.0 {
+                result = result.add(Sum(vec![a.clone().mul(b.clone())]));
+            }
+        }
+        result
+    }
+
+    fn pow(self, exponent : Sum, expand : bool) -> Sum {
+        let exponent = match exponent.as_constant() {
+            Some(exponent) => exponent,
+            None => { return Sum(vec![Term::atom(binary(Binary::Pow, self.to_expr(), exponent.to_expr()), number(1))]); }
+        };
+        if let Some(n) = exponent.to_integer() {
+            if n == 0 {
+                return Sum::constant(number(1));
+            }
+            if self.0.is_empty() && n > 0 {
+                return self;
+            }
+            if self.0.len() == 1 {
+                if let Some(term) = self.0[0].clone().pow(n) {
+                    return Sum(vec![term]);
+                }
+            }
+            if expand && self.0.len() > 1 && (2..=MAX_EXPANDED_POWER).contains(&n) {
+                let mut result = self.clone();
+                for _ in 1..n {
+                    result = result.mul(self.clone(), true);
+                }
+                return result;
+            }
+        }
+
+        // a root of a single factor, as in x^(1/2), but not of x^2, which
+        // would take the sign away
+        if let [Term { coefficient, factors }] = self.0.as_slice() {
+            if let [factor] = factors.as_slice() {
+                if *coefficient == number(1) && factor.exponent == number(1) {
+                    return Sum(vec![Term::atom(factor.base.clone(), exponent)]);
+                }
+            }
+        }
+        Sum(vec![Term::atom(self.to_expr(), exponent)])
+    }
+
+    // Terms of the highest degree first, and a number last.
+    fn to_expr(&self) -> Expr {
+        let mut terms = self.0.iter().collect::<Vec<_>>();
+        terms.sort_by(|a, b| a.order(b));
+
+        let mut result : Option<Expr> = None;
+        for term in terms {
+            let (negative, expr) = term.to_expr(result.is_none());
+            result = Some(match (result, negative) {
+                (None, _) => expr,
+                (Some(sum), false) => binary(Binary::Add, sum, expr),
+                (Some(sum), true) => binary(Binary::Sub, sum, expr)
+            });
+        }
+        result.unwrap_or(Expr::Number(number(0)))
+    }
+}
+
+// `expr` in normal form, with what isn't arithmetic kept as a factor of
+// its own, its parts simplified.
+fn normalize(expr : &Expr, expand : bool) -> Sum {
+    let simplified = |expr : &Expr| Box::new(normalize(expr, expand).to_expr());
+    let atom = match expr {
+        Expr::Number(value) => { return Sum::constant(value.clone()); }
+        Expr::UnaryOp { op : Unary::Plus, operand, .. } => { return normalize(operand, expand); }
+        Expr::UnaryOp { op : Unary::Minus, operand, .. } => { return normalize(operand, expand).scale(&number(-1)); }
+        Expr::UnaryOp { op : Unary::Percent, operand, .. } => { return normalize(operand, expand).scale(&Rational::ratio(1, 100)); }
+        Expr::BinaryOp { op : op @ (Binary::Add | Binary::Sub | Binary::Mul | Binary::Div | Binary::Pow), lhs, rhs, .. } => {
+            let (lhs, rhs) = (normalize(lhs, expand), normalize(rhs, expand));
+            return match op {
+                Binary::Add => lhs.add(rhs),
+                Binary::Sub => lhs.add(rhs.scale(&number(-1))),
+                Binary::Mul => lhs.mul(rhs, expand),
+                Binary::Div => lhs.mul(rhs.pow(Sum::constant(number(-1)), expand), expand),
+                _ => lhs.pow(rhs, expand)
+            };
+        }
+        Expr::Date(_) | Expr::Variable { .. } => expr.clone(),
+        Expr::UnaryOp { op, operand, offset } => Expr::UnaryOp { op : *op, operand : simplified(operand), offset : *offset },
+        Expr::BinaryOp { op, lhs, rhs, offset } => Expr::BinaryOp { op : *op, lhs : simplified(lhs), rhs : simplified(rhs), offset : *offset },
+        Expr::Call { name, args, offset } => Expr::Call { name : name.clone(), args : args.iter().map(|arg| *simplified(arg)).collect(), offset : *offset },
+        Expr::List(items) => Expr::List(items.iter().map(|item| *simplified(item)).collect()),
+        Expr::Index { list, index, offset } => Expr::Index { list : simplified(list), index : simplified(index), offset : *offset },
+        Expr::Conditional { cond, then, otherwise } => Expr::Conditional { cond : simplified(cond), then : simplified(then), otherwise : simplified(otherwise) }
+    };
+    Sum(vec![Term::atom(atom, number(1))])
+}
+
+fn mentions(expr : &Expr, var : &str) -> bool {
+    match expr {
+        Expr::Number(_) | Expr::Date(_) => false,
+        Expr::Variable { name, .. } => name == var,
+        Expr::UnaryOp { operand, .. } => mentions(operand, var),
+        Expr::BinaryOp { lhs, rhs, .. } | Expr::Index { list : lhs, index : rhs, .. } => mentions(lhs, var) || mentions(rhs, var),
+        Expr::Call { args : items, .. } | Expr::List(items) => items.iter().any(|item| mentions(item, var)),
+        Expr::Conditional { cond, then, otherwise } => mentions(cond, var) || mentions(then, var) || mentions(otherwise, var)
+    }
+}
+
+fn call(name : &str, arg : &Expr) -> Expr {
+    Expr::Call { name : name.to_string(), args : vec![arg.clone()], offset : 0 }
+}
+
+// The derivative of f(u) by u, for the functions that have one.
+fn outer_derivative(name : &str, u : &Expr) -> Option<Expr> {
+    let n = |value : i128| Expr::Number(number(value));
+    let square = |expr : Expr| binary(Binary::Pow, expr, n(2));
+    let reciprocal = |expr : Expr| binary(Binary::Div, n(1), expr);
+    let minus = |expr : Expr| Expr::UnaryOp { op : Unary::Minus, operand : Box::new(expr), offset : 0 };
+    let derivative = match name {
+        "sin" => call("cos", u),
+        "cos" => minus(call("sin", u)),
+        "tan" => reciprocal(square(call("cos", u))),
+        "exp" => call("exp", u),
+        "ln" | "log" => reciprocal(u.clone()),
+        "log2" => reciprocal(binary(Binary::Mul, u.clone(), call("ln", &n(2)))),
+        "log10" => reciprocal(binary(Binary::Mul, u.clone(), call("ln", &n(10)))),
+        "sqrt" => reciprocal(binary(Binary::Mul, n(2), call("sqrt", u))),
+        "cbrt" => reciprocal(binary(Binary::Mul, n(3), square(call("cbrt", u)))),
+        "asin" => reciprocal(call("sqrt", &binary(Binary::Sub, n(1), square(u.clone())))),
+        "acos" => minus(reciprocal(call("sqrt", &binary(Binary::Sub, n(1), square(u.clone()))))),
+        "atan" => reciprocal(binary(Binary::Add, n(1), square(u.clone()))),
+        "sinh" => call("cosh", u),
+        "cosh" => call("sinh", u),
+        "tanh" => reciprocal(square(call("cosh", u))),
+        "abs" => binary(Binary::Div, u.clone(), call("abs", u)),
+        _ => { return None; }
+    };
+    Some(derivative)
+}
+
+fn offset(expr : &Expr) -> usize {
+    match expr {
+        Expr::Variable { offset, .. } | Expr::UnaryOp { offset, .. } | Expr::BinaryOp { offset, .. } | Expr::Call { offset, .. } | Expr::Index { offset, .. } => *offset,
+        Expr::Number(_) | Expr::Date(_) | Expr::List(_) | Expr::Conditional { .. } => 0
+    }
+}
+
+// Unsimplified, so 0 and 1 are left in for the simplification to take out.
+fn derivative(expr : &Expr, var : &str) -> Result<Expr, CalcError> {
+    if !mentions(expr, var) {
+        return Ok(Expr::Number(number(0)));
+    }
+    let d = |expr : &Expr| derivative(expr, var);
+    let cannot = || CalcError::Differentiate(expr.to_string(), offset(expr));
+
+    match expr {
+        Expr::Variable { .. } => Ok(Expr::Number(number(1))),
+        Expr::UnaryOp { op : op @ (Unary::Plus | Unary::Minus), operand, offset } => Ok(Expr::UnaryOp { op : *op, operand : Box::new(d(operand)?), offset : *offset }),
+        Expr::UnaryOp { op : Unary::Percent, operand, .. } => Ok(binary(Binary::Div, d(operand)?, Expr::Number(number(100)))),
+        Expr::BinaryOp { op : op @ (Binary::Add | Binary::Sub), lhs, rhs, .. } => Ok(binary(*op, d(lhs)?, d(rhs)?)),
+        Expr::BinaryOp { op : Binary::Mul, lhs, rhs, .. } => {
+            Ok(binary(Binary::Add, binary(Binary::Mul, d(lhs)?, *rhs.clone()), binary(Binary::Mul, *lhs.clone(), d(rhs)?)))
+        }
+        Expr::BinaryOp { op : Binary::Div, lhs, rhs, .. } => {
+            let numerator = binary(Binary::Sub, binary(Binary::Mul, d(lhs)?, *rhs.clone()), binary(Binary::Mul, *lhs.clone(), d(rhs)?));
+            Ok(binary(Binary::Div, numerator, binary(Binary::Pow, *rhs.clone(), Expr::Number(number(2)))))
+        }
+        // n * u^(n - 1) * u' for a constant exponent, and otherwise
+        // u^v * (v' * ln(u) + v * u' / u)
+        Expr::BinaryOp { op : Binary::Pow, lhs, rhs, .. } if !mentions(rhs, var) => {
+            let lowered = binary(Binary::Pow, *lhs.clone(), binary(Binary::Sub, *rhs.clone(), Expr::Number(number(1))));
+            Ok(binary(Binary::Mul, binary(Binary::Mul, *rhs.clone(), lowered), d(lhs)?))
+        }
+        // e^v, whose ln(e) would only be 1
+        Expr::BinaryOp { op : Binary::Pow, lhs, rhs, .. } if matches!(&**lhs, Expr::Variable { name, .. } if name == "e") => {
+            Ok(binary(Binary::Mul, expr.clone(), d(rhs)?))
+        }
+        Expr::BinaryOp { op : Binary::Pow, lhs, rhs, .. } => {
+            let log = binary(Binary::Mul, d(rhs)?, call("ln", lhs));
+            let base = binary(Binary::Div, binary(Binary::Mul, *rhs.clone(), d(lhs)?), *lhs.clone());
+            Ok(binary(Binary::Mul, expr.clone(), binary(Binary::Add, log, base)))
+        }
+        Expr::Call { name, args, .. } => match args.as_slice() {
+            [u] => {
+                let outer = outer_derivative(name, u).ok_or_else(cannot)?;
+                Ok(binary(Binary::Mul, outer, d(u)?))
+            }
+            _ => Err(cannot())
+        },
+        _ => Err(cannot())
+    }
+}
+
+pub fn apply(operation : &Symbolic, expr : &Expr) -> Result<Expr, CalcError> {
+    match operation {
+        Symbolic::Simplify => Ok(normalize(expr, false).to_expr()),
+        Symbolic::Expand => Ok(normalize(expr, true).to_expr()),
+        Symbolic::Derivative(var) => Ok(normalize(&derivative(expr, var)?, false).to_expr())
+    }
+}
+
+/*
+The canonical form of an expression, for telling apart formulas that only
+differ in how they are written: 1 + x * 2 and (2 * x) + 1 both come out as
+1 + 2 * x. A chain of sums and differences, or of products and quotients,
+has its operands sorted, constants first and then by how they print, with
+those taken away or divided by after the others; the operands of &, |,
+xor, == and != are sorted too. Constants are folded exactly whatever the
+session computes in, but calls are left alone, as is a power with an
+exponent that isn't an integer, and a percentage added or taken away,
+which is of what comes before it. Unlike simplify, nothing else is
+rewritten, so x * x stays as it is, and brackets are only where printing
+needs them.
+*/
+pub fn canonical(expr : &Expr) -> Expr {
+    let boxed = |expr : &Expr| Box::new(canonical(expr));
+    let node = match expr {
+        Expr::Number(_) | Expr::Date(_) | Expr::Variable { .. } => { return expr.clone(); }
+        Expr::UnaryOp { op, operand, offset } => Expr::UnaryOp { op : *op, operand : boxed(operand), offset : *offset },
+        // the percentage stays one, as folding it would change what it is of
+        Expr::BinaryOp { op : op @ (Binary::Add | Binary::Sub), lhs, rhs, offset } if is_percent(rhs) => {
+            let rhs = match &**rhs {
+                Expr::UnaryOp { op, operand, offset } => Expr::UnaryOp { op : *op, operand : boxed(operand), offset : *offset },
+                _ => canonical(rhs)
+            };
+            Expr::BinaryOp { op : *op, lhs : boxed(lhs), rhs : Box::new(rhs), offset : *offset }
+        }
+        Expr::BinaryOp { op, lhs, rhs, offset } => Expr::BinaryOp { op : *op, lhs : boxed(lhs), rhs : boxed(rhs), offset : *offset },
+        Expr::Call { name, args, offset } => { return Expr::Call { name : name.clone(), args : args.iter().map(canonical).collect(), offset : *offset }; }
+        Expr::List(items) => { return Expr::List(items.iter().map(canonical).collect()); }
+        Expr::Index { list, index, offset } => { return Expr::Index { list : boxed(list), index : boxed(index), offset : *offset }; }
+        Expr::Conditional { cond, then, otherwise } => { return Expr::Conditional { cond : boxed(cond), then : boxed(then), otherwise : boxed(otherwise) }; }
+    };
+
+    if let Some(folded) = folded(&node) {
+        return folded;
+    }
+    match &node {
+        Expr::BinaryOp { op : Binary::Add | Binary::Sub, .. } => chain(&node, Binary::Add, Some(Binary::Sub)),
+        Expr::BinaryOp { op : Binary::Mul | Binary::Div, .. } => chain(&node, Binary::Mul, Some(Binary::Div)),
+        Expr::BinaryOp { op : op @ (Binary::BitAnd | Binary::BitOr | Binary::Xor), .. } => chain(&node, *op, None),
+        Expr::BinaryOp { op : op @ (Binary::Equal | Binary::NotEqual | Binary::ApproxEq), lhs, rhs, offset } => {
+            let (lhs, rhs) = if lhs.to_string() <= rhs.to_string() { (lhs, rhs) } else { (rhs, lhs) };
+            Expr::BinaryOp { op : *op, lhs : lhs.clone(), rhs : rhs.clone(), offset : *offset }
+        }
+        _ => node
+    }
+}
+
+fn is_percent(expr : &Expr) -> bool {
+    matches!(expr, Expr::UnaryOp { op : Unary::Percent, .. })
+}
+
+// Whether `expr` is made of numbers and operators only, with integer
+// exponents, so it folds exactly.
+fn is_constant(expr : &Expr) -> bool {
+    match expr {
+        Expr::Number(_) => true,
+        Expr::UnaryOp { operand, .. } => is_constant(operand),
+        Expr::BinaryOp { op : Binary::Pow, lhs, rhs, .. } => {
+            is_constant(lhs) && is_constant(rhs) && value(rhs).is_some_and(|exponent| exponent.to_integer().is_some())
+        }
+        Expr::BinaryOp { lhs, rhs, .. } => is_constant(lhs) && is_constant(rhs),
+        _ => false
+    }
+}
+
+// The exact value of a constant expression, unless it fails.
+fn value(expr : &Expr) -> Option<Rational> {
+    match eval::evaluate(expr, &Context::<Rational>::default()) {
+        Ok(Value::Scalar(quantity)) if quantity.dimension.is_none() => Some(quantity.value),
+        _ => None
+    }
+}
+
+// A value written as a literal would be: -2.5, or 1/3 for one with no
+// decimal form.
+fn literal(value : &Rational) -> Expr {
+    let (negative, numerator, denominator) = match value.parts() {
+        Some(parts) => parts,
+        None => { return Expr::Number(value.clone()); }
+    };
+    let whole = |n : &Natural| Expr::Number(Rational::from_parts(false, n.clone(), Natural::small(1)));
+    let magnitude = if value.to_decimal().is_some() {
+        Expr::Number(Rational::from_parts(false, numerator.clone(), denominator.clone()))
+    }
+    else {
+        binary(Binary::Div, whole(numerator), whole(denominator))
+    };
+    if negative { Expr::UnaryOp { op : Unary::Minus, operand : Box::new(magnitude), offset : 0 } } else { magnitude }
+}
+
+// `expr` folded to its value if it's constant and doesn't fail.
+fn folded(expr : &Expr) -> Option<Expr> {
+    if !is_constant(expr) {
+        return None;
+    }
+    value(expr).map(|value| literal(&value))
+}
+
+// The operands of a chain of `op`, and of `inverse` if there is one, with
+// whether each is taken away or divided by.
+fn operands(expr : &Expr, op : Binary, inverse : Option<Binary>, inverted : bool, out : &mut Vec<(bool, Expr)>) {
+    match expr {
+        Expr::BinaryOp { op : Binary::Add | Binary::Sub, rhs, .. } if is_percent(rhs) => out.push((inverted, expr.clone())),
+        Expr::BinaryOp { op : this, lhs, rhs, .. } if *this == op || Some(*this) == inverse => {
+            operands(lhs, op, inverse, inverted, out);
+            operands(rhs, op, inverse, inverted != (Some(*this) == inverse), out);
+        }
+        _ => out.push((inverted, expr.clone()))
+    }
+}
+
+// A chain with its constants folded into one, and its operands sorted.
+fn chain(expr : &Expr, op : Binary, inverse : Option<Binary>) -> Expr {
+    let mut all = Vec::new();
+    operands(expr, op, inverse, false, &mut all);
+    let (constants, mut rest) : (Vec<_>, Vec<_>) = all.into_iter().partition(|(_, operand)| is_constant(operand));
+
+    // the constants combined, from the identity if there is one
+    let identity = match op {
+        Binary::Add => Some(Expr::Number(number(0))),
+        Binary::Mul => Some(Expr::Number(number(1))),
+        _ => None
+    };
+    let combined = constants.iter().fold(identity.clone(), |acc, (inverted, operand)| match acc {
+        Some(acc) => Some(binary(if *inverted { inverse.unwrap_or(op) } else { op }, acc, operand.clone())),
+        None => Some(operand.clone())
+    });
+    match combined.as_ref().and_then(folded) {
+        Some(constant) if identity.as_ref() == Some(&constant) && !rest.is_empty() => {}
+        // a negative constant is taken away
+        Some(Expr::UnaryOp { op : Unary::Minus, operand, .. }) if op == Binary::Add => rest.push((true, *operand)),
+        Some(constant) => rest.push((false, constant)),
+        None => rest.extend(constants)
+    }
+
+    rest.sort_by_cached_key(|(inverted, operand)| (*inverted, !is_constant(operand), operand.to_string()));
+    let mut rest = rest.into_iter();
+    let first = match rest.next() {
+        Some((false, operand)) => operand,
+        Some((true, operand)) if op == Binary::Add => Expr::UnaryOp { op : Unary::Minus, operand : Box::new(operand), offset : 0 },
+        Some((true, operand)) => binary(inverse.unwrap_or(op), Expr::Number(number(1)), operand),
+        None => { return identity.unwrap_or_else(|| expr.clone()); }
+    };
+    rest.fold(first, |acc, (inverted, operand)| binary(if inverted { inverse.unwrap_or(op) } else { op }, acc, operand))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ast::Symbolic;
+    use crate::parser;
+    use crate::tokenize;
+    use crate::CalcError;
+    use crate::Dialect;
+
+    use super::apply;
+    use super::canonical;
+
+    fn shown(operation : Symbolic, input : &str) -> Result<String, CalcError> {
+        let expr = parser::parse(tokenize(input, Dialect::Standard))?;
+        apply(&operation, &expr).map(|expr| expr.to_string())
+    }
+
+    #[test]
+    fn simplify() {
+        assert_eq!(shown(Symbolic::Simplify, "2*x + 3*x").as_deref(), Ok("5 * x"));
+        assert_eq!(shown(Symbolic::Simplify, "x - x").as_deref(), Ok("0"));
+        assert_eq!(shown(Symbolic::Simplify, "x / x").as_deref(), Ok("1"));
+        assert_eq!(shown(Symbolic::Simplify, "x * x * 2 + 1/2 + 1/2").as_deref(), Ok("2 * x^2 + 1"));
+        // a product of sums is left as it is
+        assert_eq!(shown(Symbolic::Simplify, "(x + 1) * (x - 1)").as_deref(), Ok("(x + 1) * (x - 1)"));
+    }
+
+    #[test]
+    fn expand() {
+        assert_eq!(shown(Symbolic::Expand, "(x + 1)^2").as_deref(), Ok("x^2 + 2 * x + 1"));
+        assert_eq!(shown(Symbolic::Expand, "(a + b)^3").as_deref(), Ok("a^3 + 3 * a^2 * b + 3 * a * b^2 + b^3"));
+        assert_eq!(shown(Symbolic::Expand, "(x + 1) * (x - 1)").as_deref(), Ok("x^2 - 1"));
+    }
+
+    #[test]
+    fn derivatives() {
+        let d = |input| shown(Symbolic::Derivative("x".to_string()), input);
+        assert_eq!(d("x^3").as_deref(), Ok("3 * x^2"));
+        assert_eq!(d("sin(x) * x").as_deref(), Ok("x * cos(x) + sin(x)"));
+        assert_eq!(d("y * x + y").as_deref(), Ok("y"));
+        assert_eq!(d("exp(2 * x)").as_deref(), Ok("2 * exp(2 * x)"));
+        assert_eq!(d("floor(x)"), Err(CalcError::Differentiate("floor(x)".to_string(), 0)));
+    }
+
+    #[test]
+    fn canonical_forms() {
+        let form = |input| parser::parse(tokenize(input, Dialect::Standard)).map(|expr| canonical(&expr).to_string());
+        assert_eq!(form("1 + x * 2"), form("(2 * x) + 1"));
+        assert_eq!(form("1 + x * 2").as_deref(), Ok("1 + 2 * x"));
+        assert_eq!(form("x * x").as_deref(), Ok("x * x"));
+        assert_eq!(form("200 + 10%").as_deref(), Ok("220"));
+        assert_eq!(form("x + 10%").as_deref(), Ok("x + 10%"));
+    }
+}
@@ -0,0 +1,187 @@
+use crate::ast::Binary;
+use crate::ast::Expr;
+use crate::ast::Unary;
+use crate::eval;
+use crate::number::Number;
+use crate::CalcError;
+use crate::Context;
+use crate::Rational;
+use crate::Value;
+
+/*
+Evaluation one operation at a time, for showing how a result comes about:
+2 + 3 * 4, then 2 + 12, then 14. Each step works out the first operation,
+variable or call whose operands are already numbers, in the order the
+evaluator would, and puts its value into the expression in its place.
+
+A value goes back in as a literal, which only numbers and lists of them
+can be; a quantity with a unit ends the steps early, as do the names bound
+by sum, diff and the like, whose calls are worked out in one step.
+*/
+
+// How many steps are shown at most.
+pub const MAX_STEPS : usize = 1000;
+
+fn is_literal(expr : &Expr) -> bool {
+    match expr {
+        Expr::Number(_) | Expr::Date(_) => true,
+        Expr::UnaryOp { op : Unary::Minus, operand, .. } => matches!(**operand, Expr::Number(_)),
+        Expr::List(items) => items.iter().all(is_literal),
+        _ => false
+    }
+}
+
+// Written in decimal if that gives back the same number, so 0.1 + 0.2 in
+// f64 shows as 0.30000000000000004, and exactly otherwise.
+fn number<N: Number>(value : &N, precision : usize) -> Expr {
+    let text = value.to_decimal_string();
+    let digits = text.trim_start_matches('-');
+    let decimal = Some(digits).filter(|digits| {
+        !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit() || c == '.') && digits.matches('.').count() <= 1
+    });
+    let negative = text.starts_with('-');
+    let magnitude = match decimal.map(Rational::from_decimal) {
+        Some(magnitude) if N::from_rational(&magnitude, precision) == if negative { value.neg() } else { value.clone() } => Expr::Number(magnitude),
+        _ => { return Expr::Number(value.to_literal()); }
+    };
+    if negative {
+        Expr::UnaryOp { op : Unary::Minus, operand : Box::new(magnitude), offset : 0 }
+    }
+    else {
+        magnitude
+    }
+}
+
+// None for a value with a unit, or a date that isn't to the second.
+fn literal<N: Number>(value : &Value<N>, precision : usize) -> Option<Expr> {
+    match value {
+        Value::Scalar(quantity) if quantity.dimension.is_none() => Some(number(&quantity.value, precision)),
+        Value::Scalar(quantity) => quantity.date().filter(|seconds| N::from_integer(*seconds as i128) == quantity.value).map(Expr::Date),
+        Value::List(items) => items.iter().map(|item| literal(item, precision)).collect::<Option<Vec<_>>>().map(Expr::List)
+    }
+}
+
+// The whole of `expr` worked out, as a literal.
+fn reduce_node<N: Number>(expr : &Expr, ctx : &Context<N>) -> Result<Option<Expr>, CalcError> {
+    eval::evaluate(expr, ctx).map(|value| literal(&value, ctx.precision))
+}
+
+// The first of `exprs` that isn't a literal yet, one step further.
+fn reduce_first<N: Number>(exprs : &[Expr], ctx : &Context<N>) -> Result<Option<Option<Vec<Expr>>>, CalcError> {
+    for (i, expr) in exprs.iter().enumerate() {
+        if let Some(reduced) = reduce(expr, ctx)? {
+            let mut exprs = exprs.to_vec();
+            return Ok(Some(reduced.map(|reduced| { exprs[i] = reduced; exprs })));
+        }
+    }
+    Ok(None)
+}
+
+// `expr` one step further: None if it's a literal already, Some(None) if
+// the step can't be shown, as when it gives a unit.
+fn reduce<N: Number>(expr : &Expr, ctx : &Context<N>) -> Result<Option<Option<Expr>>, CalcError> {
+    if is_literal(expr) {
+        return Ok(None);
+    }
+
+    let rebuilt = match expr {
+        Expr::UnaryOp { op, operand, offset } => reduce(operand, ctx)?.map(|operand| {
+            operand.map(|operand| Expr::UnaryOp { op : *op, operand : Box::new(operand), offset : *offset })
+        }),
+        Expr::BinaryOp { op, lhs, rhs, offset } => {
+            let with = |lhs : Expr, rhs : Expr| Expr::BinaryOp { op : *op, lhs : Box::new(lhs), rhs : Box::new(rhs), offset : *offset };
+            let decided = matches!(op, Binary::And | Binary::Or) && is_literal(lhs);
+            match reduce(lhs, ctx)? {
+                Some(lhs) => Some(lhs.map(|lhs| with(lhs, *rhs.clone()))),
+                None if decided => None,
+                None => match &**rhs {
+                    // the percentage in 200 + 10% is of the 200, so it's
+                    // worked out along with the sum
+                    Expr::UnaryOp { op : Unary::Percent, operand, offset : at } if matches!(op, Binary::Add | Binary::Sub) => {
+                        reduce(operand, ctx)?.map(|operand| {
+                            operand.map(|operand| with(*lhs.clone(), Expr::UnaryOp { op : Unary::Percent, operand : Box::new(operand), offset : *at }))
+                        })
+                    }
+                    _ => reduce(rhs, ctx)?.map(|rhs| rhs.map(|rhs| with(*lhs.clone(), rhs)))
+                }
+            }
+        }
+        Expr::Call { name, args, offset } if !eval::binds_names(name, args) => reduce_first(args, ctx)?.map(|args| {
+            args.map(|args| Expr::Call { name : name.clone(), args, offset : *offset })
+        }),
+        Expr::List(items) => reduce_first(items, ctx)?.map(|items| items.map(Expr::List)),
+        Expr::Index { list, index, offset } => reduce_first(&[*list.clone(), *index.clone()], ctx)?.map(|parts| {
+            parts.map(|mut parts| {
+                let index = parts.pop().unwrap_or_else(|| unreachable!("two parts"));
+                let list = parts.pop().unwrap_or_else(|| unreachable!("two parts"));
+                Expr::Index { list : Box::new(list), index : Box::new(index), offset : *offset }
+            })
+        }),
+        // the branch taken replaces the whole, unevaluated
+        Expr::Conditional { cond, then, otherwise } => match reduce(cond, ctx)? {
+            Some(cond) => Some(cond.map(|cond| Expr::Conditional { cond : Box::new(cond), then : then.clone(), otherwise : otherwise.clone() })),
+            None => {
+                let cond = eval::evaluate(cond, ctx)?;
+                let taken = match cond {
+                    Value::Scalar(cond) if eval::is_true(&cond.value) => then,
+                    Value::Scalar(_) => otherwise,
+                    Value::List(_) => { return Err(CalcError::List(0)); }
+                };
+                Some(Some(*taken.clone()))
+            }
+        },
+        _ => None
+    };
+
+    match rebuilt {
+        Some(rebuilt) => Ok(Some(rebuilt)),
+        None => reduce_node(expr, ctx).map(Some)
+    }
+}
+
+// The expressions between `expr` and its value, each one step on from the
+// one before. The value itself isn't among them.
+pub fn trace<N: Number>(expr : &Expr, ctx : &Context<N>) -> Result<Vec<Expr>, CalcError> {
+    let mut steps = Vec::new();
+    let mut current = expr.clone();
+    while steps.len() < MAX_STEPS {
+        match reduce(&current, ctx)? {
+            Some(Some(next)) if !is_literal(&next) => {
+                steps.push(next.clone());
+                current = next;
+            }
+            _ => break
+        }
+    }
+    Ok(steps)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Context;
+    use crate::Dialect;
+    use crate::Value;
+
+    fn steps(input : &str, ctx : &Context<f64>) -> Vec<String> {
+        crate::trace(input, Dialect::Standard, ctx).map(|steps| steps.iter().map(|step| step.to_string()).collect()).unwrap_or_default()
+    }
+
+    #[test]
+    fn reductions() {
+        let ctx = Context::default();
+        assert_eq!(steps("2 + 3 * 4", &ctx), ["2 + 12"]);
+        assert_eq!(steps("(1 + 2) * sqrt(16) - 1", &ctx), ["3 * sqrt(16) - 1", "3 * 4 - 1", "12 - 1"]);
+        assert_eq!(steps("0.1 + 0.2 + 1", &ctx), ["0.30000000000000004 + 1"]);
+        assert_eq!(steps("7", &ctx), Vec::<String>::new());
+    }
+
+    #[test]
+    fn variables_and_calls() {
+        let mut ctx = Context::default();
+        ctx.variables.insert("x".to_string(), Value::number(2.0));
+        assert_eq!(steps("x^2 + sum(i, 1, 3, i)", &ctx), ["2^2 + sum(i, 1, 3, i)", "4 + sum(i, 1, 3, i)", "4 + 6"]);
+        // a unit ends them
+        assert_eq!(steps("2 km + 1 km + 1", &ctx), Vec::<String>::new());
+        assert!(crate::trace("1 / 0 + 1", Dialect::Standard, &ctx).is_err());
+    }
+}
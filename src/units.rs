@@ -0,0 +1,423 @@
+use std::fmt;
+
+use crate::date;
+use crate::lexer::Dialect;
+use crate::lexer::Lexer;
+use crate::lexer::Token;
+use crate::number::Number;
+use crate::rational::Rational;
+
+/*
+Physical units. A quantity is a number in SI base units together with its
+dimension, the powers of the base units in it, so 5 km is 5000 with the
+dimension of a length. Only quantities of the same dimension can be added,
+subtracted or compared, and results are shown in SI units unless converted
+with a trailing `in`, `to` or `->`, as in 60 mph in km/h.
+
+The units are names looked up after variables and constants, so a variable
+called `m` hides the metre. Units marked as such also take the SI prefixes,
+as in km, ms or kWh. Temperatures are kelvin only, since °C and °F don't
+start at zero.
+
+A date is a quantity too, the seconds since 1970 with a dimension of its
+own. Like a temperature in °C it doesn't start from nothing, so only a
+duration can be added to it, and the difference of two dates is a
+duration.
+
+Angles have a base unit of their own, the radian, so that 45°30' can be
+converted to deg or shown in degrees and minutes. sin, cos and tan take an
+angle in radians whatever unit the session works in; a plain number is
+still taken in that unit.
+*/
+
+// the base units, in the order of the powers in a `Dimension`
+const BASE : [&str; 8] = ["m", "kg", "s", "A", "K", "mol", "cd", "rad"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Dimension {
+    powers : [i8; 8],
+    date : bool
+}
+
+const LENGTH : Dimension = Dimension::of([1, 0, 0, 0, 0, 0, 0, 0]);
+const MASS : Dimension = Dimension::of([0, 1, 0, 0, 0, 0, 0, 0]);
+const TIME : Dimension = Dimension::of([0, 0, 1, 0, 0, 0, 0, 0]);
+const CURRENT : Dimension = Dimension::of([0, 0, 0, 1, 0, 0, 0, 0]);
+const TEMPERATURE : Dimension = Dimension::of([0, 0, 0, 0, 1, 0, 0, 0]);
+const AMOUNT : Dimension = Dimension::of([0, 0, 0, 0, 0, 1, 0, 0]);
+const LUMINOSITY : Dimension = Dimension::of([0, 0, 0, 0, 0, 0, 1, 0]);
+const AREA : Dimension = Dimension::of([2, 0, 0, 0, 0, 0, 0, 0]);
+const VOLUME : Dimension = Dimension::of([3, 0, 0, 0, 0, 0, 0, 0]);
+const SPEED : Dimension = Dimension::of([1, 0, -1, 0, 0, 0, 0, 0]);
+const FREQUENCY : Dimension = Dimension::of([0, 0, -1, 0, 0, 0, 0, 0]);
+const FORCE : Dimension = Dimension::of([1, 1, -2, 0, 0, 0, 0, 0]);
+const PRESSURE : Dimension = Dimension::of([-1, 1, -2, 0, 0, 0, 0, 0]);
+const ENERGY : Dimension = Dimension::of([2, 1, -2, 0, 0, 0, 0, 0]);
+const POWER : Dimension = Dimension::of([2, 1, -3, 0, 0, 0, 0, 0]);
+const CHARGE : Dimension = Dimension::of([0, 0, 1, 1, 0, 0, 0, 0]);
+const VOLTAGE : Dimension = Dimension::of([2, 1, -3, -1, 0, 0, 0, 0]);
+const RESISTANCE : Dimension = Dimension::of([2, 1, -3, -2, 0, 0, 0, 0]);
+const ANGLE : Dimension = Dimension::of([0, 0, 0, 0, 0, 0, 0, 1]);
+
+// Results of these dimensions are shown in the named unit rather than in
+// base units, e.g. J rather than kg*m^2/s^2.
+const DERIVED : &[(&str, Dimension)] = &[
+    ("N", FORCE),
+    ("Pa", PRESSURE),
+    ("J", ENERGY),
+    ("W", POWER),
+    ("Hz", FREQUENCY),
+    ("C", CHARGE),
+    ("V", VOLTAGE),
+    ("ohm", RESISTANCE),
+];
+
+impl Dimension {
+    // that of a plain number
+    pub const NONE : Dimension = Dimension::of([0; 8]);
+    // that of a duration, in seconds
+    pub const TIME : Dimension = TIME;
+    // that of an angle such as 45°30', in radians
+    pub const ANGLE : Dimension = ANGLE;
+    // that of a length such as 5'10", in metres
+    pub const LENGTH : Dimension = LENGTH;
+    // that of a point in time
+    pub const DATE : Dimension = Dimension { powers : TIME.powers, date : true };
+
+    const fn of(powers : [i8; 8]) -> Dimension {
+        Dimension { powers, date : false }
+    }
+
+    pub fn is_none(self) -> bool {
+        self == Dimension::NONE
+    }
+
+    pub fn is_date(self) -> bool {
+        self.date
+    }
+
+    // the powers of m, kg, s, A, K, mol, cd and rad, in that order
+    pub fn powers(self) -> [i8; 8] {
+        self.powers
+    }
+
+    pub fn from_powers(powers : [i8; 8]) -> Dimension {
+        Dimension::of(powers)
+    }
+
+    // Each power worked out by `f`; None if one leaves the range of an i8,
+    // or either is a date, which can't be multiplied or divided.
+    fn combine(self, rhs : Dimension, f : impl Fn(i8, i8) -> Option<i8>) -> Option<Dimension> {
+        if self.date || rhs.date {
+            return None;
+        }
+        let mut powers = [0; 8];
+        for (i, power) in powers.iter_mut().enumerate() {
+            *power = f(self.powers[i], rhs.powers[i])?;
+        }
+        Some(Dimension::of(powers))
+    }
+
+    // That of `self + rhs`: a date and a duration make a date, and two
+    // dates nothing.
+    pub fn sum(self, rhs : Dimension) -> Option<Dimension> {
+        match (self.date, rhs.date) {
+            (false, false) => (self == rhs).then_some(self),
+            (true, false) => (rhs == TIME).then_some(self),
+            (false, true) => (self == TIME).then_some(rhs),
+            (true, true) => None
+        }
+    }
+
+    // That of `self - rhs`: two dates are a duration apart, and a date less
+    // a duration is a date.
+    pub fn difference(self, rhs : Dimension) -> Option<Dimension> {
+        match (self.date, rhs.date) {
+            (false, false) => (self == rhs).then_some(self),
+            (true, false) => (rhs == TIME).then_some(self),
+            (false, true) => None,
+            (true, true) => Some(TIME)
+        }
+    }
+
+    pub fn product(self, rhs : Dimension) -> Option<Dimension> {
+        self.combine(rhs, i8::checked_add)
+    }
+
+    pub fn quotient(self, rhs : Dimension) -> Option<Dimension> {
+        self.combine(rhs, i8::checked_sub)
+    }
+
+    // None unless every power is a multiple of `n`, as for the square root
+    // of an area.
+    pub fn root(self, n : i8) -> Option<Dimension> {
+        self.combine(Dimension::NONE, |power, _| if power % n == 0 { Some(power / n) } else { None })
+    }
+
+    // Raised to `exponent`, which has to leave whole powers. A plain number
+    // stays one whatever the exponent.
+    pub fn pow(self, exponent : &Rational) -> Option<Dimension> {
+        if self.is_none() {
+            return Some(self);
+        }
+
+        let (negative, numerator, denominator) = exponent.parts()?;
+        let numerator = i8::try_from(numerator.to_u64()?).ok()?;
+        let denominator = i8::try_from(denominator.to_u64()?).ok()?;
+        let numerator = if negative { -numerator } else { numerator };
+        self.combine(Dimension::NONE, |power, _| power.checked_mul(numerator))?.root(denominator)
+    }
+}
+
+// In base units, with a named unit for a whole derived dimension: m/s^2,
+// kg/(m*s), J. Powers of a unit with none above the line are negative, as
+// in s^-1.
+impl fmt::Display for Dimension {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some((name, _)) = DERIVED.iter().find(|(_, dimension)| dimension == self) {
+            return write!(f, "{}", name);
+        }
+
+        let term = |name : &str, power : i8| if power == 1 { name.to_string() } else { format!("{}^{}", name, power) };
+        let above = (0..BASE.len()).filter(|&i| self.powers[i] > 0).map(|i| term(BASE[i], self.powers[i])).collect::<Vec<_>>();
+        let below = (0..BASE.len()).filter(|&i| self.powers[i] < 0).collect::<Vec<_>>();
+
+        if above.is_empty() {
+            let terms = below.iter().map(|&i| term(BASE[i], self.powers[i])).collect::<Vec<_>>();
+            return write!(f, "{}", terms.join("*"));
+        }
+
+        write!(f, "{}", above.join("*"))?;
+        let below = below.iter().map(|&i| term(BASE[i], -self.powers[i])).collect::<Vec<_>>();
+        match below.len() {
+            0 => Ok(()),
+            1 => write!(f, "/{}", below[0]),
+            _ => write!(f, "/({})", below.join("*"))
+        }
+    }
+}
+
+// A value the evaluator computed, in SI base units.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Quantity<N> {
+    pub value : N,
+    pub dimension : Dimension
+}
+
+impl<N> Quantity<N> {
+    // a plain number, without a unit
+    pub fn number(value : N) -> Quantity<N> {
+        Quantity { value, dimension : Dimension::NONE }
+    }
+}
+
+impl<N: Number> Quantity<N> {
+    // The seconds of a date to the nearest one, if it is a date that can
+    // be shown as one.
+    pub fn date(&self) -> Option<i64> {
+        let seconds = self.value.to_f64().round();
+        (self.dimension.is_date() && seconds.abs() < 1e17).then_some(seconds as i64)
+    }
+}
+
+impl<N: Number> fmt::Display for Quantity<N> {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(date) = self.date() {
+            write!(f, "{}", date::format(date))
+        }
+        else if self.dimension.is_none() {
+            write!(f, "{}", self.value)
+        }
+        else {
+            write!(f, "{} {}", self.value, self.dimension)
+        }
+    }
+}
+
+pub struct Unit {
+    pub name : &'static str,
+    // how many of the base units one of it is, as a fraction
+    pub numerator : u64,
+    pub denominator : u64,
+    pub dimension : Dimension,
+    // whether it takes the SI prefixes
+    pub prefixed : bool
+}
+
+pub const UNITS : &[Unit] = &[
+    Unit { name : "m", numerator : 1, denominator : 1, dimension : LENGTH, prefixed : true },
+    // `in` converts, so an inch is spelled out
+    Unit { name : "inch", numerator : 254, denominator : 10_000, dimension : LENGTH, prefixed : false },
+    Unit { name : "ft", numerator : 3048, denominator : 10_000, dimension : LENGTH, prefixed : false },
+    Unit { name : "yd", numerator : 9144, denominator : 10_000, dimension : LENGTH, prefixed : false },
+    Unit { name : "mi", numerator : 1_609_344, denominator : 1000, dimension : LENGTH, prefixed : false },
+    Unit { name : "nmi", numerator : 1852, denominator : 1, dimension : LENGTH, prefixed : false },
+    Unit { name : "au", numerator : 149_597_870_700, denominator : 1, dimension : LENGTH, prefixed : false },
+    Unit { name : "g", numerator : 1, denominator : 1000, dimension : MASS, prefixed : true },
+    Unit { name : "lb", numerator : 45_359_237, denominator : 100_000_000, dimension : MASS, prefixed : false },
+    Unit { name : "oz", numerator : 45_359_237, denominator : 1_600_000_000, dimension : MASS, prefixed : false },
+    Unit { name : "s", numerator : 1, denominator : 1, dimension : TIME, prefixed : true },
+    Unit { name : "min", numerator : 60, denominator : 1, dimension : TIME, prefixed : false },
+    Unit { name : "h", numerator : 3600, denominator : 1, dimension : TIME, prefixed : false },
+    Unit { name : "day", numerator : 86_400, denominator : 1, dimension : TIME, prefixed : false },
+    Unit { name : "week", numerator : 604_800, denominator : 1, dimension : TIME, prefixed : false },
+    // as in 2024-03-01 + 45 days
+    Unit { name : "days", numerator : 86_400, denominator : 1, dimension : TIME, prefixed : false },
+    Unit { name : "weeks", numerator : 604_800, denominator : 1, dimension : TIME, prefixed : false },
+    // a Julian year, 365.25 days
+    Unit { name : "yr", numerator : 31_557_600, denominator : 1, dimension : TIME, prefixed : false },
+    Unit { name : "A", numerator : 1, denominator : 1, dimension : CURRENT, prefixed : true },
+    Unit { name : "K", numerator : 1, denominator : 1, dimension : TEMPERATURE, prefixed : true },
+    Unit { name : "mol", numerator : 1, denominator : 1, dimension : AMOUNT, prefixed : true },
+    Unit { name : "cd", numerator : 1, denominator : 1, dimension : LUMINOSITY, prefixed : true },
+    Unit { name : "ha", numerator : 10_000, denominator : 1, dimension : AREA, prefixed : false },
+    Unit { name : "acre", numerator : 40_468_564_224, denominator : 10_000_000, dimension : AREA, prefixed : false },
+    Unit { name : "L", numerator : 1, denominator : 1000, dimension : VOLUME, prefixed : true },
+    // the US gallon
+    Unit { name : "gal", numerator : 3_785_411_784, denominator : 1_000_000_000_000, dimension : VOLUME, prefixed : false },
+    Unit { name : "mph", numerator : 1_609_344, denominator : 3_600_000, dimension : SPEED, prefixed : false },
+    Unit { name : "kn", numerator : 1852, denominator : 3600, dimension : SPEED, prefixed : false },
+    Unit { name : "Hz", numerator : 1, denominator : 1, dimension : FREQUENCY, prefixed : true },
+    Unit { name : "N", numerator : 1, denominator : 1, dimension : FORCE, prefixed : true },
+    Unit { name : "lbf", numerator : 44_482_216_152_605, denominator : 10_000_000_000_000, dimension : FORCE, prefixed : false },
+    Unit { name : "Pa", numerator : 1, denominator : 1, dimension : PRESSURE, prefixed : true },
+    Unit { name : "bar", numerator : 100_000, denominator : 1, dimension : PRESSURE, prefixed : true },
+    Unit { name : "atm", numerator : 101_325, denominator : 1, dimension : PRESSURE, prefixed : false },
+    // lbf per square inch
+    Unit { name : "psi", numerator : 44_482_216_152_605, denominator : 6_451_600_000, dimension : PRESSURE, prefixed : false },
+    Unit { name : "J", numerator : 1, denominator : 1, dimension : ENERGY, prefixed : true },
+    Unit { name : "Wh", numerator : 3600, denominator : 1, dimension : ENERGY, prefixed : true },
+    Unit { name : "cal", numerator : 4184, denominator : 1000, dimension : ENERGY, prefixed : true },
+    Unit { name : "W", numerator : 1, denominator : 1, dimension : POWER, prefixed : true },
+    // mechanical horsepower
+    Unit { name : "hp", numerator : 74_569_987_158_227_022, denominator : 100_000_000_000_000, dimension : POWER, prefixed : false },
+    Unit { name : "C", numerator : 1, denominator : 1, dimension : CHARGE, prefixed : true },
+    Unit { name : "V", numerator : 1, denominator : 1, dimension : VOLTAGE, prefixed : true },
+    Unit { name : "ohm", numerator : 1, denominator : 1, dimension : RESISTANCE, prefixed : true },
+    Unit { name : "Ω", numerator : 1, denominator : 1, dimension : RESISTANCE, prefixed : true },
+    Unit { name : "rad", numerator : 1, denominator : 1, dimension : ANGLE, prefixed : true },
+    // pi/180 to 18 places
+    Unit { name : "deg", numerator : 17_453_292_519_943_295, denominator : 1_000_000_000_000_000_000, dimension : ANGLE, prefixed : false },
+];
+
+// The SI prefixes, with the power of ten of each.
+const PREFIXES : &[(&str, i32)] = &[
+    ("p", -12),
+    ("n", -9),
+    ("u", -6),
+    ("µ", -6),
+    ("m", -3),
+    ("c", -2),
+    ("k", 3),
+    ("M", 6),
+    ("G", 9),
+    ("T", 12),
+];
+
+fn parse_unit(name : &str) -> Option<(Rational, &'static Unit)> {
+    let one = Rational::integer(1);
+    if let Some(unit) = UNITS.iter().find(|u| u.name == name) {
+        return Some((one, unit));
+    }
+
+    PREFIXES.iter().find_map(|&(prefix, power)| {
+        let unit = UNITS.iter().find(|u| u.prefixed && name.strip_prefix(prefix) == Some(u.name))?;
+        let scale = Rational::integer(10i128.pow(power.unsigned_abs()));
+        let scale = if power < 0 { one.div(&scale, 0)? } else { scale };
+        Some((scale, unit))
+    })
+}
+
+// How many base units one of the unit `name` is and of what, if it is one.
+pub fn find(name : &str) -> Option<(Rational, Dimension)> {
+    let (scale, unit) = parse_unit(name)?;
+    Some((scale.mul(&Rational::ratio(unit.numerator, unit.denominator)), unit.dimension))
+}
+
+// A trailing `in unit`, `to unit` or `-> unit` split off `input`, with
+// where the unit starts: 60 mph in km/h is 60 mph and km/h at 10. One
+// inside brackets isn't split off.
+pub fn split_conversion(input : &str, dialect : Dialect) -> (&str, Option<(usize, &str)>) {
+    let mut depth : usize = 0;
+    let mut split = None;
+    // the span of a `-`, which a `>` right after it makes an arrow
+    let mut minus : Option<(usize, usize)> = None;
+    for token in Lexer::new(input, dialect) {
+        match token.token {
+            Token::LeftBracket => { depth += 1; }
+            Token::RightBracket => { depth = depth.saturating_sub(1); }
+            Token::In if depth == 0 => { split = Some((token.start, token.end)); }
+            Token::Greater if depth == 0 => {
+                if let Some((start, _)) = minus.filter(|(_, end)| *end == token.start) {
+                    split = Some((start, token.end));
+                }
+            }
+            _ => {}
+        }
+        minus = (token.token == Token::Sub).then_some((token.start, token.end));
+    }
+
+    match split {
+        Some((start, end)) => {
+            let unit = input[end..].trim();
+            let offset = if unit.is_empty() { end } else { end + input[end..].find(unit).unwrap_or(0) };
+            (&input[..start], Some((offset, unit)))
+        }
+        None => (input, None)
+    }
+}
+
+// Whether `input` names units alone, as in cm or km/h^2, rather than being
+// an expression of anything else.
+pub fn is_unit(input : &str) -> bool {
+    let mut names = 0;
+    for token in Lexer::new(input, Dialect::Standard) {
+        match token.token {
+            Token::Ident(name) if find(&name).is_some() => { names += 1; }
+            Token::Number(_) | Token::Mul | Token::Div | Token::Pow | Token::Sub | Token::LeftBracket | Token::RightBracket => {}
+            _ => { return false; }
+        }
+    }
+    names > 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::find;
+    use super::is_unit;
+    use super::split_conversion;
+    use super::Dimension;
+    use super::LENGTH;
+    use super::TIME;
+    use crate::rational::Rational;
+    use crate::Dialect;
+
+    #[test]
+    fn prefixed_units() {
+        assert_eq!(find("km"), Some((Rational::integer(1000), LENGTH)));
+        assert_eq!(find("ms"), Some((Rational::ratio(1, 1000), TIME)));
+        assert_eq!(find("kWh").map(|(scale, _)| scale), Some(Rational::integer(3_600_000)));
+        assert_eq!(find("kft"), None);
+    }
+
+    #[test]
+    fn dimensions() {
+        let speed = LENGTH.quotient(TIME).expect("a dimension");
+        assert_eq!(speed.to_string(), "m/s");
+        assert_eq!(speed.quotient(TIME).map(|d| d.to_string()), Some("m/s^2".to_string()));
+        assert_eq!(Dimension::NONE.quotient(speed).map(|d| d.to_string()), Some("s/m".to_string()));
+        assert_eq!(LENGTH.sum(TIME), None);
+        assert_eq!(Dimension::DATE.difference(Dimension::DATE), Some(Dimension::TIME));
+    }
+
+    #[test]
+    fn conversions() {
+        assert_eq!(split_conversion("60 mph in km/h", Dialect::Standard), ("60 mph ", Some((10, "km/h"))));
+        assert_eq!(split_conversion("5'10\" -> cm", Dialect::Standard), ("5'10\" ", Some((9, "cm"))));
+        assert_eq!(split_conversion("f(x -> cm)", Dialect::Standard).1, None);
+        assert_eq!(split_conversion("3 - > 2", Dialect::Standard).1, None);
+        assert!(is_unit(" km/h^2"));
+        assert!(!is_unit("hex"));
+        assert!(!is_unit("2"));
+    }
+}
@@ -0,0 +1,64 @@
+use std::fmt;
+
+use crate::number::Number;
+use crate::units::Quantity;
+
+/*
+What an expression evaluates to: a quantity, or a list of values such as
+[1, 2.5, 3 m]. Arithmetic on lists works element by element, with a single
+value standing in for every element, so [1, 2] * 3 is [3, 6]. Lists may
+hold lists.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value<N> {
+    Scalar(Quantity<N>),
+    List(Vec<Value<N>>)
+}
+
+impl<N> Value<N> {
+    // a plain number, without a unit
+    pub fn number(value : N) -> Value<N> {
+        Value::Scalar(Quantity::number(value))
+    }
+
+    // The quantities in it, those of nested lists included, in order.
+    pub fn flatten(self) -> Vec<Quantity<N>> {
+        match self {
+            Value::Scalar(quantity) => vec![quantity],
+            Value::List(items) => items.into_iter().flat_map(Value::flatten).collect()
+        }
+    }
+
+    // The same shape, with `f` applied to every quantity.
+    pub fn try_map<M, E>(self, f : &mut impl FnMut(Quantity<N>) -> Result<Quantity<M>, E>) -> Result<Value<M>, E> {
+        match self {
+            Value::Scalar(quantity) => f(quantity).map(Value::Scalar),
+            Value::List(items) => items.into_iter().map(|item| item.try_map(f)).collect::<Result<_, _>>().map(Value::List)
+        }
+    }
+
+    pub fn map<M>(self, f : &mut impl FnMut(Quantity<N>) -> Quantity<M>) -> Value<M> {
+        match self {
+            Value::Scalar(quantity) => Value::Scalar(f(quantity)),
+            Value::List(items) => Value::List(items.into_iter().map(|item| item.map(f)).collect())
+        }
+    }
+}
+
+impl<N: Number> fmt::Display for Value<N> {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Scalar(quantity) => write!(f, "{}", quantity),
+            Value::List(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+        }
+    }
+}
@@ -0,0 +1,381 @@
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Read;
+use std::io::Write;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use calculator::Context;
+use calculator::Dialect;
+use calculator::Outcome;
+
+use crate::json;
+use crate::metrics::Metrics;
+use crate::serve;
+
+/*
+Live sessions over WebSocket (RFC 6455). Each connection gets a session of
+its own, as the REPL has, so what one text message assigns or defines the
+next can use. A text message is one statement, and the answer to it is a
+text message of JSON, as the HTTP server gives: {"result": ..., "value":
+...}, {"defined": name} for a definition, or {"error": ...}.
+
+Connections are limited as in the HTTP server, and so is each evaluation.
+A connection that says nothing for IDLE is closed, and so is one that
+sends a binary message, which is refused with 1003, or a message over
+MAX_MESSAGE bytes, refused with 1009. Extensions and subprotocols aren't
+offered. A GET of /metrics that doesn't ask for an upgrade gives what the
+server has counted, as the HTTP server's does.
+*/
+
+const IDLE : Duration = Duration::from_secs(300);
+const MAX_MESSAGE : usize = 64 * 1024;
+const MAX_HEADER_LINES : usize = 100;
+// appended to the client's key before hashing it, as RFC 6455 says
+const GUID : &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const CONTINUATION : u8 = 0x0;
+const TEXT : u8 = 0x1;
+const BINARY : u8 = 0x2;
+const CLOSE : u8 = 0x8;
+const PING : u8 = 0x9;
+const PONG : u8 = 0xa;
+
+const UNSUPPORTED : u16 = 1003;
+const TOO_BIG : u16 = 1009;
+const PROTOCOL_ERROR : u16 = 1002;
+
+fn sha1(message : &[u8]) -> [u8; 20] {
+    let mut h : [u32; 5] = [0x6745_2301, 0xefcd_ab89, 0x98ba_dcfe, 0x1032_5476, 0xc3d2_e1f0];
+    let mut padded = message.to_vec();
+    padded.push(0x80);
+    while padded.len() % 64 != 56 {
+        padded.push(0);
+    }
+    padded.extend_from_slice(&(message.len() as u64 * 8).to_be_bytes());
+
+    for block in padded.chunks(64) {
+        let mut w = [0u32; 80];
+        for i in 0..16 {
+            w[i] = u32::from_be_bytes([block[4 * i], block[4 * i + 1], block[4 * i + 2], block[4 * i + 3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a82_7999),
+                20..=39 => (b ^ c ^ d, 0x6ed9_eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1b_bcdc),
+                _ => (b ^ c ^ d, 0xca62_c1d6)
+            };
+            let next = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = next;
+        }
+        for (h, x) in h.iter_mut().zip([a, b, c, d, e]) {
+            *h = h.wrapping_add(x);
+        }
+    }
+
+    let mut digest = [0; 20];
+    for (i, word) in h.iter().enumerate() {
+        digest[4 * i..4 * i + 4].copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+fn base64(bytes : &[u8]) -> String {
+    const ALPHABET : &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, byte)| n | (*byte as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            }
+            else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+// What the server answers the key of a client's handshake with.
+fn accept(key : &str) -> String {
+    base64(&sha1(format!("{}{}", key.trim(), GUID).as_bytes()))
+}
+
+fn write_frame(stream : &mut TcpStream, opcode : u8, payload : &[u8]) -> io::Result<()> {
+    let mut frame = vec![0x80 | opcode];
+    match payload.len() {
+        n if n < 126 => frame.push(n as u8),
+        n if n <= u16::MAX as usize => {
+            frame.push(126);
+            frame.extend_from_slice(&(n as u16).to_be_bytes());
+        }
+        n => {
+            frame.push(127);
+            frame.extend_from_slice(&(n as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    stream.write_all(&frame)?;
+    stream.flush()
+}
+
+fn close(stream : &mut TcpStream, code : u16) -> io::Result<()> {
+    write_frame(stream, CLOSE, &code.to_be_bytes())
+}
+
+struct Frame {
+    fin : bool,
+    opcode : u8,
+    payload : Vec<u8>
+}
+
+// The next frame from the client, or Err(code) with the code to close the
+// connection with if it breaks the protocol or the limit.
+fn read_frame(reader : &mut impl Read) -> io::Result<Result<Frame, u16>> {
+    let mut head = [0; 2];
+    reader.read_exact(&mut head)?;
+    let (fin, opcode, masked) = (head[0] & 0x80 != 0, head[0] & 0x0f, head[1] & 0x80 != 0);
+    let length = match head[1] & 0x7f {
+        126 => {
+            let mut bytes = [0; 2];
+            reader.read_exact(&mut bytes)?;
+            u16::from_be_bytes(bytes) as u64
+        }
+        127 => {
+            let mut bytes = [0; 8];
+            reader.read_exact(&mut bytes)?;
+            u64::from_be_bytes(bytes)
+        }
+        n => n as u64
+    };
+    // a client has to mask what it sends, and reserved bits are unused
+    if !masked || head[0] & 0x70 != 0 {
+        return Ok(Err(PROTOCOL_ERROR));
+    }
+    if length > MAX_MESSAGE as u64 {
+        return Ok(Err(TOO_BIG));
+    }
+
+    let mut mask = [0; 4];
+    reader.read_exact(&mut mask)?;
+    let mut payload = vec![0; length as usize];
+    reader.read_exact(&mut payload)?;
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+    Ok(Ok(Frame { fin, opcode, payload }))
+}
+
+// What a connection asks for first.
+enum Handshake {
+    // to be upgraded, with its Sec-WebSocket-Key
+    Upgrade(String),
+    // a GET otherwise, of the path
+    Get(String),
+    Other
+}
+
+fn handshake(reader : &mut impl BufRead) -> io::Result<Handshake> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut words = line.split_whitespace();
+    let get = (words.next() == Some("GET")).then(|| words.next().unwrap_or_default().to_string());
+
+    let (mut key, mut upgrade) = (None, false);
+    for _ in 0..MAX_HEADER_LINES {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            match name.trim().to_ascii_lowercase().as_str() {
+                "sec-websocket-key" => { key = Some(value.trim().to_string()); }
+                "upgrade" => { upgrade = value.trim().eq_ignore_ascii_case("websocket"); }
+                _ => {}
+            }
+        }
+    }
+    Ok(match (get, key) {
+        (Some(_), Some(key)) if upgrade => Handshake::Upgrade(key),
+        (Some(path), _) => Handshake::Get(path),
+        _ => Handshake::Other
+    })
+}
+
+// The answer to one text message in `ctx`.
+fn answer(ctx : &mut Context<f64>, input : &str, metrics : &Metrics) -> json::Value {
+    metrics.message();
+    if input.chars().count() > serve::MAX_EXPRESSION_LEN {
+        let error = json::Value::object(vec![("message", json::Value::string(&format!("Expression longer than {} characters", serve::MAX_EXPRESSION_LEN)))]);
+        return json::Value::object(vec![("error", error)]);
+    }
+    match metrics.evaluate(|| calculator::execute(input, Dialect::Standard, ctx)) {
+        Ok(Outcome::Value(value) | Outcome::Assigned(_, value)) => {
+            let reply = serve::reply(&Ok(value.clone()), input);
+            ctx.results.push(value);
+            reply
+        }
+        Ok(Outcome::Defined(name)) => json::Value::object(vec![("defined", json::Value::string(&name))]),
+        Ok(Outcome::Expression(expr)) => json::Value::object(vec![("result", json::Value::string(&expr.to_string()))]),
+        Err(e) => serve::reply(&Err(e), input)
+    }
+}
+
+fn handle(mut stream : TcpStream, metrics : &Metrics) -> io::Result<()> {
+    stream.set_read_timeout(Some(IDLE))?;
+    stream.set_write_timeout(Some(IDLE))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let key = match handshake(&mut reader)? {
+        Handshake::Upgrade(key) => key,
+        Handshake::Get(path) if path == "/metrics" => {
+            let body = metrics.render();
+            write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body)?;
+            return stream.flush();
+        }
+        _ => {
+            write!(stream, "HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")?;
+            return stream.flush();
+        }
+    };
+    write!(stream, "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n", accept(&key))?;
+    stream.flush()?;
+
+    let mut ctx = Context::<f64> { max_steps : Some(serve::MAX_STEPS), time_limit : Some(serve::EVALUATION_TIME), ..Context::default() };
+    // a text message sent in fragments, until the last of them
+    let mut message : Option<Vec<u8>> = None;
+    loop {
+        let frame = match read_frame(&mut reader)? {
+            Ok(frame) => frame,
+            Err(code) => { return close(&mut stream, code); }
+        };
+        match frame.opcode {
+            PING => { write_frame(&mut stream, PONG, &frame.payload)?; }
+            PONG => {}
+            CLOSE => { return write_frame(&mut stream, CLOSE, frame.payload.get(..2).unwrap_or_default()); }
+            BINARY => { return close(&mut stream, UNSUPPORTED); }
+            TEXT if message.is_none() => { message = Some(frame.payload); }
+            CONTINUATION if message.is_some() => {
+                let text = message.get_or_insert_with(Vec::new);
+                if text.len() + frame.payload.len() > MAX_MESSAGE {
+                    return close(&mut stream, TOO_BIG);
+                }
+                text.extend_from_slice(&frame.payload);
+            }
+            _ => { return close(&mut stream, PROTOCOL_ERROR); }
+        }
+
+        if frame.fin && matches!(frame.opcode, TEXT | CONTINUATION) {
+            let text = message.take().unwrap_or_default();
+            let input = match String::from_utf8(text) {
+                Ok(input) => input,
+                // not UTF-8, as a text message has to be
+                Err(_) => { return close(&mut stream, 1007); }
+            };
+            let reply = answer(&mut ctx, &input, metrics).to_string();
+            write_frame(&mut stream, TEXT, reply.as_bytes())?;
+        }
+    }
+}
+
+// A port alone, as in --ws 8080, is one on the loopback interface.
+fn address(address : &str) -> String {
+    if address.bytes().all(|b| b.is_ascii_digit()) {
+        format!("127.0.0.1:{}", address)
+    }
+    else {
+        address.to_string()
+    }
+}
+
+pub fn run(address_given : &str) -> i32 {
+    let address = address(address_given);
+    let listener = match TcpListener::bind(&address) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("{}: {}", address, e);
+            return 1;
+        }
+    };
+
+    let open = Arc::new(AtomicUsize::new(0));
+    let metrics = Arc::new(Metrics::default());
+    for mut stream in listener.incoming().flatten() {
+        if open.fetch_add(1, Ordering::SeqCst) >= serve::MAX_CONNECTIONS {
+            open.fetch_sub(1, Ordering::SeqCst);
+            let _ = write!(stream, "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+            continue;
+        }
+        let connections = Arc::clone(&open);
+        let metrics = Arc::clone(&metrics);
+        let spawned = thread::Builder::new().spawn(move || {
+            let _ = handle(stream, &metrics);
+            connections.fetch_sub(1, Ordering::SeqCst);
+        });
+        if spawned.is_err() {
+            open.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use calculator::Context;
+
+    use super::accept;
+    use super::answer;
+    use super::base64;
+    use super::read_frame;
+    use super::sha1;
+    use super::TEXT;
+    use super::TOO_BIG;
+    use crate::metrics::Metrics;
+
+    #[test]
+    fn handshake_key() {
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+        let hex = sha1(b"abc").iter().map(|byte| format!("{:02x}", byte)).collect::<String>();
+        assert_eq!(hex, "a9993e364706816aba3e25717850c26c9cd0d89d");
+        // the example of RFC 6455
+        assert_eq!(accept("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    #[test]
+    fn frames() {
+        // "Hello" masked, from RFC 6455
+        let frame = [0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58];
+        let frame = read_frame(&mut &frame[..]).expect("a whole frame").expect("a valid frame");
+        assert!(frame.fin);
+        assert_eq!((frame.opcode, frame.payload), (TEXT, b"Hello".to_vec()));
+        // unmasked, as only a server may send
+        assert!(matches!(read_frame(&mut &[0x81, 0x05, b'H', b'e', b'l', b'l', b'o'][..]), Ok(Err(_))));
+        assert!(matches!(read_frame(&mut &[0x81, 0xff, 0, 0, 0, 0, 0, 2, 0, 0][..]), Ok(Err(TOO_BIG))));
+    }
+
+    #[test]
+    fn sessions() {
+        let (mut ctx, metrics) = (Context::default(), Metrics::default());
+        assert_eq!(answer(&mut ctx, "x = 6 * 7", &metrics).to_string(), r#"{"result":"42","value":42}"#);
+        assert_eq!(answer(&mut ctx, "f(y) = y + x", &metrics).to_string(), r#"{"defined":"f"}"#);
+        assert_eq!(answer(&mut ctx, "f(1)", &metrics).to_string(), r#"{"result":"43","value":43}"#);
+        assert!(answer(&mut ctx, "1 +", &metrics).to_string().starts_with(r#"{"error":{"code":"unexpected_end""#));
+    }
+}